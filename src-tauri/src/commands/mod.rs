@@ -1,3 +1,5 @@
+pub(crate) use crate::tools::concat::cancel as concat_cancel;
+pub(crate) use crate::tools::concat::concat;
 pub(crate) use crate::tools::data::mediaflow as data;
 pub(crate) use crate::tools::ffmpeg::cancel as ffmpeg_cancel;
 pub(crate) use crate::tools::ffmpeg::download as ffmpeg_download;
@@ -9,6 +11,7 @@ pub(crate) use crate::tools::fs::file_ops as fs_file_ops;
 pub(crate) use crate::tools::fs::metadata as fs_metadata;
 pub(crate) use crate::tools::fs::open_folder as fs_open_folder;
 pub(crate) use crate::tools::merge::cancel as merge_cancel;
+pub(crate) use crate::tools::merge::episodes as merge_episodes;
 pub(crate) use crate::tools::merge::merge;
 pub(crate) use crate::tools::ocr::cancel as ocr_cancel;
 pub(crate) use crate::tools::ocr::export as ocr_export;
@@ -17,10 +20,14 @@ pub(crate) use crate::tools::ocr::pipeline as ocr_pipeline;
 pub(crate) use crate::tools::ocr::preview as ocr_preview;
 pub(crate) use crate::tools::ocr::subtitles as ocr_subtitles;
 pub(crate) use crate::tools::power::sleep_inhibit;
+pub(crate) use crate::tools::split::cancel as split_cancel;
+pub(crate) use crate::tools::split::split;
+pub(crate) use crate::tools::subtitles::sync as subtitle_sync;
 pub(crate) use crate::tools::tokens::count as tokens;
 pub(crate) use crate::tools::transcode::analysis as transcode_analysis;
 pub(crate) use crate::tools::transcode::cancel as transcode_cancel;
 pub(crate) use crate::tools::transcode::capabilities as transcode_capabilities;
+pub(crate) use crate::tools::transcode::quality as transcode_quality;
 pub(crate) use crate::tools::transcode::transcode;
 pub(crate) use crate::tools::transcription::cancel as transcription_cancel;
 pub(crate) use crate::tools::transcription::transcode_opus as transcription_transcode;
@@ -20,6 +20,7 @@ pub fn run() {
         .invoke_handler(tauri::generate_handler![
             commands::ffprobe::probe_file,
             commands::ffmpeg_extract::extract_track,
+            commands::ffmpeg_extract::dry_run_extract_track,
             commands::ffmpeg_cancel::cancel_extract,
             commands::ffmpeg_cancel::cancel_extract_file,
             commands::fs_open_folder::open_folder,
@@ -28,8 +29,17 @@ pub fn run() {
             commands::ffmpeg_version::get_ffmpeg_info,
             commands::ffmpeg_download::download_ffmpeg,
             commands::merge::merge_tracks,
+            commands::merge::dry_run_merge_tracks,
             commands::merge_cancel::cancel_merge,
             commands::merge_cancel::cancel_merge_file,
+            commands::merge_episodes::match_merge_episodes,
+            commands::concat::check_concat_compatibility,
+            commands::concat::concat_media,
+            commands::concat_cancel::cancel_concat,
+            commands::concat_cancel::cancel_concat_file,
+            commands::split::split_media,
+            commands::split_cancel::cancel_split,
+            commands::split_cancel::cancel_split_file,
             commands::fs_file_ops::rename_file,
             commands::fs_file_ops::copy_file,
             commands::fs_cancel::cancel_copy_file,
@@ -58,9 +68,16 @@ pub fn run() {
             // General transcode commands
             commands::transcode_capabilities::get_transcode_capabilities,
             commands::transcode::transcode_media,
+            commands::transcode::preview_transcode,
+            commands::transcode::plan_transcode_streams,
+            commands::transcode::dry_run_transcode,
             commands::transcode_cancel::cancel_transcode,
             commands::transcode_cancel::cancel_transcode_file,
-            commands::transcode_analysis::extract_transcode_analysis_frames
+            commands::transcode_analysis::extract_transcode_analysis_frames,
+            commands::transcode_quality::compare_media_quality,
+            commands::transcode_cancel::cancel_quality_comparison,
+            // Subtitle timing commands
+            commands::subtitle_sync::sync_subtitle_timing
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -0,0 +1,94 @@
+use serde::Serialize;
+
+/// One ffmpeg invocation as it would be run.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct FfmpegDryRunCommand {
+    pub(crate) args: Vec<String>,
+    /// POSIX shell-quoted command line, ready to paste into a terminal.
+    pub(crate) command_line: String,
+}
+
+/// The ffmpeg commands a job would run, without running them.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct FfmpegDryRun {
+    /// Usually one command; two-pass encodes list each pass in order.
+    pub(crate) commands: Vec<FfmpegDryRunCommand>,
+    pub(crate) warnings: Vec<String>,
+}
+
+fn is_shell_safe(arg: &str) -> bool {
+    !arg.is_empty()
+        && arg.chars().all(|character| {
+            character.is_ascii_alphanumeric()
+                || matches!(
+                    character,
+                    '_' | '-' | '.' | '/' | ':' | '=' | ',' | '+' | '@' | '%'
+                )
+        })
+}
+
+/// Single-quote an argument unless it only holds characters the shell leaves alone.
+pub(crate) fn shell_quote(arg: &str) -> String {
+    if is_shell_safe(arg) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+pub(crate) fn build_dry_run(
+    ffmpeg_path: &str,
+    command_args: Vec<Vec<String>>,
+    warnings: Vec<String>,
+) -> FfmpegDryRun {
+    FfmpegDryRun {
+        commands: command_args
+            .into_iter()
+            .map(|args| FfmpegDryRunCommand {
+                command_line: std::iter::once(ffmpeg_path)
+                    .chain(args.iter().map(String::as_str))
+                    .map(shell_quote)
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                args,
+            })
+            .collect(),
+        warnings,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build_dry_run, shell_quote};
+
+    #[test]
+    fn shell_quote_leaves_plain_args_and_quotes_the_rest() {
+        assert_eq!(shell_quote("-c:a:0"), "-c:a:0");
+        assert_eq!(shell_quote("/tmp/out.mkv"), "/tmp/out.mkv");
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(shell_quote("/tmp/My Movie.mkv"), "'/tmp/My Movie.mkv'");
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+        assert_eq!(
+            shell_quote("[0:v]scale=1280:-2[v]"),
+            "'[0:v]scale=1280:-2[v]'"
+        );
+    }
+
+    #[test]
+    fn build_dry_run_prefixes_each_command_with_ffmpeg() {
+        let dry_run = build_dry_run(
+            "/opt/ffmpeg bin/ffmpeg",
+            vec![vec!["-i".to_string(), "in put.mkv".to_string()]],
+            Vec::new(),
+        );
+
+        assert_eq!(dry_run.commands.len(), 1);
+        assert_eq!(dry_run.commands[0].args, vec!["-i", "in put.mkv"]);
+        assert_eq!(
+            dry_run.commands[0].command_line,
+            "'/opt/ffmpeg bin/ffmpeg' -i 'in put.mkv'"
+        );
+    }
+}
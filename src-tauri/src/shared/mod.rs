@@ -1,4 +1,5 @@
 pub(crate) mod copy_progress;
+pub(crate) mod dry_run;
 pub(crate) mod ffmpeg_progress;
 pub(crate) mod hash;
 pub(crate) mod process;
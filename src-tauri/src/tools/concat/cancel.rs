@@ -0,0 +1,138 @@
+use crate::shared::process::force_terminate_process;
+
+fn remove_concat_artifacts(output_path: &str, work_dir: Option<&str>) {
+    let _ = std::fs::remove_file(output_path);
+    if let Some(work_dir) = work_dir {
+        let _ = std::fs::remove_dir_all(work_dir);
+    }
+}
+
+/// Cancel a specific concat by output path.
+#[tauri::command]
+pub(crate) async fn cancel_concat_file(output_path: String) -> Result<(), String> {
+    let pid = {
+        match super::state::CONCAT_PROCESS_IDS.lock() {
+            Ok(mut guard) => guard.remove(&output_path),
+            Err(_) => return Err("Failed to acquire process lock".to_string()),
+        }
+    };
+
+    let work_dir = {
+        match super::state::CONCAT_WORK_DIRS.lock() {
+            Ok(mut guard) => guard.remove(&output_path),
+            Err(_) => None,
+        }
+    };
+
+    if let Some(pid) = pid {
+        force_terminate_process(pid);
+    }
+
+    remove_concat_artifacts(&output_path, work_dir.as_deref());
+
+    Ok(())
+}
+
+/// Cancel all ongoing concats.
+#[tauri::command]
+pub(crate) async fn cancel_concat() -> Result<(), String> {
+    let processes: Vec<(String, u32)> = {
+        match super::state::CONCAT_PROCESS_IDS.lock() {
+            Ok(mut guard) => guard.drain().collect(),
+            Err(_) => return Err("Failed to acquire process lock".to_string()),
+        }
+    };
+
+    let work_dirs: Vec<(String, String)> = {
+        match super::state::CONCAT_WORK_DIRS.lock() {
+            Ok(mut guard) => guard.drain().collect(),
+            Err(_) => Vec::new(),
+        }
+    };
+
+    for (output_path, pid) in &processes {
+        force_terminate_process(*pid);
+        remove_concat_artifacts(output_path, None);
+    }
+
+    for (output_path, work_dir) in &work_dirs {
+        remove_concat_artifacts(output_path, Some(work_dir));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serial_test::serial;
+
+    use super::{cancel_concat, cancel_concat_file};
+
+    #[tokio::test]
+    #[serial]
+    async fn cancel_concat_file_cleans_state_output_and_work_dir() {
+        let temp = tempfile::tempdir().expect("failed to create tempdir");
+        let output = temp.path().join("joined.mkv");
+        let work_dir = temp.path().join("work");
+        std::fs::write(&output, b"partial").expect("failed to create output file");
+        std::fs::create_dir_all(&work_dir).expect("failed to create work dir");
+        std::fs::write(work_dir.join("inputs.txt"), b"file 'a.mkv'\n")
+            .expect("failed to create list file");
+        let output_key = output.to_string_lossy().to_string();
+
+        {
+            let mut pids = super::super::state::CONCAT_PROCESS_IDS
+                .lock()
+                .expect("failed to lock pids");
+            pids.insert(output_key.clone(), 0);
+        }
+        {
+            let mut dirs = super::super::state::CONCAT_WORK_DIRS
+                .lock()
+                .expect("failed to lock work dirs");
+            dirs.insert(output_key.clone(), work_dir.to_string_lossy().to_string());
+        }
+
+        cancel_concat_file(output_key.clone())
+            .await
+            .expect("cancel concat file should succeed");
+
+        assert!(!output.exists());
+        assert!(!work_dir.exists());
+        assert!(
+            !super::super::state::CONCAT_PROCESS_IDS
+                .lock()
+                .expect("failed to lock pids")
+                .contains_key(&output_key)
+        );
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn cancel_concat_cleans_all_tracked_concats() {
+        let temp = tempfile::tempdir().expect("failed to create tempdir");
+        let out_a = temp.path().join("a.mkv");
+        let out_b = temp.path().join("b.mkv");
+        std::fs::write(&out_a, b"partial").expect("failed to create output file a");
+        std::fs::write(&out_b, b"partial").expect("failed to create output file b");
+
+        {
+            let mut pids = super::super::state::CONCAT_PROCESS_IDS
+                .lock()
+                .expect("failed to lock pids");
+            pids.insert(out_a.to_string_lossy().to_string(), 0);
+            pids.insert(out_b.to_string_lossy().to_string(), 0);
+        }
+
+        cancel_concat().await.expect("cancel all should succeed");
+
+        assert!(!out_a.exists());
+        assert!(!out_b.exists());
+        assert!(
+            super::super::state::CONCAT_PROCESS_IDS
+                .lock()
+                .expect("failed to lock pids")
+                .is_empty()
+        );
+    }
+}
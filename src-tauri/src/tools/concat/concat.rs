@@ -0,0 +1,1048 @@
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tauri::Emitter;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+use tokio::time::timeout;
+
+use crate::shared::ffmpeg_progress::FfmpegProgressTracker;
+use crate::shared::hash::stable_hash64;
+use crate::shared::process::terminate_process;
+use crate::shared::sleep_inhibit::SleepInhibitGuard;
+use crate::shared::store::{resolve_ffmpeg_path, resolve_ffprobe_path};
+use crate::shared::validation::{validate_media_path, validate_output_path};
+use crate::tools::ffprobe::probe::probe_file_with_ffprobe;
+
+/// Timeout for FFmpeg concat operations (2 hours, the filter path re-encodes)
+const CONCAT_TIMEOUT: Duration = Duration::from_secs(7200);
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ConcatRequest {
+    pub(crate) input_paths: Vec<String>,
+    pub(crate) output_path: String,
+    /// Add one chapter per input at each join point (defaults to true).
+    pub(crate) add_chapters: Option<bool>,
+    /// Optional chapter titles, one per input; file stems are used otherwise.
+    pub(crate) chapter_titles: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ConcatMethod {
+    /// Lossless stream copy through the concat demuxer; requires matching layouts.
+    Demuxer,
+    /// Re-encode through the concat filter, normalizing every input to the first one.
+    Filter,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ConcatPlan {
+    pub(crate) method: ConcatMethod,
+    /// Layout differences that prevent a lossless join, empty when the demuxer can be used.
+    pub(crate) issues: Vec<String>,
+    pub(crate) total_duration_us: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ConcatResult {
+    pub(crate) output_path: String,
+    pub(crate) method: ConcatMethod,
+    pub(crate) issues: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct ConcatStreamLayout {
+    codec_type: String,
+    codec_name: String,
+    width: Option<u64>,
+    height: Option<u64>,
+    frame_rate: Option<String>,
+    sample_rate: Option<u64>,
+    channels: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+struct ConcatInputProbe {
+    path: String,
+    duration_us: Option<u64>,
+    streams: Vec<ConcatStreamLayout>,
+}
+
+impl ConcatInputProbe {
+    fn first_stream(&self, codec_type: &str) -> Option<&ConcatStreamLayout> {
+        self.streams
+            .iter()
+            .find(|stream| stream.codec_type == codec_type)
+    }
+}
+
+fn emit_concat_progress(
+    app: &tauri::AppHandle,
+    output_path: &str,
+    progress: i32,
+    speed_bytes_per_sec: Option<f64>,
+) {
+    let _ = app.emit(
+        "concat-progress",
+        serde_json::json!({
+            "outputPath": output_path,
+            "progress": progress,
+            "speedBytesPerSec": speed_bytes_per_sec
+        }),
+    );
+}
+
+fn parse_concat_input_probe(path: &str, probe: &Value) -> ConcatInputProbe {
+    let duration_us = probe
+        .get("format")
+        .and_then(|format| format.get("duration"))
+        .and_then(|value| value.as_str())
+        .and_then(|value| value.parse::<f64>().ok())
+        .filter(|value| value.is_finite() && *value > 0.0)
+        .map(|value| (value * 1_000_000.0).round() as u64);
+
+    let streams = probe
+        .get("streams")
+        .and_then(|value| value.as_array())
+        .map(|streams| {
+            streams
+                .iter()
+                .filter(|stream| {
+                    // Cover art is stored as a video stream but is not part of the timeline.
+                    stream
+                        .get("disposition")
+                        .and_then(|value| value.get("attached_pic"))
+                        .and_then(|value| value.as_u64())
+                        .unwrap_or(0)
+                        == 0
+                })
+                .filter_map(|stream| {
+                    let codec_type = stream.get("codec_type")?.as_str()?;
+                    if !matches!(codec_type, "video" | "audio" | "subtitle") {
+                        return None;
+                    }
+
+                    Some(ConcatStreamLayout {
+                        codec_type: codec_type.to_string(),
+                        codec_name: stream
+                            .get("codec_name")
+                            .and_then(|value| value.as_str())
+                            .unwrap_or("unknown")
+                            .to_string(),
+                        width: stream.get("width").and_then(|value| value.as_u64()),
+                        height: stream.get("height").and_then(|value| value.as_u64()),
+                        frame_rate: stream
+                            .get("r_frame_rate")
+                            .and_then(|value| value.as_str())
+                            .filter(|value| *value != "0/0")
+                            .map(str::to_string),
+                        sample_rate: stream
+                            .get("sample_rate")
+                            .and_then(|value| value.as_str())
+                            .and_then(|value| value.parse::<u64>().ok()),
+                        channels: stream.get("channels").and_then(|value| value.as_u64()),
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    ConcatInputProbe {
+        path: path.to_string(),
+        duration_us,
+        streams,
+    }
+}
+
+fn file_label(path: &str) -> String {
+    Path::new(path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(path)
+        .to_string()
+}
+
+/// Compare every input against the first one; any difference rules out the concat demuxer.
+fn compare_concat_layouts(inputs: &[ConcatInputProbe]) -> Vec<String> {
+    let Some(reference) = inputs.first() else {
+        return Vec::new();
+    };
+    let mut issues = Vec::new();
+
+    for input in inputs.iter().skip(1) {
+        let label = file_label(&input.path);
+        if input.streams.len() != reference.streams.len() {
+            issues.push(format!(
+                "{}: {} streams, expected {}",
+                label,
+                input.streams.len(),
+                reference.streams.len()
+            ));
+            continue;
+        }
+
+        for (index, (stream, expected)) in input
+            .streams
+            .iter()
+            .zip(reference.streams.iter())
+            .enumerate()
+        {
+            if stream.codec_type != expected.codec_type {
+                issues.push(format!(
+                    "{}: stream #{} is {}, expected {}",
+                    label, index, stream.codec_type, expected.codec_type
+                ));
+                continue;
+            }
+            if stream.codec_name != expected.codec_name {
+                issues.push(format!(
+                    "{}: stream #{} codec {}, expected {}",
+                    label, index, stream.codec_name, expected.codec_name
+                ));
+            }
+            if (stream.width, stream.height) != (expected.width, expected.height) {
+                issues.push(format!(
+                    "{}: stream #{} resolution {}x{}, expected {}x{}",
+                    label,
+                    index,
+                    stream.width.unwrap_or(0),
+                    stream.height.unwrap_or(0),
+                    expected.width.unwrap_or(0),
+                    expected.height.unwrap_or(0)
+                ));
+            }
+            if stream.sample_rate != expected.sample_rate {
+                issues.push(format!(
+                    "{}: stream #{} sample rate {} Hz, expected {} Hz",
+                    label,
+                    index,
+                    stream.sample_rate.unwrap_or(0),
+                    expected.sample_rate.unwrap_or(0)
+                ));
+            }
+            if stream.channels != expected.channels {
+                issues.push(format!(
+                    "{}: stream #{} has {} channels, expected {}",
+                    label,
+                    index,
+                    stream.channels.unwrap_or(0),
+                    expected.channels.unwrap_or(0)
+                ));
+            }
+        }
+    }
+
+    issues
+}
+
+/// The concat filter only joins the first video and audio stream of each input.
+fn dropped_subtitle_issues(inputs: &[ConcatInputProbe]) -> Vec<String> {
+    inputs
+        .iter()
+        .filter_map(|input| {
+            let count = input
+                .streams
+                .iter()
+                .filter(|stream| stream.codec_type == "subtitle")
+                .count();
+            (count > 0).then(|| {
+                format!(
+                    "{}: {} subtitle stream(s) will be dropped when re-encoding",
+                    file_label(&input.path),
+                    count
+                )
+            })
+        })
+        .collect()
+}
+
+fn build_concat_plan(inputs: &[ConcatInputProbe]) -> ConcatPlan {
+    let mut issues = compare_concat_layouts(inputs);
+    let method = if issues.is_empty() {
+        ConcatMethod::Demuxer
+    } else {
+        ConcatMethod::Filter
+    };
+    if method == ConcatMethod::Filter {
+        issues.extend(dropped_subtitle_issues(inputs));
+    }
+    let total_duration_us = inputs
+        .iter()
+        .map(|input| input.duration_us)
+        .sum::<Option<u64>>();
+
+    ConcatPlan {
+        method,
+        issues,
+        total_duration_us,
+    }
+}
+
+pub(crate) fn escape_concat_list_path(path: &str) -> String {
+    path.replace('\'', "'\\''")
+}
+
+fn build_concat_list(input_paths: &[String]) -> String {
+    input_paths
+        .iter()
+        .map(|path| format!("file '{}'\n", escape_concat_list_path(path)))
+        .collect()
+}
+
+pub(crate) fn escape_ffmetadata_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for character in value.chars() {
+        if matches!(character, '=' | ';' | '#' | '\\' | '\n') {
+            escaped.push('\\');
+        }
+        escaped.push(character);
+    }
+    escaped
+}
+
+/// FFMETADATA chapters with one entry per input, starting at each join point.
+fn build_chapter_metadata(
+    inputs: &[ConcatInputProbe],
+    chapter_titles: Option<&[String]>,
+) -> Option<String> {
+    let mut metadata = String::from(";FFMETADATA1\n");
+    let mut start_ms = 0u64;
+
+    for (index, input) in inputs.iter().enumerate() {
+        let duration_ms = input.duration_us? / 1000;
+        let title = chapter_titles
+            .and_then(|titles| titles.get(index))
+            .map(|title| title.trim())
+            .filter(|title| !title.is_empty())
+            .map(str::to_string)
+            .unwrap_or_else(|| {
+                Path::new(&input.path)
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .map(str::to_string)
+                    .unwrap_or_else(|| format!("Part {}", index + 1))
+            });
+
+        metadata.push_str("[CHAPTER]\nTIMEBASE=1/1000\n");
+        metadata.push_str(&format!(
+            "START={}\nEND={}\ntitle={}\n",
+            start_ms,
+            start_ms + duration_ms,
+            escape_ffmetadata_value(&title)
+        ));
+        start_ms += duration_ms;
+    }
+
+    Some(metadata)
+}
+
+fn push_progress_and_output(args: &mut Vec<String>, output_path: &str) {
+    args.push("-progress".to_string());
+    args.push("pipe:1".to_string());
+    args.push(output_path.to_string());
+}
+
+fn build_concat_demuxer_args(
+    list_path: &str,
+    chapters_path: Option<&str>,
+    output_path: &str,
+) -> Vec<String> {
+    let mut args = vec![
+        "-y".to_string(),
+        "-f".to_string(),
+        "concat".to_string(),
+        "-safe".to_string(),
+        "0".to_string(),
+        "-i".to_string(),
+        list_path.to_string(),
+    ];
+
+    if let Some(chapters_path) = chapters_path {
+        args.push("-i".to_string());
+        args.push(chapters_path.to_string());
+        args.push("-map_chapters".to_string());
+        args.push("1".to_string());
+    }
+
+    args.push("-map".to_string());
+    args.push("0".to_string());
+    args.push("-c".to_string());
+    args.push("copy".to_string());
+    push_progress_and_output(&mut args, output_path);
+    args
+}
+
+fn channel_layout_for_count(channels: Option<u64>) -> &'static str {
+    match channels {
+        Some(1) => "mono",
+        Some(6) => "5.1",
+        Some(8) => "7.1",
+        _ => "stereo",
+    }
+}
+
+/// Encoders for the re-encoding path, picked from the output extension.
+fn concat_filter_encoders(output_path: &str) -> (&'static [&'static str], &'static [&'static str]) {
+    let extension = Path::new(output_path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase())
+        .unwrap_or_default();
+
+    match extension.as_str() {
+        "webm" => (
+            &["-c:v", "libvpx-vp9", "-crf", "32", "-b:v", "0"],
+            &["-c:a", "libopus", "-b:a", "160k"],
+        ),
+        "mp3" => (&[], &["-c:a", "libmp3lame", "-q:a", "2"]),
+        "flac" => (&[], &["-c:a", "flac"]),
+        "opus" | "ogg" => (&[], &["-c:a", "libopus", "-b:a", "160k"]),
+        "wav" => (&[], &["-c:a", "pcm_s16le"]),
+        _ => (
+            &["-c:v", "libx264", "-crf", "18", "-preset", "medium"],
+            &["-c:a", "aac", "-b:a", "192k"],
+        ),
+    }
+}
+
+fn build_concat_filter_args(
+    inputs: &[ConcatInputProbe],
+    chapters_path: Option<&str>,
+    output_path: &str,
+) -> Result<Vec<String>, String> {
+    let Some(reference) = inputs.first() else {
+        return Err("No inputs to concatenate".to_string());
+    };
+
+    let reference_video = reference.first_stream("video");
+    let reference_audio = reference.first_stream("audio");
+    let include_video = reference_video.is_some();
+    let include_audio = reference_audio.is_some();
+    if !include_video && !include_audio {
+        return Err("First input has no audio or video stream".to_string());
+    }
+
+    for input in inputs {
+        if include_video && input.first_stream("video").is_none() {
+            return Err(format!("{} has no video stream", file_label(&input.path)));
+        }
+        if include_audio && input.first_stream("audio").is_none() {
+            return Err(format!("{} has no audio stream", file_label(&input.path)));
+        }
+    }
+
+    let mut args = vec!["-y".to_string()];
+    for input in inputs {
+        args.push("-i".to_string());
+        args.push(input.path.clone());
+    }
+
+    let mut filters = Vec::new();
+    let mut concat_inputs = String::new();
+    for (index, _) in inputs.iter().enumerate() {
+        if let Some(video) = reference_video {
+            let width = video.width.unwrap_or(1280);
+            let height = video.height.unwrap_or(720);
+            let mut chain = format!(
+                "[{index}:v:0]scale={width}:{height}:force_original_aspect_ratio=decrease,pad={width}:{height}:(ow-iw)/2:(oh-ih)/2,setsar=1"
+            );
+            if let Some(frame_rate) = video.frame_rate.as_deref() {
+                chain.push_str(&format!(",fps={}", frame_rate));
+            }
+            chain.push_str(&format!(",format=yuv420p[v{index}]"));
+            filters.push(chain);
+            concat_inputs.push_str(&format!("[v{index}]"));
+        }
+        if let Some(audio) = reference_audio {
+            filters.push(format!(
+                "[{index}:a:0]aresample={},aformat=sample_fmts=fltp:channel_layouts={}[a{index}]",
+                audio.sample_rate.unwrap_or(48_000),
+                channel_layout_for_count(audio.channels)
+            ));
+            concat_inputs.push_str(&format!("[a{index}]"));
+        }
+    }
+
+    let mut concat_outputs = String::new();
+    if include_video {
+        concat_outputs.push_str("[vout]");
+    }
+    if include_audio {
+        concat_outputs.push_str("[aout]");
+    }
+    filters.push(format!(
+        "{}concat=n={}:v={}:a={}{}",
+        concat_inputs,
+        inputs.len(),
+        u8::from(include_video),
+        u8::from(include_audio),
+        concat_outputs
+    ));
+
+    args.push("-filter_complex".to_string());
+    args.push(filters.join(";"));
+
+    if let Some(chapters_path) = chapters_path {
+        args.push("-i".to_string());
+        args.push(chapters_path.to_string());
+        args.push("-map_chapters".to_string());
+        args.push(inputs.len().to_string());
+    }
+
+    let (video_encoder_args, audio_encoder_args) = concat_filter_encoders(output_path);
+    if include_video {
+        if video_encoder_args.is_empty() {
+            return Err("Output container cannot store video".to_string());
+        }
+        args.push("-map".to_string());
+        args.push("[vout]".to_string());
+        args.extend(video_encoder_args.iter().map(|arg| arg.to_string()));
+    }
+    if include_audio {
+        args.push("-map".to_string());
+        args.push("[aout]".to_string());
+        args.extend(audio_encoder_args.iter().map(|arg| arg.to_string()));
+    }
+
+    push_progress_and_output(&mut args, output_path);
+    Ok(args)
+}
+
+fn build_concat_work_dir(output_path: &str) -> PathBuf {
+    std::env::temp_dir()
+        .join("mediaflow_concat")
+        .join(format!("{:016x}", stable_hash64(output_path)))
+}
+
+fn validate_concat_inputs(input_paths: &[String]) -> Result<(), String> {
+    if input_paths.len() < 2 {
+        return Err("At least two files are required to concatenate".to_string());
+    }
+    for input_path in input_paths {
+        validate_media_path(input_path)?;
+    }
+    Ok(())
+}
+
+fn validate_concat_request(request: &ConcatRequest) -> Result<(), String> {
+    validate_concat_inputs(&request.input_paths)?;
+    for input_path in &request.input_paths {
+        if Path::new(input_path) == Path::new(&request.output_path) {
+            return Err("Output path must differ from the input files".to_string());
+        }
+    }
+    validate_output_path(&request.output_path)
+}
+
+async fn probe_concat_inputs(
+    ffprobe_path: &str,
+    input_paths: &[String],
+) -> Result<Vec<ConcatInputProbe>, String> {
+    let mut inputs = Vec::with_capacity(input_paths.len());
+    for input_path in input_paths {
+        let probe_json = probe_file_with_ffprobe(ffprobe_path, input_path).await?;
+        let probe_value: Value = serde_json::from_str(&probe_json)
+            .map_err(|error| format!("Invalid probe JSON: {}", error))?;
+        inputs.push(parse_concat_input_probe(input_path, &probe_value));
+    }
+    Ok(inputs)
+}
+
+/// Write the list and chapter files and build the ffmpeg arguments for the chosen method.
+fn prepare_concat_args(
+    request: &ConcatRequest,
+    inputs: &[ConcatInputProbe],
+    plan: &ConcatPlan,
+    work_dir: &Path,
+) -> Result<Vec<String>, String> {
+    std::fs::create_dir_all(work_dir)
+        .map_err(|error| format!("Failed to create concat work directory: {}", error))?;
+
+    let chapters_path = if request.add_chapters.unwrap_or(true) {
+        match build_chapter_metadata(inputs, request.chapter_titles.as_deref()) {
+            Some(metadata) => {
+                let path = work_dir.join("chapters.ffmeta");
+                std::fs::write(&path, metadata)
+                    .map_err(|error| format!("Failed to write chapter metadata: {}", error))?;
+                Some(path.to_string_lossy().to_string())
+            }
+            None => None,
+        }
+    } else {
+        None
+    };
+
+    match plan.method {
+        ConcatMethod::Demuxer => {
+            let list_path = work_dir.join("inputs.txt");
+            std::fs::write(&list_path, build_concat_list(&request.input_paths))
+                .map_err(|error| format!("Failed to write concat list: {}", error))?;
+            Ok(build_concat_demuxer_args(
+                list_path.to_string_lossy().as_ref(),
+                chapters_path.as_deref(),
+                &request.output_path,
+            ))
+        }
+        ConcatMethod::Filter => {
+            build_concat_filter_args(inputs, chapters_path.as_deref(), &request.output_path)
+        }
+    }
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+pub(crate) async fn concat_media_with_bins(
+    ffmpeg_path: &str,
+    ffprobe_path: &str,
+    request: &ConcatRequest,
+) -> Result<ConcatResult, String> {
+    validate_concat_request(request)?;
+
+    let inputs = probe_concat_inputs(ffprobe_path, &request.input_paths).await?;
+    let plan = build_concat_plan(&inputs);
+    let work_dir = build_concat_work_dir(&request.output_path);
+    let args = prepare_concat_args(request, &inputs, &plan, &work_dir);
+
+    let output = match args {
+        Ok(args) => timeout(
+            CONCAT_TIMEOUT,
+            Command::new(ffmpeg_path).args(&args).output(),
+        )
+        .await
+        .map_err(|_| format!("Concat timeout after {} seconds", CONCAT_TIMEOUT.as_secs()))
+        .and_then(|result| result.map_err(|error| format!("Failed to execute ffmpeg: {}", error))),
+        Err(error) => Err(error),
+    };
+    let _ = std::fs::remove_dir_all(&work_dir);
+    let output = output?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let _ = std::fs::remove_file(&request.output_path);
+        return Err(format!("Concat failed: {}", stderr.trim()));
+    }
+
+    if !Path::new(&request.output_path).exists() {
+        return Err("Concat failed: output file not created".to_string());
+    }
+
+    Ok(ConcatResult {
+        output_path: request.output_path.clone(),
+        method: plan.method,
+        issues: plan.issues,
+    })
+}
+
+/// Probe the inputs and report whether they can be joined losslessly.
+#[tauri::command]
+pub(crate) async fn check_concat_compatibility(
+    app: tauri::AppHandle,
+    input_paths: Vec<String>,
+) -> Result<ConcatPlan, String> {
+    validate_concat_inputs(&input_paths)?;
+
+    let ffprobe_path = resolve_ffprobe_path(&app)?;
+    let inputs = probe_concat_inputs(&ffprobe_path, &input_paths).await?;
+    Ok(build_concat_plan(&inputs))
+}
+
+/// Join several files into one, stream-copying when their layouts match.
+#[tauri::command]
+pub(crate) async fn concat_media(
+    app: tauri::AppHandle,
+    request: ConcatRequest,
+) -> Result<ConcatResult, String> {
+    validate_concat_request(&request)?;
+
+    let _sleep_guard = SleepInhibitGuard::try_acquire("Media concatenation").ok();
+    let ffmpeg_path = resolve_ffmpeg_path(&app)?;
+    let ffprobe_path = resolve_ffprobe_path(&app)?;
+
+    let inputs = probe_concat_inputs(&ffprobe_path, &request.input_paths).await?;
+    let plan = build_concat_plan(&inputs);
+    let work_dir = build_concat_work_dir(&request.output_path);
+    let args = match prepare_concat_args(&request, &inputs, &plan, &work_dir) {
+        Ok(args) => args,
+        Err(error) => {
+            let _ = std::fs::remove_dir_all(&work_dir);
+            return Err(error);
+        }
+    };
+
+    let mut child = match Command::new(&ffmpeg_path)
+        .args(&args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(error) => {
+            let _ = std::fs::remove_dir_all(&work_dir);
+            return Err(format!("Failed to start ffmpeg: {}", error));
+        }
+    };
+
+    emit_concat_progress(&app, &request.output_path, 0, None);
+
+    if let Some(pid) = child.id()
+        && let Ok(mut guard) = super::state::CONCAT_PROCESS_IDS.lock()
+    {
+        guard.insert(request.output_path.clone(), pid);
+    }
+    if let Ok(mut guard) = super::state::CONCAT_WORK_DIRS.lock() {
+        guard.insert(
+            request.output_path.clone(),
+            work_dir.to_string_lossy().to_string(),
+        );
+    }
+
+    if let Some(stdout) = child.stdout.take() {
+        let app_for_progress = app.clone();
+        let output_path_for_progress = request.output_path.clone();
+        let duration_us = plan.total_duration_us;
+
+        tokio::spawn(async move {
+            let mut tracker = FfmpegProgressTracker::new(duration_us);
+            let mut last_progress = 0;
+            let reader = BufReader::new(stdout);
+            let mut lines = reader.lines();
+
+            while let Ok(Some(line)) = lines.next_line().await {
+                if let Some(update) = tracker.handle_line(&line) {
+                    if let Some(progress) = update.progress {
+                        last_progress = progress;
+                    }
+
+                    emit_concat_progress(
+                        &app_for_progress,
+                        &output_path_for_progress,
+                        last_progress,
+                        update.speed_bytes_per_sec,
+                    );
+                }
+            }
+        });
+    }
+
+    let child_pid = child.id();
+    let output = timeout(CONCAT_TIMEOUT, child.wait_with_output()).await;
+
+    if let Ok(mut guard) = super::state::CONCAT_PROCESS_IDS.lock() {
+        guard.remove(&request.output_path);
+    }
+    if let Ok(mut guard) = super::state::CONCAT_WORK_DIRS.lock() {
+        guard.remove(&request.output_path);
+    }
+    let _ = std::fs::remove_dir_all(&work_dir);
+
+    let output = match output {
+        Ok(result) => result.map_err(|error| {
+            let _ = std::fs::remove_file(&request.output_path);
+            format!("Failed to execute ffmpeg: {}", error)
+        })?,
+        Err(_) => {
+            if let Some(pid) = child_pid {
+                terminate_process(pid);
+                tokio::time::sleep(Duration::from_millis(200)).await;
+            }
+            let _ = std::fs::remove_file(&request.output_path);
+            return Err(format!(
+                "Concat timeout after {} seconds",
+                CONCAT_TIMEOUT.as_secs()
+            ));
+        }
+    };
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let _ = std::fs::remove_file(&request.output_path);
+        return Err(format!("Concat failed: {}", stderr.trim()));
+    }
+
+    if !Path::new(&request.output_path).exists() {
+        return Err("Concat failed: output file not created".to_string());
+    }
+
+    emit_concat_progress(&app, &request.output_path, 100, None);
+
+    Ok(ConcatResult {
+        output_path: request.output_path,
+        method: plan.method,
+        issues: plan.issues,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+
+    use super::{
+        ConcatInputProbe, ConcatMethod, ConcatRequest, build_chapter_metadata,
+        build_concat_demuxer_args, build_concat_filter_args, build_concat_list, build_concat_plan,
+        concat_media_with_bins, parse_concat_input_probe, validate_concat_inputs,
+    };
+    use crate::test_support::ffmpeg::{ffmpeg_path, ffprobe_path};
+    use crate::test_support::video::generate_test_pattern_av_mp4;
+
+    fn has_arg_pair(args: &[String], left: &str, right: &str) -> bool {
+        args.windows(2)
+            .any(|window| window[0] == left && window[1] == right)
+    }
+
+    fn probe_value(duration: &str, width: u64, codec: &str, sample_rate: &str) -> Value {
+        json!({
+            "format": {"duration": duration},
+            "streams": [
+                {
+                    "index": 0,
+                    "codec_type": "video",
+                    "codec_name": codec,
+                    "width": width,
+                    "height": width * 9 / 16,
+                    "r_frame_rate": "24000/1001"
+                },
+                {
+                    "index": 1,
+                    "codec_type": "audio",
+                    "codec_name": "aac",
+                    "sample_rate": sample_rate,
+                    "channels": 2
+                },
+                {
+                    "index": 2,
+                    "codec_type": "video",
+                    "codec_name": "mjpeg",
+                    "disposition": {"attached_pic": 1}
+                }
+            ]
+        })
+    }
+
+    fn input(
+        path: &str,
+        duration: &str,
+        width: u64,
+        codec: &str,
+        sample_rate: &str,
+    ) -> ConcatInputProbe {
+        parse_concat_input_probe(path, &probe_value(duration, width, codec, sample_rate))
+    }
+
+    #[test]
+    fn parse_concat_input_probe_skips_attached_pictures() {
+        let probe = input("/tmp/cd1.mkv", "12.5", 1920, "h264", "48000");
+
+        assert_eq!(probe.duration_us, Some(12_500_000));
+        assert_eq!(probe.streams.len(), 2);
+        assert_eq!(probe.streams[0].width, Some(1920));
+        assert_eq!(probe.streams[1].sample_rate, Some(48_000));
+    }
+
+    #[test]
+    fn build_concat_plan_uses_demuxer_for_matching_layouts() {
+        let inputs = vec![
+            input("/tmp/cd1.mkv", "10", 1920, "h264", "48000"),
+            input("/tmp/cd2.mkv", "20", 1920, "h264", "48000"),
+        ];
+
+        let plan = build_concat_plan(&inputs);
+
+        assert_eq!(plan.method, ConcatMethod::Demuxer);
+        assert!(plan.issues.is_empty());
+        assert_eq!(plan.total_duration_us, Some(30_000_000));
+    }
+
+    #[test]
+    fn build_concat_plan_reports_codec_resolution_and_sample_rate_mismatches() {
+        let inputs = vec![
+            input("/tmp/cd1.mkv", "10", 1920, "h264", "48000"),
+            input("/tmp/cd2.mkv", "10", 1280, "hevc", "44100"),
+        ];
+
+        let plan = build_concat_plan(&inputs);
+
+        assert_eq!(plan.method, ConcatMethod::Filter);
+        assert!(plan.issues.iter().any(|issue| issue.contains("codec hevc")));
+        assert!(plan.issues.iter().any(|issue| issue.contains("1280x720")));
+        assert!(plan.issues.iter().any(|issue| issue.contains("44100 Hz")));
+    }
+
+    #[test]
+    fn build_concat_plan_reports_subtitles_dropped_by_the_filter() {
+        let mut with_subtitles = probe_value("10", 1920, "h264", "48000");
+        with_subtitles["streams"]
+            .as_array_mut()
+            .expect("streams array")
+            .push(json!({"index": 3, "codec_type": "subtitle", "codec_name": "subrip"}));
+        let mut inputs = vec![
+            parse_concat_input_probe("/tmp/cd1.mkv", &with_subtitles),
+            parse_concat_input_probe("/tmp/cd2.mkv", &with_subtitles),
+        ];
+
+        let plan = build_concat_plan(&inputs);
+        assert_eq!(plan.method, ConcatMethod::Demuxer);
+        assert!(plan.issues.is_empty());
+
+        inputs[1] = input("/tmp/cd2.mkv", "10", 1280, "h264", "48000");
+        let plan = build_concat_plan(&inputs);
+        assert_eq!(plan.method, ConcatMethod::Filter);
+        assert!(
+            plan.issues
+                .iter()
+                .any(|issue| issue
+                    == "cd1.mkv: 1 subtitle stream(s) will be dropped when re-encoding")
+        );
+        assert!(
+            !plan
+                .issues
+                .iter()
+                .any(|issue| issue.starts_with("cd2.mkv: 1 subtitle"))
+        );
+    }
+
+    #[test]
+    fn validate_concat_inputs_requires_two_files() {
+        assert!(validate_concat_inputs(&[]).is_err());
+        assert!(validate_concat_inputs(&["/tmp/cd1.mkv".to_string()]).is_err());
+    }
+
+    #[test]
+    fn build_concat_list_escapes_single_quotes() {
+        let list =
+            build_concat_list(&["/tmp/it's cd1.mkv".to_string(), "/tmp/cd2.mkv".to_string()]);
+        assert_eq!(list, "file '/tmp/it'\\''s cd1.mkv'\nfile '/tmp/cd2.mkv'\n");
+    }
+
+    #[test]
+    fn build_chapter_metadata_places_chapters_at_join_points() {
+        let inputs = vec![
+            input("/tmp/Movie CD1.mkv", "10.5", 1920, "h264", "48000"),
+            input("/tmp/Movie CD2.mkv", "20", 1920, "h264", "48000"),
+        ];
+
+        let metadata = build_chapter_metadata(&inputs, Some(&["Part=1".to_string()]))
+            .expect("metadata expected when durations are known");
+
+        assert!(metadata.starts_with(";FFMETADATA1\n"));
+        assert!(metadata.contains("START=0\nEND=10500\ntitle=Part\\=1\n"));
+        assert!(metadata.contains("START=10500\nEND=30500\ntitle=Movie CD2\n"));
+    }
+
+    #[test]
+    fn build_chapter_metadata_requires_known_durations() {
+        let mut inputs = vec![input("/tmp/cd1.mkv", "10", 1920, "h264", "48000")];
+        inputs[0].duration_us = None;
+        assert!(build_chapter_metadata(&inputs, None).is_none());
+    }
+
+    #[test]
+    fn build_concat_demuxer_args_copies_streams_and_maps_chapters() {
+        let args = build_concat_demuxer_args(
+            "/tmp/list.txt",
+            Some("/tmp/chapters.ffmeta"),
+            "/tmp/out.mkv",
+        );
+
+        assert!(has_arg_pair(&args, "-f", "concat"));
+        assert!(has_arg_pair(&args, "-safe", "0"));
+        assert!(has_arg_pair(&args, "-i", "/tmp/list.txt"));
+        assert!(has_arg_pair(&args, "-i", "/tmp/chapters.ffmeta"));
+        assert!(has_arg_pair(&args, "-map_chapters", "1"));
+        assert!(has_arg_pair(&args, "-c", "copy"));
+        assert!(has_arg_pair(&args, "-progress", "pipe:1"));
+        assert_eq!(args.last().map(String::as_str), Some("/tmp/out.mkv"));
+    }
+
+    #[test]
+    fn build_concat_filter_args_normalizes_inputs_to_first_layout() {
+        let inputs = vec![
+            input("/tmp/cd1.mkv", "10", 1920, "h264", "48000"),
+            input("/tmp/cd2.mkv", "10", 1280, "hevc", "44100"),
+        ];
+
+        let args = build_concat_filter_args(&inputs, Some("/tmp/chapters.ffmeta"), "/tmp/out.mkv")
+            .expect("filter args should build");
+        let graph = args
+            .windows(2)
+            .find(|window| window[0] == "-filter_complex")
+            .map(|window| window[1].clone())
+            .expect("filter graph expected");
+
+        assert!(graph.contains("[1:v:0]scale=1920:1080:force_original_aspect_ratio=decrease"));
+        assert!(graph.contains("fps=24000/1001"));
+        assert!(graph.contains("[1:a:0]aresample=48000"));
+        assert!(graph.ends_with("[v0][a0][v1][a1]concat=n=2:v=1:a=1[vout][aout]"));
+        assert!(has_arg_pair(&args, "-map_chapters", "2"));
+        assert!(has_arg_pair(&args, "-map", "[vout]"));
+        assert!(has_arg_pair(&args, "-c:v", "libx264"));
+        assert!(has_arg_pair(&args, "-c:a", "aac"));
+    }
+
+    #[test]
+    fn build_concat_filter_args_rejects_inputs_missing_audio() {
+        let mut inputs = vec![
+            input("/tmp/cd1.mkv", "10", 1920, "h264", "48000"),
+            input("/tmp/cd2.mkv", "10", 1920, "h264", "48000"),
+        ];
+        inputs[1]
+            .streams
+            .retain(|stream| stream.codec_type != "audio");
+
+        let error = build_concat_filter_args(&inputs, None, "/tmp/out.mkv")
+            .expect_err("missing audio should be rejected");
+        assert!(error.contains("cd2.mkv has no audio stream"));
+    }
+
+    #[tokio::test]
+    async fn concat_media_joins_matching_files_with_chapters() {
+        let first = generate_test_pattern_av_mp4()
+            .await
+            .expect("failed to generate first fixture");
+        let second = generate_test_pattern_av_mp4()
+            .await
+            .expect("failed to generate second fixture");
+        let temp = tempfile::tempdir().expect("failed to create tempdir");
+        let output = temp.path().join("joined.mkv");
+
+        let result = concat_media_with_bins(
+            ffmpeg_path(),
+            ffprobe_path(),
+            &ConcatRequest {
+                input_paths: vec![
+                    first.path.to_string_lossy().to_string(),
+                    second.path.to_string_lossy().to_string(),
+                ],
+                output_path: output.to_string_lossy().to_string(),
+                add_chapters: Some(true),
+                chapter_titles: None,
+            },
+        )
+        .await
+        .expect("concat should succeed");
+
+        assert_eq!(result.method, ConcatMethod::Demuxer);
+        assert!(output.exists());
+
+        let probe = tokio::process::Command::new(ffprobe_path())
+            .args(["-v", "quiet", "-print_format", "json", "-show_chapters"])
+            .arg(&output)
+            .output()
+            .await
+            .expect("ffprobe should run");
+        let chapters: Value =
+            serde_json::from_slice(&probe.stdout).expect("chapter probe should be json");
+        assert_eq!(
+            chapters
+                .get("chapters")
+                .and_then(|value| value.as_array())
+                .map(Vec::len),
+            Some(2)
+        );
+    }
+}
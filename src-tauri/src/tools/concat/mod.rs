@@ -0,0 +1,3 @@
+pub(crate) mod cancel;
+pub(crate) mod concat;
+mod state;
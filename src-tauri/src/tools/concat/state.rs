@@ -0,0 +1,10 @@
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+
+/// Store concat process IDs keyed by output path for individual cancellation
+pub(super) static CONCAT_PROCESS_IDS: LazyLock<Mutex<HashMap<String, u32>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Store concat list/chapter work directories for cleanup on cancel
+pub(super) static CONCAT_WORK_DIRS: LazyLock<Mutex<HashMap<String, String>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
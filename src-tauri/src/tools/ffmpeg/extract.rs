@@ -1,3 +1,4 @@
+use crate::shared::dry_run::{FfmpegDryRun, build_dry_run};
 use crate::shared::ffmpeg_progress::FfmpegProgressTracker;
 use crate::shared::process::terminate_process;
 use crate::shared::sleep_inhibit::SleepInhibitGuard;
@@ -150,6 +151,25 @@ fn build_extract_args(
     args
 }
 
+fn build_extract_warnings(output_path: &str, track_type: &str, codec: &str) -> Vec<String> {
+    match track_type {
+        "audio"
+            if get_ffmpeg_format_for_codec(codec).is_none()
+                && !has_recognized_extension(output_path) =>
+        {
+            vec![format!(
+                "No output format is known for {} and the output extension is not recognized; FFmpeg may refuse to write it",
+                codec
+            )]
+        }
+        "audio" | "video" | "subtitle" => Vec::new(),
+        other => vec![format!(
+            "Unknown track type {}; every stream type of the track is copied",
+            other
+        )],
+    }
+}
+
 fn emit_extract_progress(
     app: &tauri::AppHandle,
     input_path: &str,
@@ -340,11 +360,38 @@ pub(crate) async fn extract_track(
     .await
 }
 
+/// Return the extraction command without running it.
+#[tauri::command]
+pub(crate) async fn dry_run_extract_track(
+    app: tauri::AppHandle,
+    input_path: String,
+    output_path: String,
+    track_index: i32,
+    track_type: String,
+    codec: String,
+) -> Result<FfmpegDryRun, String> {
+    validate_media_path(&input_path)?;
+    validate_output_path(&output_path)?;
+    let ffmpeg_path = resolve_ffmpeg_path(&app)?;
+
+    Ok(build_dry_run(
+        &ffmpeg_path,
+        vec![build_extract_args(
+            &input_path,
+            &output_path,
+            track_index,
+            &track_type,
+            &codec,
+        )],
+        build_extract_warnings(&output_path, &track_type, &codec),
+    ))
+}
+
 #[cfg(test)]
 mod tests {
     use super::{
-        build_extract_args, extract_track_with_ffmpeg, get_ffmpeg_format_for_codec,
-        has_recognized_extension,
+        build_extract_args, build_extract_warnings, extract_track_with_ffmpeg,
+        get_ffmpeg_format_for_codec, has_recognized_extension,
     };
 
     #[test]
@@ -378,6 +425,16 @@ mod tests {
         assert!(args.windows(2).any(|w| w == ["-progress", "pipe:1"]));
     }
 
+    #[test]
+    fn build_extract_warnings_flags_audio_without_a_known_format() {
+        assert!(build_extract_warnings("/tmp/output.custom", "audio", "wmav2").is_empty());
+        assert_eq!(
+            build_extract_warnings("/tmp/output.mka", "audio", "dts").len(),
+            1
+        );
+        assert!(build_extract_warnings("/tmp/output.dts", "audio", "dts").is_empty());
+    }
+
     #[tokio::test]
     async fn extract_track_extracts_video_stream_from_sample_video() {
         let video = crate::test_support::assets::ensure_sample_video()
@@ -10,6 +10,8 @@ pub(crate) struct ContainerMetadataSchema {
     pub(crate) supports_default: bool,
     pub(crate) supports_forced: bool,
     pub(crate) clears_matroska_statistics: bool,
+    pub(crate) supports_chapters: bool,
+    pub(crate) supports_attachments: bool,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -28,6 +30,12 @@ pub(crate) struct MediaMetadataRequest {
     pub(crate) container_title: Option<String>,
     #[serde(default)]
     pub(crate) track_edits: Vec<TrackMetadataEdit>,
+    /// Copy the source chapters instead of dropping them.
+    #[serde(default)]
+    pub(crate) keep_chapters: bool,
+    /// Copy Matroska attachments such as embedded fonts.
+    #[serde(default)]
+    pub(crate) keep_attachments: bool,
 }
 
 impl Default for MediaMetadataRequest {
@@ -35,6 +43,8 @@ impl Default for MediaMetadataRequest {
         Self {
             container_title: None,
             track_edits: Vec::new(),
+            keep_chapters: false,
+            keep_attachments: false,
         }
     }
 }
@@ -47,6 +57,8 @@ pub(crate) struct OutputStreamMetadata {
     pub(crate) language: Option<String>,
     pub(crate) is_default: bool,
     pub(crate) is_forced: bool,
+    /// Cover art keeps its `attached_pic` disposition so players don't treat it as video.
+    pub(crate) is_attached_picture: bool,
 }
 
 const MATROSKA_STATISTICS_TAGS: &[&str] = &[
@@ -72,6 +84,8 @@ pub(crate) fn metadata_schema_for_container(container_id: &str) -> ContainerMeta
             supports_default: true,
             supports_forced: container_id == "mkv",
             clears_matroska_statistics: true,
+            supports_chapters: true,
+            supports_attachments: container_id == "mkv",
         },
         "mp4" | "mov" => ContainerMetadataSchema {
             supports_container_title: true,
@@ -80,6 +94,8 @@ pub(crate) fn metadata_schema_for_container(container_id: &str) -> ContainerMeta
             supports_default: true,
             supports_forced: false,
             clears_matroska_statistics: false,
+            supports_chapters: true,
+            supports_attachments: false,
         },
         "aac" | "flac" | "mp3" | "ogg" | "opus" | "wav" => ContainerMetadataSchema {
             supports_container_title: true,
@@ -88,6 +104,8 @@ pub(crate) fn metadata_schema_for_container(container_id: &str) -> ContainerMeta
             supports_default: false,
             supports_forced: false,
             clears_matroska_statistics: false,
+            supports_chapters: !matches!(container_id, "aac" | "wav"),
+            supports_attachments: false,
         },
         _ => ContainerMetadataSchema {
             supports_container_title: true,
@@ -96,6 +114,8 @@ pub(crate) fn metadata_schema_for_container(container_id: &str) -> ContainerMeta
             supports_default: true,
             supports_forced: true,
             clears_matroska_statistics: false,
+            supports_chapters: true,
+            supports_attachments: false,
         },
     }
 }
@@ -131,6 +151,7 @@ pub(crate) fn output_stream_metadata_from_request(
         is_forced: edit
             .and_then(|edit| edit.forced)
             .unwrap_or_else(|| disposition_flag(source_stream, "forced")),
+        is_attached_picture: disposition_flag(source_stream, "attached_pic"),
     }
 }
 
@@ -167,6 +188,8 @@ pub(crate) fn output_stream_metadata_from_config(
                     .map(|stream| disposition_flag(stream, "forced"))
                     .unwrap_or(false)
             }),
+        is_attached_picture: source_stream
+            .is_some_and(|stream| disposition_flag(stream, "attached_pic")),
     }
 }
 
@@ -216,8 +239,11 @@ pub(crate) fn apply_metadata_args(
         if schema.supports_forced && stream.is_forced {
             dispositions.push("forced");
         }
+        if stream.is_attached_picture {
+            dispositions.push("attached_pic");
+        }
 
-        if schema.supports_default || schema.supports_forced {
+        if schema.supports_default || schema.supports_forced || stream.is_attached_picture {
             args.push(format!("-disposition:{}", stream.output_index));
             args.push(if dispositions.is_empty() {
                 "0".to_string()
@@ -228,6 +254,34 @@ pub(crate) fn apply_metadata_args(
     }
 }
 
+/// Chapters are dropped unless requested and supported by the container.
+pub(crate) fn apply_chapter_args(args: &mut Vec<String>, container_id: &str, keep_chapters: bool) {
+    let schema = metadata_schema_for_container(container_id);
+
+    args.push("-map_chapters".to_string());
+    args.push(if keep_chapters && schema.supports_chapters {
+        "0".to_string()
+    } else {
+        "-1".to_string()
+    });
+}
+
+/// Attachments are mapped after every other stream so output indices stay put.
+pub(crate) fn apply_attachment_args(
+    args: &mut Vec<String>,
+    container_id: &str,
+    keep_attachments: bool,
+) {
+    if !keep_attachments || !metadata_schema_for_container(container_id).supports_attachments {
+        return;
+    }
+
+    args.push("-map".to_string());
+    args.push("0:t?".to_string());
+    args.push("-c:t".to_string());
+    args.push("copy".to_string());
+}
+
 fn clear_matroska_statistics_tags(args: &mut Vec<String>, output_index: usize) {
     for tag in MATROSKA_STATISTICS_TAGS {
         args.push(format!("-metadata:s:{}", output_index));
@@ -266,8 +320,8 @@ mod tests {
     use serde_json::json;
 
     use super::{
-        MediaMetadataRequest, TrackMetadataEdit, apply_metadata_args,
-        output_stream_metadata_from_request,
+        MediaMetadataRequest, TrackMetadataEdit, apply_attachment_args, apply_chapter_args,
+        apply_metadata_args, output_stream_metadata_from_request,
     };
 
     fn has_arg_pair(args: &[String], left: &str, right: &str) -> bool {
@@ -297,6 +351,7 @@ mod tests {
                 default: Some(true),
                 forced: Some(false),
             }],
+            ..MediaMetadataRequest::default()
         };
 
         let metadata = output_stream_metadata_from_request(1, &stream, &request);
@@ -433,4 +488,45 @@ mod tests {
             "name=Commentary subtitles"
         ));
     }
+
+    #[test]
+    fn attached_picture_disposition_is_kept_on_output() {
+        let stream = output_stream_metadata_from_request(
+            1,
+            &json!({
+                "index": 2,
+                "codec_type": "video",
+                "disposition": { "default": 0, "attached_pic": 1 }
+            }),
+            &MediaMetadataRequest::default(),
+        );
+        let mut args = Vec::new();
+
+        apply_metadata_args(&mut args, "mkv", None, std::slice::from_ref(&stream));
+        assert!(has_arg_pair(&args, "-disposition:1", "attached_pic"));
+
+        let mut args = Vec::new();
+        apply_metadata_args(&mut args, "mp3", None, &[stream]);
+        assert!(has_arg_pair(&args, "-disposition:1", "attached_pic"));
+    }
+
+    #[test]
+    fn chapters_and_attachments_follow_container_support() {
+        let mut args = Vec::new();
+        apply_chapter_args(&mut args, "mkv", true);
+        apply_attachment_args(&mut args, "mkv", true);
+        assert!(has_arg_pair(&args, "-map_chapters", "0"));
+        assert!(has_arg_pair(&args, "-map", "0:t?"));
+        assert!(has_arg_pair(&args, "-c:t", "copy"));
+
+        let mut args = Vec::new();
+        apply_chapter_args(&mut args, "mp4", true);
+        apply_attachment_args(&mut args, "mp4", true);
+        assert!(has_arg_pair(&args, "-map_chapters", "0"));
+        assert!(!args.iter().any(|arg| arg == "0:t?"));
+
+        let mut args = Vec::new();
+        apply_chapter_args(&mut args, "wav", true);
+        assert!(has_arg_pair(&args, "-map_chapters", "-1"));
+    }
 }
@@ -0,0 +1,656 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use serde::Serialize;
+
+/// Ranges wider than this are treated as noise (e.g. "01-2000") rather than multi-episode files.
+const MAX_EPISODE_RANGE: u32 = 24;
+/// Candidates scoring within this margin of the best one make a pairing ambiguous.
+const AMBIGUITY_MARGIN: f64 = 0.05;
+const MAX_REPORTED_CANDIDATES: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum EpisodeNumbering {
+    /// `S01E02`, `1x02` or "Season 1 Episode 2"
+    SeasonEpisode,
+    /// `E05`, `Episode 5`, `第5話` or a bare number such as `Show - 05`
+    Absolute,
+    /// Daily shows named by air date
+    Date,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct EpisodeInfo {
+    pub(crate) numbering: EpisodeNumbering,
+    pub(crate) season: Option<u32>,
+    /// One entry per episode; multi-episode files (`S01E01-E03`) list every episode.
+    pub(crate) episodes: Vec<u32>,
+    /// ISO `YYYY-MM-DD` for date-based names.
+    pub(crate) date: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct EpisodeFile {
+    pub(crate) path: String,
+    pub(crate) info: Option<EpisodeInfo>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct EpisodeCandidate {
+    pub(crate) video_path: String,
+    pub(crate) score: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct EpisodePairing {
+    pub(crate) track_path: String,
+    pub(crate) video_path: Option<String>,
+    pub(crate) score: f64,
+    /// Set when several videos score about the same, or the track could not be parsed.
+    pub(crate) ambiguous: bool,
+    pub(crate) reason: String,
+    pub(crate) candidates: Vec<EpisodeCandidate>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct EpisodeMatchResult {
+    pub(crate) videos: Vec<EpisodeFile>,
+    pub(crate) tracks: Vec<EpisodeFile>,
+    pub(crate) pairings: Vec<EpisodePairing>,
+}
+
+fn file_stem_for_matching(path: &str) -> String {
+    let file_name = Path::new(path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(path);
+    let stem = match file_name.rfind('.') {
+        Some(position) if position > 0 => &file_name[..position],
+        _ => file_name,
+    };
+
+    // Tracks extracted by the app are named "Show 02.track2" or "Show 02.fre.track2".
+    let mut stem = stem.to_string();
+    if let Some(position) = stem.to_ascii_lowercase().rfind(".track")
+        && stem[position + 6..].chars().all(|c| c.is_ascii_digit())
+        && position + 6 < stem.len()
+    {
+        stem.truncate(position);
+        if let Some(language_position) = stem.rfind('.')
+            && (2..=8).contains(&(stem.len() - language_position - 1))
+            && stem[language_position + 1..]
+                .chars()
+                .all(|c| c.is_ascii_alphabetic())
+        {
+            stem.truncate(language_position);
+        }
+    }
+    stem.to_lowercase()
+}
+
+fn is_boundary(character: Option<&char>) -> bool {
+    character.is_none_or(|character| !character.is_alphanumeric())
+}
+
+fn is_separator(character: char) -> bool {
+    matches!(character, ' ' | '.' | '_' | '-')
+}
+
+/// Read a run of ASCII digits starting at `index`; fails if the run is longer than `max_digits`.
+fn read_number(chars: &[char], index: usize, max_digits: usize) -> Option<(u32, usize)> {
+    let mut end = index;
+    while end < chars.len() && chars[end].is_ascii_digit() {
+        end += 1;
+    }
+    if end == index || end - index > max_digits {
+        return None;
+    }
+    let value = chars[index..end].iter().collect::<String>().parse().ok()?;
+    Some((value, end))
+}
+
+/// Parse `-03`, `-E03`, `E02E03` style continuations after a first episode number.
+fn read_episode_continuation(chars: &[char], mut index: usize, first: u32) -> (Vec<u32>, usize) {
+    let mut episodes = vec![first];
+
+    loop {
+        let mut cursor = index;
+        let is_range = chars.get(cursor) == Some(&'-');
+        if is_range {
+            cursor += 1;
+        }
+        let has_marker = matches!(chars.get(cursor), Some('e'));
+        if has_marker {
+            cursor += 1;
+        }
+        if !is_range && !has_marker {
+            break;
+        }
+        let Some((value, end)) = read_number(chars, cursor, 4) else {
+            break;
+        };
+        if !is_boundary(chars.get(end)) && !matches!(chars.get(end), Some('e' | 'v')) {
+            break;
+        }
+
+        let last = *episodes.last().unwrap_or(&first);
+        if is_range && value > last && value - last <= MAX_EPISODE_RANGE {
+            episodes.extend(last + 1..=value);
+        } else if !is_range && value != last {
+            episodes.push(value);
+        } else {
+            break;
+        }
+        index = end;
+    }
+
+    (episodes, index)
+}
+
+fn parse_season_episode(chars: &[char]) -> Option<EpisodeInfo> {
+    for start in 0..chars.len() {
+        if chars[start] != 's' || (start > 0 && !is_boundary(chars.get(start - 1))) {
+            continue;
+        }
+        let Some((season, mut index)) = read_number(chars, start + 1, 4) else {
+            continue;
+        };
+        if chars.get(index).copied().is_some_and(is_separator) {
+            index += 1;
+        }
+        if !matches!(chars.get(index), Some('e' | 'x')) {
+            continue;
+        }
+        let Some((episode, end)) = read_number(chars, index + 1, 4) else {
+            continue;
+        };
+        let (episodes, _) = read_episode_continuation(chars, end, episode);
+        return Some(EpisodeInfo {
+            numbering: EpisodeNumbering::SeasonEpisode,
+            season: Some(season),
+            episodes,
+            date: None,
+        });
+    }
+    None
+}
+
+fn parse_cross_notation(chars: &[char]) -> Option<EpisodeInfo> {
+    for start in 0..chars.len() {
+        if !chars[start].is_ascii_digit() || (start > 0 && !is_boundary(chars.get(start - 1))) {
+            continue;
+        }
+        let Some((season, index)) = read_number(chars, start, 2) else {
+            continue;
+        };
+        if chars.get(index) != Some(&'x') {
+            continue;
+        }
+        let Some((episode, end)) = read_number(chars, index + 1, 3) else {
+            continue;
+        };
+        if !is_boundary(chars.get(end)) && chars.get(end) != Some(&'-') {
+            continue;
+        }
+        let mut episodes = vec![episode];
+        if chars.get(end) == Some(&'-')
+            && let Some((last, range_end)) = read_number(chars, end + 1, 3)
+            && is_boundary(chars.get(range_end))
+            && last > episode
+            && last - episode <= MAX_EPISODE_RANGE
+        {
+            episodes.extend(episode + 1..=last);
+        }
+        return Some(EpisodeInfo {
+            numbering: EpisodeNumbering::SeasonEpisode,
+            season: Some(season),
+            episodes,
+            date: None,
+        });
+    }
+    None
+}
+
+fn parse_air_date(chars: &[char]) -> Option<EpisodeInfo> {
+    for start in 0..chars.len() {
+        if start > 0 && !is_boundary(chars.get(start - 1)) {
+            continue;
+        }
+        let Some((year, index)) = read_number(chars, start, 4) else {
+            continue;
+        };
+        if index - start != 4 || !(1900..=2100).contains(&year) {
+            continue;
+        }
+        let separator = chars.get(index).copied();
+        if !separator.is_some_and(is_separator) {
+            continue;
+        }
+        let Some((month, index)) = read_number(chars, index + 1, 2) else {
+            continue;
+        };
+        if chars.get(index).copied() != separator {
+            continue;
+        }
+        let Some((day, end)) = read_number(chars, index + 1, 2) else {
+            continue;
+        };
+        if !is_boundary(chars.get(end)) || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+            continue;
+        }
+        return Some(EpisodeInfo {
+            numbering: EpisodeNumbering::Date,
+            season: None,
+            episodes: Vec::new(),
+            date: Some(format!("{:04}-{:02}-{:02}", year, month, day)),
+        });
+    }
+    None
+}
+
+fn find_word(chars: &[char], word: &str, from: usize) -> Option<usize> {
+    let word = word.chars().collect::<Vec<_>>();
+    (from..chars.len().saturating_sub(word.len() - 1)).find(|start| {
+        chars[*start..].starts_with(&word) && (*start == 0 || !chars[start - 1].is_alphabetic())
+    })
+}
+
+fn skip_separators(chars: &[char], mut index: usize) -> usize {
+    while chars.get(index).copied().is_some_and(is_separator) {
+        index += 1;
+    }
+    index
+}
+
+fn parse_verbose(chars: &[char]) -> Option<EpisodeInfo> {
+    let season_start = find_word(chars, "season", 0)?;
+    let (season, after_season) = read_number(chars, skip_separators(chars, season_start + 6), 4)?;
+    let episode_start = find_word(chars, "episode", after_season)?;
+    let (episode, end) = read_number(chars, skip_separators(chars, episode_start + 7), 4)?;
+    let (episodes, _) = read_episode_continuation(chars, end, episode);
+    Some(EpisodeInfo {
+        numbering: EpisodeNumbering::SeasonEpisode,
+        season: Some(season),
+        episodes,
+        date: None,
+    })
+}
+
+fn parse_episode_marker(chars: &[char]) -> Option<EpisodeInfo> {
+    for marker in ["episode", "ep", "e"] {
+        let mut from = 0;
+        while let Some(start) = find_word(chars, marker, from) {
+            from = start + 1;
+            let index = skip_separators(chars, start + marker.len());
+            let Some((episode, end)) = read_number(chars, index, 4) else {
+                continue;
+            };
+            if !is_boundary(chars.get(end)) && !matches!(chars.get(end), Some('-' | 'v')) {
+                continue;
+            }
+            let (episodes, _) = read_episode_continuation(chars, end, episode);
+            return Some(EpisodeInfo {
+                numbering: EpisodeNumbering::Absolute,
+                season: None,
+                episodes,
+                date: None,
+            });
+        }
+    }
+
+    // Japanese "第01話"
+    let start = chars.iter().position(|character| *character == '第')?;
+    let (episode, end) = read_number(chars, skip_separators(chars, start + 1), 4)?;
+    (chars.get(skip_separators(chars, end)) == Some(&'話')).then(|| EpisodeInfo {
+        numbering: EpisodeNumbering::Absolute,
+        season: None,
+        episodes: vec![episode],
+        date: None,
+    })
+}
+
+/// Bare numbers outside brackets, preferring one that follows a " - " separator
+/// (`[Group] Show - 05 [1080p]`) and skipping years and resolutions.
+fn parse_bare_number(chars: &[char]) -> Option<EpisodeInfo> {
+    let mut depth = 0i32;
+    let mut candidates = Vec::new();
+    let mut index = 0usize;
+
+    while index < chars.len() {
+        match chars[index] {
+            '[' | '(' | '{' => depth += 1,
+            ']' | ')' | '}' => depth = (depth - 1).max(0),
+            character
+                if depth == 0
+                    && character.is_ascii_digit()
+                    && (index == 0 || is_boundary(chars.get(index - 1))) =>
+            {
+                let mut end = index;
+                while end < chars.len() && chars[end].is_ascii_digit() {
+                    end += 1;
+                }
+                let digits = end - index;
+                let value = chars[index..end]
+                    .iter()
+                    .collect::<String>()
+                    .parse::<u32>()
+                    .ok();
+                let followed_by_version = chars.get(end) == Some(&'v')
+                    && chars.get(end + 1).is_some_and(|c| c.is_ascii_digit());
+                if let Some(value) = value
+                    && (is_boundary(chars.get(end)) || followed_by_version)
+                    && digits <= 4
+                    && !(digits == 4 && (1900..=2100).contains(&value))
+                    && value > 0
+                {
+                    let after_dash =
+                        index >= 2 && chars[index - 2] == '-' && chars[index - 1] == ' ';
+                    candidates.push((value, end, after_dash));
+                }
+                index = end;
+                continue;
+            }
+            _ => {}
+        }
+        index += 1;
+    }
+
+    let (episode, end, _) = candidates
+        .iter()
+        .rev()
+        .find(|(_, _, after_dash)| *after_dash)
+        .or_else(|| candidates.last())
+        .copied()?;
+    let (episodes, _) = read_episode_continuation(chars, end, episode);
+    Some(EpisodeInfo {
+        numbering: EpisodeNumbering::Absolute,
+        season: None,
+        episodes,
+        date: None,
+    })
+}
+
+/// Extract season/episode information from a file name.
+pub(crate) fn parse_episode_info(path: &str) -> Option<EpisodeInfo> {
+    let chars = file_stem_for_matching(path).chars().collect::<Vec<_>>();
+
+    parse_season_episode(&chars)
+        .or_else(|| parse_cross_notation(&chars))
+        .or_else(|| parse_verbose(&chars))
+        .or_else(|| parse_air_date(&chars))
+        .or_else(|| parse_episode_marker(&chars))
+        .or_else(|| parse_bare_number(&chars))
+}
+
+/// Lowercase alphabetic words of the name, used to tell different shows apart.
+fn title_words(path: &str) -> HashSet<String> {
+    file_stem_for_matching(path)
+        .split(|character: char| !character.is_alphabetic())
+        .filter(|word| word.len() > 1)
+        .map(str::to_string)
+        .collect()
+}
+
+fn title_similarity(left: &HashSet<String>, right: &HashSet<String>) -> f64 {
+    let union = left.union(right).count();
+    if union == 0 {
+        return 0.0;
+    }
+    left.intersection(right).count() as f64 / union as f64
+}
+
+/// Score how well a track's episode information matches a video (0 means incompatible).
+fn score_episode_pair(track: &EpisodeInfo, video: &EpisodeInfo) -> f64 {
+    if let (Some(track_date), Some(video_date)) = (&track.date, &video.date) {
+        return if track_date == video_date { 1.0 } else { 0.0 };
+    }
+    if track.episodes.is_empty() || video.episodes.is_empty() {
+        return 0.0;
+    }
+
+    if let (Some(track_season), Some(video_season)) = (track.season, video.season)
+        && track_season != video_season
+    {
+        return 0.0;
+    }
+
+    let overlap = track
+        .episodes
+        .iter()
+        .filter(|episode| video.episodes.contains(episode))
+        .count();
+    if overlap == 0 {
+        return 0.0;
+    }
+
+    let mut score = if track.episodes == video.episodes {
+        0.8
+    } else {
+        0.5
+    };
+    if track.season.is_some() && track.season == video.season {
+        score += 0.15;
+    } else if track.numbering != video.numbering {
+        // An absolute number only lines up with S/E numbering within the first season.
+        score -= 0.1;
+    }
+    score
+}
+
+pub(crate) fn match_episode_files(
+    video_paths: &[String],
+    track_paths: &[String],
+) -> EpisodeMatchResult {
+    let videos = video_paths
+        .iter()
+        .map(|path| EpisodeFile {
+            path: path.clone(),
+            info: parse_episode_info(path),
+        })
+        .collect::<Vec<_>>();
+    let tracks = track_paths
+        .iter()
+        .map(|path| EpisodeFile {
+            path: path.clone(),
+            info: parse_episode_info(path),
+        })
+        .collect::<Vec<_>>();
+    let video_titles = videos
+        .iter()
+        .map(|video| title_words(&video.path))
+        .collect::<Vec<_>>();
+
+    let pairings = tracks
+        .iter()
+        .map(|track| {
+            let Some(track_info) = track.info.as_ref() else {
+                return EpisodePairing {
+                    track_path: track.path.clone(),
+                    video_path: None,
+                    score: 0.0,
+                    ambiguous: true,
+                    reason: "No episode number found in the track name".to_string(),
+                    candidates: Vec::new(),
+                };
+            };
+            let track_title = title_words(&track.path);
+
+            let mut candidates = videos
+                .iter()
+                .zip(video_titles.iter())
+                .filter_map(|(video, video_title)| {
+                    let base = score_episode_pair(track_info, video.info.as_ref()?);
+                    (base > 0.0).then(|| EpisodeCandidate {
+                        video_path: video.path.clone(),
+                        score: ((base + title_similarity(&track_title, video_title) * 0.05)
+                            * 1000.0)
+                            .round()
+                            / 1000.0,
+                    })
+                })
+                .collect::<Vec<_>>();
+            candidates.sort_by(|left, right| right.score.total_cmp(&left.score));
+            candidates.truncate(MAX_REPORTED_CANDIDATES);
+
+            let Some(best) = candidates.first() else {
+                return EpisodePairing {
+                    track_path: track.path.clone(),
+                    video_path: None,
+                    score: 0.0,
+                    ambiguous: false,
+                    reason: "No video with a matching episode".to_string(),
+                    candidates,
+                };
+            };
+
+            let ambiguous = candidates
+                .get(1)
+                .is_some_and(|second| best.score - second.score < AMBIGUITY_MARGIN);
+            let reason = if ambiguous {
+                "Several videos match this episode equally well".to_string()
+            } else if best.score >= 0.9 {
+                "Season and episode match".to_string()
+            } else {
+                "Episode number matches".to_string()
+            };
+
+            EpisodePairing {
+                track_path: track.path.clone(),
+                video_path: (!ambiguous).then(|| best.video_path.clone()),
+                score: best.score,
+                ambiguous,
+                reason,
+                candidates,
+            }
+        })
+        .collect();
+
+    EpisodeMatchResult {
+        videos,
+        tracks,
+        pairings,
+    }
+}
+
+/// Propose which external track belongs to which video for a batch merge.
+#[tauri::command]
+pub(crate) async fn match_merge_episodes(
+    video_paths: Vec<String>,
+    track_paths: Vec<String>,
+) -> Result<EpisodeMatchResult, String> {
+    Ok(match_episode_files(&video_paths, &track_paths))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EpisodeNumbering, match_episode_files, parse_episode_info};
+
+    fn episodes(path: &str) -> (Option<u32>, Vec<u32>) {
+        let info = parse_episode_info(path).expect("episode info expected");
+        (info.season, info.episodes)
+    }
+
+    #[test]
+    fn parse_episode_info_reads_season_episode_notations() {
+        assert_eq!(episodes("Show.S01E02.1080p.mkv"), (Some(1), vec![2]));
+        assert_eq!(episodes("show s2.e10 x264.mkv"), (Some(2), vec![10]));
+        assert_eq!(episodes("Show - 1x05 - Title.srt"), (Some(1), vec![5]));
+        assert_eq!(episodes("Show Season 3 Episode 7.ass"), (Some(3), vec![7]));
+    }
+
+    #[test]
+    fn parse_episode_info_expands_multi_episode_ranges() {
+        assert_eq!(episodes("Show.S01E01E02.mkv"), (Some(1), vec![1, 2]));
+        assert_eq!(episodes("Show.S01E01-E03.mkv"), (Some(1), vec![1, 2, 3]));
+        assert_eq!(episodes("Show 2x03-04.mkv"), (Some(2), vec![3, 4]));
+        assert_eq!(episodes("Show - 11-12 [720p].mkv"), (None, vec![11, 12]));
+    }
+
+    #[test]
+    fn parse_episode_info_reads_absolute_numbers() {
+        assert_eq!(
+            episodes("[Group] Show Name 2 - 05 (1080p) [ABCD1234].mkv"),
+            (None, vec![5])
+        );
+        assert_eq!(episodes("Show EP11_Title.mkv"), (None, vec![11]));
+        assert_eq!(episodes("番組 第12話.ass"), (None, vec![12]));
+        assert_eq!(episodes("Show 2019 - 07v2.mkv"), (None, vec![7]));
+        assert_eq!(episodes("Show 02.fre.track3.ass"), (None, vec![2]));
+    }
+
+    #[test]
+    fn parse_episode_info_reads_air_dates() {
+        let info = parse_episode_info("Daily.Show.2024.03.15.720p.mkv").expect("date expected");
+        assert_eq!(info.numbering, EpisodeNumbering::Date);
+        assert_eq!(info.date.as_deref(), Some("2024-03-15"));
+        assert!(parse_episode_info("Movie (2021) 1080p.mkv").is_none());
+    }
+
+    #[test]
+    fn match_episode_files_pairs_tracks_with_matching_episodes() {
+        let videos = vec![
+            "/v/Show.S01E01.mkv".to_string(),
+            "/v/Show.S01E02.mkv".to_string(),
+            "/v/Show.S02E01.mkv".to_string(),
+        ];
+        let tracks = vec![
+            "/t/Show.S01E02.eng.srt".to_string(),
+            "/t/Show 2x01.ass".to_string(),
+            "/t/Show.S01E09.srt".to_string(),
+            "/t/Commentary.srt".to_string(),
+        ];
+
+        let result = match_episode_files(&videos, &tracks);
+
+        assert_eq!(
+            result.pairings[0].video_path.as_deref(),
+            Some("/v/Show.S01E02.mkv")
+        );
+        assert!(!result.pairings[0].ambiguous);
+        assert_eq!(
+            result.pairings[1].video_path.as_deref(),
+            Some("/v/Show.S02E01.mkv")
+        );
+        assert!(result.pairings[2].video_path.is_none());
+        assert!(!result.pairings[2].ambiguous);
+        assert!(result.pairings[3].video_path.is_none());
+        assert!(result.pairings[3].ambiguous);
+    }
+
+    #[test]
+    fn match_episode_files_flags_seasonless_tracks_matching_several_seasons() {
+        let videos = vec![
+            "/v/Show.S01E03.mkv".to_string(),
+            "/v/Show.S02E03.mkv".to_string(),
+        ];
+        let tracks = vec!["/t/Show - 03.srt".to_string()];
+
+        let result = match_episode_files(&videos, &tracks);
+
+        assert!(result.pairings[0].ambiguous);
+        assert!(result.pairings[0].video_path.is_none());
+        assert_eq!(result.pairings[0].candidates.len(), 2);
+    }
+
+    #[test]
+    fn match_episode_files_prefers_full_multi_episode_overlap() {
+        let videos = vec![
+            "/v/Show.S01E01E02.mkv".to_string(),
+            "/v/Show.S01E02.mkv".to_string(),
+        ];
+        let tracks = vec!["/t/Show.S01E01-E02.srt".to_string()];
+
+        let result = match_episode_files(&videos, &tracks);
+
+        assert_eq!(
+            result.pairings[0].video_path.as_deref(),
+            Some("/v/Show.S01E01E02.mkv")
+        );
+    }
+}
@@ -1,3 +1,4 @@
+use crate::shared::dry_run::{FfmpegDryRun, build_dry_run};
 use crate::shared::ffmpeg_progress::FfmpegProgressTracker;
 use crate::shared::sleep_inhibit::SleepInhibitGuard;
 use crate::shared::store::{resolve_ffmpeg_path, resolve_ffprobe_path};
@@ -44,11 +45,192 @@ fn enabled_source_indices(
     }
 }
 
+/// atempo only accepts factors in this range per filter instance; larger
+/// changes are split across chained instances.
+const MIN_ATEMPO: f64 = 0.5;
+const MAX_ATEMPO: f64 = 2.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MergeTrackKind {
+    Video,
+    Audio,
+    Subtitle,
+    Other,
+}
+
+impl MergeTrackKind {
+    fn from_type_name(value: &str) -> Self {
+        match value {
+            "video" => Self::Video,
+            "audio" => Self::Audio,
+            "subtitle" => Self::Subtitle,
+            _ => Self::Other,
+        }
+    }
+}
+
+/// Time-stretch for a track authored at a different frame rate than the output video,
+/// e.g. a 25 fps PAL track muxed against 23.976 fps video.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct FrameRateConversion {
+    source_fps: f64,
+    target_fps: f64,
+}
+
+impl FrameRateConversion {
+    /// Factor applied to the track timestamps (>1 slows the track down).
+    fn time_scale(&self) -> f64 {
+        self.source_fps / self.target_fps
+    }
+
+    /// Playback speed factor for `atempo`.
+    fn tempo(&self) -> f64 {
+        self.target_fps / self.source_fps
+    }
+
+    /// `atempo` factors whose product is the tempo, each within the filter's range.
+    fn atempo_stages(&self) -> Vec<f64> {
+        let mut remaining = self.tempo();
+        let mut stages = Vec::new();
+        while remaining < MIN_ATEMPO {
+            stages.push(MIN_ATEMPO);
+            remaining /= MIN_ATEMPO;
+        }
+        while remaining > MAX_ATEMPO {
+            stages.push(MAX_ATEMPO);
+            remaining /= MAX_ATEMPO;
+        }
+        stages.push(remaining);
+        stages
+    }
+
+    fn atempo_filter(&self) -> String {
+        self.atempo_stages()
+            .iter()
+            .map(|tempo| format!("atempo={:.6}", tempo))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+fn parse_frame_rate(value: &Value) -> Option<f64> {
+    let fps = if let Some(number) = value.as_f64() {
+        number
+    } else {
+        let text = value.as_str()?.trim();
+        if let Some((num, den)) = text.split_once('/') {
+            let num = num.trim().parse::<f64>().ok()?;
+            let den = den.trim().parse::<f64>().ok()?;
+            if den == 0.0 {
+                return None;
+            }
+            num / den
+        } else {
+            text.parse::<f64>().ok()?
+        }
+    };
+
+    (fps.is_finite() && fps > 0.0).then_some(fps)
+}
+
+fn frame_rate_conversion_from_config(config: Option<&Value>) -> Option<FrameRateConversion> {
+    let config = config?;
+    let source_fps = config.get("sourceFps").and_then(parse_frame_rate)?;
+    let target_fps = config.get("targetFps").and_then(parse_frame_rate)?;
+    let conversion = FrameRateConversion {
+        source_fps,
+        target_fps,
+    };
+
+    if (conversion.time_scale() - 1.0).abs() < 1e-6 {
+        return None;
+    }
+    Some(conversion)
+}
+
+fn source_track_kind(
+    source_config: Option<&Value>,
+    source_stream: Option<&Value>,
+) -> MergeTrackKind {
+    source_config
+        .and_then(|cfg| cfg.get("type"))
+        .or_else(|| source_stream.and_then(|stream| stream.get("codec_type")))
+        .and_then(|value| value.as_str())
+        .map(MergeTrackKind::from_type_name)
+        .unwrap_or(MergeTrackKind::Other)
+}
+
+fn attached_track_kind(track: &Value, input_path: &str) -> MergeTrackKind {
+    if let Some(kind) = track.get("type").and_then(|value| value.as_str()) {
+        return MergeTrackKind::from_type_name(kind);
+    }
+
+    let extension = std::path::Path::new(input_path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "srt" | "ass" | "ssa" | "vtt" | "sub" | "sup" => MergeTrackKind::Subtitle,
+        "aac" | "m4a" | "ac3" | "eac3" | "dts" | "mp3" | "opus" | "ogg" | "flac" | "wav"
+        | "mka" | "thd" => MergeTrackKind::Audio,
+        _ => MergeTrackKind::Other,
+    }
+}
+
+fn attached_audio_codec_from_path(input_path: &str) -> Option<&'static str> {
+    let extension = std::path::Path::new(input_path)
+        .extension()
+        .and_then(|ext| ext.to_str())?
+        .to_ascii_lowercase();
+    match extension.as_str() {
+        "aac" | "m4a" => Some("aac"),
+        "ac3" => Some("ac3"),
+        "eac3" => Some("eac3"),
+        "mp3" => Some("mp3"),
+        "opus" => Some("opus"),
+        _ => None,
+    }
+}
+
+/// Re-encoding is unavoidable for a tempo change; keep the source codec when FFmpeg can
+/// encode it and fall back to lossless FLAC otherwise (TrueHD, DTS, PCM, ...).
+fn frame_rate_audio_encoder(codec_name: Option<&str>) -> &'static str {
+    match codec_name {
+        Some("aac") => "aac",
+        Some("ac3") => "ac3",
+        Some("eac3") => "eac3",
+        Some("mp3") => "libmp3lame",
+        Some("opus") => "libopus",
+        _ => "flac",
+    }
+}
+
+/// Key of a dedicated source input: delay in milliseconds and timestamp scale bits.
+type SourceInputKey = (i64, u64);
+
 struct SourceTrackSelection<'a> {
     input_idx: usize,
     original_index: usize,
     source_stream: Option<&'a Value>,
     config: Option<&'a Value>,
+    kind: MergeTrackKind,
+    frame_rate_conversion: Option<FrameRateConversion>,
+}
+
+/// Push the input timing options for one input. `-itsoffset` is applied before
+/// `-itsscale`, so the offset is pre-divided to keep the delay in output time.
+fn push_input_timing_args(args: &mut Vec<String>, delay_ms: i64, time_scale: Option<f64>) {
+    let scale = time_scale.unwrap_or(1.0);
+    if delay_ms != 0 {
+        let delay_sec = delay_ms as f64 / 1000.0 / scale;
+        args.push("-itsoffset".to_string());
+        args.push(format!("{:.3}", delay_sec));
+    }
+    if let Some(scale) = time_scale {
+        args.push("-itsscale".to_string());
+        args.push(format!("{:.6}", scale));
+    }
 }
 
 fn build_source_track_selections<'a>(
@@ -58,7 +240,7 @@ fn build_source_track_selections<'a>(
     video_path: &str,
 ) -> (Vec<SourceTrackSelection<'a>>, usize) {
     let mut selections = Vec::new();
-    let mut delayed_input_indices: HashMap<i64, usize> = HashMap::new();
+    let mut delayed_input_indices: HashMap<SourceInputKey, usize> = HashMap::new();
     let mut next_input_idx = 1usize;
 
     if let Some(configs) = source_track_configs {
@@ -86,19 +268,36 @@ fn build_source_track_selections<'a>(
                 .and_then(|v| v.as_i64())
                 .unwrap_or(0);
 
-            let input_idx = if delay_ms == 0 {
+            let source_stream = source_streams.iter().find(|stream| {
+                stream
+                    .get("index")
+                    .and_then(|value| value.as_u64())
+                    .is_some_and(|index| index as usize == original_index)
+            });
+            let kind = source_track_kind(Some(source_config), source_stream);
+            let frame_rate_conversion = match kind {
+                MergeTrackKind::Audio | MergeTrackKind::Subtitle => {
+                    frame_rate_conversion_from_config(source_config.get("config"))
+                }
+                _ => None,
+            };
+            // Audio is stretched with atempo after decoding; only subtitles need scaled input timestamps.
+            let time_scale = frame_rate_conversion
+                .filter(|_| kind == MergeTrackKind::Subtitle)
+                .map(|conversion| conversion.time_scale());
+
+            let input_key = (delay_ms, time_scale.unwrap_or(1.0).to_bits());
+            let input_idx = if delay_ms == 0 && time_scale.is_none() {
                 0
-            } else if let Some(existing_input_idx) = delayed_input_indices.get(&delay_ms) {
+            } else if let Some(existing_input_idx) = delayed_input_indices.get(&input_key) {
                 *existing_input_idx
             } else {
-                let delay_sec = delay_ms as f64 / 1000.0;
-                args.push("-itsoffset".to_string());
-                args.push(format!("{:.3}", delay_sec));
+                push_input_timing_args(args, delay_ms, time_scale);
                 args.push("-i".to_string());
                 args.push(video_path.to_string());
 
                 let new_input_idx = next_input_idx;
-                delayed_input_indices.insert(delay_ms, new_input_idx);
+                delayed_input_indices.insert(input_key, new_input_idx);
                 next_input_idx += 1;
                 new_input_idx
             };
@@ -106,13 +305,10 @@ fn build_source_track_selections<'a>(
             selections.push(SourceTrackSelection {
                 input_idx,
                 original_index,
-                source_stream: source_streams.iter().find(|stream| {
-                    stream
-                        .get("index")
-                        .and_then(|value| value.as_u64())
-                        .is_some_and(|index| index as usize == original_index)
-                }),
+                source_stream,
                 config: source_config.get("config"),
+                kind,
+                frame_rate_conversion,
             });
         }
     } else {
@@ -126,6 +322,8 @@ fn build_source_track_selections<'a>(
                 original_index,
                 source_stream: Some(source_stream),
                 config: None,
+                kind: source_track_kind(None, Some(source_stream)),
+                frame_rate_conversion: None,
             });
         }
     }
@@ -133,6 +331,14 @@ fn build_source_track_selections<'a>(
     (selections, next_input_idx)
 }
 
+struct AttachedTrackInput<'a> {
+    input_idx: usize,
+    track: &'a Value,
+    kind: MergeTrackKind,
+    frame_rate_conversion: Option<FrameRateConversion>,
+    codec_name: Option<&'static str>,
+}
+
 fn build_merge_args(
     video_path: &str,
     tracks: &[Value],
@@ -143,7 +349,7 @@ fn build_merge_args(
     let mut args = vec!["-y".to_string(), "-i".to_string(), video_path.to_string()];
     let (source_track_selections, mut next_input_idx) =
         build_source_track_selections(source_track_configs, source_streams, &mut args, video_path);
-    let mut attached_track_inputs: Vec<(usize, &Value)> = Vec::new();
+    let mut attached_track_inputs: Vec<AttachedTrackInput> = Vec::new();
     let mut output_metadata = Vec::<OutputStreamMetadata>::new();
 
     for track in tracks {
@@ -153,16 +359,27 @@ fn build_merge_args(
                 .and_then(|c| c.get("delayMs"))
                 .and_then(|v| v.as_i64())
                 .unwrap_or(0);
+            let kind = attached_track_kind(track, input_path);
+            let frame_rate_conversion = match kind {
+                MergeTrackKind::Audio | MergeTrackKind::Subtitle => {
+                    frame_rate_conversion_from_config(track.get("config"))
+                }
+                _ => None,
+            };
+            let time_scale = frame_rate_conversion
+                .filter(|_| kind == MergeTrackKind::Subtitle)
+                .map(|conversion| conversion.time_scale());
 
-            if delay_ms != 0 {
-                let delay_sec = delay_ms as f64 / 1000.0;
-                args.push("-itsoffset".to_string());
-                args.push(format!("{:.3}", delay_sec));
-            }
-
+            push_input_timing_args(&mut args, delay_ms, time_scale);
             args.push("-i".to_string());
             args.push(input_path.to_string());
-            attached_track_inputs.push((next_input_idx, track));
+            attached_track_inputs.push(AttachedTrackInput {
+                input_idx: next_input_idx,
+                track,
+                kind,
+                frame_rate_conversion,
+                codec_name: attached_audio_codec_from_path(input_path),
+            });
             next_input_idx += 1;
         }
     }
@@ -180,9 +397,9 @@ fn build_merge_args(
         ));
     }
 
-    for (input_idx, _) in &attached_track_inputs {
+    for attached_track in &attached_track_inputs {
         args.push("-map".to_string());
-        args.push(format!("{}:0", input_idx));
+        args.push(format!("{}:0", attached_track.input_idx));
     }
 
     args.push("-c:v".to_string());
@@ -192,8 +409,53 @@ fn build_merge_args(
     args.push("-c:s".to_string());
     args.push("copy".to_string());
 
+    let output_audio_tracks = source_track_selections
+        .iter()
+        .map(|selection| {
+            (
+                selection.kind,
+                selection.frame_rate_conversion,
+                selection
+                    .source_stream
+                    .and_then(|stream| stream.get("codec_name"))
+                    .and_then(|value| value.as_str()),
+                selection
+                    .source_stream
+                    .and_then(|stream| stream.get("bit_rate"))
+                    .and_then(|value| value.as_str())
+                    .and_then(|value| value.parse::<u64>().ok()),
+            )
+        })
+        .chain(attached_track_inputs.iter().map(|attached_track| {
+            (
+                attached_track.kind,
+                attached_track.frame_rate_conversion,
+                attached_track.codec_name,
+                None,
+            )
+        }))
+        .filter(|(kind, ..)| *kind == MergeTrackKind::Audio);
+    for (audio_output_idx, (_, conversion, codec_name, bit_rate)) in output_audio_tracks.enumerate()
+    {
+        let Some(conversion) = conversion else {
+            continue;
+        };
+        let encoder = frame_rate_audio_encoder(codec_name);
+        args.push(format!("-filter:a:{}", audio_output_idx));
+        args.push(conversion.atempo_filter());
+        args.push(format!("-c:a:{}", audio_output_idx));
+        args.push(encoder.to_string());
+        if encoder != "flac"
+            && let Some(bit_rate) = bit_rate
+        {
+            args.push(format!("-b:a:{}", audio_output_idx));
+            args.push(format!("{}k", bit_rate / 1000));
+        }
+    }
+
     let attached_start_idx = source_track_selections.len();
-    for (i, (_, track)) in attached_track_inputs.iter().enumerate() {
+    for (i, attached_track) in attached_track_inputs.iter().enumerate() {
+        let track = attached_track.track;
         let output_stream_idx = attached_start_idx + i;
 
         output_metadata.push(output_stream_metadata_from_config(
@@ -211,6 +473,120 @@ fn build_merge_args(
     args
 }
 
+fn stream_label(stream: &Value, index: usize) -> String {
+    let codec_type = stream
+        .get("codec_type")
+        .and_then(|value| value.as_str())
+        .unwrap_or("unknown");
+    match stream.get("codec_name").and_then(|value| value.as_str()) {
+        Some(codec_name) => format!("#{} ({} {})", index, codec_type, codec_name),
+        None => format!("#{} ({})", index, codec_type),
+    }
+}
+
+/// Things the merge does silently that are worth flagging next to its command.
+fn push_audio_conversion_warnings(
+    warnings: &mut Vec<String>,
+    label: &str,
+    conversion: FrameRateConversion,
+    encoder: &str,
+) {
+    warnings.push(format!(
+        "{} is re-encoded to {} for the frame rate conversion",
+        label, encoder
+    ));
+    let stage_count = conversion.atempo_stages().len();
+    if stage_count > 1 {
+        warnings.push(format!(
+            "{} chains {} atempo filters: tempo {:.3} is outside {}-{}",
+            label,
+            stage_count,
+            conversion.tempo(),
+            MIN_ATEMPO,
+            MAX_ATEMPO
+        ));
+    }
+}
+
+fn build_merge_warnings(
+    video_path: &str,
+    tracks: &[Value],
+    source_track_configs: Option<&[Value]>,
+    source_streams: &[Value],
+) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let mut ignored_args = Vec::new();
+    let (selections, _) = build_source_track_selections(
+        source_track_configs,
+        source_streams,
+        &mut ignored_args,
+        video_path,
+    );
+
+    for (stream_position, stream) in source_streams.iter().enumerate() {
+        let index = stream
+            .get("index")
+            .and_then(|value| value.as_u64())
+            .unwrap_or(stream_position as u64) as usize;
+        if !selections
+            .iter()
+            .any(|selection| selection.original_index == index)
+        {
+            warnings.push(format!(
+                "Source stream {} is dropped from the output",
+                stream_label(stream, index)
+            ));
+        }
+    }
+
+    for selection in &selections {
+        if selection.source_stream.is_none() {
+            warnings.push(format!(
+                "Source stream #{} is not in the probed video; FFmpeg will fail to map it",
+                selection.original_index
+            ));
+        }
+    }
+
+    for selection in &selections {
+        if selection.kind != MergeTrackKind::Audio {
+            continue;
+        }
+        let Some(conversion) = selection.frame_rate_conversion else {
+            continue;
+        };
+        let codec_name = selection
+            .source_stream
+            .and_then(|stream| stream.get("codec_name"))
+            .and_then(|value| value.as_str());
+        let label = format!("Source stream #{}", selection.original_index);
+        push_audio_conversion_warnings(
+            &mut warnings,
+            &label,
+            conversion,
+            frame_rate_audio_encoder(codec_name),
+        );
+    }
+    for track in tracks {
+        let Some(input_path) = track.get("inputPath").and_then(|v| v.as_str()) else {
+            continue;
+        };
+        if attached_track_kind(track, input_path) != MergeTrackKind::Audio {
+            continue;
+        }
+        if let Some(conversion) = frame_rate_conversion_from_config(track.get("config")) {
+            push_audio_conversion_warnings(
+                &mut warnings,
+                input_path,
+                conversion,
+                frame_rate_audio_encoder(attached_audio_codec_from_path(input_path)),
+            );
+        }
+    }
+
+    warnings
+}
+
 fn emit_merge_progress(
     app: &tauri::AppHandle,
     video_path: &str,
@@ -229,13 +605,9 @@ fn emit_merge_progress(
     );
 }
 
-#[cfg_attr(not(test), allow(dead_code))]
-pub(super) async fn merge_tracks_with_bins(
-    ffprobe_path: &str,
-    ffmpeg_path: &str,
+fn validate_merge_paths(
     video_path: &str,
     tracks: &[Value],
-    source_track_configs: Option<&[Value]>,
     output_path: &str,
 ) -> Result<(), String> {
     validate_media_path(video_path)?;
@@ -246,7 +618,11 @@ pub(super) async fn merge_tracks_with_bins(
             validate_media_path(input_path)?;
         }
     }
+    Ok(())
+}
 
+/// Probe the streams of the video the tracks are merged into.
+async fn probe_video_streams(ffprobe_path: &str, video_path: &str) -> Result<Vec<Value>, String> {
     let probe_future = async move {
         Command::new(ffprobe_path)
             .args([
@@ -282,6 +658,22 @@ pub(super) async fn merge_tracks_with_bins(
         .and_then(|s| s.as_array())
         .cloned()
         .unwrap_or_default();
+
+    Ok(streams)
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+pub(super) async fn merge_tracks_with_bins(
+    ffprobe_path: &str,
+    ffmpeg_path: &str,
+    video_path: &str,
+    tracks: &[Value],
+    source_track_configs: Option<&[Value]>,
+    output_path: &str,
+) -> Result<(), String> {
+    validate_merge_paths(video_path, tracks, output_path)?;
+
+    let streams = probe_video_streams(ffprobe_path, video_path).await?;
     let args = build_merge_args(
         video_path,
         tracks,
@@ -328,58 +720,13 @@ pub(crate) async fn merge_tracks(
     output_path: String,
     duration_us: Option<u64>,
 ) -> Result<(), String> {
-    // Validate input paths
-    validate_media_path(&video_path)?;
-    validate_output_path(&output_path)?;
+    validate_merge_paths(&video_path, &tracks, &output_path)?;
 
     let _sleep_guard = SleepInhibitGuard::try_acquire("FFmpeg merge").ok();
 
-    // Validate all track input paths
-    for track in &tracks {
-        if let Some(input_path) = track.get("inputPath").and_then(|v| v.as_str()) {
-            validate_media_path(input_path)?;
-        }
-    }
-
     // First, probe the video to count streams and get their types
     let ffprobe_path = resolve_ffprobe_path(&app)?;
-    let video_path_for_probe = video_path.clone();
-    let probe_future = async move {
-        Command::new(ffprobe_path)
-            .args([
-                "-v",
-                "quiet",
-                "-print_format",
-                "json",
-                "-show_streams",
-                &video_path_for_probe,
-            ])
-            .output()
-            .await
-    };
-
-    let probe_output = timeout(FFPROBE_TIMEOUT, probe_future)
-        .await
-        .map_err(|_| {
-            format!(
-                "FFprobe timeout after {} seconds",
-                FFPROBE_TIMEOUT.as_secs()
-            )
-        })?
-        .map_err(|e| format!("Failed to probe video: {}", e))?;
-
-    if !probe_output.status.success() {
-        return Err("Failed to probe video file".to_string());
-    }
-
-    let probe_json: Value = serde_json::from_slice(&probe_output.stdout)
-        .map_err(|e| format!("Failed to parse probe output: {}", e))?;
-
-    let streams = probe_json
-        .get("streams")
-        .and_then(|s| s.as_array())
-        .cloned()
-        .unwrap_or_default();
+    let streams = probe_video_streams(&ffprobe_path, &video_path).await?;
 
     let args = build_merge_args(
         &video_path,
@@ -488,12 +835,47 @@ pub(crate) async fn merge_tracks(
     Ok(())
 }
 
+/// Return the merge command without running it.
+#[tauri::command]
+pub(crate) async fn dry_run_merge_tracks(
+    app: tauri::AppHandle,
+    video_path: String,
+    tracks: Vec<Value>,
+    source_track_configs: Option<Vec<Value>>,
+    output_path: String,
+) -> Result<FfmpegDryRun, String> {
+    validate_merge_paths(&video_path, &tracks, &output_path)?;
+
+    let ffprobe_path = resolve_ffprobe_path(&app)?;
+    let streams = probe_video_streams(&ffprobe_path, &video_path).await?;
+    let ffmpeg_path = resolve_ffmpeg_path(&app)?;
+
+    Ok(build_dry_run(
+        &ffmpeg_path,
+        vec![build_merge_args(
+            &video_path,
+            &tracks,
+            source_track_configs.as_deref(),
+            &streams,
+            &output_path,
+        )],
+        build_merge_warnings(
+            &video_path,
+            &tracks,
+            source_track_configs.as_deref(),
+            &streams,
+        ),
+    ))
+}
+
 #[cfg(test)]
 mod tests {
     use serde_json::Value;
     use serde_json::json;
 
-    use super::{build_merge_args, enabled_source_indices, merge_tracks_with_bins};
+    use super::{
+        build_merge_args, build_merge_warnings, enabled_source_indices, merge_tracks_with_bins,
+    };
 
     fn has_arg_pair(args: &[String], left: &str, right: &str) -> bool {
         args.windows(2)
@@ -713,6 +1095,137 @@ mod tests {
         assert!(has_arg_pair(&args, "-disposition:1", "default"));
     }
 
+    #[test]
+    fn build_merge_args_scales_source_subtitle_timestamps_for_frame_rate_conversion() {
+        let source_streams = vec![
+            json!({"index": 0, "codec_type": "video", "codec_name": "h264"}),
+            json!({"index": 1, "codec_type": "subtitle", "codec_name": "subrip"}),
+        ];
+        let source_configs = vec![
+            json!({"originalIndex": 0, "config": {"enabled": true}}),
+            json!({
+                "originalIndex": 1,
+                "config": {"enabled": true, "delayMs": 1500, "sourceFps": 25, "targetFps": 23.976}
+            }),
+        ];
+
+        let args = build_merge_args(
+            "/tmp/video.mkv",
+            &[],
+            Some(&source_configs),
+            &source_streams,
+            "/tmp/out.mkv",
+        );
+
+        assert!(has_arg_pair(&args, "-itsoffset", "1.439"));
+        assert!(has_arg_pair(&args, "-itsscale", "1.042709"));
+        assert!(has_arg_pair(&args, "-map", "0:0"));
+        assert!(has_arg_pair(&args, "-map", "1:1"));
+        assert!(!args.iter().any(|arg| arg.starts_with("-filter:a")));
+    }
+
+    #[test]
+    fn build_merge_args_reencodes_source_audio_with_atempo_for_frame_rate_conversion() {
+        let source_streams = vec![
+            json!({"index": 0, "codec_type": "video", "codec_name": "h264"}),
+            json!({"index": 1, "codec_type": "audio", "codec_name": "truehd"}),
+            json!({"index": 2, "codec_type": "audio", "codec_name": "ac3", "bit_rate": "448000"}),
+        ];
+        let source_configs = vec![
+            json!({"originalIndex": 0, "config": {"enabled": true}}),
+            json!({"originalIndex": 1, "config": {"enabled": true}}),
+            json!({
+                "originalIndex": 2,
+                "config": {"enabled": true, "delayMs": 500, "sourceFps": "25", "targetFps": "24000/1001"}
+            }),
+        ];
+
+        let args = build_merge_args(
+            "/tmp/video.mkv",
+            &[],
+            Some(&source_configs),
+            &source_streams,
+            "/tmp/out.mkv",
+        );
+
+        assert!(has_arg_pair(&args, "-itsoffset", "0.500"));
+        assert!(!args.iter().any(|arg| arg == "-itsscale"));
+        assert!(has_arg_pair(&args, "-map", "1:2"));
+        assert!(has_arg_pair(&args, "-filter:a:1", "atempo=0.959041"));
+        assert!(has_arg_pair(&args, "-c:a:1", "ac3"));
+        assert!(has_arg_pair(&args, "-b:a:1", "448k"));
+        assert!(!args.iter().any(|arg| arg == "-filter:a:0"));
+    }
+
+    #[test]
+    fn build_merge_args_applies_frame_rate_conversion_to_external_tracks() {
+        let tracks = vec![
+            json!({
+                "inputPath": "/tmp/sub.srt",
+                "config": {"sourceFps": 25, "targetFps": 23.976}
+            }),
+            json!({
+                "inputPath": "/tmp/audio.dts",
+                "config": {"sourceFps": 25, "targetFps": 23.976}
+            }),
+        ];
+
+        let args = build_merge_args(
+            "/tmp/video.mkv",
+            &tracks,
+            None,
+            &[json!({"index": 0, "codec_type": "video"})],
+            "/tmp/out.mkv",
+        );
+
+        assert!(has_arg_pair(&args, "-itsscale", "1.042709"));
+        assert_eq!(args.iter().filter(|arg| *arg == "-itsscale").count(), 1);
+        assert!(has_arg_pair(&args, "-filter:a:0", "atempo=0.959040"));
+        assert!(has_arg_pair(&args, "-c:a:0", "flac"));
+    }
+
+    #[test]
+    fn build_merge_args_ignores_unusable_frame_rate_pairs() {
+        let tracks = vec![
+            json!({"inputPath": "/tmp/same.srt", "config": {"sourceFps": 25, "targetFps": 25}}),
+            json!({"inputPath": "/tmp/zero.srt", "config": {"sourceFps": 0, "targetFps": 25}}),
+        ];
+
+        let args = build_merge_args("/tmp/video.mkv", &tracks, None, &[], "/tmp/out.mkv");
+
+        assert!(!args.iter().any(|arg| arg == "-itsscale"));
+        assert!(!args.iter().any(|arg| arg.starts_with("-filter:a")));
+    }
+
+    #[test]
+    fn build_merge_args_chains_atempo_for_large_frame_rate_changes() {
+        let tracks = vec![
+            json!({"inputPath": "/tmp/far.srt", "config": {"sourceFps": 60, "targetFps": 24}}),
+            json!({"inputPath": "/tmp/slow.dts", "config": {"sourceFps": 60, "targetFps": 24}}),
+            json!({"inputPath": "/tmp/fast.dts", "config": {"sourceFps": 12, "targetFps": 60}}),
+        ];
+
+        let args = build_merge_args(
+            "/tmp/video.mkv",
+            &tracks,
+            None,
+            &[json!({"index": 0, "codec_type": "video"})],
+            "/tmp/out.mkv",
+        );
+
+        assert!(has_arg_pair(&args, "-itsscale", "2.500000"));
+        assert!(has_arg_pair(
+            &args,
+            "-filter:a:0",
+            "atempo=0.500000,atempo=0.800000"
+        ));
+        assert!(has_arg_pair(
+            &args,
+            "-filter:a:1",
+            "atempo=2.000000,atempo=2.000000,atempo=1.250000"
+        ));
+    }
+
     #[tokio::test]
     async fn merge_tracks_adds_external_subtitle_track() {
         let video = crate::test_support::assets::ensure_sample_video()
@@ -911,4 +1424,42 @@ mod tests {
             "video stream should remain near 0s start, got {video_start}"
         );
     }
+
+    #[test]
+    fn build_merge_warnings_flags_dropped_streams_and_audio_conversions() {
+        let streams = vec![
+            json!({"index": 0, "codec_type": "video", "codec_name": "h264"}),
+            json!({"index": 1, "codec_type": "audio", "codec_name": "dts"}),
+            json!({"index": 2, "codec_type": "subtitle", "codec_name": "subrip"}),
+        ];
+        let source_configs = vec![
+            json!({"originalIndex": 0, "type": "video", "config": {"enabled": true}}),
+            json!({
+                "originalIndex": 1,
+                "type": "audio",
+                "config": {"enabled": true, "sourceFps": 25, "targetFps": 23.976}
+            }),
+            json!({"originalIndex": 2, "type": "subtitle", "config": {"enabled": false}}),
+            json!({"originalIndex": 5, "type": "audio", "config": {"enabled": true}}),
+        ];
+        let tracks = vec![json!({
+            "inputPath": "/tmp/far.dts",
+            "config": {"sourceFps": 60, "targetFps": 24}
+        })];
+
+        let warnings =
+            build_merge_warnings("/tmp/input.mkv", &tracks, Some(&source_configs), &streams);
+        assert_eq!(
+            warnings,
+            vec![
+                "Source stream #2 (subtitle subrip) is dropped from the output",
+                "Source stream #5 is not in the probed video; FFmpeg will fail to map it",
+                "Source stream #1 is re-encoded to flac for the frame rate conversion",
+                "/tmp/far.dts is re-encoded to flac for the frame rate conversion",
+                "/tmp/far.dts chains 2 atempo filters: tempo 0.400 is outside 0.5-2",
+            ]
+        );
+
+        assert!(build_merge_warnings("/tmp/input.mkv", &[], None, &streams).is_empty());
+    }
 }
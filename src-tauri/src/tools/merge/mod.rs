@@ -1,3 +1,4 @@
 pub(crate) mod cancel;
+pub(crate) mod episodes;
 pub(crate) mod merge;
 mod state;
@@ -1,3 +1,4 @@
+pub(crate) mod concat;
 pub(crate) mod data;
 pub(crate) mod ffmpeg;
 pub(crate) mod ffprobe;
@@ -6,6 +7,8 @@ pub(crate) mod media_metadata;
 pub(crate) mod merge;
 pub(crate) mod ocr;
 pub(crate) mod power;
+pub(crate) mod split;
+pub(crate) mod subtitles;
 pub(crate) mod tokens;
 pub(crate) mod transcode;
 pub(crate) mod transcription;
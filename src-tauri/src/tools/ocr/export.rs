@@ -65,7 +65,7 @@ fn format_txt(subtitles: &[OcrSubtitleEntry]) -> String {
 }
 
 /// Format time for SRT (00:00:00,000)
-fn format_srt_time(ms: u64) -> String {
+pub(crate) fn format_srt_time(ms: u64) -> String {
     let hours = ms / 3_600_000;
     let minutes = (ms % 3_600_000) / 60_000;
     let seconds = (ms % 60_000) / 1000;
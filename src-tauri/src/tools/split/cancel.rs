@@ -0,0 +1,165 @@
+use crate::shared::process::force_terminate_process;
+
+fn remove_output_files(paths: &[String]) {
+    for path in paths {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+/// Cancel a specific split by input path.
+#[tauri::command]
+pub(crate) async fn cancel_split_file(input_path: String) -> Result<(), String> {
+    let pid = {
+        match super::state::SPLIT_PROCESS_IDS.lock() {
+            Ok(mut guard) => guard.remove(&input_path),
+            Err(_) => return Err("Failed to acquire process lock".to_string()),
+        }
+    };
+
+    let output_paths = {
+        match super::state::SPLIT_OUTPUT_PATHS.lock() {
+            Ok(mut guard) => guard.remove(&input_path),
+            Err(_) => None,
+        }
+    };
+
+    if output_paths.is_some()
+        && let Ok(mut guard) = super::state::CANCELLED_SPLITS.lock()
+    {
+        guard.insert(input_path.clone());
+    }
+
+    if let Some(pid) = pid {
+        force_terminate_process(pid);
+    }
+
+    if let Some(paths) = output_paths {
+        remove_output_files(&paths);
+    }
+
+    Ok(())
+}
+
+/// Cancel all ongoing splits.
+#[tauri::command]
+pub(crate) async fn cancel_split() -> Result<(), String> {
+    let pids: Vec<u32> = {
+        match super::state::SPLIT_PROCESS_IDS.lock() {
+            Ok(mut guard) => guard.drain().map(|(_, pid)| pid).collect(),
+            Err(_) => return Err("Failed to acquire process lock".to_string()),
+        }
+    };
+
+    let output_paths: Vec<(String, Vec<String>)> = {
+        match super::state::SPLIT_OUTPUT_PATHS.lock() {
+            Ok(mut guard) => guard.drain().collect(),
+            Err(_) => Vec::new(),
+        }
+    };
+
+    if let Ok(mut guard) = super::state::CANCELLED_SPLITS.lock() {
+        guard.extend(
+            output_paths
+                .iter()
+                .map(|(input_path, _)| input_path.clone()),
+        );
+    }
+
+    for pid in pids {
+        force_terminate_process(pid);
+    }
+
+    for (_, paths) in &output_paths {
+        remove_output_files(paths);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serial_test::serial;
+
+    use super::{cancel_split, cancel_split_file};
+
+    #[tokio::test]
+    #[serial]
+    async fn cancel_split_file_marks_cancelled_and_removes_parts() {
+        let temp = tempfile::tempdir().expect("failed to create tempdir");
+        let part_a = temp.path().join("movie - Part 01.mkv");
+        let part_b = temp.path().join("movie - Part 02.mkv");
+        std::fs::write(&part_a, b"done").expect("failed to create part a");
+        std::fs::write(&part_b, b"partial").expect("failed to create part b");
+        let input = "/tmp/test-split-input.mkv".to_string();
+
+        {
+            let mut pids = super::super::state::SPLIT_PROCESS_IDS
+                .lock()
+                .expect("failed to lock pids");
+            pids.insert(input.clone(), 0);
+        }
+        {
+            let mut outputs = super::super::state::SPLIT_OUTPUT_PATHS
+                .lock()
+                .expect("failed to lock outputs");
+            outputs.insert(
+                input.clone(),
+                vec![
+                    part_a.to_string_lossy().to_string(),
+                    part_b.to_string_lossy().to_string(),
+                ],
+            );
+        }
+
+        cancel_split_file(input.clone())
+            .await
+            .expect("cancel split file should succeed");
+
+        assert!(!part_a.exists());
+        assert!(!part_b.exists());
+        assert!(
+            super::super::state::CANCELLED_SPLITS
+                .lock()
+                .expect("failed to lock cancelled splits")
+                .remove(&input)
+        );
+        assert!(
+            !super::super::state::SPLIT_PROCESS_IDS
+                .lock()
+                .expect("failed to lock pids")
+                .contains_key(&input)
+        );
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn cancel_split_cleans_all_tracked_splits() {
+        let temp = tempfile::tempdir().expect("failed to create tempdir");
+        let part = temp.path().join("part.mkv");
+        std::fs::write(&part, b"partial").expect("failed to create part");
+
+        {
+            let mut outputs = super::super::state::SPLIT_OUTPUT_PATHS
+                .lock()
+                .expect("failed to lock outputs");
+            outputs.insert(
+                "video-a".to_string(),
+                vec![part.to_string_lossy().to_string()],
+            );
+        }
+
+        cancel_split().await.expect("cancel all should succeed");
+
+        assert!(!part.exists());
+        assert!(
+            super::super::state::SPLIT_OUTPUT_PATHS
+                .lock()
+                .expect("failed to lock outputs")
+                .is_empty()
+        );
+        super::super::state::CANCELLED_SPLITS
+            .lock()
+            .expect("failed to lock cancelled splits")
+            .clear();
+    }
+}
@@ -0,0 +1,3 @@
+pub(crate) mod cancel;
+pub(crate) mod split;
+mod state;
@@ -0,0 +1,1102 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tauri::Emitter;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+use tokio::time::timeout;
+
+use crate::shared::ffmpeg_progress::FfmpegProgressTracker;
+use crate::shared::process::terminate_process;
+use crate::shared::sleep_inhibit::SleepInhibitGuard;
+use crate::shared::store::{resolve_ffmpeg_path, resolve_ffprobe_path};
+use crate::shared::validation::{
+    validate_directory_path, validate_media_path, validate_output_path,
+};
+use crate::tools::ffprobe::FFPROBE_TIMEOUT;
+use crate::tools::media_metadata::{
+    MediaMetadataRequest, apply_metadata_args, output_stream_metadata_from_request,
+};
+
+/// Timeout for a single split part (2 hours, exact cuts re-encode video)
+const SPLIT_PART_TIMEOUT: Duration = Duration::from_secs(7200);
+/// Timeout for listing keyframes, which reads every packet of the video stream
+const KEYFRAME_PROBE_TIMEOUT: Duration = Duration::from_secs(300);
+
+const DEFAULT_SPLIT_NAMING_PATTERN: &str = "{name} - Part {part}";
+/// Parts shorter than this are not worth a separate file.
+const MIN_SPLIT_PART_MS: u64 = 1_000;
+/// Size-based parts aim slightly below the target since bitrate varies over the file.
+const SIZE_SPLIT_MARGIN: f64 = 0.97;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SplitRequest {
+    pub(crate) input_path: String,
+    pub(crate) output_dir: String,
+    /// One of `chapters`, `timestamps`, `duration` or `size`.
+    pub(crate) mode: String,
+    pub(crate) timestamps_ms: Option<Vec<u64>>,
+    pub(crate) part_duration_ms: Option<u64>,
+    pub(crate) part_size_mb: Option<f64>,
+    /// Supports `{name}`, `{part}`, `{total}` and `{title}`; the input extension is kept.
+    pub(crate) naming_pattern: Option<String>,
+    /// Re-encode video so cuts land exactly on the requested times instead of keyframes.
+    #[serde(default)]
+    pub(crate) exact_cuts: bool,
+    #[serde(default)]
+    pub(crate) metadata: MediaMetadataRequest,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SplitPart {
+    pub(crate) output_path: String,
+    pub(crate) start_ms: u64,
+    pub(crate) end_ms: u64,
+    pub(crate) title: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct SplitChapter {
+    start_ms: u64,
+    title: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct SplitSource {
+    duration_ms: u64,
+    size_bytes: Option<u64>,
+    bit_rate: Option<u64>,
+    container_title: Option<String>,
+    chapters: Vec<SplitChapter>,
+    streams: Vec<Value>,
+}
+
+impl SplitSource {
+    fn has_timeline_video(&self) -> bool {
+        self.streams.iter().any(|stream| {
+            stream.get("codec_type").and_then(|value| value.as_str()) == Some("video")
+                && !is_attached_picture(stream)
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct PlannedSegment {
+    start_ms: u64,
+    end_ms: u64,
+    title: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct PlannedSplitPart {
+    part: SplitPart,
+    args: Vec<String>,
+}
+
+fn emit_split_progress(
+    app: &tauri::AppHandle,
+    input_path: &str,
+    part: usize,
+    part_count: usize,
+    progress: i32,
+    speed_bytes_per_sec: Option<f64>,
+) {
+    let _ = app.emit(
+        "split-progress",
+        serde_json::json!({
+            "inputPath": input_path,
+            "part": part,
+            "partCount": part_count,
+            "progress": progress,
+            "speedBytesPerSec": speed_bytes_per_sec
+        }),
+    );
+}
+
+fn is_attached_picture(stream: &Value) -> bool {
+    stream
+        .get("disposition")
+        .and_then(|value| value.get("attached_pic"))
+        .and_then(|value| value.as_u64())
+        .unwrap_or(0)
+        == 1
+}
+
+fn seconds_to_ms(value: &Value) -> Option<u64> {
+    value
+        .as_str()
+        .and_then(|value| value.parse::<f64>().ok())
+        .filter(|value| value.is_finite() && *value >= 0.0)
+        .map(|value| (value * 1000.0).round() as u64)
+}
+
+fn parse_split_source(probe: &Value) -> Result<SplitSource, String> {
+    let format = probe.get("format");
+    let duration_ms = format
+        .and_then(|format| format.get("duration"))
+        .and_then(seconds_to_ms)
+        .filter(|duration_ms| *duration_ms > 0)
+        .ok_or_else(|| "Could not determine media duration".to_string())?;
+
+    let chapters = probe
+        .get("chapters")
+        .and_then(|value| value.as_array())
+        .map(|chapters| {
+            chapters
+                .iter()
+                .filter_map(|chapter| {
+                    Some(SplitChapter {
+                        start_ms: chapter.get("start_time").and_then(seconds_to_ms)?,
+                        title: chapter
+                            .get("tags")
+                            .and_then(|tags| tags.get("title"))
+                            .and_then(|value| value.as_str())
+                            .map(str::trim)
+                            .filter(|title| !title.is_empty())
+                            .map(str::to_string),
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    Ok(SplitSource {
+        duration_ms,
+        size_bytes: format
+            .and_then(|format| format.get("size"))
+            .and_then(|value| value.as_str())
+            .and_then(|value| value.parse::<u64>().ok()),
+        bit_rate: format
+            .and_then(|format| format.get("bit_rate"))
+            .and_then(|value| value.as_str())
+            .and_then(|value| value.parse::<u64>().ok()),
+        container_title: format
+            .and_then(|format| format.get("tags"))
+            .and_then(|tags| tags.get("title"))
+            .and_then(|value| value.as_str())
+            .map(str::to_string),
+        chapters,
+        streams: probe
+            .get("streams")
+            .and_then(|value| value.as_array())
+            .cloned()
+            .unwrap_or_default(),
+    })
+}
+
+fn parse_keyframe_times_ms(output: &str) -> Vec<u64> {
+    let mut keyframes = output
+        .lines()
+        .filter_map(|line| {
+            let (pts_time, flags) = line.trim().split_once(',')?;
+            if !flags.contains('K') {
+                return None;
+            }
+            pts_time
+                .parse::<f64>()
+                .ok()
+                .filter(|value| value.is_finite() && *value >= 0.0)
+                .map(|value| (value * 1000.0).round() as u64)
+        })
+        .collect::<Vec<_>>();
+    keyframes.sort_unstable();
+    keyframes.dedup();
+    keyframes
+}
+
+/// Segment start points (the first one is always 0) with an optional title for each.
+fn plan_split_starts(
+    request: &SplitRequest,
+    source: &SplitSource,
+) -> Result<Vec<(u64, Option<String>)>, String> {
+    let duration_ms = source.duration_ms;
+    let interior = |time_ms: u64| time_ms > 0 && time_ms < duration_ms;
+
+    let starts = match request.mode.as_str() {
+        "chapters" => {
+            if source.chapters.is_empty() {
+                return Err("File has no chapters to split on".to_string());
+            }
+            let mut starts = Vec::new();
+            if source.chapters.iter().all(|chapter| chapter.start_ms > 0) {
+                starts.push((0, None));
+            }
+            for chapter in &source.chapters {
+                if chapter.start_ms == 0 || interior(chapter.start_ms) {
+                    starts.push((chapter.start_ms, chapter.title.clone()));
+                }
+            }
+            starts
+        }
+        "timestamps" => {
+            let mut timestamps = request
+                .timestamps_ms
+                .clone()
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|time_ms| interior(*time_ms))
+                .collect::<Vec<_>>();
+            timestamps.sort_unstable();
+            timestamps.dedup();
+            std::iter::once(0)
+                .chain(timestamps)
+                .map(|time_ms| (time_ms, None))
+                .collect()
+        }
+        "duration" | "size" => {
+            let part_duration_ms = if request.mode == "duration" {
+                request
+                    .part_duration_ms
+                    .ok_or_else(|| "Part duration is required".to_string())?
+            } else {
+                let part_size_mb = request
+                    .part_size_mb
+                    .filter(|value| value.is_finite() && *value > 0.0)
+                    .ok_or_else(|| "Part size is required".to_string())?;
+                let bytes_per_ms = source
+                    .size_bytes
+                    .map(|size| size as f64 / duration_ms as f64)
+                    .or_else(|| source.bit_rate.map(|bit_rate| bit_rate as f64 / 8000.0))
+                    .filter(|value| *value > 0.0)
+                    .ok_or_else(|| "Could not determine file bitrate".to_string())?;
+                (part_size_mb * 1024.0 * 1024.0 * SIZE_SPLIT_MARGIN / bytes_per_ms) as u64
+            };
+            if part_duration_ms < MIN_SPLIT_PART_MS {
+                return Err("Parts must be at least one second long".to_string());
+            }
+            (0..)
+                .map(|index| index * part_duration_ms)
+                .take_while(|time_ms| *time_ms < duration_ms)
+                .map(|time_ms| (time_ms, None))
+                .collect()
+        }
+        _ => return Err(format!("Unsupported split mode: {}", request.mode)),
+    };
+
+    Ok(starts)
+}
+
+/// Move each start to the nearest keyframe so stream-copied parts begin with a decodable frame.
+fn snap_starts_to_keyframes(
+    starts: Vec<(u64, Option<String>)>,
+    keyframes_ms: &[u64],
+) -> Vec<(u64, Option<String>)> {
+    if keyframes_ms.is_empty() {
+        return starts;
+    }
+
+    starts
+        .into_iter()
+        .map(|(start_ms, title)| {
+            if start_ms == 0 {
+                return (0, title);
+            }
+            let position = keyframes_ms.partition_point(|keyframe| *keyframe < start_ms);
+            let after = keyframes_ms.get(position).copied();
+            let before = position
+                .checked_sub(1)
+                .and_then(|index| keyframes_ms.get(index).copied());
+            let snapped = match (before, after) {
+                (Some(before), Some(after)) => {
+                    if start_ms - before <= after - start_ms {
+                        before
+                    } else {
+                        after
+                    }
+                }
+                (Some(before), None) => before,
+                (None, Some(after)) => after,
+                (None, None) => start_ms,
+            };
+            (snapped, title)
+        })
+        .collect()
+}
+
+fn segments_from_starts(starts: &[(u64, Option<String>)], duration_ms: u64) -> Vec<PlannedSegment> {
+    let mut segments: Vec<PlannedSegment> = Vec::new();
+
+    for (index, (start_ms, title)) in starts.iter().enumerate() {
+        let end_ms = starts
+            .get(index + 1)
+            .map(|(next_start, _)| *next_start)
+            .unwrap_or(duration_ms)
+            .min(duration_ms);
+        if end_ms <= *start_ms {
+            continue;
+        }
+
+        // Merge slivers (e.g. two cuts snapped next to each other) into the previous part.
+        if end_ms - start_ms < MIN_SPLIT_PART_MS
+            && let Some(previous) = segments.last_mut()
+        {
+            previous.end_ms = end_ms;
+            continue;
+        }
+
+        segments.push(PlannedSegment {
+            start_ms: *start_ms,
+            end_ms,
+            title: title.clone(),
+        });
+    }
+
+    segments
+}
+
+fn sanitize_file_name(name: &str) -> String {
+    let sanitized = name
+        .chars()
+        .map(|character| match character {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            character if character.is_control() => '_',
+            character => character,
+        })
+        .collect::<String>()
+        .replace("..", "_");
+    sanitized.trim().trim_end_matches('.').to_string()
+}
+
+fn render_part_name(
+    pattern: &str,
+    name: &str,
+    part: usize,
+    part_count: usize,
+    title: Option<&str>,
+) -> String {
+    let width = part_count.to_string().len().max(2);
+    let part_label = format!("{:0width$}", part, width = width);
+    let title = title
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("Part {}", part_label));
+
+    sanitize_file_name(
+        &pattern
+            .replace("{name}", name)
+            .replace("{part}", &part_label)
+            .replace("{total}", &part_count.to_string())
+            .replace("{title}", &title),
+    )
+}
+
+fn container_id_for_extension(extension: &str) -> &str {
+    match extension {
+        "m4v" => "mp4",
+        "mka" | "mks" => "mkv",
+        other => other,
+    }
+}
+
+fn exact_cut_video_encoder_args(container_id: &str) -> &'static [&'static str] {
+    match container_id {
+        "webm" => &["-c:v", "libvpx-vp9", "-crf", "32", "-b:v", "0"],
+        _ => &["-c:v", "libx264", "-crf", "18", "-preset", "medium"],
+    }
+}
+
+fn build_split_part_args(
+    request: &SplitRequest,
+    source: &SplitSource,
+    segment: &PlannedSegment,
+    part_number: usize,
+    container_id: &str,
+    output_path: &str,
+) -> Vec<String> {
+    let mut args = vec![
+        "-y".to_string(),
+        "-ss".to_string(),
+        format!("{:.3}", segment.start_ms as f64 / 1000.0),
+        "-i".to_string(),
+        request.input_path.clone(),
+        "-t".to_string(),
+        format!("{:.3}", (segment.end_ms - segment.start_ms) as f64 / 1000.0),
+    ];
+
+    let mapped_streams = source
+        .streams
+        .iter()
+        .filter(|stream| {
+            matches!(
+                stream.get("codec_type").and_then(|value| value.as_str()),
+                Some("video" | "audio" | "subtitle")
+            )
+        })
+        .filter_map(|stream| {
+            stream
+                .get("index")
+                .and_then(|value| value.as_u64())
+                .map(|index| (index, stream))
+        })
+        .collect::<Vec<_>>();
+    for (index, _) in &mapped_streams {
+        args.push("-map".to_string());
+        args.push(format!("0:{}", index));
+    }
+
+    args.push("-c".to_string());
+    args.push("copy".to_string());
+    if request.exact_cuts && source.has_timeline_video() {
+        args.extend(
+            exact_cut_video_encoder_args(container_id)
+                .iter()
+                .map(|arg| arg.to_string()),
+        );
+    } else {
+        args.push("-avoid_negative_ts".to_string());
+        args.push("make_zero".to_string());
+    }
+
+    let base_title = request
+        .metadata
+        .container_title
+        .clone()
+        .or_else(|| source.container_title.clone());
+    let part_title = match (&segment.title, base_title) {
+        (Some(chapter_title), _) => Some(chapter_title.clone()),
+        (None, Some(base_title)) => Some(format!("{} - Part {}", base_title.trim(), part_number)),
+        (None, None) => None,
+    };
+    let part_metadata = MediaMetadataRequest {
+        container_title: part_title,
+        track_edits: request.metadata.track_edits.clone(),
+        ..MediaMetadataRequest::default()
+    };
+    let stream_metadata = mapped_streams
+        .iter()
+        .enumerate()
+        .map(|(output_index, (_, stream))| {
+            output_stream_metadata_from_request(output_index, stream, &part_metadata)
+        })
+        .collect::<Vec<_>>();
+    apply_metadata_args(
+        &mut args,
+        container_id,
+        Some(&part_metadata),
+        &stream_metadata,
+    );
+
+    args.push("-progress".to_string());
+    args.push("pipe:1".to_string());
+    args.push(output_path.to_string());
+    args
+}
+
+fn build_split_parts(
+    request: &SplitRequest,
+    source: &SplitSource,
+    segments: &[PlannedSegment],
+) -> Result<Vec<PlannedSplitPart>, String> {
+    if segments.len() < 2 {
+        return Err("Split points produce a single part; nothing to split".to_string());
+    }
+
+    let input = Path::new(&request.input_path);
+    let name = input
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("output");
+    let extension = input
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase())
+        .unwrap_or_else(|| "mkv".to_string());
+    let container_id = container_id_for_extension(&extension);
+    let pattern = request
+        .naming_pattern
+        .as_deref()
+        .map(str::trim)
+        .filter(|pattern| !pattern.is_empty())
+        .unwrap_or(DEFAULT_SPLIT_NAMING_PATTERN);
+
+    let mut used_names = HashSet::new();
+    let mut parts = Vec::with_capacity(segments.len());
+    for (index, segment) in segments.iter().enumerate() {
+        let part_number = index + 1;
+        let file_name = render_part_name(
+            pattern,
+            name,
+            part_number,
+            segments.len(),
+            segment.title.as_deref(),
+        );
+        if file_name.is_empty() || !used_names.insert(file_name.clone()) {
+            return Err(format!(
+                "Naming pattern produces duplicate file names: {}",
+                pattern
+            ));
+        }
+
+        let output_path = Path::new(&request.output_dir)
+            .join(format!("{}.{}", file_name, extension))
+            .to_string_lossy()
+            .to_string();
+        validate_output_path(&output_path)?;
+        if Path::new(&output_path) == input {
+            return Err("Split output would overwrite the input file".to_string());
+        }
+
+        parts.push(PlannedSplitPart {
+            args: build_split_part_args(
+                request,
+                source,
+                segment,
+                part_number,
+                container_id,
+                &output_path,
+            ),
+            part: SplitPart {
+                output_path,
+                start_ms: segment.start_ms,
+                end_ms: segment.end_ms,
+                title: segment.title.clone(),
+            },
+        });
+    }
+
+    Ok(parts)
+}
+
+async fn probe_split_source(ffprobe_path: &str, input_path: &str) -> Result<SplitSource, String> {
+    let output = timeout(
+        FFPROBE_TIMEOUT,
+        Command::new(ffprobe_path)
+            .args([
+                "-v",
+                "quiet",
+                "-print_format",
+                "json",
+                "-show_format",
+                "-show_streams",
+                "-show_chapters",
+                input_path,
+            ])
+            .output(),
+    )
+    .await
+    .map_err(|_| {
+        format!(
+            "FFprobe timeout after {} seconds",
+            FFPROBE_TIMEOUT.as_secs()
+        )
+    })?
+    .map_err(|error| format!("Failed to execute ffprobe: {}", error))?;
+
+    if !output.status.success() {
+        return Err("Failed to probe input file".to_string());
+    }
+
+    let probe: Value = serde_json::from_slice(&output.stdout)
+        .map_err(|error| format!("Invalid probe JSON: {}", error))?;
+    parse_split_source(&probe)
+}
+
+async fn probe_keyframe_times(ffprobe_path: &str, input_path: &str) -> Result<Vec<u64>, String> {
+    let output = timeout(
+        KEYFRAME_PROBE_TIMEOUT,
+        Command::new(ffprobe_path)
+            .args([
+                "-v",
+                "error",
+                "-select_streams",
+                "v:0",
+                "-show_entries",
+                "packet=pts_time,flags",
+                "-of",
+                "csv=p=0",
+                input_path,
+            ])
+            .output(),
+    )
+    .await
+    .map_err(|_| {
+        format!(
+            "Keyframe probe timeout after {} seconds",
+            KEYFRAME_PROBE_TIMEOUT.as_secs()
+        )
+    })?
+    .map_err(|error| format!("Failed to execute ffprobe: {}", error))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("Failed to list keyframes: {}", stderr.trim()));
+    }
+
+    Ok(parse_keyframe_times_ms(&String::from_utf8_lossy(
+        &output.stdout,
+    )))
+}
+
+fn validate_split_request(request: &SplitRequest) -> Result<(), String> {
+    validate_media_path(&request.input_path)?;
+    validate_directory_path(&request.output_dir)
+}
+
+async fn plan_split_with_ffprobe(
+    ffprobe_path: &str,
+    request: &SplitRequest,
+) -> Result<Vec<PlannedSplitPart>, String> {
+    let source = probe_split_source(ffprobe_path, &request.input_path).await?;
+    let mut starts = plan_split_starts(request, &source)?;
+
+    if !request.exact_cuts && source.has_timeline_video() {
+        let keyframes_ms = probe_keyframe_times(ffprobe_path, &request.input_path).await?;
+        starts = snap_starts_to_keyframes(starts, &keyframes_ms);
+    }
+
+    let segments = segments_from_starts(&starts, source.duration_ms);
+    build_split_parts(request, &source, &segments)
+}
+
+fn remove_split_outputs(parts: &[PlannedSplitPart]) {
+    for part in parts {
+        let _ = std::fs::remove_file(&part.part.output_path);
+    }
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+pub(crate) async fn split_media_with_bins(
+    ffmpeg_path: &str,
+    ffprobe_path: &str,
+    request: &SplitRequest,
+) -> Result<Vec<SplitPart>, String> {
+    validate_split_request(request)?;
+    let parts = plan_split_with_ffprobe(ffprobe_path, request).await?;
+
+    for planned in &parts {
+        let output = timeout(
+            SPLIT_PART_TIMEOUT,
+            Command::new(ffmpeg_path).args(&planned.args).output(),
+        )
+        .await
+        .map_err(|_| {
+            remove_split_outputs(&parts);
+            format!(
+                "Split timeout after {} seconds",
+                SPLIT_PART_TIMEOUT.as_secs()
+            )
+        })?
+        .map_err(|error| format!("Failed to execute ffmpeg: {}", error))?;
+
+        if !output.status.success() {
+            remove_split_outputs(&parts);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(format!("Split failed: {}", stderr.trim()));
+        }
+    }
+
+    Ok(parts.into_iter().map(|planned| planned.part).collect())
+}
+
+fn clear_split_state(input_path: &str) {
+    if let Ok(mut guard) = super::state::SPLIT_PROCESS_IDS.lock() {
+        guard.remove(input_path);
+    }
+    if let Ok(mut guard) = super::state::SPLIT_OUTPUT_PATHS.lock() {
+        guard.remove(input_path);
+    }
+    if let Ok(mut guard) = super::state::CANCELLED_SPLITS.lock() {
+        guard.remove(input_path);
+    }
+}
+
+fn is_split_cancelled(input_path: &str) -> bool {
+    super::state::CANCELLED_SPLITS
+        .lock()
+        .map(|guard| guard.contains(input_path))
+        .unwrap_or(false)
+}
+
+/// Split a file into parts at chapters, timestamps, or by size/duration.
+#[tauri::command]
+pub(crate) async fn split_media(
+    app: tauri::AppHandle,
+    request: SplitRequest,
+) -> Result<Vec<SplitPart>, String> {
+    validate_split_request(&request)?;
+
+    let _sleep_guard = SleepInhibitGuard::try_acquire("Media split").ok();
+    let ffmpeg_path = resolve_ffmpeg_path(&app)?;
+    let ffprobe_path = resolve_ffprobe_path(&app)?;
+    let parts = plan_split_with_ffprobe(&ffprobe_path, &request).await?;
+
+    if let Ok(mut guard) = super::state::CANCELLED_SPLITS.lock() {
+        guard.remove(&request.input_path);
+    }
+    if let Ok(mut guard) = super::state::SPLIT_OUTPUT_PATHS.lock() {
+        guard.insert(
+            request.input_path.clone(),
+            parts
+                .iter()
+                .map(|planned| planned.part.output_path.clone())
+                .collect(),
+        );
+    }
+
+    let total_ms = parts
+        .last()
+        .map(|planned| planned.part.end_ms)
+        .unwrap_or(0)
+        .max(1);
+    let part_count = parts.len();
+    emit_split_progress(&app, &request.input_path, 1, part_count, 0, None);
+
+    for (index, planned) in parts.iter().enumerate() {
+        if is_split_cancelled(&request.input_path) {
+            clear_split_state(&request.input_path);
+            remove_split_outputs(&parts);
+            return Err("Split cancelled".to_string());
+        }
+
+        let mut child = Command::new(&ffmpeg_path)
+            .args(&planned.args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|error| {
+                clear_split_state(&request.input_path);
+                remove_split_outputs(&parts);
+                format!("Failed to start ffmpeg: {}", error)
+            })?;
+
+        if let Some(pid) = child.id()
+            && let Ok(mut guard) = super::state::SPLIT_PROCESS_IDS.lock()
+        {
+            guard.insert(request.input_path.clone(), pid);
+        }
+
+        if let Some(stdout) = child.stdout.take() {
+            let app_for_progress = app.clone();
+            let input_path_for_progress = request.input_path.clone();
+            let part_start_ms = planned.part.start_ms;
+            let part_duration_ms = planned.part.end_ms - planned.part.start_ms;
+            let part_number = index + 1;
+
+            tokio::spawn(async move {
+                let mut tracker = FfmpegProgressTracker::new(Some(part_duration_ms * 1000));
+                let reader = BufReader::new(stdout);
+                let mut lines = reader.lines();
+
+                while let Ok(Some(line)) = lines.next_line().await {
+                    if let Some(update) = tracker.handle_line(&line) {
+                        let part_progress = update.progress.unwrap_or(0).clamp(0, 100) as u64;
+                        let done_ms = part_start_ms + part_duration_ms * part_progress / 100;
+                        emit_split_progress(
+                            &app_for_progress,
+                            &input_path_for_progress,
+                            part_number,
+                            part_count,
+                            (done_ms * 100 / total_ms) as i32,
+                            update.speed_bytes_per_sec,
+                        );
+                    }
+                }
+            });
+        }
+
+        let child_pid = child.id();
+        let output = match timeout(SPLIT_PART_TIMEOUT, child.wait_with_output()).await {
+            Ok(result) => result.map_err(|error| {
+                clear_split_state(&request.input_path);
+                remove_split_outputs(&parts);
+                format!("Failed to execute ffmpeg: {}", error)
+            })?,
+            Err(_) => {
+                if let Some(pid) = child_pid {
+                    terminate_process(pid);
+                    tokio::time::sleep(Duration::from_millis(200)).await;
+                }
+                clear_split_state(&request.input_path);
+                remove_split_outputs(&parts);
+                return Err(format!(
+                    "Split timeout after {} seconds",
+                    SPLIT_PART_TIMEOUT.as_secs()
+                ));
+            }
+        };
+
+        if let Ok(mut guard) = super::state::SPLIT_PROCESS_IDS.lock() {
+            guard.remove(&request.input_path);
+        }
+
+        if !output.status.success() {
+            let cancelled = is_split_cancelled(&request.input_path);
+            clear_split_state(&request.input_path);
+            remove_split_outputs(&parts);
+            if cancelled {
+                return Err("Split cancelled".to_string());
+            }
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(format!("Split failed: {}", stderr.trim()));
+        }
+    }
+
+    clear_split_state(&request.input_path);
+    emit_split_progress(&app, &request.input_path, part_count, part_count, 100, None);
+
+    Ok(parts.into_iter().map(|planned| planned.part).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use serde_json::{Value, json};
+
+    use super::{
+        PlannedSegment, SplitRequest, build_split_parts, parse_keyframe_times_ms,
+        parse_split_source, plan_split_starts, render_part_name, segments_from_starts,
+        snap_starts_to_keyframes, split_media_with_bins,
+    };
+    use crate::test_support::ffmpeg::{ffmpeg_path, ffprobe_path};
+    use crate::tools::media_metadata::MediaMetadataRequest;
+
+    fn has_arg_pair(args: &[String], left: &str, right: &str) -> bool {
+        args.windows(2)
+            .any(|window| window[0] == left && window[1] == right)
+    }
+
+    fn build_request(mode: &str) -> SplitRequest {
+        SplitRequest {
+            input_path: "/tmp/movie.mkv".to_string(),
+            output_dir: "/tmp".to_string(),
+            mode: mode.to_string(),
+            timestamps_ms: None,
+            part_duration_ms: None,
+            part_size_mb: None,
+            naming_pattern: None,
+            exact_cuts: false,
+            metadata: MediaMetadataRequest::default(),
+        }
+    }
+
+    fn probe() -> Value {
+        json!({
+            "format": {"duration": "100.000", "size": "104857600", "tags": {"title": "Movie"}},
+            "chapters": [
+                {"start_time": "0.000000", "tags": {"title": "Opening"}},
+                {"start_time": "40.000000", "tags": {"title": "Act: Two"}},
+                {"start_time": "75.500000", "tags": {}}
+            ],
+            "streams": [
+                {"index": 0, "codec_type": "video", "codec_name": "h264"},
+                {"index": 1, "codec_type": "audio", "codec_name": "aac", "tags": {"language": "jpn"}},
+                {"index": 2, "codec_type": "attachment", "codec_name": "ttf"}
+            ]
+        })
+    }
+
+    #[test]
+    fn plan_split_starts_uses_chapter_boundaries_and_titles() {
+        let source = parse_split_source(&probe()).expect("probe should parse");
+        let starts = plan_split_starts(&build_request("chapters"), &source).expect("plan");
+
+        assert_eq!(
+            starts,
+            vec![
+                (0, Some("Opening".to_string())),
+                (40_000, Some("Act: Two".to_string())),
+                (75_500, None)
+            ]
+        );
+    }
+
+    #[test]
+    fn plan_split_starts_filters_and_sorts_timestamps() {
+        let source = parse_split_source(&probe()).expect("probe should parse");
+        let mut request = build_request("timestamps");
+        request.timestamps_ms = Some(vec![60_000, 0, 20_000, 60_000, 150_000]);
+
+        let starts = plan_split_starts(&request, &source).expect("plan");
+
+        assert_eq!(
+            starts.iter().map(|(start, _)| *start).collect::<Vec<_>>(),
+            vec![0, 20_000, 60_000]
+        );
+    }
+
+    #[test]
+    fn plan_split_starts_derives_duration_from_target_size() {
+        let source = parse_split_source(&probe()).expect("probe should parse");
+        let mut request = build_request("size");
+        request.part_size_mb = Some(25.0);
+
+        let starts = plan_split_starts(&request, &source).expect("plan");
+
+        // 100 MiB over 100 s is ~1 MiB/s, so 25 MiB parts (with margin) last ~24.25 s.
+        assert_eq!(
+            starts.iter().map(|(start, _)| *start).collect::<Vec<_>>(),
+            vec![0, 24_250, 48_500, 72_750, 97_000]
+        );
+    }
+
+    #[test]
+    fn plan_split_starts_rejects_tiny_parts_and_unknown_modes() {
+        let source = parse_split_source(&probe()).expect("probe should parse");
+        let mut request = build_request("duration");
+        request.part_duration_ms = Some(500);
+        assert!(plan_split_starts(&request, &source).is_err());
+        assert!(plan_split_starts(&build_request("scenes"), &source).is_err());
+    }
+
+    #[test]
+    fn snap_starts_to_keyframes_picks_nearest_keyframe() {
+        let keyframes =
+            parse_keyframe_times_ms("0.000000,K__\n1.000000,___\n9.800000,K__\n12.000000,K_\n");
+        assert_eq!(keyframes, vec![0, 9_800, 12_000]);
+
+        let snapped =
+            snap_starts_to_keyframes(vec![(0, None), (10_000, None), (11_500, None)], &keyframes);
+        assert_eq!(
+            snapped.iter().map(|(start, _)| *start).collect::<Vec<_>>(),
+            vec![0, 9_800, 12_000]
+        );
+    }
+
+    #[test]
+    fn segments_from_starts_merges_slivers_and_caps_at_duration() {
+        let segments = segments_from_starts(
+            &[
+                (0, Some("A".to_string())),
+                (10_000, Some("B".to_string())),
+                (10_400, Some("C".to_string())),
+                (30_000, Some("D".to_string())),
+            ],
+            20_000,
+        );
+
+        assert_eq!(
+            segments,
+            vec![
+                PlannedSegment {
+                    start_ms: 0,
+                    end_ms: 10_400,
+                    title: Some("A".to_string())
+                },
+                PlannedSegment {
+                    start_ms: 10_400,
+                    end_ms: 20_000,
+                    title: Some("C".to_string())
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn render_part_name_expands_tokens_and_sanitizes() {
+        assert_eq!(
+            render_part_name(
+                "{name} - {part} of {total} - {title}",
+                "Movie",
+                3,
+                12,
+                Some("Act: Two")
+            ),
+            "Movie - 03 of 12 - Act_ Two"
+        );
+        assert_eq!(render_part_name("{title}", "Movie", 1, 2, None), "Part 01");
+    }
+
+    #[test]
+    fn build_split_parts_seeks_maps_and_applies_part_metadata() {
+        let source = parse_split_source(&probe()).expect("probe should parse");
+        let mut request = build_request("chapters");
+        request.naming_pattern = Some("{name} {part} {title}".to_string());
+        let segments = vec![
+            PlannedSegment {
+                start_ms: 0,
+                end_ms: 40_000,
+                title: Some("Opening".to_string()),
+            },
+            PlannedSegment {
+                start_ms: 40_000,
+                end_ms: 100_000,
+                title: None,
+            },
+        ];
+
+        let parts = build_split_parts(&request, &source, &segments).expect("parts should build");
+
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].part.output_path, "/tmp/movie 01 Opening.mkv");
+        assert_eq!(parts[1].part.output_path, "/tmp/movie 02 Part 02.mkv");
+
+        let args = &parts[1].args;
+        assert!(has_arg_pair(args, "-ss", "40.000"));
+        assert!(has_arg_pair(args, "-t", "60.000"));
+        assert!(has_arg_pair(args, "-map", "0:0"));
+        assert!(has_arg_pair(args, "-map", "0:1"));
+        assert!(!has_arg_pair(args, "-map", "0:2"));
+        assert!(has_arg_pair(args, "-c", "copy"));
+        assert!(has_arg_pair(args, "-avoid_negative_ts", "make_zero"));
+        assert!(has_arg_pair(args, "-metadata", "title=Movie - Part 2"));
+        assert!(has_arg_pair(args, "-metadata:s:1", "language=jpn"));
+        assert!(has_arg_pair(&parts[0].args, "-metadata", "title=Opening"));
+    }
+
+    #[test]
+    fn build_split_parts_reencodes_video_for_exact_cuts_and_rejects_duplicate_names() {
+        let source = parse_split_source(&probe()).expect("probe should parse");
+        let mut request = build_request("timestamps");
+        request.exact_cuts = true;
+        let segments = vec![
+            PlannedSegment {
+                start_ms: 0,
+                end_ms: 30_000,
+                title: None,
+            },
+            PlannedSegment {
+                start_ms: 30_000,
+                end_ms: 100_000,
+                title: None,
+            },
+        ];
+
+        let parts = build_split_parts(&request, &source, &segments).expect("parts should build");
+        assert!(has_arg_pair(&parts[0].args, "-c:v", "libx264"));
+        assert!(!parts[0].args.iter().any(|arg| arg == "-avoid_negative_ts"));
+
+        request.naming_pattern = Some("{name} cut".to_string());
+        let error = build_split_parts(&request, &source, &segments)
+            .expect_err("duplicate names should be rejected");
+        assert!(error.contains("duplicate"));
+
+        request.naming_pattern = Some("{name}".to_string());
+        let error = build_split_parts(&request, &source, &segments)
+            .expect_err("overwriting the input should be rejected");
+        assert!(error.contains("overwrite the input"));
+    }
+
+    #[tokio::test]
+    async fn split_media_cuts_file_by_duration() {
+        let video = crate::test_support::assets::ensure_sample_video()
+            .await
+            .expect("failed to load local sample video");
+        let temp = tempfile::tempdir().expect("failed to create tempdir");
+        let source = crate::tools::ffprobe::get_media_duration_us_with_ffprobe(
+            ffprobe_path(),
+            video.to_string_lossy().as_ref(),
+        )
+        .await
+        .expect("duration should be readable");
+
+        let mut request = build_request("duration");
+        request.input_path = video.to_string_lossy().to_string();
+        request.output_dir = temp.path().to_string_lossy().to_string();
+        request.part_duration_ms = Some((source / 1000 / 2).max(1_000));
+
+        let parts = split_media_with_bins(ffmpeg_path(), ffprobe_path(), &request)
+            .await
+            .expect("split should succeed");
+
+        assert!(parts.len() >= 2);
+        for part in &parts {
+            assert!(Path::new(&part.output_path).exists());
+        }
+    }
+}
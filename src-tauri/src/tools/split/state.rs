@@ -0,0 +1,14 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{LazyLock, Mutex};
+
+/// Store the running part's process ID keyed by input path for cancellation
+pub(super) static SPLIT_PROCESS_IDS: LazyLock<Mutex<HashMap<String, u32>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Store every planned part path so a cancelled split leaves no partial set behind
+pub(super) static SPLIT_OUTPUT_PATHS: LazyLock<Mutex<HashMap<String, Vec<String>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Input paths for which cancellation has been requested, checked between parts
+pub(super) static CANCELLED_SPLITS: LazyLock<Mutex<HashSet<String>>> =
+    LazyLock::new(|| Mutex::new(HashSet::new()));
@@ -0,0 +1,395 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::tools::ocr::export::format_srt_time;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum SubtitleFormat {
+    Srt,
+    Ass,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SubtitleCue {
+    pub(crate) start_ms: i64,
+    pub(crate) end_ms: i64,
+    pub(crate) text: String,
+}
+
+/// Parsed subtitle file. ASS documents keep their original lines so that styles, script
+/// info and dialogue fields other than the timings survive a round trip untouched.
+#[derive(Debug, Clone)]
+pub(crate) struct SubtitleDocument {
+    pub(crate) format: SubtitleFormat,
+    pub(crate) cues: Vec<SubtitleCue>,
+    ass_lines: Vec<String>,
+    ass_dialogue_lines: Vec<AssDialogueLine>,
+}
+
+#[derive(Debug, Clone)]
+struct AssDialogueLine {
+    line_index: usize,
+    fields: Vec<String>,
+    start_field: usize,
+    end_field: usize,
+}
+
+pub(crate) fn subtitle_format_from_path(path: &str) -> Option<SubtitleFormat> {
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|value| value.to_str())
+        .map(|value| value.to_lowercase())?;
+
+    match extension.as_str() {
+        "srt" => Some(SubtitleFormat::Srt),
+        "ass" | "ssa" => Some(SubtitleFormat::Ass),
+        _ => None,
+    }
+}
+
+pub(crate) fn parse_subtitle_document(
+    content: &str,
+    format: SubtitleFormat,
+) -> Result<SubtitleDocument, String> {
+    let content = content.trim_start_matches('\u{feff}');
+
+    match format {
+        SubtitleFormat::Srt => Ok(SubtitleDocument {
+            format,
+            cues: parse_srt_cues(content),
+            ass_lines: Vec::new(),
+            ass_dialogue_lines: Vec::new(),
+        }),
+        SubtitleFormat::Ass => parse_ass_document(content),
+    }
+}
+
+impl SubtitleDocument {
+    pub(crate) fn render(&self) -> String {
+        match self.format {
+            SubtitleFormat::Srt => render_srt(&self.cues),
+            SubtitleFormat::Ass => self.render_ass(),
+        }
+    }
+
+    fn render_ass(&self) -> String {
+        let mut lines = self.ass_lines.clone();
+
+        for (dialogue, cue) in self.ass_dialogue_lines.iter().zip(&self.cues) {
+            let mut fields = dialogue.fields.clone();
+            fields[dialogue.start_field] = format_ass_time(cue.start_ms);
+            fields[dialogue.end_field] = format_ass_time(cue.end_ms);
+            lines[dialogue.line_index] = format!("Dialogue: {}", fields.join(","));
+        }
+
+        let mut output = lines.join("\n");
+        output.push('\n');
+        output
+    }
+}
+
+/// Apply `time * scale + offset` to every cue, clamping negative timestamps to zero.
+pub(crate) fn retime_cues(cues: &[SubtitleCue], offset_ms: i64, scale: f64) -> Vec<SubtitleCue> {
+    cues.iter()
+        .map(|cue| SubtitleCue {
+            start_ms: retime_ms(cue.start_ms, offset_ms, scale),
+            end_ms: retime_ms(cue.end_ms, offset_ms, scale),
+            text: cue.text.clone(),
+        })
+        .collect()
+}
+
+fn retime_ms(time_ms: i64, offset_ms: i64, scale: f64) -> i64 {
+    ((time_ms as f64 * scale).round() as i64 + offset_ms).max(0)
+}
+
+fn parse_srt_cues(content: &str) -> Vec<SubtitleCue> {
+    let mut cues = Vec::new();
+    let mut lines = content.lines().peekable();
+
+    while let Some(line) = lines.next() {
+        let Some((start_ms, end_ms)) = parse_srt_timing_line(line) else {
+            continue;
+        };
+
+        let mut text_lines = Vec::new();
+        while let Some(next_line) = lines.peek() {
+            if next_line.trim().is_empty() {
+                break;
+            }
+            text_lines.push(next_line.trim_end().to_string());
+            lines.next();
+        }
+
+        cues.push(SubtitleCue {
+            start_ms,
+            end_ms,
+            text: text_lines.join("\n"),
+        });
+    }
+
+    cues
+}
+
+fn parse_srt_timing_line(line: &str) -> Option<(i64, i64)> {
+    let (start, rest) = line.trim().split_once("-->")?;
+    // Some files append positioning hints (X1:.. Y1:..) after the end timestamp.
+    let end = rest.split_whitespace().next()?;
+    Some((parse_srt_time(start.trim())?, parse_srt_time(end)?))
+}
+
+fn parse_srt_time(value: &str) -> Option<i64> {
+    let (clock, millis) = value.split_once([',', '.'])?;
+    let mut parts = clock.split(':');
+    let hours = parts.next()?.trim().parse::<i64>().ok()?;
+    let minutes = parts.next()?.trim().parse::<i64>().ok()?;
+    let seconds = parts.next()?.trim().parse::<i64>().ok()?;
+    if parts.next().is_some() {
+        return None;
+    }
+
+    let millis_digits = millis.trim();
+    let millis = millis_digits.parse::<i64>().ok()?;
+    let millis = match millis_digits.len() {
+        1 => millis * 100,
+        2 => millis * 10,
+        _ => millis,
+    };
+
+    Some(((hours * 60 + minutes) * 60 + seconds) * 1000 + millis)
+}
+
+fn render_srt(cues: &[SubtitleCue]) -> String {
+    cues.iter()
+        .enumerate()
+        .map(|(index, cue)| {
+            format!(
+                "{}\n{} --> {}\n{}\n",
+                index + 1,
+                format_srt_time(cue.start_ms.max(0) as u64),
+                format_srt_time(cue.end_ms.max(0) as u64),
+                cue.text
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn parse_ass_document(content: &str) -> Result<SubtitleDocument, String> {
+    let ass_lines = content
+        .lines()
+        .map(|line| line.to_string())
+        .collect::<Vec<_>>();
+    let mut cues = Vec::new();
+    let mut ass_dialogue_lines = Vec::new();
+    let mut in_events = false;
+    let mut event_fields: Option<Vec<String>> = None;
+
+    for (line_index, line) in ass_lines.iter().enumerate() {
+        let trimmed = line.trim();
+
+        if trimmed.starts_with('[') {
+            in_events = trimmed.eq_ignore_ascii_case("[events]");
+            continue;
+        }
+
+        if !in_events {
+            continue;
+        }
+
+        if let Some(format_line) = trimmed.strip_prefix("Format:") {
+            event_fields = Some(
+                format_line
+                    .split(',')
+                    .map(|field| field.trim().to_lowercase())
+                    .collect(),
+            );
+            continue;
+        }
+
+        let Some(dialogue) = trimmed.strip_prefix("Dialogue:") else {
+            continue;
+        };
+        let Some(field_names) = event_fields.as_ref() else {
+            return Err("ASS subtitle is missing the [Events] Format line".to_string());
+        };
+        let field_position = |name: &str| field_names.iter().position(|field| field == name);
+        let (Some(start_field), Some(end_field)) = (field_position("start"), field_position("end"))
+        else {
+            return Err("ASS subtitle Format line must declare Start and End".to_string());
+        };
+        let text_field = field_position("text").unwrap_or(field_names.len() - 1);
+
+        let fields = dialogue
+            .trim_start()
+            .splitn(field_names.len(), ',')
+            .map(|field| field.to_string())
+            .collect::<Vec<_>>();
+        if fields.len() != field_names.len() {
+            continue;
+        }
+
+        let (Some(start_ms), Some(end_ms)) = (
+            parse_ass_time(&fields[start_field]),
+            parse_ass_time(&fields[end_field]),
+        ) else {
+            continue;
+        };
+
+        cues.push(SubtitleCue {
+            start_ms,
+            end_ms,
+            text: fields.get(text_field).cloned().unwrap_or_default(),
+        });
+        ass_dialogue_lines.push(AssDialogueLine {
+            line_index,
+            fields,
+            start_field,
+            end_field,
+        });
+    }
+
+    Ok(SubtitleDocument {
+        format: SubtitleFormat::Ass,
+        cues,
+        ass_lines,
+        ass_dialogue_lines,
+    })
+}
+
+fn parse_ass_time(value: &str) -> Option<i64> {
+    let (clock, centis) = value.trim().split_once('.')?;
+    let mut parts = clock.split(':');
+    let hours = parts.next()?.parse::<i64>().ok()?;
+    let minutes = parts.next()?.parse::<i64>().ok()?;
+    let seconds = parts.next()?.parse::<i64>().ok()?;
+    let centis = centis.parse::<i64>().ok()?;
+
+    Some(((hours * 60 + minutes) * 60 + seconds) * 1000 + centis * 10)
+}
+
+fn format_ass_time(ms: i64) -> String {
+    let centis = (ms.max(0) + 5) / 10;
+    let hours = centis / 360_000;
+    let minutes = (centis % 360_000) / 6000;
+    let seconds = (centis % 6000) / 100;
+    format!(
+        "{}:{:02}:{:02}.{:02}",
+        hours,
+        minutes,
+        seconds,
+        centis % 100
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        SubtitleCue, SubtitleFormat, format_ass_time, parse_srt_time, parse_subtitle_document,
+        retime_cues, subtitle_format_from_path,
+    };
+
+    const SAMPLE_ASS: &str = "[Script Info]\nTitle: Sample\n\n[V4+ Styles]\nFormat: Name, Fontname\nStyle: Default,Arial\n\n[Events]\nFormat: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\nDialogue: 0,0:00:01.00,0:00:02.50,Default,,0,0,0,,Hello, world\nComment: 0,0:00:03.00,0:00:04.00,Default,,0,0,0,,note\nDialogue: 0,0:00:05.20,0:00:06.00,Default,,0,0,0,,{\\i1}Bye{\\i0}\n";
+
+    #[test]
+    fn subtitle_format_from_path_detects_srt_and_ass() {
+        assert_eq!(
+            subtitle_format_from_path("/tmp/a.SRT"),
+            Some(SubtitleFormat::Srt)
+        );
+        assert_eq!(
+            subtitle_format_from_path("/tmp/a.ssa"),
+            Some(SubtitleFormat::Ass)
+        );
+        assert_eq!(subtitle_format_from_path("/tmp/a.vtt"), None);
+    }
+
+    #[test]
+    fn parse_srt_time_accepts_short_fractions() {
+        assert_eq!(parse_srt_time("00:01:02,345"), Some(62_345));
+        assert_eq!(parse_srt_time("00:00:01.5"), Some(1_500));
+        assert_eq!(parse_srt_time("nonsense"), None);
+    }
+
+    #[test]
+    fn srt_document_round_trips_multiline_cues() {
+        let content = "\u{feff}1\r\n00:00:01,000 --> 00:00:02,000\r\nLine one\r\nLine two\r\n\r\n2\r\n00:00:03,500 --> 00:00:04,000 X1:10\r\nSecond\r\n";
+
+        let document =
+            parse_subtitle_document(content, SubtitleFormat::Srt).expect("srt should parse");
+
+        assert_eq!(
+            document.cues,
+            vec![
+                SubtitleCue {
+                    start_ms: 1_000,
+                    end_ms: 2_000,
+                    text: "Line one\nLine two".to_string(),
+                },
+                SubtitleCue {
+                    start_ms: 3_500,
+                    end_ms: 4_000,
+                    text: "Second".to_string(),
+                },
+            ]
+        );
+        assert_eq!(
+            document.render(),
+            "1\n00:00:01,000 --> 00:00:02,000\nLine one\nLine two\n\n2\n00:00:03,500 --> 00:00:04,000\nSecond\n"
+        );
+    }
+
+    #[test]
+    fn ass_document_rewrites_only_dialogue_timings() {
+        let mut document =
+            parse_subtitle_document(SAMPLE_ASS, SubtitleFormat::Ass).expect("ass should parse");
+        assert_eq!(document.cues.len(), 2);
+        assert_eq!(document.cues[0].text, "Hello, world");
+        assert_eq!(document.cues[1].start_ms, 5_200);
+
+        document.cues = retime_cues(&document.cues, 1_000, 1.0);
+        let rendered = document.render();
+
+        assert!(
+            rendered.contains("Dialogue: 0,0:00:02.00,0:00:03.50,Default,,0,0,0,,Hello, world")
+        );
+        assert!(rendered.contains("Comment: 0,0:00:03.00,0:00:04.00,Default,,0,0,0,,note"));
+        assert!(
+            rendered.contains("Dialogue: 0,0:00:06.20,0:00:07.00,Default,,0,0,0,,{\\i1}Bye{\\i0}")
+        );
+        assert!(rendered.contains("Style: Default,Arial"));
+    }
+
+    #[test]
+    fn ass_document_without_format_line_is_rejected() {
+        let error = parse_subtitle_document(
+            "[Events]\nDialogue: 0,0:00:01.00,0:00:02.00,Default,,0,0,0,,Hi\n",
+            SubtitleFormat::Ass,
+        )
+        .expect_err("missing format should fail");
+        assert!(error.contains("Format line"));
+    }
+
+    #[test]
+    fn retime_cues_applies_scale_then_offset_and_clamps_at_zero() {
+        let cues = vec![SubtitleCue {
+            start_ms: 1_000,
+            end_ms: 25_000,
+            text: "Hi".to_string(),
+        }];
+
+        let retimed = retime_cues(&cues, -2_000, 25.0 / 24.0);
+
+        assert_eq!(retimed[0].start_ms, 0);
+        assert_eq!(retimed[0].end_ms, 24_042);
+    }
+
+    #[test]
+    fn format_ass_time_rounds_to_centiseconds() {
+        assert_eq!(format_ass_time(3_723_456), "1:02:03.46");
+        assert_eq!(format_ass_time(-10), "0:00:00.00");
+    }
+}
@@ -0,0 +1,2 @@
+pub(crate) mod cues;
+pub(crate) mod sync;
@@ -0,0 +1,642 @@
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncReadExt;
+use tokio::process::Command;
+use tokio::time::timeout;
+
+use crate::shared::sleep_inhibit::SleepInhibitGuard;
+use crate::shared::store::resolve_ffmpeg_path;
+use crate::shared::validation::{validate_media_path, validate_output_path};
+use crate::tools::subtitles::cues::{
+    SubtitleCue, parse_subtitle_document, retime_cues, subtitle_format_from_path,
+};
+
+/// Timeout for decoding the reference audio track (10 minutes)
+const SUBTITLE_SYNC_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Sample rate used for speech detection; speech energy sits well below 4 kHz.
+const SPEECH_SAMPLE_RATE: usize = 8_000;
+/// Resolution of the voice-activity signal and of the fine offset search.
+const SPEECH_FRAME_MS: i64 = 10;
+const SPEECH_FRAME_SAMPLES: usize = SPEECH_SAMPLE_RATE * SPEECH_FRAME_MS as usize / 1000;
+/// Gaps shorter than this inside speech are bridged (pauses between words).
+const SPEECH_GAP_FILL_MS: i64 = 250;
+/// Isolated bursts shorter than this are treated as noise.
+const SPEECH_MIN_RUN_MS: i64 = 100;
+
+const DEFAULT_MAX_OFFSET_MS: u32 = 60_000;
+const COARSE_OFFSET_STEP_MS: i64 = 100;
+
+/// Linear scale candidates covering the usual frame-rate mismatches
+/// (23.976 / 24 / 25 / 29.97 / 30 fps) plus a fine grid for drifting rips.
+const FRAME_RATE_SCALES: &[f64] = &[
+    25.0 / 23.976,
+    23.976 / 25.0,
+    25.0 / 24.0,
+    24.0 / 25.0,
+    24.0 / 23.976,
+    23.976 / 24.0,
+    30.0 / 29.97,
+    29.97 / 30.0,
+];
+const FINE_SCALE_STEP: f64 = 0.0005;
+const FINE_SCALE_STEPS: i32 = 6;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SubtitleSyncRequest {
+    pub(crate) media_path: String,
+    /// Relative audio stream index (`0:a:N`) used as the timing reference.
+    pub(crate) audio_track_index: Option<usize>,
+    pub(crate) subtitle_path: String,
+    pub(crate) output_path: Option<String>,
+    pub(crate) max_offset_ms: Option<u32>,
+    #[serde(default)]
+    pub(crate) allow_scale: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SubtitleSyncResult {
+    pub(crate) offset_ms: i64,
+    pub(crate) scale: f64,
+    /// Share of subtitle time that overlaps detected speech after alignment (0-1).
+    pub(crate) score: f64,
+    pub(crate) original_score: f64,
+    pub(crate) speech_region_count: usize,
+    pub(crate) cues: Vec<SubtitleCue>,
+    pub(crate) output_path: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct SubtitleAlignment {
+    offset_ms: i64,
+    scale: f64,
+    score: f64,
+}
+
+struct SpeechTimeline {
+    /// `prefix[i]` is the number of speech frames in `[0, i)`.
+    prefix: Vec<u32>,
+}
+
+impl SpeechTimeline {
+    fn from_frames(frames: &[bool]) -> Self {
+        let mut prefix = Vec::with_capacity(frames.len() + 1);
+        prefix.push(0);
+        for is_speech in frames {
+            let last = *prefix.last().unwrap_or(&0);
+            prefix.push(last + u32::from(*is_speech));
+        }
+        Self { prefix }
+    }
+
+    fn frame_count(&self) -> i64 {
+        (self.prefix.len() - 1) as i64
+    }
+
+    fn speech_frames_between(&self, start_ms: f64, end_ms: f64) -> u32 {
+        let frame_count = self.frame_count();
+        let start = ((start_ms / SPEECH_FRAME_MS as f64).floor() as i64).clamp(0, frame_count);
+        let end = ((end_ms / SPEECH_FRAME_MS as f64).ceil() as i64).clamp(0, frame_count);
+        if end <= start {
+            return 0;
+        }
+        self.prefix[end as usize] - self.prefix[start as usize]
+    }
+}
+
+fn frame_energy_db(samples: &[i16]) -> f64 {
+    if samples.is_empty() {
+        return -120.0;
+    }
+
+    let sum_squares = samples
+        .iter()
+        .map(|sample| {
+            let normalized = *sample as f64 / i16::MAX as f64;
+            normalized * normalized
+        })
+        .sum::<f64>();
+    let rms = (sum_squares / samples.len() as f64).sqrt();
+    if rms <= 0.0 {
+        -120.0
+    } else {
+        20.0 * rms.log10()
+    }
+}
+
+fn percentile(sorted_values: &[f64], ratio: f64) -> f64 {
+    if sorted_values.is_empty() {
+        return -120.0;
+    }
+    let index = ((sorted_values.len() - 1) as f64 * ratio).round() as usize;
+    sorted_values[index.min(sorted_values.len() - 1)]
+}
+
+/// Classify per-frame energies as speech using a threshold placed between the noise floor
+/// and the loud end of the track, then smooth the result into contiguous regions.
+fn detect_speech_frames(frame_energies_db: &[f64]) -> Vec<bool> {
+    let mut sorted = frame_energies_db
+        .iter()
+        .copied()
+        .filter(|value| value.is_finite())
+        .collect::<Vec<_>>();
+    sorted.sort_by(|left, right| left.total_cmp(right));
+
+    let noise_floor = percentile(&sorted, 0.15);
+    let loud_level = percentile(&sorted, 0.95);
+    if loud_level - noise_floor < 6.0 {
+        return vec![false; frame_energies_db.len()];
+    }
+    let threshold = (noise_floor + (loud_level - noise_floor) * 0.35).max(-55.0);
+
+    let mut frames = frame_energies_db
+        .iter()
+        .map(|energy| *energy >= threshold)
+        .collect::<Vec<_>>();
+    fill_short_runs(&mut frames, false, SPEECH_GAP_FILL_MS / SPEECH_FRAME_MS);
+    fill_short_runs(&mut frames, true, SPEECH_MIN_RUN_MS / SPEECH_FRAME_MS);
+    frames
+}
+
+/// Flip interior runs of `value` shorter than `max_len` frames.
+fn fill_short_runs(frames: &mut [bool], value: bool, max_len: i64) {
+    let mut index = 0usize;
+    while index < frames.len() {
+        if frames[index] != value {
+            index += 1;
+            continue;
+        }
+
+        let run_start = index;
+        while index < frames.len() && frames[index] == value {
+            index += 1;
+        }
+        let is_interior = run_start > 0 && index < frames.len();
+        if is_interior && ((index - run_start) as i64) < max_len {
+            frames[run_start..index].fill(!value);
+        }
+    }
+}
+
+fn count_speech_regions(frames: &[bool]) -> usize {
+    frames
+        .iter()
+        .enumerate()
+        .filter(|(index, is_speech)| **is_speech && (*index == 0 || !frames[index - 1]))
+        .count()
+}
+
+fn score_alignment(
+    cues: &[SubtitleCue],
+    speech: &SpeechTimeline,
+    offset_ms: i64,
+    scale: f64,
+) -> f64 {
+    let mut cue_frames = 0.0;
+    let mut speech_frames = 0.0;
+
+    for cue in cues {
+        let start_ms = cue.start_ms as f64 * scale + offset_ms as f64;
+        let end_ms = cue.end_ms as f64 * scale + offset_ms as f64;
+        if end_ms <= start_ms {
+            continue;
+        }
+        cue_frames += (end_ms - start_ms) / SPEECH_FRAME_MS as f64;
+        speech_frames += speech.speech_frames_between(start_ms, end_ms) as f64;
+    }
+
+    if cue_frames <= 0.0 {
+        0.0
+    } else {
+        (speech_frames / cue_frames).min(1.0)
+    }
+}
+
+fn best_offset_for_scale(
+    cues: &[SubtitleCue],
+    speech: &SpeechTimeline,
+    scale: f64,
+    max_offset_ms: i64,
+) -> SubtitleAlignment {
+    let mut best = SubtitleAlignment {
+        offset_ms: 0,
+        scale,
+        score: score_alignment(cues, speech, 0, scale),
+    };
+    let consider = |offset_ms: i64, best: &mut SubtitleAlignment| {
+        let score = score_alignment(cues, speech, offset_ms, scale);
+        // Prefer the smallest correction when several offsets score the same.
+        if score > best.score + f64::EPSILON
+            || ((score - best.score).abs() <= f64::EPSILON
+                && offset_ms.abs() < best.offset_ms.abs())
+        {
+            *best = SubtitleAlignment {
+                offset_ms,
+                scale,
+                score,
+            };
+        }
+    };
+
+    let mut offset_ms = -max_offset_ms;
+    while offset_ms <= max_offset_ms {
+        consider(offset_ms, &mut best);
+        offset_ms += COARSE_OFFSET_STEP_MS;
+    }
+
+    let coarse_offset_ms = best.offset_ms;
+    let mut offset_ms = coarse_offset_ms - COARSE_OFFSET_STEP_MS;
+    while offset_ms <= coarse_offset_ms + COARSE_OFFSET_STEP_MS {
+        if offset_ms.abs() <= max_offset_ms {
+            consider(offset_ms, &mut best);
+        }
+        offset_ms += SPEECH_FRAME_MS;
+    }
+
+    best
+}
+
+fn candidate_scales(allow_scale: bool) -> Vec<f64> {
+    let mut scales = vec![1.0];
+    if !allow_scale {
+        return scales;
+    }
+
+    scales.extend_from_slice(FRAME_RATE_SCALES);
+    for step in 1..=FINE_SCALE_STEPS {
+        let delta = FINE_SCALE_STEP * step as f64;
+        scales.push(1.0 + delta);
+        scales.push(1.0 - delta);
+    }
+    scales
+}
+
+fn find_best_alignment(
+    cues: &[SubtitleCue],
+    speech: &SpeechTimeline,
+    max_offset_ms: i64,
+    allow_scale: bool,
+) -> SubtitleAlignment {
+    let mut best: Option<SubtitleAlignment> = None;
+
+    for scale in candidate_scales(allow_scale) {
+        let candidate = best_offset_for_scale(cues, speech, scale, max_offset_ms);
+        // A scaled fit has to be clearly better than the unscaled one to be worth applying.
+        let margin = if best.is_some_and(|best| best.scale == 1.0) {
+            0.01
+        } else {
+            f64::EPSILON
+        };
+        if best.is_none_or(|best| candidate.score > best.score + margin) {
+            best = Some(candidate);
+        }
+    }
+
+    best.unwrap_or(SubtitleAlignment {
+        offset_ms: 0,
+        scale: 1.0,
+        score: 0.0,
+    })
+}
+
+async fn decode_speech_frame_energies(
+    ffmpeg_path: &str,
+    media_path: &str,
+    audio_track_index: usize,
+) -> Result<Vec<f64>, String> {
+    let map_arg = format!("0:a:{}", audio_track_index);
+    let sample_rate_arg = SPEECH_SAMPLE_RATE.to_string();
+    let mut child = Command::new(ffmpeg_path)
+        .args([
+            "-hide_banner",
+            "-loglevel",
+            "error",
+            "-i",
+            media_path,
+            "-map",
+            &map_arg,
+            "-vn",
+            "-sn",
+            "-ac",
+            "1",
+            "-ar",
+            &sample_rate_arg,
+            "-c:a",
+            "pcm_s16le",
+            "-f",
+            "s16le",
+            "pipe:1",
+        ])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|error| format!("Failed to start ffmpeg: {}", error))?;
+
+    let Some(mut stdout) = child.stdout.take() else {
+        return Err("Failed to read decoded audio".to_string());
+    };
+
+    let read_future = async {
+        let mut energies = Vec::new();
+        let mut pending = Vec::<u8>::with_capacity(SPEECH_FRAME_SAMPLES * 2 * 64);
+        let mut buffer = vec![0u8; 64 * 1024];
+        let frame_bytes = SPEECH_FRAME_SAMPLES * 2;
+
+        loop {
+            let read = stdout
+                .read(&mut buffer)
+                .await
+                .map_err(|error| format!("Failed to read decoded audio: {}", error))?;
+            if read == 0 {
+                break;
+            }
+            pending.extend_from_slice(&buffer[..read]);
+
+            let complete_frames = pending.len() / frame_bytes;
+            for frame in pending[..complete_frames * frame_bytes].chunks_exact(frame_bytes) {
+                let samples = frame
+                    .chunks_exact(2)
+                    .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]))
+                    .collect::<Vec<_>>();
+                energies.push(frame_energy_db(&samples));
+            }
+            pending.drain(..complete_frames * frame_bytes);
+        }
+
+        Ok::<_, String>(energies)
+    };
+
+    let energies = timeout(SUBTITLE_SYNC_TIMEOUT, read_future)
+        .await
+        .map_err(|_| {
+            format!(
+                "Subtitle sync timeout after {} seconds",
+                SUBTITLE_SYNC_TIMEOUT.as_secs()
+            )
+        })??;
+
+    let output = child
+        .wait_with_output()
+        .await
+        .map_err(|error| format!("Failed to execute ffmpeg: {}", error))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("Failed to decode audio track: {}", stderr.trim()));
+    }
+
+    Ok(energies)
+}
+
+fn validate_subtitle_sync_request(request: &SubtitleSyncRequest) -> Result<(), String> {
+    validate_media_path(&request.media_path)?;
+    validate_media_path(&request.subtitle_path)?;
+
+    let Some(input_format) = subtitle_format_from_path(&request.subtitle_path) else {
+        return Err("Subtitle sync supports SRT and ASS/SSA files only".to_string());
+    };
+
+    if let Some(output_path) = request.output_path.as_deref() {
+        validate_output_path(output_path)?;
+        if subtitle_format_from_path(output_path) != Some(input_format) {
+            return Err(
+                "Synced subtitle output must use the same format as the input subtitle".to_string(),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) async fn sync_subtitle_timing_with_ffmpeg(
+    ffmpeg_path: &str,
+    request: &SubtitleSyncRequest,
+) -> Result<SubtitleSyncResult, String> {
+    validate_subtitle_sync_request(request)?;
+
+    let Some(format) = subtitle_format_from_path(&request.subtitle_path) else {
+        return Err("Subtitle sync supports SRT and ASS/SSA files only".to_string());
+    };
+    let content = std::fs::read_to_string(&request.subtitle_path)
+        .map_err(|error| format!("Failed to read subtitle file: {}", error))?;
+    let mut document = parse_subtitle_document(&content, format)?;
+    if document.cues.is_empty() {
+        return Err("Subtitle file does not contain any cues".to_string());
+    }
+
+    let energies = decode_speech_frame_energies(
+        ffmpeg_path,
+        &request.media_path,
+        request.audio_track_index.unwrap_or(0),
+    )
+    .await?;
+    let speech_frames = detect_speech_frames(&energies);
+    let speech_region_count = count_speech_regions(&speech_frames);
+    if speech_region_count == 0 {
+        return Err("No speech detected in the selected audio track".to_string());
+    }
+
+    let speech = SpeechTimeline::from_frames(&speech_frames);
+    let max_offset_ms = request.max_offset_ms.unwrap_or(DEFAULT_MAX_OFFSET_MS) as i64;
+    let alignment =
+        find_best_alignment(&document.cues, &speech, max_offset_ms, request.allow_scale);
+    let original_score = score_alignment(&document.cues, &speech, 0, 1.0);
+
+    document.cues = retime_cues(&document.cues, alignment.offset_ms, alignment.scale);
+
+    if let Some(output_path) = request.output_path.as_deref() {
+        std::fs::write(output_path, document.render())
+            .map_err(|error| format!("Failed to write subtitle file: {}", error))?;
+        if !Path::new(output_path).exists() {
+            return Err("Subtitle sync failed: output file not created".to_string());
+        }
+    }
+
+    Ok(SubtitleSyncResult {
+        offset_ms: alignment.offset_ms,
+        scale: alignment.scale,
+        score: alignment.score,
+        original_score,
+        speech_region_count,
+        cues: document.cues,
+        output_path: request.output_path.clone(),
+    })
+}
+
+/// Align subtitle cue timings to the speech detected in an audio track.
+#[tauri::command]
+pub(crate) async fn sync_subtitle_timing(
+    app: tauri::AppHandle,
+    request: SubtitleSyncRequest,
+) -> Result<SubtitleSyncResult, String> {
+    validate_subtitle_sync_request(&request)?;
+
+    let _sleep_guard = SleepInhibitGuard::try_acquire("Subtitle sync").ok();
+    let ffmpeg_path = resolve_ffmpeg_path(&app)?;
+    sync_subtitle_timing_with_ffmpeg(&ffmpeg_path, &request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::process::Command;
+
+    use super::{
+        SPEECH_FRAME_MS, SpeechTimeline, SubtitleSyncRequest, detect_speech_frames,
+        fill_short_runs, find_best_alignment, score_alignment, sync_subtitle_timing_with_ffmpeg,
+    };
+    use crate::tools::subtitles::cues::SubtitleCue;
+
+    fn cue(start_ms: i64, end_ms: i64) -> SubtitleCue {
+        SubtitleCue {
+            start_ms,
+            end_ms,
+            text: "line".to_string(),
+        }
+    }
+
+    fn speech_from_regions(total_ms: i64, regions: &[(i64, i64)]) -> Vec<bool> {
+        (0..total_ms / SPEECH_FRAME_MS)
+            .map(|frame| {
+                let time_ms = frame * SPEECH_FRAME_MS;
+                regions
+                    .iter()
+                    .any(|(start, end)| time_ms >= *start && time_ms < *end)
+            })
+            .collect()
+    }
+
+    fn irregular_regions() -> Vec<(i64, i64)> {
+        vec![
+            (2_000, 3_200),
+            (5_100, 7_000),
+            (9_400, 10_000),
+            (14_000, 16_500),
+            (21_300, 22_100),
+            (26_000, 29_000),
+        ]
+    }
+
+    #[test]
+    fn fill_short_runs_bridges_interior_gaps_only() {
+        let mut frames = vec![false, true, false, true, true, false, false];
+        fill_short_runs(&mut frames, false, 2);
+        assert_eq!(frames, vec![false, true, true, true, true, false, false]);
+    }
+
+    #[test]
+    fn detect_speech_frames_separates_loud_frames_from_noise_floor() {
+        let mut energies = vec![-70.0; 100];
+        energies[40..60].fill(-20.0);
+
+        let frames = detect_speech_frames(&energies);
+
+        assert!(frames[40..60].iter().all(|frame| *frame));
+        assert!(frames[..40].iter().all(|frame| !*frame));
+        assert!(frames[60..].iter().all(|frame| !*frame));
+    }
+
+    #[test]
+    fn detect_speech_frames_returns_nothing_for_flat_signal() {
+        let frames = detect_speech_frames(&[-40.0; 50]);
+        assert!(frames.iter().all(|frame| !*frame));
+    }
+
+    #[test]
+    fn find_best_alignment_recovers_constant_offset() {
+        let speech =
+            SpeechTimeline::from_frames(&speech_from_regions(35_000, &irregular_regions()));
+        let cues = irregular_regions()
+            .iter()
+            .map(|(start, end)| cue(start - 1_370, end - 1_370))
+            .collect::<Vec<_>>();
+
+        let alignment = find_best_alignment(&cues, &speech, 5_000, false);
+
+        assert_eq!(alignment.offset_ms, 1_370);
+        assert_eq!(alignment.scale, 1.0);
+        assert!(alignment.score > 0.95);
+        assert!(score_alignment(&cues, &speech, 0, 1.0) < alignment.score);
+    }
+
+    #[test]
+    fn find_best_alignment_recovers_frame_rate_scale_and_offset() {
+        let scale = 25.0 / 23.976;
+        let speech =
+            SpeechTimeline::from_frames(&speech_from_regions(35_000, &irregular_regions()));
+        let cues = irregular_regions()
+            .iter()
+            .map(|(start, end)| {
+                cue(
+                    ((*start as f64 - 500.0) / scale).round() as i64,
+                    ((*end as f64 - 500.0) / scale).round() as i64,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let alignment = find_best_alignment(&cues, &speech, 5_000, true);
+
+        assert!((alignment.scale - scale).abs() < 1e-9);
+        assert!((alignment.offset_ms - 500).abs() <= SPEECH_FRAME_MS);
+        assert!(alignment.score > 0.95);
+    }
+
+    #[tokio::test]
+    async fn sync_subtitle_timing_shifts_cues_onto_tone_bursts() {
+        let temp = tempfile::tempdir().expect("failed to create tempdir");
+        let audio = temp.path().join("bursts.wav");
+        let subtitle = temp.path().join("late.srt");
+        let output = temp.path().join("synced.srt");
+
+        let status = Command::new(crate::test_support::ffmpeg::ffmpeg_path())
+            .args([
+                "-hide_banner",
+                "-loglevel",
+                "error",
+                "-y",
+                "-f",
+                "lavfi",
+                "-i",
+                "aevalsrc='if(between(t,2,3.2)+between(t,5.1,7)+between(t,9.4,10)+between(t,14,16.5),0.5*sin(2*PI*440*t),0)':s=8000:d=20",
+                audio.to_string_lossy().as_ref(),
+            ])
+            .status()
+            .await
+            .expect("ffmpeg should run");
+        assert!(status.success());
+
+        std::fs::write(
+            &subtitle,
+            "1\n00:00:02,800 --> 00:00:04,000\nOne\n\n2\n00:00:05,900 --> 00:00:07,800\nTwo\n\n3\n00:00:10,200 --> 00:00:10,800\nThree\n\n4\n00:00:14,800 --> 00:00:17,300\nFour\n",
+        )
+        .expect("failed to write subtitle");
+
+        let result = sync_subtitle_timing_with_ffmpeg(
+            crate::test_support::ffmpeg::ffmpeg_path(),
+            &SubtitleSyncRequest {
+                media_path: audio.to_string_lossy().to_string(),
+                audio_track_index: Some(0),
+                subtitle_path: subtitle.to_string_lossy().to_string(),
+                output_path: Some(output.to_string_lossy().to_string()),
+                max_offset_ms: Some(2_000),
+                allow_scale: false,
+            },
+        )
+        .await
+        .expect("subtitle sync should succeed");
+
+        assert!(
+            (result.offset_ms + 800).abs() <= 50,
+            "expected ~-800ms offset, got {}",
+            result.offset_ms
+        );
+        assert!(result.score > result.original_score);
+        assert_eq!(result.cues.len(), 4);
+        let written = std::fs::read_to_string(&output).expect("synced subtitle should exist");
+        assert!(written.starts_with("1\n00:00:0"));
+    }
+}
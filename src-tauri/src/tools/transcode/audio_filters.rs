@@ -0,0 +1,442 @@
+use serde::Deserialize;
+use serde_json::Value;
+
+/// Filters the typed settings below can emit, reported by the capability probe.
+pub(crate) const KNOWN_AUDIO_FILTERS: &[&str] =
+    &["acompressor", "aresample", "loudnorm", "pan", "volume"];
+
+const MIN_LOUDNORM_TARGET_LUFS: f64 = -70.0;
+const MAX_LOUDNORM_TARGET_LUFS: f64 = -5.0;
+const MAX_GAIN_DB: f64 = 30.0;
+const MIN_GAIN_DB: f64 = -60.0;
+const MIN_RESAMPLE_RATE: u32 = 8000;
+const MAX_RESAMPLE_RATE: u32 = 192000;
+/// Sample rate restored after `loudnorm`, which always outputs 192 kHz.
+const LOUDNORM_FALLBACK_SAMPLE_RATE: u32 = 48000;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TranscodeLoudnormFilter {
+    /// Integrated loudness target in LUFS, e.g. `-23` (EBU R128) or `-16` (streaming)
+    pub(crate) target_lufs: f64,
+    pub(crate) true_peak_db: Option<f64>,
+    pub(crate) loudness_range: Option<f64>,
+    /// Measure the track first and apply a linear correction on the real encode.
+    #[serde(default)]
+    pub(crate) two_pass: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TranscodeDownmixFilter {
+    /// `standard` (ITU-style) or `dialog` (keeps the centre channel prominent)
+    pub(crate) mode: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TranscodeGainFilter {
+    pub(crate) db: f64,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TranscodeResampleFilter {
+    pub(crate) sample_rate: Option<u32>,
+    #[serde(default)]
+    pub(crate) use_soxr: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TranscodeCompressorFilter {
+    /// `light`, `medium` or `strong`
+    pub(crate) strength: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TranscodeAudioFilters {
+    pub(crate) loudnorm: Option<TranscodeLoudnormFilter>,
+    pub(crate) downmix: Option<TranscodeDownmixFilter>,
+    pub(crate) gain: Option<TranscodeGainFilter>,
+    pub(crate) resample: Option<TranscodeResampleFilter>,
+    pub(crate) compressor: Option<TranscodeCompressorFilter>,
+}
+
+impl TranscodeAudioFilters {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.loudnorm.is_none()
+            && self.downmix.is_none()
+            && self.gain.is_none()
+            && self.resample.is_none()
+            && self.compressor.is_none()
+    }
+
+    /// Filter names the chain needs, checked against `ffmpeg -filters`.
+    pub(crate) fn required_filters(&self) -> Vec<&'static str> {
+        let mut required = Vec::new();
+        if self.downmix.is_some() {
+            required.push("pan");
+        }
+        if self.compressor.is_some() {
+            required.push("acompressor");
+        }
+        if self.gain.is_some() {
+            required.push("volume");
+        }
+        if self.loudnorm.is_some() {
+            required.push("loudnorm");
+        }
+        if self.loudnorm.is_some() || self.resample.is_some() {
+            required.push("aresample");
+        }
+        required
+    }
+
+    pub(crate) fn uses_soxr(&self) -> bool {
+        self.resample
+            .as_ref()
+            .is_some_and(|resample| resample.use_soxr)
+    }
+}
+
+/// Values reported by a `loudnorm` measurement run.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct LoudnormMeasurement {
+    pub(crate) input_i: String,
+    pub(crate) input_tp: String,
+    pub(crate) input_lra: String,
+    pub(crate) input_thresh: String,
+    pub(crate) target_offset: String,
+}
+
+/// Source stream properties some filters depend on.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct AudioFilterSource {
+    pub(crate) channels: u64,
+    pub(crate) sample_rate: Option<u32>,
+}
+
+fn build_downmix_filter(
+    downmix: &TranscodeDownmixFilter,
+    source: AudioFilterSource,
+) -> Result<String, String> {
+    if source.channels != 6 {
+        return Err(format!(
+            "Stereo downmix needs a 5.1 source, but the track has {} channels",
+            source.channels
+        ));
+    }
+
+    // Channel indices work for both `5.1` and `5.1(side)`: FL FR FC LFE (B|S)L (B|S)R.
+    match downmix.mode.as_deref().unwrap_or("standard") {
+        "standard" => Ok("pan=stereo|FL<c0+0.707*c2+0.707*c4|FR<c1+0.707*c2+0.707*c5".to_string()),
+        "dialog" => Ok("pan=stereo|FL<c2+0.30*c0+0.30*c4|FR<c2+0.30*c1+0.30*c5".to_string()),
+        other => Err(format!("Unsupported downmix mode: {}", other)),
+    }
+}
+
+fn build_compressor_filter(compressor: &TranscodeCompressorFilter) -> Result<String, String> {
+    match compressor.strength.as_deref().unwrap_or("medium") {
+        "light" => Ok("acompressor=threshold=-18dB:ratio=2:attack=20:release=250".to_string()),
+        "medium" => Ok("acompressor=threshold=-24dB:ratio=3:attack=10:release=200".to_string()),
+        "strong" => Ok("acompressor=threshold=-30dB:ratio=6:attack=5:release=150".to_string()),
+        other => Err(format!("Unsupported compressor strength: {}", other)),
+    }
+}
+
+fn build_gain_filter(gain: &TranscodeGainFilter) -> Result<String, String> {
+    if !gain.db.is_finite() || gain.db < MIN_GAIN_DB || gain.db > MAX_GAIN_DB {
+        return Err(format!(
+            "Gain must be between {} and {} dB",
+            MIN_GAIN_DB, MAX_GAIN_DB
+        ));
+    }
+
+    Ok(format!("volume={}dB", gain.db))
+}
+
+fn build_loudnorm_targets(loudnorm: &TranscodeLoudnormFilter) -> Result<String, String> {
+    if !loudnorm.target_lufs.is_finite()
+        || loudnorm.target_lufs < MIN_LOUDNORM_TARGET_LUFS
+        || loudnorm.target_lufs > MAX_LOUDNORM_TARGET_LUFS
+    {
+        return Err(format!(
+            "Loudness target must be between {} and {} LUFS",
+            MIN_LOUDNORM_TARGET_LUFS, MAX_LOUDNORM_TARGET_LUFS
+        ));
+    }
+    let true_peak_db = loudnorm.true_peak_db.unwrap_or(-1.0);
+    if !true_peak_db.is_finite() || !(-9.0..=0.0).contains(&true_peak_db) {
+        return Err("True peak must be between -9 and 0 dBTP".to_string());
+    }
+    let loudness_range = loudnorm.loudness_range.unwrap_or(11.0);
+    if !loudness_range.is_finite() || !(1.0..=50.0).contains(&loudness_range) {
+        return Err("Loudness range must be between 1 and 50 LU".to_string());
+    }
+
+    Ok(format!(
+        "I={}:TP={}:LRA={}",
+        loudnorm.target_lufs, true_peak_db, loudness_range
+    ))
+}
+
+fn build_resample_filter(resample: &TranscodeResampleFilter) -> Result<String, String> {
+    let mut options = Vec::new();
+    if let Some(sample_rate) = resample.sample_rate {
+        if !(MIN_RESAMPLE_RATE..=MAX_RESAMPLE_RATE).contains(&sample_rate) {
+            return Err(format!(
+                "Resample rate must be between {} and {} Hz",
+                MIN_RESAMPLE_RATE, MAX_RESAMPLE_RATE
+            ));
+        }
+        options.push(sample_rate.to_string());
+    }
+    if resample.use_soxr {
+        options.push("resampler=soxr".to_string());
+    }
+    if options.is_empty() {
+        return Err("Resample needs a sample rate or the SoX resampler".to_string());
+    }
+
+    Ok(format!("aresample={}", options.join(":")))
+}
+
+/// Filters that run ahead of `loudnorm`; the measurement pass has to see the same signal.
+fn build_pre_loudnorm_chain(
+    filters: &TranscodeAudioFilters,
+    source: AudioFilterSource,
+) -> Result<Vec<String>, String> {
+    let mut chain = Vec::new();
+    if let Some(downmix) = filters.downmix.as_ref() {
+        chain.push(build_downmix_filter(downmix, source)?);
+    }
+    if let Some(compressor) = filters.compressor.as_ref() {
+        chain.push(build_compressor_filter(compressor)?);
+    }
+    if let Some(gain) = filters.gain.as_ref() {
+        chain.push(build_gain_filter(gain)?);
+    }
+    Ok(chain)
+}
+
+/// Build the `-af` chain for a loudnorm measurement run, or `None` when the
+/// track doesn't use two-pass loudness normalisation.
+pub(crate) fn build_loudnorm_measure_graph(
+    filters: &TranscodeAudioFilters,
+    source: AudioFilterSource,
+) -> Result<Option<String>, String> {
+    let Some(loudnorm) = filters
+        .loudnorm
+        .as_ref()
+        .filter(|loudnorm| loudnorm.two_pass)
+    else {
+        return Ok(None);
+    };
+
+    let mut chain = build_pre_loudnorm_chain(filters, source)?;
+    chain.push(format!(
+        "loudnorm={}:print_format=json",
+        build_loudnorm_targets(loudnorm)?
+    ));
+    Ok(Some(chain.join(",")))
+}
+
+/// Build a per-track `-af` chain from the typed filter settings.
+///
+/// Filters run in a fixed order: downmix, compressor, gain, loudnorm, resample.
+/// Two-pass loudnorm falls back to a single dynamic pass without a measurement.
+pub(crate) fn build_audio_filter_graph(
+    filters: &TranscodeAudioFilters,
+    source: AudioFilterSource,
+    measurement: Option<&LoudnormMeasurement>,
+) -> Result<Option<String>, String> {
+    let mut chain = build_pre_loudnorm_chain(filters, source)?;
+
+    if let Some(loudnorm) = filters.loudnorm.as_ref() {
+        let targets = build_loudnorm_targets(loudnorm)?;
+        chain.push(match measurement.filter(|_| loudnorm.two_pass) {
+            Some(measurement) => format!(
+                "loudnorm={}:measured_I={}:measured_TP={}:measured_LRA={}:measured_thresh={}:offset={}:linear=true",
+                targets,
+                measurement.input_i,
+                measurement.input_tp,
+                measurement.input_lra,
+                measurement.input_thresh,
+                measurement.target_offset
+            ),
+            None => format!("loudnorm={}", targets),
+        });
+    }
+
+    match filters.resample.as_ref() {
+        Some(resample) => chain.push(build_resample_filter(resample)?),
+        None if filters.loudnorm.is_some() => chain.push(format!(
+            "aresample={}",
+            source.sample_rate.unwrap_or(LOUDNORM_FALLBACK_SAMPLE_RATE)
+        )),
+        None => {}
+    }
+
+    Ok((!chain.is_empty()).then(|| chain.join(",")))
+}
+
+/// Pull the JSON block `loudnorm` prints at the end of stderr.
+pub(crate) fn parse_loudnorm_measurement(stderr: &str) -> Result<LoudnormMeasurement, String> {
+    let error = || "Loudness measurement did not report any values".to_string();
+    let start = stderr.rfind('{').ok_or_else(error)?;
+    let end = stderr[start..].find('}').ok_or_else(error)? + start;
+    let value: Value = serde_json::from_str(&stderr[start..=end]).map_err(|_| error())?;
+
+    let field = |key: &str| {
+        value
+            .get(key)
+            .and_then(|value| value.as_str())
+            .map(|value| value.trim().to_string())
+            .filter(|value| value.parse::<f64>().is_ok_and(f64::is_finite))
+            .ok_or_else(|| format!("Loudness measurement is missing a valid {}", key))
+    };
+
+    Ok(LoudnormMeasurement {
+        input_i: field("input_i")?,
+        input_tp: field("input_tp")?,
+        input_lra: field("input_lra")?,
+        input_thresh: field("input_thresh")?,
+        target_offset: field("target_offset")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        AudioFilterSource, LoudnormMeasurement, TranscodeAudioFilters, TranscodeCompressorFilter,
+        TranscodeDownmixFilter, TranscodeGainFilter, TranscodeLoudnormFilter,
+        TranscodeResampleFilter, build_audio_filter_graph, build_loudnorm_measure_graph,
+        parse_loudnorm_measurement,
+    };
+
+    const SURROUND: AudioFilterSource = AudioFilterSource {
+        channels: 6,
+        sample_rate: Some(44100),
+    };
+
+    fn loudnorm(two_pass: bool) -> TranscodeLoudnormFilter {
+        TranscodeLoudnormFilter {
+            target_lufs: -16.0,
+            true_peak_db: Some(-1.5),
+            loudness_range: None,
+            two_pass,
+        }
+    }
+
+    #[test]
+    fn audio_filter_graph_orders_filters_and_restores_sample_rate_after_loudnorm() {
+        let filters = TranscodeAudioFilters {
+            loudnorm: Some(loudnorm(false)),
+            downmix: Some(TranscodeDownmixFilter {
+                mode: Some("dialog".to_string()),
+            }),
+            gain: Some(TranscodeGainFilter { db: -3.0 }),
+            resample: None,
+            compressor: Some(TranscodeCompressorFilter {
+                strength: Some("light".to_string()),
+            }),
+        };
+
+        assert_eq!(
+            build_audio_filter_graph(&filters, SURROUND, None).expect("graph should build"),
+            Some(
+                "pan=stereo|FL<c2+0.30*c0+0.30*c4|FR<c2+0.30*c1+0.30*c5,acompressor=threshold=-18dB:ratio=2:attack=20:release=250,volume=-3dB,loudnorm=I=-16:TP=-1.5:LRA=11,aresample=44100"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            filters.required_filters(),
+            vec!["pan", "acompressor", "volume", "loudnorm", "aresample"]
+        );
+    }
+
+    #[test]
+    fn two_pass_loudnorm_uses_the_measurement_and_soxr_resampling() {
+        let filters = TranscodeAudioFilters {
+            loudnorm: Some(loudnorm(true)),
+            resample: Some(TranscodeResampleFilter {
+                sample_rate: Some(48000),
+                use_soxr: true,
+            }),
+            ..TranscodeAudioFilters::default()
+        };
+        let measurement = parse_loudnorm_measurement(
+            r#"[Parsed_loudnorm_0 @ 0x1]
+{
+	"input_i" : "-27.61",
+	"input_tp" : "-4.47",
+	"input_lra" : "18.06",
+	"input_thresh" : "-39.20",
+	"output_i" : "-16.58",
+	"target_offset" : "0.58"
+}"#,
+        )
+        .expect("measurement should parse");
+
+        assert_eq!(
+            measurement,
+            LoudnormMeasurement {
+                input_i: "-27.61".to_string(),
+                input_tp: "-4.47".to_string(),
+                input_lra: "18.06".to_string(),
+                input_thresh: "-39.20".to_string(),
+                target_offset: "0.58".to_string(),
+            }
+        );
+        assert_eq!(
+            build_loudnorm_measure_graph(&filters, SURROUND).expect("graph should build"),
+            Some("loudnorm=I=-16:TP=-1.5:LRA=11:print_format=json".to_string())
+        );
+        assert_eq!(
+            build_audio_filter_graph(&filters, SURROUND, Some(&measurement))
+                .expect("graph should build"),
+            Some(
+                "loudnorm=I=-16:TP=-1.5:LRA=11:measured_I=-27.61:measured_TP=-4.47:measured_LRA=18.06:measured_thresh=-39.20:offset=0.58:linear=true,aresample=48000:resampler=soxr"
+                    .to_string()
+            )
+        );
+        assert!(filters.uses_soxr());
+        assert!(parse_loudnorm_measurement("no json here").is_err());
+    }
+
+    #[test]
+    fn audio_filter_graph_rejects_invalid_settings() {
+        let stereo = AudioFilterSource {
+            channels: 2,
+            sample_rate: None,
+        };
+        let downmix = TranscodeAudioFilters {
+            downmix: Some(TranscodeDownmixFilter::default()),
+            ..TranscodeAudioFilters::default()
+        };
+        assert!(build_audio_filter_graph(&downmix, stereo, None).is_err());
+
+        let gain = TranscodeAudioFilters {
+            gain: Some(TranscodeGainFilter { db: 45.0 }),
+            ..TranscodeAudioFilters::default()
+        };
+        assert!(build_audio_filter_graph(&gain, stereo, None).is_err());
+
+        let target = TranscodeAudioFilters {
+            loudnorm: Some(TranscodeLoudnormFilter {
+                target_lufs: 0.0,
+                ..loudnorm(false)
+            }),
+            ..TranscodeAudioFilters::default()
+        };
+        assert!(build_audio_filter_graph(&target, stereo, None).is_err());
+
+        assert_eq!(
+            build_audio_filter_graph(&TranscodeAudioFilters::default(), stereo, None)
+                .expect("empty filters should build"),
+            None
+        );
+    }
+}
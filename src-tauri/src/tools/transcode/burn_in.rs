@@ -0,0 +1,361 @@
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::process::Command;
+
+use crate::shared::hash::stable_hash64;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TranscodeSubtitleBurnIn {
+    /// Source subtitle stream index; ignored when `external_path` is set.
+    pub(crate) track_id: Option<usize>,
+    /// SRT or ASS file drawn instead of an internal stream.
+    pub(crate) external_path: Option<String>,
+    /// Only draw captions flagged as forced (PGS and VobSub only).
+    #[serde(default)]
+    pub(crate) forced_only: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum SubtitleBurnInPlan {
+    /// Rendered by libass at the start of the `-vf` chain.
+    Text(String),
+    /// Composited with `overlay`, which needs the subtitle stream as a second filter input.
+    Bitmap {
+        subtitle_relative_index: usize,
+        forced_only: bool,
+    },
+}
+
+fn is_bitmap_subtitle_codec(codec: &str) -> bool {
+    matches!(
+        codec,
+        "dvb_subtitle" | "dvd_subtitle" | "hdmv_pgs_subtitle" | "xsub"
+    )
+}
+
+fn is_font_attachment(stream: &Value) -> bool {
+    if stream.get("codec_type").and_then(|value| value.as_str()) != Some("attachment") {
+        return false;
+    }
+
+    let tags = stream.get("tags");
+    let mimetype = tags
+        .and_then(|tags| tags.get("mimetype"))
+        .and_then(|value| value.as_str())
+        .unwrap_or_default()
+        .to_lowercase();
+    let filename = tags
+        .and_then(|tags| tags.get("filename"))
+        .and_then(|value| value.as_str())
+        .unwrap_or_default()
+        .to_lowercase();
+
+    mimetype.contains("font")
+        || [".ttf", ".otf", ".ttc"]
+            .iter()
+            .any(|extension| filename.ends_with(extension))
+}
+
+/// Escape a path for a filter option value and quote it for the filtergraph parser.
+pub(crate) fn escape_filter_path(path: &str) -> String {
+    let option_value = path
+        .replace('\\', "\\\\")
+        .replace('\'', "\\'")
+        .replace(':', "\\:");
+    format!("'{}'", option_value.replace('\'', "'\\''"))
+}
+
+pub(crate) fn build_burn_in_fonts_dir(input_path: &str, output_path: &str) -> PathBuf {
+    std::env::temp_dir()
+        .join("mediaflow_transcode_fonts")
+        .join(format!(
+            "{:016x}",
+            stable_hash64(&format!("{}\n{}", input_path, output_path))
+        ))
+}
+
+/// External subtitles don't see the source's attachments, so their fonts are
+/// dumped to a temp dir that libass is pointed at.
+pub(crate) fn needs_extracted_fonts(burn_in: &TranscodeSubtitleBurnIn, streams: &[Value]) -> bool {
+    burn_in.external_path.is_some() && streams.iter().any(is_font_attachment)
+}
+
+pub(crate) async fn extract_font_attachments(
+    ffmpeg_path: &str,
+    input_path: &str,
+    fonts_dir: &Path,
+) -> Result<(), String> {
+    std::fs::create_dir_all(fonts_dir)
+        .map_err(|error| format!("Failed to create fonts directory: {}", error))?;
+
+    // `-dump_attachment` writes into the working directory using each attachment's filename.
+    let output = Command::new(ffmpeg_path)
+        .args([
+            "-hide_banner",
+            "-y",
+            "-dump_attachment:t",
+            "",
+            "-i",
+            input_path,
+            "-t",
+            "0",
+            "-f",
+            "null",
+            "-",
+        ])
+        .current_dir(fonts_dir)
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()
+        .await
+        .map_err(|error| format!("Failed to start ffmpeg: {}", error))?;
+
+    let extracted_any = std::fs::read_dir(fonts_dir)
+        .map(|mut entries| entries.next().is_some())
+        .unwrap_or(false);
+    if !output.status.success() && !extracted_any {
+        return Err(format!(
+            "Failed to extract attached fonts: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Resolve the burn-in source into a filter. `start_offset_ms` shifts text
+/// subtitles back in line when the input is seeked; `fonts_dir` is only used
+/// for external files.
+pub(crate) fn plan_subtitle_burn_in(
+    burn_in: &TranscodeSubtitleBurnIn,
+    input_path: &str,
+    streams: &[Value],
+    start_offset_ms: u64,
+    fonts_dir: Option<&Path>,
+) -> Result<SubtitleBurnInPlan, String> {
+    let text_filter = if let Some(external_path) = burn_in.external_path.as_deref() {
+        if burn_in.forced_only {
+            return Err(
+                "Forced-only burn-in is only available for PGS and VobSub subtitles".to_string(),
+            );
+        }
+
+        let extension = Path::new(external_path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default()
+            .to_lowercase();
+        let filter_name = match extension.as_str() {
+            "ass" | "ssa" => "ass",
+            "srt" => "subtitles",
+            _ => {
+                return Err("External burn-in subtitles must be an SRT or ASS file".to_string());
+            }
+        };
+
+        let mut filter = format!(
+            "{}=filename={}",
+            filter_name,
+            escape_filter_path(external_path)
+        );
+        if let Some(fonts_dir) = fonts_dir {
+            filter.push_str(&format!(
+                ":fontsdir={}",
+                escape_filter_path(&fonts_dir.to_string_lossy())
+            ));
+        }
+        filter
+    } else {
+        let Some(track_id) = burn_in.track_id else {
+            return Err("Choose a subtitle track or file to burn in".to_string());
+        };
+        let (relative_index, codec_name) = streams
+            .iter()
+            .filter(|stream| {
+                stream.get("codec_type").and_then(|value| value.as_str()) == Some("subtitle")
+            })
+            .enumerate()
+            .find(|(_, stream)| {
+                stream.get("index").and_then(|value| value.as_u64()) == Some(track_id as u64)
+            })
+            .map(|(relative_index, stream)| {
+                (
+                    relative_index,
+                    stream
+                        .get("codec_name")
+                        .and_then(|value| value.as_str())
+                        .unwrap_or_default(),
+                )
+            })
+            .ok_or_else(|| format!("Subtitle track {} was not found in the input", track_id))?;
+
+        if is_bitmap_subtitle_codec(codec_name) {
+            return Ok(SubtitleBurnInPlan::Bitmap {
+                subtitle_relative_index: relative_index,
+                forced_only: burn_in.forced_only,
+            });
+        }
+        if burn_in.forced_only {
+            return Err(
+                "Forced-only burn-in is only available for PGS and VobSub subtitles".to_string(),
+            );
+        }
+
+        // Reading the source file lets libass pick up its attached fonts.
+        format!(
+            "subtitles=filename={}:si={}",
+            escape_filter_path(input_path),
+            relative_index
+        )
+    };
+
+    if start_offset_ms == 0 {
+        return Ok(SubtitleBurnInPlan::Text(text_filter));
+    }
+
+    let offset_seconds = format!("{}.{:03}", start_offset_ms / 1000, start_offset_ms % 1000);
+    Ok(SubtitleBurnInPlan::Text(format!(
+        "setpts=PTS+{}/TB,{},setpts=PTS-STARTPTS",
+        offset_seconds, text_filter
+    )))
+}
+
+/// `overlay` graph for bitmap subtitles, followed by the regular video filters.
+pub(crate) fn build_bitmap_burn_in_filter(
+    video_relative_index: usize,
+    subtitle_relative_index: usize,
+    video_filter_graph: Option<&str>,
+) -> String {
+    let mut filter = format!(
+        "[0:v:{}][0:s:{}]overlay",
+        video_relative_index, subtitle_relative_index
+    );
+    if let Some(video_filter_graph) = video_filter_graph {
+        filter.push(',');
+        filter.push_str(video_filter_graph);
+    }
+    filter.push_str("[vburn]");
+    filter
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use serde_json::json;
+
+    use super::{
+        SubtitleBurnInPlan, TranscodeSubtitleBurnIn, build_bitmap_burn_in_filter,
+        escape_filter_path, needs_extracted_fonts, plan_subtitle_burn_in,
+    };
+
+    fn streams() -> Vec<serde_json::Value> {
+        vec![
+            json!({ "index": 0, "codec_type": "video", "codec_name": "h264" }),
+            json!({ "index": 1, "codec_type": "subtitle", "codec_name": "ass" }),
+            json!({ "index": 2, "codec_type": "subtitle", "codec_name": "hdmv_pgs_subtitle" }),
+            json!({
+                "index": 3,
+                "codec_type": "attachment",
+                "codec_name": "ttf",
+                "tags": { "filename": "Font.ttf", "mimetype": "application/x-truetype-font" }
+            }),
+        ]
+    }
+
+    #[test]
+    fn internal_text_subtitles_use_the_subtitles_filter_on_the_source() {
+        let burn_in = TranscodeSubtitleBurnIn {
+            track_id: Some(1),
+            ..TranscodeSubtitleBurnIn::default()
+        };
+
+        let plan = plan_subtitle_burn_in(&burn_in, "/media/show.mkv", &streams(), 0, None)
+            .expect("plan should build");
+        assert_eq!(
+            plan,
+            SubtitleBurnInPlan::Text("subtitles=filename='/media/show.mkv':si=0".to_string())
+        );
+
+        let plan = plan_subtitle_burn_in(&burn_in, "/media/show.mkv", &streams(), 90_500, None)
+            .expect("plan should build");
+        assert_eq!(
+            plan,
+            SubtitleBurnInPlan::Text(
+                "setpts=PTS+90.500/TB,subtitles=filename='/media/show.mkv':si=0,setpts=PTS-STARTPTS"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn bitmap_subtitles_use_overlay_and_allow_forced_only() {
+        let burn_in = TranscodeSubtitleBurnIn {
+            track_id: Some(2),
+            forced_only: true,
+            ..TranscodeSubtitleBurnIn::default()
+        };
+
+        let plan = plan_subtitle_burn_in(&burn_in, "/media/show.mkv", &streams(), 0, None)
+            .expect("plan should build");
+        assert_eq!(
+            plan,
+            SubtitleBurnInPlan::Bitmap {
+                subtitle_relative_index: 1,
+                forced_only: true
+            }
+        );
+        assert_eq!(
+            build_bitmap_burn_in_filter(0, 1, Some("scale=-2:720")),
+            "[0:v:0][0:s:1]overlay,scale=-2:720[vburn]"
+        );
+
+        let text_forced = TranscodeSubtitleBurnIn {
+            track_id: Some(1),
+            forced_only: true,
+            ..TranscodeSubtitleBurnIn::default()
+        };
+        assert!(
+            plan_subtitle_burn_in(&text_forced, "/media/show.mkv", &streams(), 0, None).is_err()
+        );
+    }
+
+    #[test]
+    fn external_files_pick_the_filter_by_extension_and_use_extracted_fonts() {
+        let burn_in = TranscodeSubtitleBurnIn {
+            external_path: Some("C:\\Subs\\it's.ass".to_string()),
+            ..TranscodeSubtitleBurnIn::default()
+        };
+
+        assert!(needs_extracted_fonts(&burn_in, &streams()));
+        let plan = plan_subtitle_burn_in(
+            &burn_in,
+            "/media/show.mkv",
+            &streams(),
+            0,
+            Some(Path::new("/tmp/fonts")),
+        )
+        .expect("plan should build");
+        assert_eq!(
+            plan,
+            SubtitleBurnInPlan::Text(format!(
+                "ass=filename={}:fontsdir='/tmp/fonts'",
+                escape_filter_path("C:\\Subs\\it's.ass")
+            ))
+        );
+        assert_eq!(
+            escape_filter_path("C:\\Subs\\it's.ass"),
+            "'C\\:\\\\Subs\\\\it\\'\\''s.ass'"
+        );
+
+        let vtt = TranscodeSubtitleBurnIn {
+            external_path: Some("/subs/show.vtt".to_string()),
+            ..TranscodeSubtitleBurnIn::default()
+        };
+        assert!(plan_subtitle_burn_in(&vtt, "/media/show.mkv", &streams(), 0, None).is_err());
+    }
+}
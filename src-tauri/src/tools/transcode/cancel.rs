@@ -1,9 +1,15 @@
 use crate::shared::process::force_terminate_process;
 
+use super::chunked::terminate_chunk_processes;
+
 fn remove_output_file(path: &str) {
     let _ = std::fs::remove_file(path);
 }
 
+fn remove_passlog_dir(path: &str) {
+    let _ = std::fs::remove_dir_all(path);
+}
+
 /// Cancel a specific transcode by input path.
 #[tauri::command]
 pub(crate) async fn cancel_transcode_file(input_path: String) -> Result<(), String> {
@@ -21,14 +27,26 @@ pub(crate) async fn cancel_transcode_file(input_path: String) -> Result<(), Stri
         }
     };
 
+    let passlog_dir = {
+        match super::state::TRANSCODE_PASSLOG_DIRS.lock() {
+            Ok(mut guard) => guard.remove(&input_path),
+            Err(_) => None,
+        }
+    };
+
     if let Some(pid) = pid {
         force_terminate_process(pid);
     }
+    terminate_chunk_processes(&input_path);
 
     if let Some(path) = output_path {
         remove_output_file(&path);
     }
 
+    if let Some(path) = passlog_dir {
+        remove_passlog_dir(&path);
+    }
+
     Ok(())
 }
 
@@ -57,7 +75,25 @@ pub(crate) async fn cancel_transcode() -> Result<(), String> {
         }
     };
 
-    for pid in pids {
+    let passlog_dirs: Vec<String> = {
+        match super::state::TRANSCODE_PASSLOG_DIRS.lock() {
+            Ok(mut guard) => {
+                let paths: Vec<String> = guard.values().cloned().collect();
+                guard.clear();
+                paths
+            }
+            Err(_) => Vec::new(),
+        }
+    };
+
+    let chunk_pids: Vec<u32> = {
+        match super::state::TRANSCODE_CHUNK_PROCESS_IDS.lock() {
+            Ok(mut guard) => guard.drain().flat_map(|(_, pids)| pids).collect(),
+            Err(_) => Vec::new(),
+        }
+    };
+
+    for pid in pids.into_iter().chain(chunk_pids) {
         force_terminate_process(pid);
     }
 
@@ -65,6 +101,27 @@ pub(crate) async fn cancel_transcode() -> Result<(), String> {
         remove_output_file(&path);
     }
 
+    for path in passlog_dirs {
+        remove_passlog_dir(&path);
+    }
+
+    Ok(())
+}
+
+/// Cancel a quality comparison by distorted path.
+#[tauri::command]
+pub(crate) async fn cancel_quality_comparison(distorted_path: String) -> Result<(), String> {
+    let pid = {
+        match super::state::QUALITY_PROCESS_IDS.lock() {
+            Ok(mut guard) => guard.remove(&distorted_path),
+            Err(_) => return Err("Failed to acquire process lock".to_string()),
+        }
+    };
+
+    if let Some(pid) = pid {
+        force_terminate_process(pid);
+    }
+
     Ok(())
 }
 
@@ -80,6 +137,10 @@ mod tests {
         let temp = tempfile::tempdir().expect("failed to create tempdir");
         let output = temp.path().join("transcode-out.mp4");
         std::fs::write(&output, b"partial").expect("failed to create output file");
+        let passlog_dir = temp.path().join("passlog");
+        std::fs::create_dir_all(&passlog_dir).expect("failed to create passlog dir");
+        std::fs::write(passlog_dir.join("ffmpeg2pass-0.log"), b"stats")
+            .expect("failed to create passlog file");
         let input = "/tmp/test-video-transcode-file.mkv".to_string();
 
         {
@@ -94,12 +155,19 @@ mod tests {
                 .expect("failed to lock outputs");
             outputs.insert(input.clone(), output.to_string_lossy().to_string());
         }
+        {
+            let mut passlog_dirs = super::super::state::TRANSCODE_PASSLOG_DIRS
+                .lock()
+                .expect("failed to lock passlog dirs");
+            passlog_dirs.insert(input.clone(), passlog_dir.to_string_lossy().to_string());
+        }
 
         cancel_transcode_file(input.clone())
             .await
             .expect("cancel transcode should succeed");
 
         assert!(!output.exists());
+        assert!(!passlog_dir.exists());
         assert!(
             !super::super::state::TRANSCODE_PROCESS_IDS
                 .lock()
@@ -112,6 +180,12 @@ mod tests {
                 .expect("failed to lock outputs")
                 .contains_key(&input)
         );
+        assert!(
+            !super::super::state::TRANSCODE_PASSLOG_DIRS
+                .lock()
+                .expect("failed to lock passlog dirs")
+                .contains_key(&input)
+        );
     }
 
     #[tokio::test]
@@ -6,6 +6,9 @@ use tokio::process::Command;
 use crate::shared::store::resolve_ffmpeg_path;
 use crate::tools::media_metadata::{ContainerMetadataSchema, metadata_schema_for_container};
 
+use super::audio_filters::{KNOWN_AUDIO_FILTERS, TranscodeAudioFilters};
+use super::filters::{KNOWN_VIDEO_FILTERS, TranscodeVideoFilters};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub(crate) struct TranscodeCapabilities {
@@ -15,6 +18,10 @@ pub(crate) struct TranscodeCapabilities {
     pub(crate) video_encoders: Vec<TranscodeVideoEncoderCapability>,
     pub(crate) audio_encoders: Vec<TranscodeAudioEncoderCapability>,
     pub(crate) subtitle_encoders: Vec<TranscodeSubtitleEncoderCapability>,
+    pub(crate) supported_video_filters: Vec<String>,
+    pub(crate) supported_audio_filters: Vec<String>,
+    /// Whether `aresample` can use the SoX resampler (`--enable-libsoxr`).
+    pub(crate) supports_soxr: bool,
     pub(crate) default_analysis_frame_count: usize,
 }
 
@@ -142,6 +149,15 @@ struct KnownContainer {
     default_video_encoder_priority: &'static [&'static str],
     default_audio_encoder_priority: &'static [&'static str],
     default_subtitle_encoder_priority: &'static [&'static str],
+    fallback_encoders: ContainerFallbackEncoders,
+}
+
+/// Encoders the `auto` mode uses for streams that can't be copied into a container.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct ContainerFallbackEncoders {
+    pub(crate) video: Option<&'static str>,
+    pub(crate) audio: Option<&'static str>,
+    pub(crate) subtitle: Option<&'static str>,
 }
 
 const DEFAULT_ANALYSIS_FRAME_COUNT: usize = 6;
@@ -152,28 +168,28 @@ const KNOWN_VIDEO_ENCODERS: &[KnownVideoEncoder] = &[
         codec: "h264",
         label: "H.264 (VideoToolbox)",
         is_hardware: true,
-        supported_container_ids: &["mp4", "mkv", "mov"],
+        supported_container_ids: &["mp4", "mkv", "mov", "hls", "dash"],
     },
     KnownVideoEncoder {
         id: "hevc_videotoolbox",
         codec: "hevc",
         label: "HEVC (VideoToolbox)",
         is_hardware: true,
-        supported_container_ids: &["mp4", "mkv", "mov"],
+        supported_container_ids: &["mp4", "mkv", "mov", "hls", "dash"],
     },
     KnownVideoEncoder {
         id: "libx264",
         codec: "h264",
         label: "H.264 (libx264)",
         is_hardware: false,
-        supported_container_ids: &["mp4", "mkv", "mov"],
+        supported_container_ids: &["mp4", "mkv", "mov", "hls", "dash"],
     },
     KnownVideoEncoder {
         id: "libx265",
         codec: "hevc",
         label: "HEVC (libx265)",
         is_hardware: false,
-        supported_container_ids: &["mp4", "mkv", "mov"],
+        supported_container_ids: &["mp4", "mkv", "mov", "hls", "dash"],
     },
     KnownVideoEncoder {
         id: "libsvtav1",
@@ -224,13 +240,13 @@ const KNOWN_AUDIO_ENCODERS: &[KnownAudioEncoder] = &[
         id: "aac",
         codec: "aac",
         label: "AAC",
-        supported_container_ids: &["mp4", "mkv", "mov", "aac"],
+        supported_container_ids: &["mp4", "mkv", "mov", "aac", "hls", "dash"],
     },
     KnownAudioEncoder {
         id: "aac_at",
         codec: "aac",
         label: "AAC (AudioToolbox)",
-        supported_container_ids: &["mp4", "mkv", "mov", "aac"],
+        supported_container_ids: &["mp4", "mkv", "mov", "aac", "hls", "dash"],
     },
     KnownAudioEncoder {
         id: "libopus",
@@ -310,6 +326,11 @@ const KNOWN_CONTAINERS: &[KnownContainer] = &[
         ],
         default_audio_encoder_priority: &["aac_at", "aac"],
         default_subtitle_encoder_priority: &["mov_text"],
+        fallback_encoders: ContainerFallbackEncoders {
+            video: Some("libx264"),
+            audio: Some("aac"),
+            subtitle: Some("mov_text"),
+        },
     },
     KnownContainer {
         id: "mkv",
@@ -336,6 +357,11 @@ const KNOWN_CONTAINERS: &[KnownContainer] = &[
             "libmp3lame",
         ],
         default_subtitle_encoder_priority: &["srt", "ass"],
+        fallback_encoders: ContainerFallbackEncoders {
+            video: Some("libx264"),
+            audio: Some("aac"),
+            subtitle: Some("srt"),
+        },
     },
     KnownContainer {
         id: "mov",
@@ -353,6 +379,11 @@ const KNOWN_CONTAINERS: &[KnownContainer] = &[
         ],
         default_audio_encoder_priority: &["aac_at", "aac"],
         default_subtitle_encoder_priority: &["mov_text"],
+        fallback_encoders: ContainerFallbackEncoders {
+            video: Some("libx264"),
+            audio: Some("aac"),
+            subtitle: Some("mov_text"),
+        },
     },
     KnownContainer {
         id: "webm",
@@ -363,6 +394,11 @@ const KNOWN_CONTAINERS: &[KnownContainer] = &[
         default_video_encoder_priority: &["libvpx-vp9", "libsvtav1", "libaom-av1", "libvpx"],
         default_audio_encoder_priority: &["libopus", "libvorbis"],
         default_subtitle_encoder_priority: &["webvtt"],
+        fallback_encoders: ContainerFallbackEncoders {
+            video: Some("libvpx-vp9"),
+            audio: Some("libopus"),
+            subtitle: Some("webvtt"),
+        },
     },
     KnownContainer {
         id: "aac",
@@ -373,6 +409,11 @@ const KNOWN_CONTAINERS: &[KnownContainer] = &[
         default_video_encoder_priority: &[],
         default_audio_encoder_priority: &["aac_at", "aac"],
         default_subtitle_encoder_priority: &[],
+        fallback_encoders: ContainerFallbackEncoders {
+            video: None,
+            audio: Some("aac"),
+            subtitle: None,
+        },
     },
     KnownContainer {
         id: "mp3",
@@ -383,6 +424,11 @@ const KNOWN_CONTAINERS: &[KnownContainer] = &[
         default_video_encoder_priority: &[],
         default_audio_encoder_priority: &["libmp3lame"],
         default_subtitle_encoder_priority: &[],
+        fallback_encoders: ContainerFallbackEncoders {
+            video: None,
+            audio: Some("libmp3lame"),
+            subtitle: None,
+        },
     },
     KnownContainer {
         id: "flac",
@@ -393,6 +439,11 @@ const KNOWN_CONTAINERS: &[KnownContainer] = &[
         default_video_encoder_priority: &[],
         default_audio_encoder_priority: &["flac"],
         default_subtitle_encoder_priority: &[],
+        fallback_encoders: ContainerFallbackEncoders {
+            video: None,
+            audio: Some("flac"),
+            subtitle: None,
+        },
     },
     KnownContainer {
         id: "opus",
@@ -403,6 +454,11 @@ const KNOWN_CONTAINERS: &[KnownContainer] = &[
         default_video_encoder_priority: &[],
         default_audio_encoder_priority: &["libopus"],
         default_subtitle_encoder_priority: &[],
+        fallback_encoders: ContainerFallbackEncoders {
+            video: None,
+            audio: Some("libopus"),
+            subtitle: None,
+        },
     },
     KnownContainer {
         id: "ogg",
@@ -413,6 +469,11 @@ const KNOWN_CONTAINERS: &[KnownContainer] = &[
         default_video_encoder_priority: &[],
         default_audio_encoder_priority: &["libvorbis", "libopus"],
         default_subtitle_encoder_priority: &[],
+        fallback_encoders: ContainerFallbackEncoders {
+            video: None,
+            audio: Some("libvorbis"),
+            subtitle: None,
+        },
     },
     KnownContainer {
         id: "wav",
@@ -423,6 +484,51 @@ const KNOWN_CONTAINERS: &[KnownContainer] = &[
         default_video_encoder_priority: &[],
         default_audio_encoder_priority: &["pcm_s16le"],
         default_subtitle_encoder_priority: &[],
+        fallback_encoders: ContainerFallbackEncoders {
+            video: None,
+            audio: Some("pcm_s16le"),
+            subtitle: None,
+        },
+    },
+    KnownContainer {
+        id: "hls",
+        label: "HLS",
+        extension: ".m3u8",
+        kind: "stream",
+        muxer_name: "hls",
+        default_video_encoder_priority: &[
+            "h264_videotoolbox",
+            "libx264",
+            "hevc_videotoolbox",
+            "libx265",
+        ],
+        default_audio_encoder_priority: &["aac_at", "aac"],
+        default_subtitle_encoder_priority: &[],
+        fallback_encoders: ContainerFallbackEncoders {
+            video: Some("libx264"),
+            audio: Some("aac"),
+            subtitle: None,
+        },
+    },
+    KnownContainer {
+        id: "dash",
+        label: "DASH",
+        extension: ".mpd",
+        kind: "stream",
+        muxer_name: "dash",
+        default_video_encoder_priority: &[
+            "h264_videotoolbox",
+            "libx264",
+            "hevc_videotoolbox",
+            "libx265",
+        ],
+        default_audio_encoder_priority: &["aac_at", "aac"],
+        default_subtitle_encoder_priority: &[],
+        fallback_encoders: ContainerFallbackEncoders {
+            video: Some("libx264"),
+            audio: Some("aac"),
+            subtitle: None,
+        },
     },
 ];
 
@@ -434,6 +540,47 @@ pub(crate) fn container_extension_for_id(container_id: &str) -> Option<&'static
         .map(|container| container.extension)
 }
 
+/// Codec a known encoder writes, as ffprobe reports it.
+pub(crate) fn codec_for_encoder_id(encoder_id: &str) -> Option<&'static str> {
+    KNOWN_VIDEO_ENCODERS
+        .iter()
+        .find(|encoder| encoder.id == encoder_id)
+        .map(|encoder| encoder.codec)
+        .or_else(|| {
+            KNOWN_AUDIO_ENCODERS
+                .iter()
+                .find(|encoder| encoder.id == encoder_id)
+                .map(|encoder| encoder.codec)
+        })
+        .or_else(|| {
+            KNOWN_SUBTITLE_ENCODERS
+                .iter()
+                .find(|encoder| encoder.id == encoder_id)
+                .map(|encoder| encoder.codec)
+        })
+}
+
+pub(crate) fn fallback_encoders_for_container(container_id: &str) -> ContainerFallbackEncoders {
+    KNOWN_CONTAINERS
+        .iter()
+        .find(|container| container.id == container_id)
+        .map(|container| container.fallback_encoders)
+        .unwrap_or_default()
+}
+
+/// Bitrate for audio that `auto` mode re-encodes without a requested bitrate;
+/// `None` for lossless encoders and encoders picking their own rate.
+pub(crate) fn fallback_audio_bitrate_kbps(encoder_id: &str, channels: u64) -> Option<u32> {
+    let surround = channels > 2;
+    match encoder_id {
+        "aac" | "aac_at" => Some(if surround { 640 } else { 192 }),
+        "libopus" => Some(if surround { 384 } else { 160 }),
+        "libvorbis" => Some(if surround { 448 } else { 192 }),
+        "libmp3lame" => Some(320),
+        _ => None,
+    }
+}
+
 async fn run_ffmpeg_command(ffmpeg_path: &str, args: &[&str]) -> Result<String, String> {
     let output = Command::new(ffmpeg_path)
         .args(args)
@@ -531,6 +678,144 @@ pub(crate) fn parse_ffmpeg_hwaccel_names(output: &str) -> Vec<String> {
         .collect()
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FilterMediaType {
+    Audio,
+    Video,
+}
+
+impl FilterMediaType {
+    /// Letter `ffmpeg -filters` uses for the media type in its `in->out` column.
+    fn io_marker(self) -> char {
+        match self {
+            Self::Audio => 'A',
+            Self::Video => 'V',
+        }
+    }
+}
+
+/// Parse `ffmpeg -filters`, keeping only filters with an input or output of `media_type`.
+pub(crate) fn parse_ffmpeg_filter_names(
+    output: &str,
+    media_type: FilterMediaType,
+) -> HashSet<String> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            let _flags = parts.next()?;
+            let name = parts.next()?;
+            let io = parts.next()?;
+            (io.contains("->") && io.contains(media_type.io_marker())).then(|| name.to_string())
+        })
+        .collect()
+}
+
+/// `ffmpeg -filters` output, run once per ffmpeg binary.
+pub(crate) async fn ffmpeg_filter_output(ffmpeg_path: &str) -> Result<String, String> {
+    if let Ok(guard) = super::state::FFMPEG_FILTER_OUTPUTS.lock()
+        && let Some(output) = guard.get(ffmpeg_path)
+    {
+        return Ok(output.clone());
+    }
+
+    let output = run_ffmpeg_command(ffmpeg_path, &["-hide_banner", "-filters"]).await?;
+    if let Ok(mut guard) = super::state::FFMPEG_FILTER_OUTPUTS.lock() {
+        guard.insert(ffmpeg_path.to_string(), output.clone());
+    }
+    Ok(output)
+}
+
+fn build_supported_video_filters(available_filters: &HashSet<String>) -> Vec<String> {
+    KNOWN_VIDEO_FILTERS
+        .iter()
+        .filter(|filter| available_filters.contains(**filter))
+        .map(|filter| (*filter).to_string())
+        .collect()
+}
+
+fn build_supported_audio_filters(available_filters: &HashSet<String>) -> Vec<String> {
+    KNOWN_AUDIO_FILTERS
+        .iter()
+        .filter(|filter| available_filters.contains(**filter))
+        .map(|filter| (*filter).to_string())
+        .collect()
+}
+
+fn parse_ffmpeg_supports_soxr(version_output: &str) -> bool {
+    version_output.contains("--enable-libsoxr")
+}
+
+/// Check that the installed ffmpeg has every filter the requested audio chains use.
+pub(crate) async fn validate_audio_filter_support(
+    ffmpeg_path: &str,
+    filters: &[&TranscodeAudioFilters],
+) -> Result<(), String> {
+    if filters.iter().all(|filters| filters.is_empty()) {
+        return Ok(());
+    }
+
+    let filter_output = ffmpeg_filter_output(ffmpeg_path).await?;
+    let supported_filters = build_supported_audio_filters(&parse_ffmpeg_filter_names(
+        &filter_output,
+        FilterMediaType::Audio,
+    ));
+    if let Some(missing) = filters
+        .iter()
+        .flat_map(|filters| filters.required_filters())
+        .find(|filter| {
+            !supported_filters
+                .iter()
+                .any(|supported| supported == filter)
+        })
+    {
+        return Err(format!(
+            "This ffmpeg build does not include the {} audio filter",
+            missing
+        ));
+    }
+
+    if filters.iter().any(|filters| filters.uses_soxr()) {
+        let version_output = run_ffmpeg_command(ffmpeg_path, &["-version"]).await?;
+        if !parse_ffmpeg_supports_soxr(&version_output) {
+            return Err(
+                "This ffmpeg build does not include the SoX resampler. Turn off SoX resampling."
+                    .to_string(),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Check that the installed ffmpeg has every filter the typed video settings use.
+pub(crate) async fn validate_video_filter_support(
+    ffmpeg_path: &str,
+    filters: &[&TranscodeVideoFilters],
+) -> Result<(), String> {
+    let required = filters
+        .iter()
+        .flat_map(|filters| filters.required_filters())
+        .collect::<BTreeSet<_>>();
+    if required.is_empty() {
+        return Ok(());
+    }
+
+    let filter_output = ffmpeg_filter_output(ffmpeg_path).await?;
+    let available_filters = parse_ffmpeg_filter_names(&filter_output, FilterMediaType::Video);
+    if let Some(missing) = required
+        .into_iter()
+        .find(|filter| !available_filters.contains(*filter))
+    {
+        return Err(format!(
+            "This ffmpeg build does not include the {} video filter",
+            missing
+        ));
+    }
+
+    Ok(())
+}
+
 fn parse_supported_pixel_formats(output: &str) -> Vec<String> {
     output
         .lines()
@@ -1007,10 +1292,19 @@ pub(crate) async fn get_transcode_capabilities_with_ffmpeg_path(
     let hwaccel_output = run_ffmpeg_command(ffmpeg_path, &["-hide_banner", "-hwaccels"])
         .await
         .unwrap_or_default();
+    let filter_output = ffmpeg_filter_output(ffmpeg_path).await.unwrap_or_default();
 
     let available_encoders = parse_ffmpeg_encoder_names(&encoder_output);
     let available_muxers = parse_ffmpeg_muxer_names(&muxer_output);
     let hwaccels = parse_ffmpeg_hwaccel_names(&hwaccel_output);
+    let supported_video_filters = build_supported_video_filters(&parse_ffmpeg_filter_names(
+        &filter_output,
+        FilterMediaType::Video,
+    ));
+    let supported_audio_filters = build_supported_audio_filters(&parse_ffmpeg_filter_names(
+        &filter_output,
+        FilterMediaType::Audio,
+    ));
 
     let video_encoders = build_video_encoder_capabilities(ffmpeg_path, &available_encoders).await;
     let audio_encoders = build_audio_encoder_capabilities(ffmpeg_path, &available_encoders).await;
@@ -1030,6 +1324,9 @@ pub(crate) async fn get_transcode_capabilities_with_ffmpeg_path(
         video_encoders,
         audio_encoders,
         subtitle_encoders,
+        supported_video_filters,
+        supported_audio_filters,
+        supports_soxr: parse_ffmpeg_supports_soxr(&version_output),
         default_analysis_frame_count: DEFAULT_ANALYSIS_FRAME_COUNT,
     })
 }
@@ -1045,11 +1342,13 @@ pub(crate) async fn get_transcode_capabilities(
 #[cfg(test)]
 mod tests {
     use super::{
-        TranscodeAudioEncoderCapability, TranscodeEncoderOptionValueKind,
+        FilterMediaType, TranscodeAudioEncoderCapability, TranscodeEncoderOptionValueKind,
         TranscodeSubtitleEncoderCapability, TranscodeVideoEncoderCapability,
-        build_container_capabilities, derive_bit_depths_from_pixel_formats, parse_encoder_options,
-        parse_ffmpeg_encoder_names, parse_ffmpeg_hwaccel_names, parse_ffmpeg_muxer_names,
-        parse_option_enum_values, parse_supported_pixel_formats, video_encoder_supports_bitrate,
+        build_container_capabilities, build_supported_audio_filters, build_supported_video_filters,
+        derive_bit_depths_from_pixel_formats, parse_encoder_options, parse_ffmpeg_encoder_names,
+        parse_ffmpeg_filter_names, parse_ffmpeg_hwaccel_names, parse_ffmpeg_muxer_names,
+        parse_ffmpeg_supports_soxr, parse_option_enum_values, parse_supported_pixel_formats,
+        video_encoder_supports_bitrate,
     };
     use std::collections::HashSet;
 
@@ -1114,6 +1413,56 @@ mod tests {
         assert!(!parsed.contains("ssegment"));
     }
 
+    #[test]
+    fn parse_ffmpeg_filter_names_keeps_known_video_filters() {
+        let sample = r#"
+Filters:
+  T.. = Timeline support
+  .S. = Slice threading
+  ..C = Command support
+  A = Audio input/output
+  V = Video input/output
+ TS. bwdif             V->V       Deinterlace the input image.
+ ..C scale             V->V       Scale the input video size and/or convert the image format.
+ TSC hqdn3d            V->V       Apply a High Quality 3D Denoiser.
+ ..C atempo            A->A       Adjust audio tempo.
+ ... split             V->N       Pass on the input to N video outputs.
+ "#;
+        let parsed = parse_ffmpeg_filter_names(sample, FilterMediaType::Video);
+        assert!(parsed.contains("bwdif"));
+        assert!(parsed.contains("split"));
+        assert!(!parsed.contains("atempo"));
+        assert!(!parsed.contains("="));
+
+        assert_eq!(
+            build_supported_video_filters(&parsed),
+            vec!["bwdif", "hqdn3d", "scale"]
+        );
+    }
+
+    #[test]
+    fn parse_ffmpeg_filter_names_keeps_known_audio_filters() {
+        let sample = r#"
+ ... loudnorm          A->A       EBU R128 loudness normalization
+ T.C volume            A->A       Change input volume.
+ ... aresample         A->A       Resample audio data.
+ ..C scale             V->V       Scale the input video size and/or convert the image format.
+ ... showwaves         A->V       Convert input audio to a video output.
+ "#;
+        let parsed = parse_ffmpeg_filter_names(sample, FilterMediaType::Audio);
+        assert!(parsed.contains("showwaves"));
+        assert!(!parsed.contains("scale"));
+
+        assert_eq!(
+            build_supported_audio_filters(&parsed),
+            vec!["aresample", "loudnorm", "volume"]
+        );
+        assert!(parse_ffmpeg_supports_soxr(
+            "ffmpeg version 7.1\nconfiguration: --enable-gpl --enable-libsoxr"
+        ));
+        assert!(!parse_ffmpeg_supports_soxr("configuration: --enable-gpl"));
+    }
+
     #[test]
     fn parse_ffmpeg_hwaccel_names_extracts_hwaccels() {
         let sample = "Hardware acceleration methods:\nvideotoolbox\nqsv\n";
@@ -0,0 +1,837 @@
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+use tokio::task::JoinSet;
+use tokio::time::timeout;
+
+use crate::shared::ffmpeg_progress::FfmpegProgressTracker;
+use crate::shared::hash::stable_hash64;
+use crate::shared::process::force_terminate_process;
+
+use super::filters::TranscodeVideoFilters;
+use super::transcode::{
+    StreamInfo, TranscodeContext, TranscodeRequest, TranscodeVideoTrackOverride,
+    build_transcode_args, clear_transcode_state, ensure_transcode_tracked, extract_streams_by_type,
+    is_attached_picture, request_with_only_video, resolve_video_settings_for_stream,
+};
+
+const CHUNK_TIMEOUT: Duration = Duration::from_secs(7200);
+/// Reading packet times only demuxes, so it is much quicker than an encode.
+const FRAME_PROBE_TIMEOUT: Duration = Duration::from_secs(600);
+const CONCAT_TIMEOUT: Duration = Duration::from_secs(600);
+const DEFAULT_MIN_CHUNK_SECONDS: u32 = 30;
+const DEFAULT_SCENE_THRESHOLD: f64 = 0.4;
+const MAX_CHUNK_WORKERS: usize = 16;
+/// Seek slightly before a chunk's first frame so timestamp rounding can't skip it.
+const CHUNK_SEEK_LEAD_SECONDS: f64 = 0.0005;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TranscodeChunkedEncoding {
+    /// Chunks encoded at once; defaults to a quarter of the CPU threads.
+    pub(crate) workers: Option<usize>,
+    /// `keyframe` (default) or `scene`
+    pub(crate) split_mode: Option<String>,
+    pub(crate) min_chunk_seconds: Option<u32>,
+    /// `scene` score between 0 and 1 above which a frame starts a new shot.
+    pub(crate) scene_threshold: Option<f64>,
+}
+
+impl TranscodeChunkedEncoding {
+    pub(crate) fn workers(&self) -> usize {
+        self.workers
+            .unwrap_or_else(|| {
+                std::thread::available_parallelism()
+                    .map(|threads| threads.get() / 4)
+                    .unwrap_or(1)
+            })
+            .clamp(1, MAX_CHUNK_WORKERS)
+    }
+
+    pub(crate) fn min_chunk_seconds(&self) -> f64 {
+        f64::from(
+            self.min_chunk_seconds
+                .unwrap_or(DEFAULT_MIN_CHUNK_SECONDS)
+                .max(1),
+        )
+    }
+
+    /// The scene threshold when splitting on scene changes, `None` for keyframes.
+    pub(crate) fn scene_threshold(&self) -> Result<Option<f64>, String> {
+        match self.split_mode.as_deref().map(str::trim) {
+            None | Some("") | Some("keyframe") => Ok(None),
+            Some("scene") => {
+                let threshold = self.scene_threshold.unwrap_or(DEFAULT_SCENE_THRESHOLD);
+                if !threshold.is_finite() || threshold <= 0.0 || threshold >= 1.0 {
+                    return Err("Scene threshold must be between 0 and 1".to_string());
+                }
+                Ok(Some(threshold))
+            }
+            Some(other) => Err(format!("Unsupported chunk split mode: {}", other)),
+        }
+    }
+}
+
+/// Presentation times of the video frames in order, with the keyframe positions.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct VideoFrameTimes {
+    pub(crate) pts: Vec<f64>,
+    pub(crate) keyframes: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct VideoChunk {
+    pub(crate) first_frame: usize,
+    pub(crate) frame_count: usize,
+    pub(crate) pts_seconds: f64,
+    pub(crate) duration_us: u64,
+}
+
+pub(crate) struct ChunkEncodeJob {
+    pub(crate) args: Vec<String>,
+    pub(crate) duration_us: u64,
+}
+
+pub(crate) fn build_chunk_dir(input_path: &str, output_path: &str) -> PathBuf {
+    std::env::temp_dir()
+        .join("mediaflow_transcode_chunks")
+        .join(format!(
+            "{:016x}",
+            stable_hash64(&format!("{}\n{}", input_path, output_path))
+        ))
+}
+
+/// Parse `ffprobe -show_entries packet=pts_time,flags -of csv=p=0`.
+pub(crate) fn parse_packet_times(output: &str) -> VideoFrameTimes {
+    let mut packets = output
+        .lines()
+        .filter_map(|line| {
+            let (pts_time, flags) = line.trim().split_once(',')?;
+            let pts_time = pts_time.parse::<f64>().ok()?;
+            Some((pts_time, flags.starts_with('K')))
+        })
+        .collect::<Vec<_>>();
+    // Packets arrive in decode order; B-frames put presentation order elsewhere.
+    packets.sort_by(|left, right| left.0.total_cmp(&right.0));
+
+    VideoFrameTimes {
+        pts: packets.iter().map(|(pts_time, _)| *pts_time).collect(),
+        keyframes: packets
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, is_keyframe))| *is_keyframe)
+            .map(|(index, _)| index)
+            .collect(),
+    }
+}
+
+/// Frame times reported by `showinfo` after a `select='gt(scene,N)'` filter.
+pub(crate) fn parse_scene_change_times(stderr: &str) -> Vec<f64> {
+    stderr
+        .lines()
+        .filter(|line| line.contains("Parsed_showinfo"))
+        .filter_map(|line| {
+            line.split_whitespace()
+                .find_map(|token| token.strip_prefix("pts_time:"))
+                .and_then(|value| value.parse::<f64>().ok())
+        })
+        .collect()
+}
+
+/// Nearest frame index for each time, sorted and deduplicated.
+pub(crate) fn snap_to_frames(times: &[f64], pts: &[f64]) -> Vec<usize> {
+    if pts.is_empty() {
+        return Vec::new();
+    }
+
+    let mut frames = times
+        .iter()
+        .map(|time| {
+            let next = pts.partition_point(|pts_time| pts_time < time);
+            if next == 0 {
+                0
+            } else if next >= pts.len() || time - pts[next - 1] < pts[next] - time {
+                next.min(pts.len()) - 1
+            } else {
+                next
+            }
+        })
+        .collect::<Vec<_>>();
+    frames.sort_unstable();
+    frames.dedup();
+    frames
+}
+
+/// Split at candidate frames so every chunk lasts at least `min_chunk_seconds`;
+/// the last chunk may be down to half of that.
+pub(crate) fn plan_video_chunks(
+    frames: &VideoFrameTimes,
+    candidates: &[usize],
+    min_chunk_seconds: f64,
+) -> Vec<VideoChunk> {
+    let frame_total = frames.pts.len();
+    if frame_total == 0 {
+        return Vec::new();
+    }
+
+    let pts = &frames.pts;
+    let last_pts = pts[frame_total - 1];
+    let frame_duration = if frame_total > 1 {
+        (last_pts - pts[0]) / (frame_total - 1) as f64
+    } else {
+        0.0
+    };
+
+    let mut starts = vec![0];
+    for &candidate in candidates {
+        let previous = *starts.last().unwrap_or(&0);
+        if candidate <= previous || candidate >= frame_total {
+            continue;
+        }
+        if pts[candidate] - pts[previous] >= min_chunk_seconds
+            && last_pts - pts[candidate] >= min_chunk_seconds / 2.0
+        {
+            starts.push(candidate);
+        }
+    }
+
+    starts
+        .iter()
+        .enumerate()
+        .map(|(index, &first_frame)| {
+            let (end_frame, end_pts) = match starts.get(index + 1) {
+                Some(&next) => (next, pts[next]),
+                None => (frame_total, last_pts + frame_duration),
+            };
+            VideoChunk {
+                first_frame,
+                frame_count: end_frame - first_frame,
+                pts_seconds: pts[first_frame],
+                duration_us: ((end_pts - pts[first_frame]).max(0.0) * 1_000_000.0).round() as u64,
+            }
+        })
+        .collect()
+}
+
+/// Input seek and frame limit for one chunk. `-ss` counts from the input's
+/// start time, so the first chunk simply starts at the beginning.
+pub(crate) fn apply_chunk_window(args: &mut Vec<String>, chunk: &VideoChunk, start_time: f64) {
+    if chunk.first_frame > 0
+        && let Some(input_index) = args.iter().position(|arg| arg == "-i")
+    {
+        let seek_seconds = (chunk.pts_seconds - start_time - CHUNK_SEEK_LEAD_SECONDS).max(0.0);
+        args.splice(
+            input_index..input_index,
+            ["-ss".to_string(), format!("{:.6}", seek_seconds)],
+        );
+    }
+
+    let output_index = args.len().saturating_sub(1);
+    args.splice(
+        output_index..output_index,
+        ["-frames:v".to_string(), chunk.frame_count.to_string()],
+    );
+}
+
+pub(crate) fn build_concat_list(chunk_paths: &[PathBuf]) -> String {
+    chunk_paths
+        .iter()
+        .map(|path| format!("file '{}'\n", path.to_string_lossy().replace('\'', "'\\''")))
+        .collect()
+}
+
+/// Run a helper process under `process_key`, so cancelling the transcode stops it too.
+async fn run_tracked_process(
+    mut command: Command,
+    process_key: &str,
+    limit: Duration,
+    label: &str,
+) -> Result<std::process::Output, String> {
+    let child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|error| format!("Failed to start {}: {}", label, error))?;
+    let child_pid = child.id();
+    if let Some(pid) = child_pid {
+        track_chunk_process(process_key, pid, true);
+    }
+
+    let output = timeout(limit, child.wait_with_output()).await;
+    if let Some(pid) = child_pid {
+        track_chunk_process(process_key, pid, false);
+    }
+    output
+        .map_err(|_| format!("{} timeout after {} seconds", label, limit.as_secs()))?
+        .map_err(|error| format!("Failed to execute {}: {}", label, error))
+}
+
+pub(crate) async fn probe_video_frame_times(
+    ffprobe_path: &str,
+    process_key: &str,
+    input_path: &str,
+    video_relative_index: usize,
+) -> Result<VideoFrameTimes, String> {
+    let mut command = Command::new(ffprobe_path);
+    command.args([
+        "-v",
+        "error",
+        "-select_streams",
+        &format!("v:{}", video_relative_index),
+        "-show_entries",
+        "packet=pts_time,flags",
+        "-of",
+        "csv=p=0",
+        input_path,
+    ]);
+    let output = run_tracked_process(command, process_key, FRAME_PROBE_TIMEOUT, "ffprobe").await?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "Failed to read video frame times: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(parse_packet_times(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Scene detection decodes the whole stream, so it gets the chunk encode timeout.
+pub(crate) async fn detect_scene_changes(
+    ffmpeg_path: &str,
+    process_key: &str,
+    input_path: &str,
+    video_relative_index: usize,
+    threshold: f64,
+) -> Result<Vec<f64>, String> {
+    let mut command = Command::new(ffmpeg_path);
+    command.args([
+        "-hide_banner",
+        "-nostats",
+        "-i",
+        input_path,
+        "-map",
+        &format!("0:v:{}", video_relative_index),
+        "-vf",
+        &format!("select='gt(scene,{})',showinfo", threshold),
+        "-an",
+        "-f",
+        "null",
+        "-",
+    ]);
+    let output = run_tracked_process(command, process_key, CHUNK_TIMEOUT, "ffmpeg").await?;
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        return Err(format!("Scene detection failed: {}", stderr.trim()));
+    }
+
+    Ok(parse_scene_change_times(&stderr))
+}
+
+pub(crate) fn terminate_chunk_processes(process_key: &str) {
+    let pids = super::state::TRANSCODE_CHUNK_PROCESS_IDS
+        .lock()
+        .ok()
+        .and_then(|mut guard| guard.remove(process_key))
+        .unwrap_or_default();
+    for pid in pids {
+        force_terminate_process(pid);
+    }
+}
+
+fn track_chunk_process(process_key: &str, pid: u32, running: bool) {
+    if let Ok(mut guard) = super::state::TRANSCODE_CHUNK_PROCESS_IDS.lock() {
+        let pids = guard.entry(process_key.to_string()).or_default();
+        if running {
+            pids.push(pid);
+        } else {
+            pids.retain(|tracked| *tracked != pid);
+            if pids.is_empty() {
+                guard.remove(process_key);
+            }
+        }
+    }
+}
+
+async fn encode_chunk<F>(
+    ffmpeg_path: String,
+    process_key: String,
+    index: usize,
+    job: ChunkEncodeJob,
+    encoded_us: Arc<Mutex<Vec<u64>>>,
+    total_us: u64,
+    on_progress: Arc<F>,
+) -> Result<(), String>
+where
+    F: Fn(i32) + Send + Sync + 'static,
+{
+    let mut child = Command::new(&ffmpeg_path)
+        .args(&job.args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|error| format!("Failed to start ffmpeg: {}", error))?;
+    let child_pid = child.id();
+    if let Some(pid) = child_pid {
+        track_chunk_process(&process_key, pid, true);
+    }
+
+    let report_progress = {
+        let encoded_us = encoded_us.clone();
+        let on_progress = on_progress.clone();
+        move |chunk_us: u64| {
+            let Ok(mut encoded) = encoded_us.lock() else {
+                return;
+            };
+            encoded[index] = chunk_us;
+            let progress = encoded.iter().sum::<u64>() as f64 / total_us as f64 * 100.0;
+            on_progress(progress.clamp(0.0, 99.0).round() as i32);
+        }
+    };
+
+    if let Some(stdout) = child.stdout.take() {
+        let report_progress = report_progress.clone();
+        let duration_us = job.duration_us;
+        tokio::spawn(async move {
+            let mut tracker = FfmpegProgressTracker::new(Some(duration_us));
+            let mut lines = BufReader::new(stdout).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                if let Some(progress) = tracker
+                    .handle_line(&line)
+                    .and_then(|update| update.progress)
+                {
+                    report_progress(duration_us * progress.clamp(0, 100) as u64 / 100);
+                }
+            }
+        });
+    }
+
+    let output = timeout(CHUNK_TIMEOUT, child.wait_with_output()).await;
+    if let Some(pid) = child_pid {
+        track_chunk_process(&process_key, pid, false);
+    }
+    let output = output
+        .map_err(|_| {
+            format!(
+                "Chunk {} timeout after {} seconds",
+                index + 1,
+                CHUNK_TIMEOUT.as_secs()
+            )
+        })?
+        .map_err(|error| format!("Failed to execute ffmpeg: {}", error))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("Chunk {} failed: {}", index + 1, stderr.trim()));
+    }
+
+    report_progress(job.duration_us);
+    Ok(())
+}
+
+/// Run the chunk encodes with at most `workers` at a time. The first failure
+/// stops every other chunk process tracked under `process_key`.
+pub(crate) async fn encode_chunks_in_parallel<F>(
+    ffmpeg_path: &str,
+    process_key: &str,
+    jobs: Vec<ChunkEncodeJob>,
+    workers: usize,
+    on_progress: F,
+) -> Result<(), String>
+where
+    F: Fn(i32) + Send + Sync + 'static,
+{
+    let total_us = jobs.iter().map(|job| job.duration_us).sum::<u64>().max(1);
+    let encoded_us = Arc::new(Mutex::new(vec![0; jobs.len()]));
+    let on_progress = Arc::new(on_progress);
+    let mut pending = jobs.into_iter().enumerate();
+    let mut running = JoinSet::new();
+    let mut result = Ok(());
+
+    loop {
+        while result.is_ok()
+            && running.len() < workers.max(1)
+            && let Some((index, job)) = pending.next()
+        {
+            running.spawn(encode_chunk(
+                ffmpeg_path.to_string(),
+                process_key.to_string(),
+                index,
+                job,
+                encoded_us.clone(),
+                total_us,
+                on_progress.clone(),
+            ));
+        }
+
+        let Some(joined) = running.join_next().await else {
+            break;
+        };
+        let chunk_result = joined
+            .map_err(|error| format!("Chunk encode task failed: {}", error))
+            .and_then(|chunk_result| chunk_result);
+        if let Err(error) = chunk_result
+            && result.is_ok()
+        {
+            result = Err(error);
+            terminate_chunk_processes(process_key);
+        }
+    }
+
+    result
+}
+
+/// Join the encoded chunks without re-encoding.
+pub(crate) async fn concat_chunks(
+    ffmpeg_path: &str,
+    process_key: &str,
+    chunk_paths: &[PathBuf],
+    list_path: &Path,
+    output_path: &Path,
+) -> Result<(), String> {
+    std::fs::write(list_path, build_concat_list(chunk_paths))
+        .map_err(|error| format!("Failed to write chunk list: {}", error))?;
+
+    let mut command = Command::new(ffmpeg_path);
+    command.args([
+        "-hide_banner",
+        "-y",
+        "-f",
+        "concat",
+        "-safe",
+        "0",
+        "-i",
+        list_path.to_string_lossy().as_ref(),
+        "-map",
+        "0:v",
+        "-c",
+        "copy",
+        output_path.to_string_lossy().as_ref(),
+    ]);
+    let output = run_tracked_process(command, process_key, CONCAT_TIMEOUT, "ffmpeg").await?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "Failed to join encoded chunks: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Chunked encodes only cover the main video stream, re-encoded from start to end.
+pub(crate) fn validate_chunked_request(
+    request: &TranscodeRequest,
+    video_streams: &[StreamInfo],
+) -> Result<StreamInfo, String> {
+    let Some(primary) = video_streams
+        .iter()
+        .find(|stream| !is_attached_picture(stream))
+    else {
+        return Err("Chunked encoding needs a video stream".to_string());
+    };
+    if resolve_video_settings_for_stream(request, primary, true).mode != "transcode" {
+        return Err("Chunked encoding requires video transcoding".to_string());
+    }
+    if !request.ranges.is_empty() {
+        return Err("Chunked encoding cannot be combined with trimming ranges".to_string());
+    }
+    if request.burn_in.is_some() {
+        return Err("Chunked encoding cannot be combined with subtitle burn-in".to_string());
+    }
+    if request.video.two_pass || request.video.quality_mode.as_deref() == Some("targetSize") {
+        return Err(
+            "Chunked encoding does not support two-pass or target size encoding".to_string(),
+        );
+    }
+    if request.video.map_all_streams
+        || request.video.track_overrides.iter().any(|track_override| {
+            track_override.track_id != primary.stream_index && track_override.mode != "disable"
+        })
+    {
+        return Err(
+            "Chunked encoding supports a single video stream. Disable the other video streams."
+                .to_string(),
+        );
+    }
+
+    Ok(primary.clone())
+}
+
+/// Video-only encode of the main stream; the chunk window is applied to the args.
+pub(crate) fn build_chunk_request(
+    request: &TranscodeRequest,
+    video_streams: &[StreamInfo],
+    primary: &StreamInfo,
+    chunk_path: &Path,
+) -> TranscodeRequest {
+    let mut chunk = request_with_only_video(request, chunk_path);
+    chunk.container_id = "mkv".to_string();
+    chunk.video.chunked = None;
+    chunk
+        .video
+        .track_overrides
+        .retain(|track_override| track_override.track_id == primary.stream_index);
+    chunk.video.track_overrides.extend(
+        video_streams
+            .iter()
+            .filter(|stream| stream.stream_index != primary.stream_index)
+            .map(|stream| TranscodeVideoTrackOverride {
+                track_id: stream.stream_index,
+                mode: "disable".to_string(),
+                ..TranscodeVideoTrackOverride::default()
+            }),
+    );
+    chunk
+}
+
+/// Final mux: the main video is copied from the joined chunks, everything else
+/// is handled from the source as usual.
+pub(crate) fn request_for_chunked_mux(request: &TranscodeRequest) -> TranscodeRequest {
+    let mut mux = request.clone();
+    mux.video.mode = "copy".to_string();
+    mux.video.filters = TranscodeVideoFilters::default();
+    mux.video.track_overrides.clear();
+    mux
+}
+
+fn input_start_time(streams: &[Value]) -> f64 {
+    streams
+        .iter()
+        .filter_map(|stream| {
+            stream
+                .get("start_time")
+                .and_then(|value| value.as_str())
+                .and_then(|value| value.parse::<f64>().ok())
+        })
+        .fold(None, |earliest: Option<f64>, start_time| {
+            Some(earliest.map_or(start_time, |earliest| earliest.min(start_time)))
+        })
+        .unwrap_or(0.0)
+}
+
+/// Split the main video at keyframes or scene changes, encode the chunks in
+/// parallel and join them; returns the request and context for the final mux.
+pub(crate) async fn encode_chunked_video<F>(
+    ffmpeg_path: &str,
+    ffprobe_path: &str,
+    request: &TranscodeRequest,
+    context: &TranscodeContext,
+    streams: &[Value],
+    chunk_dir: &Path,
+    on_progress: F,
+) -> Result<Option<(TranscodeRequest, TranscodeContext)>, String>
+where
+    F: Fn(i32) + Send + Sync + 'static,
+{
+    let Some(chunked) = request.video.chunked.as_ref() else {
+        return Ok(None);
+    };
+    let video_streams = extract_streams_by_type(streams, "video");
+    let primary = validate_chunked_request(request, &video_streams)?;
+    let scene_threshold = chunked.scene_threshold()?;
+
+    // Tracked from the first probe so a cancel also stops frame reading and scene detection.
+    if let Ok(mut guard) = super::state::TRANSCODE_OUTPUT_PATHS.lock() {
+        guard.insert(request.input_path.clone(), request.output_path.clone());
+    }
+    let process_key = request.input_path.as_str();
+    let video_path = chunk_dir.join("video.mkv");
+    let result = async {
+        let frames = probe_video_frame_times(
+            ffprobe_path,
+            process_key,
+            &request.input_path,
+            primary.relative_index,
+        )
+        .await?;
+        let candidates = match scene_threshold {
+            Some(threshold) => {
+                ensure_transcode_tracked(&request.input_path)?;
+                let scene_times = detect_scene_changes(
+                    ffmpeg_path,
+                    process_key,
+                    &request.input_path,
+                    primary.relative_index,
+                    threshold,
+                )
+                .await?;
+                snap_to_frames(&scene_times, &frames.pts)
+            }
+            None => frames.keyframes.clone(),
+        };
+        ensure_transcode_tracked(&request.input_path)?;
+        let chunks = plan_video_chunks(&frames, &candidates, chunked.min_chunk_seconds());
+        if chunks.is_empty() {
+            return Err("No video frames were found to split into chunks".to_string());
+        }
+
+        std::fs::create_dir_all(chunk_dir)
+            .map_err(|error| format!("Failed to create chunk directory: {}", error))?;
+        let start_time = input_start_time(streams);
+        let chunk_context = context.for_video_only();
+        let mut chunk_paths = Vec::with_capacity(chunks.len());
+        let mut jobs = Vec::with_capacity(chunks.len());
+        for (index, chunk) in chunks.iter().enumerate() {
+            let chunk_path = chunk_dir.join(format!("chunk-{:04}.mkv", index + 1));
+            let chunk_request = build_chunk_request(request, &video_streams, &primary, &chunk_path);
+            let mut args = build_transcode_args(
+                &chunk_request,
+                &chunk_context,
+                streams,
+                Some(chunk.duration_us),
+            )?;
+            apply_chunk_window(&mut args, chunk, start_time);
+            jobs.push(ChunkEncodeJob {
+                args,
+                duration_us: chunk.duration_us,
+            });
+            chunk_paths.push(chunk_path);
+        }
+
+        encode_chunks_in_parallel(
+            ffmpeg_path,
+            process_key,
+            jobs,
+            chunked.workers(),
+            on_progress,
+        )
+        .await?;
+        ensure_transcode_tracked(&request.input_path)?;
+
+        concat_chunks(
+            ffmpeg_path,
+            process_key,
+            &chunk_paths,
+            &chunk_dir.join("chunks.txt"),
+            &video_path,
+        )
+        .await
+    }
+    .await;
+    // A killed helper fails with its own error, so report the cancel instead.
+    let result = ensure_transcode_tracked(&request.input_path).and(result);
+    clear_transcode_state(&request.input_path);
+    result?;
+
+    let mux_context = TranscodeContext {
+        chunked_video_path: Some(video_path.to_string_lossy().to_string()),
+        ..context.clone()
+    };
+    Ok(Some((request_for_chunked_mux(request), mux_context)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{
+        TranscodeChunkedEncoding, VideoFrameTimes, apply_chunk_window, build_concat_list,
+        parse_packet_times, parse_scene_change_times, plan_video_chunks, snap_to_frames,
+    };
+
+    /// 10 fps frames for `seconds`, with a keyframe every `gop` frames.
+    fn frames(seconds: usize, gop: usize) -> VideoFrameTimes {
+        let count = seconds * 10;
+        VideoFrameTimes {
+            pts: (0..count).map(|index| index as f64 / 10.0).collect(),
+            keyframes: (0..count).step_by(gop).collect(),
+        }
+    }
+
+    #[test]
+    fn parse_packet_times_sorts_into_presentation_order() {
+        let parsed =
+            parse_packet_times("0.000000,K__\n0.200000,___\n0.100000,___\nN/A,___\n0.300000,K_\n");
+        assert_eq!(parsed.pts, vec![0.0, 0.1, 0.2, 0.3]);
+        assert_eq!(parsed.keyframes, vec![0, 3]);
+
+        let scenes = parse_scene_change_times(
+            "[Parsed_showinfo_1 @ 0x1] n:   0 pts:  12012 pts_time:12.012 duration:1001\n\
+             frame=  10 fps=0.0 q=-0.0 size=N/A time=00:00:12.01\n\
+             [Parsed_showinfo_1 @ 0x1] n:   1 pts:  45045 pts_time:45.045 duration:1001\n",
+        );
+        assert_eq!(scenes, vec![12.012, 45.045]);
+        assert_eq!(
+            snap_to_frames(&[0.04, 0.16, 9.0], &[0.0, 0.1, 0.2]),
+            vec![0, 2]
+        );
+    }
+
+    #[test]
+    fn plan_video_chunks_respects_the_minimum_length() {
+        let frames = frames(100, 20);
+        let chunks = plan_video_chunks(&frames, &frames.keyframes, 30.0);
+
+        assert_eq!(
+            chunks
+                .iter()
+                .map(|chunk| chunk.first_frame)
+                .collect::<Vec<_>>(),
+            vec![0, 300, 600]
+        );
+        assert_eq!(
+            chunks.iter().map(|chunk| chunk.frame_count).sum::<usize>(),
+            1000
+        );
+        assert_eq!(chunks[2].duration_us, 40_000_000);
+        assert_eq!(
+            plan_video_chunks(&frames, &frames.keyframes, 200.0).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn apply_chunk_window_seeks_inputs_and_limits_frames() {
+        let chunks = plan_video_chunks(&frames(100, 20), &[300], 30.0);
+        let base = [
+            "-hide_banner",
+            "-i",
+            "/media/in.mkv",
+            "-c:v",
+            "libsvtav1",
+            "/tmp/chunk.mkv",
+        ]
+        .map(String::from)
+        .to_vec();
+
+        let mut first = base.clone();
+        apply_chunk_window(&mut first, &chunks[0], 0.0);
+        assert_eq!(first[1], "-i");
+        assert_eq!(
+            &first[first.len() - 3..],
+            ["-frames:v", "300", "/tmp/chunk.mkv"]
+        );
+
+        let mut second = base;
+        apply_chunk_window(&mut second, &chunks[1], 0.0);
+        assert_eq!(&second[1..4], ["-ss", "29.999500", "-i"]);
+        assert_eq!(
+            &second[second.len() - 3..],
+            ["-frames:v", "700", "/tmp/chunk.mkv"]
+        );
+
+        assert_eq!(
+            build_concat_list(&[PathBuf::from("/tmp/it's/chunk-0001.mkv")]),
+            "file '/tmp/it'\\''s/chunk-0001.mkv'\n"
+        );
+        assert!(
+            TranscodeChunkedEncoding {
+                split_mode: Some("shots".to_string()),
+                ..TranscodeChunkedEncoding::default()
+            }
+            .scene_threshold()
+            .is_err()
+        );
+    }
+}
@@ -0,0 +1,21 @@
+use serde_json::Value;
+use tauri::Emitter;
+
+/// Where a transcode reports progress and results. The app forwards them as
+/// events; the `_with_bins` entry points used by tests drop them.
+pub(crate) trait TranscodeEvents: Clone + Send + Sync + 'static {
+    fn emit_event(&self, event: &str, payload: Value);
+}
+
+impl TranscodeEvents for tauri::AppHandle {
+    fn emit_event(&self, event: &str, payload: Value) {
+        let _ = self.emit(event, payload);
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct NoTranscodeEvents;
+
+impl TranscodeEvents for NoTranscodeEvents {
+    fn emit_event(&self, _event: &str, _payload: Value) {}
+}
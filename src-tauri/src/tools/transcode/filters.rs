@@ -0,0 +1,654 @@
+use std::borrow::Cow;
+
+use serde::Deserialize;
+
+/// Filters the typed settings below can emit, reported by the capability probe.
+pub(crate) const KNOWN_VIDEO_FILTERS: &[&str] = &[
+    "bwdif", "crop", "format", "fps", "hqdn3d", "nlmeans", "pad", "scale", "setsar", "tonemap",
+    "unsharp", "yadif", "zscale",
+];
+
+const MAX_FILTER_DIMENSION: u32 = 16384;
+const MAX_FILTER_FPS: f64 = 240.0;
+const MAX_SHARPEN_AMOUNT: f64 = 1.5;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TranscodeScaleFilter {
+    pub(crate) width: Option<u32>,
+    pub(crate) height: Option<u32>,
+    /// `keep` (fit inside), `pad` (fit and letterbox), `fill` (cover and crop) or `stretch`
+    pub(crate) aspect_mode: Option<String>,
+    pub(crate) algorithm: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TranscodeCropFilter {
+    #[serde(default)]
+    pub(crate) top: u32,
+    #[serde(default)]
+    pub(crate) bottom: u32,
+    #[serde(default)]
+    pub(crate) left: u32,
+    #[serde(default)]
+    pub(crate) right: u32,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TranscodeDeinterlaceFilter {
+    /// `yadif` or `bwdif`
+    pub(crate) method: String,
+    /// `frame` keeps the frame rate, `field` doubles it
+    pub(crate) mode: Option<String>,
+    #[serde(default)]
+    pub(crate) only_interlaced: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TranscodeDenoiseFilter {
+    /// `hqdn3d` or `nlmeans`
+    pub(crate) method: String,
+    /// `light`, `medium` or `strong`
+    pub(crate) strength: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TranscodeFpsFilter {
+    /// Number or rational such as `24000/1001`
+    pub(crate) value: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TranscodeSharpenFilter {
+    pub(crate) amount: f64,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TranscodeTonemapFilter {
+    /// `hable` (default), `mobius`, `reinhard`, or `off` to keep HDR sources untouched
+    pub(crate) algorithm: Option<String>,
+}
+
+impl TranscodeTonemapFilter {
+    pub(crate) fn is_off(&self) -> bool {
+        self.algorithm.as_deref().map(str::trim) == Some("off")
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TranscodeVideoFilters {
+    pub(crate) scale: Option<TranscodeScaleFilter>,
+    pub(crate) crop: Option<TranscodeCropFilter>,
+    pub(crate) deinterlace: Option<TranscodeDeinterlaceFilter>,
+    pub(crate) denoise: Option<TranscodeDenoiseFilter>,
+    pub(crate) fps: Option<TranscodeFpsFilter>,
+    pub(crate) sharpen: Option<TranscodeSharpenFilter>,
+    /// HDR (PQ or HLG) to SDR BT.709 conversion.
+    pub(crate) tonemap: Option<TranscodeTonemapFilter>,
+}
+
+impl TranscodeVideoFilters {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.scale.is_none()
+            && self.crop.is_none()
+            && self.deinterlace.is_none()
+            && self.denoise.is_none()
+            && self.fps.is_none()
+            && self.sharpen.is_none()
+            && self
+                .tonemap
+                .as_ref()
+                .is_none_or(TranscodeTonemapFilter::is_off)
+    }
+
+    /// Filter names the chain needs, checked against `ffmpeg -filters`.
+    pub(crate) fn required_filters(&self) -> Vec<&'static str> {
+        let mut required = Vec::new();
+        if let Some(deinterlace) = self.deinterlace.as_ref() {
+            match deinterlace.method.trim() {
+                "yadif" => required.push("yadif"),
+                "bwdif" => required.push("bwdif"),
+                _ => {}
+            }
+        }
+        if self
+            .crop
+            .as_ref()
+            .is_some_and(|crop| [crop.top, crop.bottom, crop.left, crop.right] != [0; 4])
+        {
+            required.push("crop");
+        }
+        if self
+            .tonemap
+            .as_ref()
+            .is_some_and(|tonemap| !tonemap.is_off())
+        {
+            required.extend(["zscale", "format", "tonemap"]);
+        }
+        if let Some(denoise) = self.denoise.as_ref() {
+            match denoise.method.trim() {
+                "hqdn3d" => required.push("hqdn3d"),
+                "nlmeans" => required.push("nlmeans"),
+                _ => {}
+            }
+        }
+        if let Some(scale) = self.scale.as_ref() {
+            required.push("scale");
+            if scale.width.is_some_and(|width| width > 0)
+                && scale.height.is_some_and(|height| height > 0)
+            {
+                match scale.aspect_mode.as_deref().unwrap_or("keep") {
+                    "pad" => required.extend(["pad", "setsar"]),
+                    "fill" => required.extend(["crop", "setsar"]),
+                    "stretch" => required.push("setsar"),
+                    _ => {}
+                }
+            }
+        }
+        if self.fps.is_some() {
+            required.push("fps");
+        }
+        if self.sharpen.is_some() {
+            required.push("unsharp");
+        }
+        required
+    }
+}
+
+fn validate_dimension(name: &str, value: Option<u32>) -> Result<Option<u32>, String> {
+    match value {
+        None | Some(0) => Ok(None),
+        Some(value) if value > MAX_FILTER_DIMENSION => Err(format!(
+            "Scale {} must be at most {}",
+            name, MAX_FILTER_DIMENSION
+        )),
+        Some(value) if value % 2 != 0 => Err(format!("Scale {} must be even", name)),
+        Some(value) => Ok(Some(value)),
+    }
+}
+
+fn build_scale_filters(scale: &TranscodeScaleFilter) -> Result<Vec<String>, String> {
+    let width = validate_dimension("width", scale.width)?;
+    let height = validate_dimension("height", scale.height)?;
+    let flags = match scale.algorithm.as_deref().map(str::trim) {
+        None | Some("") => String::new(),
+        Some(algorithm @ ("bilinear" | "bicubic" | "lanczos" | "spline" | "area" | "neighbor")) => {
+            format!(":flags={}", algorithm)
+        }
+        Some(other) => return Err(format!("Unsupported scale algorithm: {}", other)),
+    };
+
+    let (width, height) = match (width, height) {
+        (None, None) => return Err("Scale filter needs a width or a height".to_string()),
+        // A single dimension keeps the source aspect ratio with an even counterpart.
+        (Some(width), None) => return Ok(vec![format!("scale={}:-2{}", width, flags)]),
+        (None, Some(height)) => return Ok(vec![format!("scale=-2:{}{}", height, flags)]),
+        (Some(width), Some(height)) => (width, height),
+    };
+
+    match scale.aspect_mode.as_deref().unwrap_or("keep") {
+        "keep" => Ok(vec![format!(
+            "scale={}:{}:force_original_aspect_ratio=decrease:force_divisible_by=2{}",
+            width, height, flags
+        )]),
+        "pad" => Ok(vec![
+            format!(
+                "scale={}:{}:force_original_aspect_ratio=decrease{}",
+                width, height, flags
+            ),
+            format!("pad={}:{}:(ow-iw)/2:(oh-ih)/2", width, height),
+            "setsar=1".to_string(),
+        ]),
+        "fill" => Ok(vec![
+            format!(
+                "scale={}:{}:force_original_aspect_ratio=increase{}",
+                width, height, flags
+            ),
+            format!("crop={}:{}", width, height),
+            "setsar=1".to_string(),
+        ]),
+        "stretch" => Ok(vec![
+            format!("scale={}:{}{}", width, height, flags),
+            "setsar=1".to_string(),
+        ]),
+        other => Err(format!("Unsupported scale aspect mode: {}", other)),
+    }
+}
+
+fn build_crop_filter(crop: &TranscodeCropFilter) -> Result<Option<String>, String> {
+    let edges = [crop.top, crop.bottom, crop.left, crop.right];
+    if edges.iter().all(|edge| *edge == 0) {
+        return Ok(None);
+    }
+    if edges
+        .iter()
+        .any(|edge| *edge % 2 != 0 || *edge > MAX_FILTER_DIMENSION)
+    {
+        return Err("Crop edges must be even and at most 16384 pixels".to_string());
+    }
+
+    Ok(Some(format!(
+        "crop=iw-{}:ih-{}:{}:{}",
+        crop.left + crop.right,
+        crop.top + crop.bottom,
+        crop.left,
+        crop.top
+    )))
+}
+
+fn build_deinterlace_filter(deinterlace: &TranscodeDeinterlaceFilter) -> Result<String, String> {
+    let method = deinterlace.method.trim();
+    if !matches!(method, "yadif" | "bwdif") {
+        return Err(format!("Unsupported deinterlace method: {}", method));
+    }
+    let mode = match deinterlace.mode.as_deref().unwrap_or("frame") {
+        "frame" => "send_frame",
+        "field" => "send_field",
+        other => return Err(format!("Unsupported deinterlace mode: {}", other)),
+    };
+    let deint = if deinterlace.only_interlaced {
+        "interlaced"
+    } else {
+        "all"
+    };
+
+    Ok(format!("{}=mode={}:deint={}", method, mode, deint))
+}
+
+fn build_denoise_filter(denoise: &TranscodeDenoiseFilter) -> Result<String, String> {
+    let strength = denoise.strength.as_deref().unwrap_or("medium");
+    match (denoise.method.trim(), strength) {
+        ("hqdn3d", "light") => Ok("hqdn3d=2:1.5:3:2.25".to_string()),
+        ("hqdn3d", "medium") => Ok("hqdn3d=4:3:6:4.5".to_string()),
+        ("hqdn3d", "strong") => Ok("hqdn3d=8:6:12:9".to_string()),
+        ("nlmeans", "light") => Ok("nlmeans=s=1.5".to_string()),
+        ("nlmeans", "medium") => Ok("nlmeans=s=3".to_string()),
+        ("nlmeans", "strong") => Ok("nlmeans=s=6".to_string()),
+        ("hqdn3d" | "nlmeans", other) => Err(format!("Unsupported denoise strength: {}", other)),
+        (other, _) => Err(format!("Unsupported denoise method: {}", other)),
+    }
+}
+
+fn build_fps_filter(fps: &TranscodeFpsFilter) -> Result<String, String> {
+    let value = fps.value.trim();
+    let error = || format!("Invalid frame rate: {}", value);
+    let parsed = if let Some((num, den)) = value.split_once('/') {
+        let num = num.parse::<u32>().map_err(|_| error())?;
+        let den = den.parse::<u32>().map_err(|_| error())?;
+        if den == 0 {
+            return Err(error());
+        }
+        num as f64 / den as f64
+    } else {
+        value.parse::<f64>().map_err(|_| error())?
+    };
+
+    if !parsed.is_finite() || parsed <= 0.0 || parsed > MAX_FILTER_FPS {
+        return Err(error());
+    }
+
+    Ok(format!("fps={}", value))
+}
+
+fn build_sharpen_filter(sharpen: &TranscodeSharpenFilter) -> Result<String, String> {
+    if !sharpen.amount.is_finite() || sharpen.amount <= 0.0 || sharpen.amount > MAX_SHARPEN_AMOUNT {
+        return Err(format!(
+            "Sharpen amount must be greater than 0 and at most {}",
+            MAX_SHARPEN_AMOUNT
+        ));
+    }
+
+    Ok(format!("unsharp=5:5:{}:5:5:0", sharpen.amount))
+}
+
+pub(crate) fn is_hdr_transfer(color_transfer: &str) -> bool {
+    matches!(color_transfer, "smpte2084" | "arib-std-b67")
+}
+
+fn is_8bit_pixel_format(pixel_format: &str) -> bool {
+    let pixel_format = pixel_format.trim();
+    if matches!(pixel_format, "nv12" | "nv21") {
+        return true;
+    }
+
+    !pixel_format.is_empty()
+        && !["10", "12", "14", "16", "f32"]
+            .iter()
+            .any(|marker| pixel_format.contains(marker))
+}
+
+/// Turn tonemapping on for HDR sources encoded to an 8-bit pixel format, unless
+/// the settings already choose an algorithm or switch it off.
+pub(crate) fn with_automatic_tonemap<'a>(
+    filters: &'a TranscodeVideoFilters,
+    color_transfer: Option<&str>,
+    pixel_format: Option<&str>,
+) -> Cow<'a, TranscodeVideoFilters> {
+    if filters.tonemap.is_some()
+        || !color_transfer.is_some_and(is_hdr_transfer)
+        || !pixel_format.is_some_and(is_8bit_pixel_format)
+    {
+        return Cow::Borrowed(filters);
+    }
+
+    Cow::Owned(TranscodeVideoFilters {
+        tonemap: Some(TranscodeTonemapFilter::default()),
+        ..filters.clone()
+    })
+}
+
+fn build_tonemap_filters(
+    tonemap: &TranscodeTonemapFilter,
+    pixel_format: Option<&str>,
+) -> Result<Vec<String>, String> {
+    let algorithm = match tonemap.algorithm.as_deref().map(str::trim) {
+        None | Some("") => "hable",
+        Some(algorithm @ ("hable" | "mobius" | "reinhard")) => algorithm,
+        Some(other) => return Err(format!("Unsupported tonemap algorithm: {}", other)),
+    };
+    let pixel_format = match pixel_format.map(str::trim) {
+        None | Some("") => "yuv420p",
+        Some(pixel_format)
+            if pixel_format
+                .chars()
+                .all(|character| character.is_ascii_alphanumeric() || character == '_') =>
+        {
+            pixel_format
+        }
+        Some(other) => return Err(format!("Invalid pixel format: {}", other)),
+    };
+
+    // Linearize, map the BT.2020 primaries to BT.709 in float, tonemap, then
+    // re-apply the BT.709 transfer and matrix.
+    Ok(vec![
+        "zscale=t=linear:npl=100".to_string(),
+        "format=gbrpf32le".to_string(),
+        "zscale=p=bt709".to_string(),
+        format!("tonemap=tonemap={}:desat=0", algorithm),
+        "zscale=t=bt709:m=bt709:r=tv".to_string(),
+        format!("format={}", pixel_format),
+    ])
+}
+
+/// Build a `-vf` chain from the typed filter settings.
+///
+/// Filters run in a fixed order: deinterlace, crop, tonemap, denoise, scale,
+/// fps, sharpen. `pixel_format` is the encoder's output format, which the
+/// tonemap chain converts to.
+pub(crate) fn build_video_filter_graph(
+    filters: &TranscodeVideoFilters,
+    pixel_format: Option<&str>,
+) -> Result<Option<String>, String> {
+    let mut chain = Vec::new();
+
+    if let Some(deinterlace) = filters.deinterlace.as_ref() {
+        chain.push(build_deinterlace_filter(deinterlace)?);
+    }
+    if let Some(crop) = filters.crop.as_ref()
+        && let Some(filter) = build_crop_filter(crop)?
+    {
+        chain.push(filter);
+    }
+    if let Some(tonemap) = filters.tonemap.as_ref()
+        && !tonemap.is_off()
+    {
+        chain.extend(build_tonemap_filters(tonemap, pixel_format)?);
+    }
+    if let Some(denoise) = filters.denoise.as_ref() {
+        chain.push(build_denoise_filter(denoise)?);
+    }
+    if let Some(scale) = filters.scale.as_ref() {
+        chain.extend(build_scale_filters(scale)?);
+    }
+    if let Some(fps) = filters.fps.as_ref() {
+        chain.push(build_fps_filter(fps)?);
+    }
+    if let Some(sharpen) = filters.sharpen.as_ref() {
+        chain.push(build_sharpen_filter(sharpen)?);
+    }
+
+    Ok((!chain.is_empty()).then(|| chain.join(",")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        TranscodeCropFilter, TranscodeDeinterlaceFilter, TranscodeDenoiseFilter,
+        TranscodeFpsFilter, TranscodeScaleFilter, TranscodeSharpenFilter, TranscodeTonemapFilter,
+        TranscodeVideoFilters, build_video_filter_graph, with_automatic_tonemap,
+    };
+
+    fn scale(width: Option<u32>, height: Option<u32>, aspect_mode: &str) -> TranscodeScaleFilter {
+        TranscodeScaleFilter {
+            width,
+            height,
+            aspect_mode: Some(aspect_mode.to_string()),
+            algorithm: None,
+        }
+    }
+
+    fn graph(filters: TranscodeVideoFilters) -> String {
+        build_video_filter_graph(&filters, None)
+            .expect("filter graph should build")
+            .expect("filter graph should not be empty")
+    }
+
+    #[test]
+    fn required_filters_cover_every_filter_in_the_graph() {
+        let filters = TranscodeVideoFilters {
+            scale: Some(scale(Some(1920), Some(1080), "pad")),
+            crop: Some(TranscodeCropFilter {
+                top: 4,
+                ..TranscodeCropFilter::default()
+            }),
+            deinterlace: Some(TranscodeDeinterlaceFilter {
+                method: "bwdif".to_string(),
+                mode: None,
+                only_interlaced: false,
+            }),
+            denoise: Some(TranscodeDenoiseFilter {
+                method: "nlmeans".to_string(),
+                strength: None,
+            }),
+            fps: Some(TranscodeFpsFilter {
+                value: "24".to_string(),
+            }),
+            sharpen: Some(TranscodeSharpenFilter { amount: 0.5 }),
+            tonemap: Some(TranscodeTonemapFilter::default()),
+        };
+
+        let mut used = graph(filters.clone())
+            .split(',')
+            .map(|filter| filter.split('=').next().unwrap_or(filter).to_string())
+            .collect::<Vec<_>>();
+        used.sort();
+        used.dedup();
+        let mut required = filters.required_filters();
+        required.sort();
+        required.dedup();
+
+        assert_eq!(used, required);
+        assert!(
+            TranscodeVideoFilters::default()
+                .required_filters()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn build_video_filter_graph_returns_none_without_filters() {
+        let filters = TranscodeVideoFilters {
+            crop: Some(TranscodeCropFilter::default()),
+            ..TranscodeVideoFilters::default()
+        };
+
+        assert!(
+            build_video_filter_graph(&filters, None)
+                .expect("empty graph should build")
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn build_video_filter_graph_scales_with_aspect_modes() {
+        let keep = graph(TranscodeVideoFilters {
+            scale: Some(scale(Some(1920), Some(1080), "keep")),
+            ..TranscodeVideoFilters::default()
+        });
+        assert_eq!(
+            keep,
+            "scale=1920:1080:force_original_aspect_ratio=decrease:force_divisible_by=2"
+        );
+
+        let pad = graph(TranscodeVideoFilters {
+            scale: Some(scale(Some(1920), Some(1080), "pad")),
+            ..TranscodeVideoFilters::default()
+        });
+        assert!(pad.contains("pad=1920:1080:(ow-iw)/2:(oh-ih)/2,setsar=1"));
+
+        let width_only = graph(TranscodeVideoFilters {
+            scale: Some(TranscodeScaleFilter {
+                algorithm: Some("lanczos".to_string()),
+                ..scale(Some(1280), None, "keep")
+            }),
+            ..TranscodeVideoFilters::default()
+        });
+        assert_eq!(width_only, "scale=1280:-2:flags=lanczos");
+    }
+
+    #[test]
+    fn build_video_filter_graph_orders_the_full_chain() {
+        let chain = graph(TranscodeVideoFilters {
+            scale: Some(scale(None, Some(720), "keep")),
+            crop: Some(TranscodeCropFilter {
+                top: 140,
+                bottom: 140,
+                left: 0,
+                right: 0,
+            }),
+            deinterlace: Some(TranscodeDeinterlaceFilter {
+                method: "bwdif".to_string(),
+                mode: Some("field".to_string()),
+                only_interlaced: true,
+            }),
+            denoise: Some(TranscodeDenoiseFilter {
+                method: "hqdn3d".to_string(),
+                strength: Some("light".to_string()),
+            }),
+            fps: Some(TranscodeFpsFilter {
+                value: "24000/1001".to_string(),
+            }),
+            sharpen: Some(TranscodeSharpenFilter { amount: 0.5 }),
+            tonemap: None,
+        });
+
+        assert_eq!(
+            chain,
+            "bwdif=mode=send_field:deint=interlaced,crop=iw-0:ih-280:0:140,hqdn3d=2:1.5:3:2.25,scale=-2:720,fps=24000/1001,unsharp=5:5:0.5:5:5:0"
+        );
+    }
+
+    #[test]
+    fn tonemap_switches_on_for_hdr_sources_encoded_to_8bit() {
+        let filters = TranscodeVideoFilters {
+            scale: Some(scale(None, Some(1080), "keep")),
+            ..TranscodeVideoFilters::default()
+        };
+
+        let automatic = with_automatic_tonemap(&filters, Some("smpte2084"), Some("yuv420p"));
+        assert_eq!(
+            build_video_filter_graph(&automatic, Some("yuv420p"))
+                .expect("tonemap graph should build")
+                .as_deref(),
+            Some(
+                "zscale=t=linear:npl=100,format=gbrpf32le,zscale=p=bt709,tonemap=tonemap=hable:desat=0,zscale=t=bt709:m=bt709:r=tv,format=yuv420p,scale=-2:1080"
+            )
+        );
+        assert!(
+            with_automatic_tonemap(&filters, Some("arib-std-b67"), Some("nv12"))
+                .tonemap
+                .is_some()
+        );
+        assert!(
+            with_automatic_tonemap(&filters, Some("smpte2084"), Some("yuv420p10le"))
+                .tonemap
+                .is_none()
+        );
+        assert!(
+            with_automatic_tonemap(&filters, Some("bt709"), Some("yuv420p"))
+                .tonemap
+                .is_none()
+        );
+
+        let off = TranscodeVideoFilters {
+            tonemap: Some(TranscodeTonemapFilter {
+                algorithm: Some("off".to_string()),
+            }),
+            ..TranscodeVideoFilters::default()
+        };
+        let off = with_automatic_tonemap(&off, Some("smpte2084"), Some("yuv420p"));
+        assert!(off.is_empty());
+        assert!(
+            build_video_filter_graph(&off, Some("yuv420p"))
+                .expect("graph should build")
+                .is_none()
+        );
+
+        let mobius = graph(TranscodeVideoFilters {
+            tonemap: Some(TranscodeTonemapFilter {
+                algorithm: Some("mobius".to_string()),
+            }),
+            ..TranscodeVideoFilters::default()
+        });
+        assert!(mobius.contains("tonemap=tonemap=mobius:desat=0"));
+        assert!(mobius.ends_with("format=yuv420p"));
+    }
+
+    #[test]
+    fn build_video_filter_graph_rejects_invalid_values() {
+        let cases = [
+            TranscodeVideoFilters {
+                scale: Some(scale(Some(1919), Some(1080), "keep")),
+                ..TranscodeVideoFilters::default()
+            },
+            TranscodeVideoFilters {
+                scale: Some(scale(Some(1920), Some(1080), "zoom")),
+                ..TranscodeVideoFilters::default()
+            },
+            TranscodeVideoFilters {
+                deinterlace: Some(TranscodeDeinterlaceFilter {
+                    method: "kerndeint".to_string(),
+                    ..TranscodeDeinterlaceFilter::default()
+                }),
+                ..TranscodeVideoFilters::default()
+            },
+            TranscodeVideoFilters {
+                fps: Some(TranscodeFpsFilter {
+                    value: "30,scale=1:1".to_string(),
+                }),
+                ..TranscodeVideoFilters::default()
+            },
+            TranscodeVideoFilters {
+                sharpen: Some(TranscodeSharpenFilter { amount: 3.0 }),
+                ..TranscodeVideoFilters::default()
+            },
+            TranscodeVideoFilters {
+                tonemap: Some(TranscodeTonemapFilter {
+                    algorithm: Some("clip".to_string()),
+                }),
+                ..TranscodeVideoFilters::default()
+            },
+        ];
+
+        for filters in cases {
+            assert!(build_video_filter_graph(&filters, None).is_err());
+        }
+    }
+}
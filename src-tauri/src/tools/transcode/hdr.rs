@@ -0,0 +1,354 @@
+use serde_json::Value;
+use tokio::process::Command;
+
+use super::filters::is_hdr_transfer;
+
+const MASTERING_DISPLAY_SIDE_DATA: &str = "Mastering display metadata";
+const CONTENT_LIGHT_SIDE_DATA: &str = "Content light level metadata";
+const DOLBY_VISION_SIDE_DATA: &str = "DOVI configuration record";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct MasteringDisplay {
+    /// CIE 1931 xy chromaticities.
+    pub(crate) red: (f64, f64),
+    pub(crate) green: (f64, f64),
+    pub(crate) blue: (f64, f64),
+    pub(crate) white_point: (f64, f64),
+    /// Luminance in cd/m².
+    pub(crate) min_luminance: f64,
+    pub(crate) max_luminance: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ContentLightLevel {
+    pub(crate) max_content: u32,
+    pub(crate) max_average: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct DolbyVisionConfig {
+    pub(crate) profile: u8,
+    pub(crate) level: u8,
+    pub(crate) rpu_present: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct HdrSideData {
+    pub(crate) mastering_display: Option<MasteringDisplay>,
+    pub(crate) content_light_level: Option<ContentLightLevel>,
+    pub(crate) dolby_vision: Option<DolbyVisionConfig>,
+}
+
+impl HdrSideData {
+    pub(crate) fn has_hdr10_metadata(&self) -> bool {
+        self.mastering_display.is_some() || self.content_light_level.is_some()
+    }
+}
+
+/// ffprobe reports side data values either as numbers or as `num/den` strings.
+fn parse_side_data_number(entry: &Value, key: &str) -> Option<f64> {
+    match entry.get(key)? {
+        Value::Number(number) => number.as_f64(),
+        Value::String(value) => match value.split_once('/') {
+            Some((numerator, denominator)) => {
+                let numerator = numerator.trim().parse::<f64>().ok()?;
+                let denominator = denominator.trim().parse::<f64>().ok()?;
+                (denominator != 0.0).then(|| numerator / denominator)
+            }
+            None => value.trim().parse::<f64>().ok(),
+        },
+        _ => None,
+    }
+}
+
+fn parse_side_data_point(entry: &Value, prefix: &str) -> Option<(f64, f64)> {
+    Some((
+        parse_side_data_number(entry, &format!("{}_x", prefix))?,
+        parse_side_data_number(entry, &format!("{}_y", prefix))?,
+    ))
+}
+
+fn parse_mastering_display(entry: &Value) -> Option<MasteringDisplay> {
+    Some(MasteringDisplay {
+        red: parse_side_data_point(entry, "red")?,
+        green: parse_side_data_point(entry, "green")?,
+        blue: parse_side_data_point(entry, "blue")?,
+        white_point: parse_side_data_point(entry, "white_point")?,
+        min_luminance: parse_side_data_number(entry, "min_luminance")?,
+        max_luminance: parse_side_data_number(entry, "max_luminance")?,
+    })
+}
+
+fn parse_content_light_level(entry: &Value) -> Option<ContentLightLevel> {
+    let max_content = parse_side_data_number(entry, "max_content")? as u32;
+    let max_average = parse_side_data_number(entry, "max_average")? as u32;
+    (max_content > 0 || max_average > 0).then_some(ContentLightLevel {
+        max_content,
+        max_average,
+    })
+}
+
+fn parse_dolby_vision_config(entry: &Value) -> Option<DolbyVisionConfig> {
+    Some(DolbyVisionConfig {
+        profile: parse_side_data_number(entry, "dv_profile")? as u8,
+        level: parse_side_data_number(entry, "dv_level").unwrap_or(0.0) as u8,
+        rpu_present: parse_side_data_number(entry, "rpu_present_flag")
+            .is_some_and(|flag| flag > 0.0),
+    })
+}
+
+/// HDR metadata from a probed stream's `side_data_list`, including any frame
+/// side data merged in by [`probe_hdr_frame_side_data`].
+pub(crate) fn hdr_side_data(probe_stream: &Value) -> HdrSideData {
+    let mut side_data = HdrSideData::default();
+    let Some(entries) = probe_stream
+        .get("side_data_list")
+        .and_then(|value| value.as_array())
+    else {
+        return side_data;
+    };
+
+    for entry in entries {
+        match entry.get("side_data_type").and_then(|value| value.as_str()) {
+            Some(MASTERING_DISPLAY_SIDE_DATA) if side_data.mastering_display.is_none() => {
+                side_data.mastering_display = parse_mastering_display(entry);
+            }
+            Some(CONTENT_LIGHT_SIDE_DATA) if side_data.content_light_level.is_none() => {
+                side_data.content_light_level = parse_content_light_level(entry);
+            }
+            Some(DOLBY_VISION_SIDE_DATA) if side_data.dolby_vision.is_none() => {
+                side_data.dolby_vision = parse_dolby_vision_config(entry);
+            }
+            _ => {}
+        }
+    }
+
+    side_data
+}
+
+/// Encoder option that carries key=value parameters for HDR signalling.
+pub(crate) fn encoder_params_flag(encoder_id: &str) -> Option<&'static str> {
+    match encoder_id {
+        "libx265" => Some("-x265-params"),
+        "libsvtav1" => Some("-svtav1-params"),
+        _ => None,
+    }
+}
+
+/// `master-display`/`max-cll` for x265 and `mastering-display`/`content-light`
+/// for SVT-AV1; other encoders take HDR10 metadata from the frames themselves.
+pub(crate) fn hdr_encoder_params(encoder_id: &str, side_data: &HdrSideData) -> Vec<String> {
+    let mut params = Vec::new();
+    match encoder_id {
+        "libx265" => {
+            // x265 wants chromaticities in 0.00002 and luminance in 0.0001 cd/m² steps.
+            let chromaticity = |(x, y): (f64, f64)| {
+                format!("({},{})", (x * 50_000.0).round(), (y * 50_000.0).round())
+            };
+            if let Some(display) = side_data.mastering_display {
+                params.push(format!(
+                    "master-display=G{}B{}R{}WP{}L({},{})",
+                    chromaticity(display.green),
+                    chromaticity(display.blue),
+                    chromaticity(display.red),
+                    chromaticity(display.white_point),
+                    (display.max_luminance * 10_000.0).round(),
+                    (display.min_luminance * 10_000.0).round()
+                ));
+            }
+            if let Some(light) = side_data.content_light_level {
+                params.push(format!(
+                    "max-cll={},{}",
+                    light.max_content, light.max_average
+                ));
+            }
+        }
+        "libsvtav1" => {
+            let chromaticity = |(x, y): (f64, f64)| format!("({:.4},{:.4})", x, y);
+            if let Some(display) = side_data.mastering_display {
+                params.push(format!(
+                    "mastering-display=G{}B{}R{}WP{}L({:.4},{:.4})",
+                    chromaticity(display.green),
+                    chromaticity(display.blue),
+                    chromaticity(display.red),
+                    chromaticity(display.white_point),
+                    display.max_luminance,
+                    display.min_luminance
+                ));
+            }
+            if let Some(light) = side_data.content_light_level {
+                params.push(format!(
+                    "content-light={},{}",
+                    light.max_content, light.max_average
+                ));
+            }
+            if !params.is_empty() {
+                params.insert(0, "enable-hdr=1".to_string());
+            }
+        }
+        _ => {}
+    }
+    params
+}
+
+/// Mastering display and light level data usually live on the frames, so copy
+/// the first frame's side data onto HDR video streams that lack it.
+pub(crate) async fn probe_hdr_frame_side_data(
+    ffprobe_path: &str,
+    input_path: &str,
+    streams: &mut [Value],
+) {
+    let mut video_relative_index = 0;
+    for stream in streams.iter_mut() {
+        if stream.get("codec_type").and_then(|value| value.as_str()) != Some("video") {
+            continue;
+        }
+        let relative_index = video_relative_index;
+        video_relative_index += 1;
+
+        let is_hdr = stream
+            .get("color_transfer")
+            .and_then(|value| value.as_str())
+            .is_some_and(is_hdr_transfer);
+        if !is_hdr || hdr_side_data(stream).has_hdr10_metadata() {
+            continue;
+        }
+
+        let Ok(output) = Command::new(ffprobe_path)
+            .args([
+                "-v",
+                "error",
+                "-select_streams",
+                &format!("v:{}", relative_index),
+                "-read_intervals",
+                "%+#1",
+                "-show_entries",
+                "frame=side_data_list",
+                "-of",
+                "json",
+                input_path,
+            ])
+            .output()
+            .await
+        else {
+            continue;
+        };
+        let frame_side_data = serde_json::from_slice::<Value>(&output.stdout)
+            .ok()
+            .and_then(|value| {
+                value
+                    .get("frames")?
+                    .as_array()?
+                    .first()?
+                    .get("side_data_list")?
+                    .as_array()
+                    .cloned()
+            })
+            .unwrap_or_default();
+        if frame_side_data.is_empty() {
+            continue;
+        }
+
+        if let Some(object) = stream.as_object_mut() {
+            let entries = object
+                .entry("side_data_list")
+                .or_insert_with(|| Value::Array(Vec::new()));
+            if let Some(entries) = entries.as_array_mut() {
+                entries.extend(frame_side_data);
+            }
+        }
+    }
+}
+
+/// FFmpeg 7 and later can carry Dolby Vision RPUs into libx265.
+pub(crate) async fn ffmpeg_supports_x265_dolby_vision(ffmpeg_path: &str) -> bool {
+    Command::new(ffmpeg_path)
+        .args(["-hide_banner", "-h", "encoder=libx265"])
+        .output()
+        .await
+        .map(|output| String::from_utf8_lossy(&output.stdout).contains("-dolbyvision"))
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{hdr_encoder_params, hdr_side_data};
+
+    fn hdr_stream() -> serde_json::Value {
+        json!({
+            "codec_type": "video",
+            "color_transfer": "smpte2084",
+            "side_data_list": [
+                {
+                    "side_data_type": "DOVI configuration record",
+                    "dv_profile": 8,
+                    "dv_level": 6,
+                    "rpu_present_flag": 1
+                },
+                {
+                    "side_data_type": "Mastering display metadata",
+                    "red_x": "34000/50000",
+                    "red_y": "16000/50000",
+                    "green_x": "13250/50000",
+                    "green_y": "34500/50000",
+                    "blue_x": "7500/50000",
+                    "blue_y": "3000/50000",
+                    "white_point_x": "15635/50000",
+                    "white_point_y": "16450/50000",
+                    "min_luminance": "50/10000",
+                    "max_luminance": "10000000/10000"
+                },
+                {
+                    "side_data_type": "Content light level metadata",
+                    "max_content": 1000,
+                    "max_average": 400
+                }
+            ]
+        })
+    }
+
+    #[test]
+    fn hdr_side_data_reads_mastering_light_level_and_dolby_vision() {
+        let side_data = hdr_side_data(&hdr_stream());
+
+        let display = side_data
+            .mastering_display
+            .expect("mastering display should parse");
+        assert_eq!(display.red, (0.68, 0.32));
+        assert_eq!(display.max_luminance, 1000.0);
+        assert_eq!(display.min_luminance, 0.005);
+        let light = side_data
+            .content_light_level
+            .expect("light level should parse");
+        assert_eq!((light.max_content, light.max_average), (1000, 400));
+        let dolby_vision = side_data.dolby_vision.expect("dovi should parse");
+        assert_eq!((dolby_vision.profile, dolby_vision.level), (8, 6));
+        assert!(dolby_vision.rpu_present);
+
+        assert!(!hdr_side_data(&json!({ "codec_type": "video" })).has_hdr10_metadata());
+    }
+
+    #[test]
+    fn hdr_encoder_params_use_each_encoders_units() {
+        let side_data = hdr_side_data(&hdr_stream());
+
+        assert_eq!(
+            hdr_encoder_params("libx265", &side_data),
+            vec![
+                "master-display=G(13250,34500)B(7500,3000)R(34000,16000)WP(15635,16450)L(10000000,50)",
+                "max-cll=1000,400",
+            ]
+        );
+        assert_eq!(
+            hdr_encoder_params("libsvtav1", &side_data),
+            vec![
+                "enable-hdr=1",
+                "mastering-display=G(0.2650,0.6900)B(0.1500,0.0600)R(0.6800,0.3200)WP(0.3127,0.3290)L(1000.0000,0.0050)",
+                "content-light=1000,400",
+            ]
+        );
+        assert!(hdr_encoder_params("libx264", &side_data).is_empty());
+    }
+}
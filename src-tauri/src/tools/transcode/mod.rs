@@ -1,5 +1,17 @@
 pub(crate) mod analysis;
+pub(crate) mod audio_filters;
+pub(crate) mod burn_in;
 pub(crate) mod cancel;
 pub(crate) mod capabilities;
+pub(crate) mod chunked;
+pub(crate) mod events;
+pub(crate) mod filters;
+pub(crate) mod hdr;
+pub(crate) mod packaging;
+pub(crate) mod preview;
+pub(crate) mod quality;
+pub(crate) mod ranges;
 mod state;
+pub(crate) mod target_quality;
 pub(crate) mod transcode;
+pub(crate) mod verify;
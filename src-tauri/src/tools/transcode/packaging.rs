@@ -0,0 +1,712 @@
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::shared::hash::stable_hash64;
+
+use super::events::TranscodeEvents;
+use super::filters::TranscodeScaleFilter;
+use super::transcode::{
+    TranscodeContext, TranscodePhase, TranscodeRequest, build_transcode_command_args,
+    check_output_streams, clear_transcode_state, emit_transcode_progress, expected_output_streams,
+    is_transcode_tracked, prepare_burn_in_fonts, run_transcode_pass, verification_failure,
+    verify_transcode_output,
+};
+use super::verify::{
+    ExpectedOutputStream, TranscodeVerificationReport, handle_failed_package, move_folder_files,
+};
+
+const DEFAULT_SEGMENT_SECONDS: u32 = 6;
+
+/// One step of the bitrate ladder; unset fields keep the request's settings.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TranscodeRendition {
+    /// Used in playlist and segment names; `<height>p` when unset.
+    pub(crate) name: Option<String>,
+    pub(crate) height: Option<u32>,
+    pub(crate) video_bitrate_kbps: Option<u32>,
+    pub(crate) audio_bitrate_kbps: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TranscodePackagingSettings {
+    /// `fmp4` (default) or `ts`; DASH only writes fMP4 segments.
+    pub(crate) segment_format: Option<String>,
+    pub(crate) segment_seconds: Option<u32>,
+    /// A single rendition with the request's own settings when empty.
+    #[serde(default)]
+    pub(crate) renditions: Vec<TranscodeRendition>,
+}
+
+impl TranscodePackagingSettings {
+    pub(crate) fn segment_format(&self, container_id: &str) -> Result<&str, String> {
+        match (
+            container_id,
+            self.segment_format.as_deref().unwrap_or("fmp4"),
+        ) {
+            (_, "fmp4") => Ok("fmp4"),
+            ("hls", "ts") => Ok("ts"),
+            ("dash", "ts") => Err("DASH packaging only supports fMP4 segments".to_string()),
+            (_, other) => Err(format!("Unsupported segment format: {}", other)),
+        }
+    }
+
+    pub(crate) fn segment_seconds(&self) -> u32 {
+        self.segment_seconds
+            .unwrap_or(DEFAULT_SEGMENT_SECONDS)
+            .max(1)
+    }
+
+    /// Playlist-safe rendition names, in ladder order.
+    pub(crate) fn rendition_names(&self) -> Result<Vec<String>, String> {
+        let mut names = Vec::<String>::new();
+        for (index, rendition) in self.renditions.iter().enumerate() {
+            let name = match (rendition.name.as_deref().map(str::trim), rendition.height) {
+                (Some(name), _) if !name.is_empty() => name
+                    .chars()
+                    .map(|character| {
+                        if character.is_ascii_alphanumeric() || matches!(character, '-' | '_') {
+                            character
+                        } else {
+                            '_'
+                        }
+                    })
+                    .collect(),
+                (_, Some(height)) => format!("{}p", height),
+                _ => format!("r{}", index),
+            };
+            if names.contains(&name) {
+                return Err(format!("Rendition name {} is used more than once", name));
+            }
+            names.push(name);
+        }
+        if names.is_empty() {
+            names.push("main".to_string());
+        }
+        Ok(names)
+    }
+}
+
+/// HLS and DASH write a playlist or manifest with segments next to it.
+pub(crate) fn is_packaging_container(container_id: &str) -> bool {
+    matches!(container_id, "hls" | "dash")
+}
+
+/// Temporary folder holding the encoded renditions until they are packaged.
+pub(crate) fn build_rendition_dir(input_path: &str, output_path: &str) -> PathBuf {
+    std::env::temp_dir()
+        .join("mediaflow_transcode_renditions")
+        .join(format!(
+            "{:016x}",
+            stable_hash64(&format!("{}\n{}", input_path, output_path))
+        ))
+}
+
+/// Hidden folder next to the output that the package is written into, so a
+/// failed or cancelled run leaves no playlists or segments behind.
+pub(crate) fn build_packaging_staging_dir(output_path: &str) -> PathBuf {
+    let output = Path::new(output_path);
+    let file_name = output
+        .file_name()
+        .map(|value| value.to_string_lossy().to_string())
+        .unwrap_or_default();
+    output
+        .parent()
+        .unwrap_or_else(|| Path::new(""))
+        .join(format!(".{}.partial", file_name))
+}
+
+/// An encoded rendition ready to be segmented.
+#[derive(Debug, Clone)]
+pub(crate) struct PackagedRendition {
+    pub(crate) name: String,
+    pub(crate) path: PathBuf,
+    pub(crate) has_audio: bool,
+}
+
+/// Remux the renditions into segments plus an HLS master playlist or a DASH
+/// manifest at `output_path`; every rendition carries its first audio track.
+pub(crate) fn build_packaging_args(
+    container_id: &str,
+    settings: &TranscodePackagingSettings,
+    renditions: &[PackagedRendition],
+    output_path: &str,
+) -> Result<Vec<String>, String> {
+    let segment_format = settings.segment_format(container_id)?;
+    let output = Path::new(output_path);
+    let output_dir = output.parent().unwrap_or_else(|| Path::new(""));
+    let output_stem = output
+        .file_stem()
+        .and_then(|value| value.to_str())
+        .ok_or_else(|| "Output path has no file name".to_string())?;
+
+    let mut args = vec!["-y".to_string()];
+    for rendition in renditions {
+        args.push("-i".to_string());
+        args.push(rendition.path.to_string_lossy().to_string());
+    }
+
+    let mut var_stream_map = Vec::new();
+    let mut audio_count = 0usize;
+    for (input_index, rendition) in renditions.iter().enumerate() {
+        args.push("-map".to_string());
+        args.push(format!("{}:v:0", input_index));
+        let mut variant = format!("v:{}", input_index);
+        if rendition.has_audio {
+            args.push("-map".to_string());
+            args.push(format!("{}:a:0", input_index));
+            variant.push_str(&format!(",a:{}", audio_count));
+            audio_count += 1;
+        }
+        variant.push_str(&format!(",name:{}", rendition.name));
+        var_stream_map.push(variant);
+    }
+    args.push("-c".to_string());
+    args.push("copy".to_string());
+
+    let segment_seconds = settings.segment_seconds().to_string();
+    match container_id {
+        "hls" => {
+            let (segment_type, segment_extension) = match segment_format {
+                "ts" => ("mpegts", "ts"),
+                _ => ("fmp4", "m4s"),
+            };
+            let file_name = output
+                .file_name()
+                .and_then(|value| value.to_str())
+                .ok_or_else(|| "Output path has no file name".to_string())?;
+            args.extend(
+                [
+                    "-f",
+                    "hls",
+                    "-hls_time",
+                    &segment_seconds,
+                    "-hls_playlist_type",
+                    "vod",
+                    "-hls_flags",
+                    "independent_segments",
+                    "-hls_segment_type",
+                    segment_type,
+                ]
+                .map(str::to_string),
+            );
+            if segment_format == "fmp4" {
+                args.push("-hls_fmp4_init_filename".to_string());
+                args.push(format!("{}_%v_init.mp4", output_stem));
+            }
+            args.push("-hls_segment_filename".to_string());
+            args.push(
+                output_dir
+                    .join(format!("{}_%v_%05d.{}", output_stem, segment_extension))
+                    .to_string_lossy()
+                    .to_string(),
+            );
+            args.push("-master_pl_name".to_string());
+            args.push(file_name.to_string());
+            args.push("-var_stream_map".to_string());
+            args.push(var_stream_map.join(" "));
+            args.push("-progress".to_string());
+            args.push("pipe:1".to_string());
+            args.push(
+                output_dir
+                    .join(format!("{}_%v.m3u8", output_stem))
+                    .to_string_lossy()
+                    .to_string(),
+            );
+        }
+        "dash" => {
+            let mut adaptation_sets = vec!["id=0,streams=v".to_string()];
+            if audio_count > 0 {
+                adaptation_sets.push("id=1,streams=a".to_string());
+            }
+            args.extend(
+                [
+                    "-f",
+                    "dash",
+                    "-seg_duration",
+                    &segment_seconds,
+                    "-use_template",
+                    "1",
+                    "-use_timeline",
+                    "1",
+                ]
+                .map(str::to_string),
+            );
+            args.push("-init_seg_name".to_string());
+            args.push(format!("{}_init_$RepresentationID$.m4s", output_stem));
+            args.push("-media_seg_name".to_string());
+            args.push(format!(
+                "{}_$RepresentationID$_$Number%05d$.m4s",
+                output_stem
+            ));
+            args.push("-adaptation_sets".to_string());
+            args.push(adaptation_sets.join(" "));
+            args.push("-progress".to_string());
+            args.push("pipe:1".to_string());
+            args.push(output_path.to_string());
+        }
+        other => return Err(format!("{} is not a packaging container", other)),
+    }
+
+    Ok(args)
+}
+
+pub(crate) fn validate_packaging_request(request: &TranscodeRequest) -> Result<(), String> {
+    if !is_packaging_container(&request.container_id) {
+        return Ok(());
+    }
+    request.packaging.segment_format(&request.container_id)?;
+    request.packaging.rendition_names()?;
+    if request.video.chunked.is_some()
+        || request.video.target_quality.is_some()
+        || request.video.quality_mode.as_deref() == Some("targetSize")
+    {
+        return Err(
+            "HLS and DASH packaging does not support chunked, target size or target quality encoding"
+                .to_string(),
+        );
+    }
+    if !request.packaging.renditions.is_empty() && request.video.mode != "transcode" {
+        return Err("A bitrate ladder requires video transcoding".to_string());
+    }
+    Ok(())
+}
+
+/// One rendition of a packaged output, encoded to an MP4 in the rendition folder.
+fn build_rendition_request(
+    request: &TranscodeRequest,
+    rendition_index: usize,
+    output_path: &Path,
+) -> TranscodeRequest {
+    let mut rendition_request = request.clone();
+    rendition_request.container_id = "mp4".to_string();
+    rendition_request.output_path = output_path.to_string_lossy().to_string();
+    rendition_request.subtitles.mode = "disable".to_string();
+    rendition_request.subtitles.track_overrides.clear();
+    rendition_request.packaging = TranscodePackagingSettings::default();
+    rendition_request.compare_quality = false;
+
+    let Some(rendition) = request.packaging.renditions.get(rendition_index) else {
+        return rendition_request;
+    };
+    if let Some(height) = rendition.height {
+        let algorithm = request
+            .video
+            .filters
+            .scale
+            .as_ref()
+            .and_then(|scale| scale.algorithm.clone());
+        rendition_request.video.filters.scale = Some(TranscodeScaleFilter {
+            width: None,
+            height: Some(height),
+            aspect_mode: None,
+            algorithm,
+        });
+    }
+    if let Some(bitrate_kbps) = rendition.video_bitrate_kbps {
+        rendition_request.video.quality_mode = Some("bitrate".to_string());
+        rendition_request.video.bitrate_kbps = Some(bitrate_kbps);
+    }
+    if let Some(bitrate_kbps) = rendition.audio_bitrate_kbps {
+        rendition_request.audio.bitrate_kbps = Some(bitrate_kbps);
+    }
+    rendition_request
+}
+
+pub(crate) struct PackagingPlan {
+    pub(crate) rendition_dir: PathBuf,
+    pub(crate) renditions: Vec<PackagedRendition>,
+    pub(crate) rendition_requests: Vec<TranscodeRequest>,
+    /// ffmpeg invocations per rendition, two for two-pass encodes.
+    pub(crate) rendition_args: Vec<Vec<Vec<String>>>,
+    pub(crate) passlog_dirs: Vec<PathBuf>,
+    /// Packaging writes here first; files move next to the output once verified.
+    pub(crate) staging_dir: PathBuf,
+    pub(crate) staged_output_path: String,
+    /// Streams the manifest should list: each rendition's video and first audio track.
+    pub(crate) expected_streams: Vec<ExpectedOutputStream>,
+    pub(crate) packaging_args: Vec<String>,
+}
+
+impl PackagingPlan {
+    pub(crate) fn pass_count(&self) -> u8 {
+        let encode_passes = self.rendition_args.iter().map(Vec::len).sum::<usize>();
+        (encode_passes + 1) as u8
+    }
+}
+
+/// Every rendition encode followed by the remux into segments; nothing is written.
+pub(crate) fn plan_packaged_transcode(
+    request: &TranscodeRequest,
+    context: &TranscodeContext,
+    streams: &[Value],
+    duration_us: Option<u64>,
+) -> Result<PackagingPlan, String> {
+    validate_packaging_request(request)?;
+    let rendition_dir = build_rendition_dir(&request.input_path, &request.output_path);
+    let rendition_context = TranscodeContext {
+        segment_seconds: Some(request.packaging.segment_seconds()),
+        ..context.clone()
+    };
+    let staging_dir = build_packaging_staging_dir(&request.output_path);
+    let output_file_name = Path::new(&request.output_path)
+        .file_name()
+        .ok_or_else(|| "Output path has no file name".to_string())?;
+    let mut plan = PackagingPlan {
+        rendition_dir: rendition_dir.clone(),
+        renditions: Vec::new(),
+        rendition_requests: Vec::new(),
+        rendition_args: Vec::new(),
+        passlog_dirs: Vec::new(),
+        staged_output_path: staging_dir
+            .join(output_file_name)
+            .to_string_lossy()
+            .to_string(),
+        staging_dir,
+        expected_streams: Vec::new(),
+        packaging_args: Vec::new(),
+    };
+
+    for (rendition_index, name) in request.packaging.rendition_names()?.into_iter().enumerate() {
+        let path = rendition_dir.join(format!("{}.mp4", name));
+        let rendition_request = build_rendition_request(request, rendition_index, &path);
+        let passlog_dir = rendition_dir.join(format!("{}-passlog", name));
+        let args = build_transcode_command_args(
+            &rendition_request,
+            &rendition_context,
+            streams,
+            duration_us,
+            &passlog_dir,
+        )?;
+        if args.len() > 1 {
+            plan.passlog_dirs.push(passlog_dir);
+        }
+        let rendition_streams = expected_output_streams(&rendition_request, streams)?;
+        let first_of_type = |codec_type: &str| {
+            rendition_streams
+                .iter()
+                .find(|stream| stream.codec_type == codec_type)
+                .cloned()
+        };
+        let audio_stream = first_of_type("audio");
+        let has_audio = audio_stream.is_some();
+        plan.expected_streams
+            .extend(first_of_type("video").into_iter().chain(audio_stream));
+
+        plan.renditions.push(PackagedRendition {
+            name,
+            path,
+            has_audio,
+        });
+        plan.rendition_requests.push(rendition_request);
+        plan.rendition_args.push(args);
+    }
+
+    plan.packaging_args = build_packaging_args(
+        &request.container_id,
+        &request.packaging,
+        &plan.renditions,
+        &plan.staged_output_path,
+    )?;
+    Ok(plan)
+}
+
+fn create_packaging_dirs(plan: &PackagingPlan) -> Result<(), String> {
+    std::fs::create_dir_all(&plan.rendition_dir)
+        .map_err(|error| format!("Failed to create rendition folder: {}", error))?;
+    // Leftovers from an interrupted run would otherwise be moved in with the new package.
+    let _ = std::fs::remove_dir_all(&plan.staging_dir);
+    std::fs::create_dir_all(&plan.staging_dir)
+        .map_err(|error| format!("Failed to create packaging folder: {}", error))?;
+    for passlog_dir in &plan.passlog_dirs {
+        std::fs::create_dir_all(passlog_dir)
+            .map_err(|error| format!("Failed to create pass log directory: {}", error))?;
+    }
+    Ok(())
+}
+
+/// Encode every rendition, check it, then package them; progress covers all
+/// renditions plus the packaging step.
+pub(crate) async fn run_packaged_transcode(
+    events: &impl TranscodeEvents,
+    ffmpeg_path: &str,
+    ffprobe_path: &str,
+    request: &TranscodeRequest,
+    context: &TranscodeContext,
+    streams: &[Value],
+    duration_us: Option<u64>,
+) -> Result<(), String> {
+    let plan = plan_packaged_transcode(request, context, streams, duration_us)?;
+    create_packaging_dirs(&plan)?;
+    let pass_count = plan.pass_count();
+
+    if let Ok(mut guard) = super::state::TRANSCODE_OUTPUT_PATHS.lock() {
+        guard.insert(request.input_path.clone(), request.output_path.clone());
+    }
+    if let Ok(mut guard) = super::state::TRANSCODE_PASSLOG_DIRS.lock() {
+        guard.insert(
+            request.input_path.clone(),
+            plan.rendition_dir.to_string_lossy().to_string(),
+        );
+    }
+
+    let result = async {
+        let mut pass = 0u8;
+        for (rendition_request, pass_args) in
+            plan.rendition_requests.iter().zip(&plan.rendition_args)
+        {
+            let fonts_dir = prepare_burn_in_fonts(ffmpeg_path, rendition_request, streams).await?;
+            let mut rendition_result = Ok(());
+            for args in pass_args {
+                if !is_transcode_tracked(&request.input_path) {
+                    rendition_result = Err("Transcode cancelled".to_string());
+                    break;
+                }
+                pass += 1;
+                let phase = TranscodePhase { pass, pass_count };
+                rendition_result =
+                    run_transcode_pass(events, ffmpeg_path, request, args, duration_us, phase)
+                        .await;
+                if rendition_result.is_err() {
+                    break;
+                }
+            }
+            if let Some(fonts_dir) = fonts_dir {
+                let _ = std::fs::remove_dir_all(fonts_dir);
+            }
+            rendition_result?;
+
+            let expected_streams = expected_output_streams(rendition_request, streams)?;
+            let verification = verify_transcode_output(
+                ffprobe_path,
+                rendition_request,
+                &expected_streams,
+                duration_us,
+            )
+            .await?;
+            if let Some(error) = verification_failure(&verification) {
+                return Err(error);
+            }
+        }
+
+        if !is_transcode_tracked(&request.input_path) {
+            return Err("Transcode cancelled".to_string());
+        }
+        let phase = TranscodePhase {
+            pass: pass + 1,
+            pass_count,
+        };
+        run_transcode_pass(
+            events,
+            ffmpeg_path,
+            request,
+            &plan.packaging_args,
+            duration_us,
+            phase,
+        )
+        .await
+    }
+    .await;
+
+    clear_transcode_state(&request.input_path);
+    let _ = std::fs::remove_dir_all(&plan.rendition_dir);
+    let verification = match result {
+        Ok(()) => finish_packaged_output(ffprobe_path, request, &plan, duration_us).await,
+        Err(error) => Err(error),
+    };
+    let _ = std::fs::remove_dir_all(&plan.staging_dir);
+    let verification = verification?;
+    events.emit_event(
+        "media-transcode-verification",
+        serde_json::json!({
+            "inputPath": request.input_path,
+            "outputPath": request.output_path,
+            "report": verification
+        }),
+    );
+    if let Some(error) = verification_failure(&verification) {
+        return Err(error);
+    }
+
+    emit_transcode_progress(
+        events,
+        &request.input_path,
+        &request.output_path,
+        100,
+        None,
+        TranscodePhase {
+            pass: pass_count,
+            pass_count,
+        },
+    );
+    Ok(())
+}
+
+/// Probe the staged playlist or manifest, then move the package next to the
+/// output unless verification failed and the settings drop it.
+async fn finish_packaged_output(
+    ffprobe_path: &str,
+    request: &TranscodeRequest,
+    plan: &PackagingPlan,
+    expected_duration_us: Option<u64>,
+) -> Result<TranscodeVerificationReport, String> {
+    if !Path::new(&plan.staged_output_path).exists() {
+        return Err("Transcode failed: output playlist not created".to_string());
+    }
+
+    let mut report = check_output_streams(
+        ffprobe_path,
+        &plan.staged_output_path,
+        &request.verification,
+        &plan.expected_streams,
+        expected_duration_us,
+    )
+    .await;
+    if !report.passed {
+        handle_failed_package(
+            &plan.staging_dir,
+            &request.output_path,
+            &request.verification,
+            &mut report,
+        )?;
+    }
+    if report.action == "kept" {
+        let output_dir = Path::new(&request.output_path)
+            .parent()
+            .unwrap_or_else(|| Path::new(""));
+        move_folder_files(&plan.staging_dir, output_dir)?;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{
+        PackagedRendition, TranscodePackagingSettings, TranscodeRendition, build_packaging_args,
+    };
+
+    fn has_arg_pair(args: &[String], left: &str, right: &str) -> bool {
+        args.windows(2)
+            .any(|window| window[0] == left && window[1] == right)
+    }
+
+    fn ladder() -> TranscodePackagingSettings {
+        TranscodePackagingSettings {
+            segment_format: None,
+            segment_seconds: Some(4),
+            renditions: vec![
+                TranscodeRendition {
+                    height: Some(720),
+                    video_bitrate_kbps: Some(3000),
+                    ..Default::default()
+                },
+                TranscodeRendition {
+                    name: Some("low bandwidth".to_string()),
+                    height: Some(360),
+                    video_bitrate_kbps: Some(800),
+                    audio_bitrate_kbps: Some(96),
+                },
+            ],
+        }
+    }
+
+    fn packaged(settings: &TranscodePackagingSettings) -> Vec<PackagedRendition> {
+        settings
+            .rendition_names()
+            .expect("names should resolve")
+            .into_iter()
+            .map(|name| PackagedRendition {
+                path: PathBuf::from(format!("/tmp/renditions/{}.mp4", name)),
+                name,
+                has_audio: true,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rendition_names_fall_back_to_height_and_reject_duplicates() {
+        let mut settings = ladder();
+        assert_eq!(
+            settings.rendition_names().expect("names should resolve"),
+            vec!["720p", "low_bandwidth"]
+        );
+
+        settings.renditions[1].name = Some("720p".to_string());
+        assert!(settings.rendition_names().is_err());
+
+        assert_eq!(
+            TranscodePackagingSettings::default()
+                .rendition_names()
+                .expect("names should resolve"),
+            vec!["main"]
+        );
+    }
+
+    #[test]
+    fn build_packaging_args_writes_hls_variants_with_a_master_playlist() {
+        let settings = ladder();
+        let args =
+            build_packaging_args("hls", &settings, &packaged(&settings), "/out/preview.m3u8")
+                .expect("hls args should build");
+
+        assert!(has_arg_pair(&args, "-map", "1:a:0"));
+        assert!(has_arg_pair(&args, "-hls_time", "4"));
+        assert!(has_arg_pair(&args, "-hls_segment_type", "fmp4"));
+        assert!(has_arg_pair(&args, "-master_pl_name", "preview.m3u8"));
+        assert!(has_arg_pair(
+            &args,
+            "-var_stream_map",
+            "v:0,a:0,name:720p v:1,a:1,name:low_bandwidth"
+        ));
+        assert!(has_arg_pair(
+            &args,
+            "-hls_segment_filename",
+            "/out/preview_%v_%05d.m4s"
+        ));
+        assert_eq!(
+            args.last().map(String::as_str),
+            Some("/out/preview_%v.m3u8")
+        );
+
+        let mut ts_settings = ladder();
+        ts_settings.segment_format = Some("ts".to_string());
+        let args = build_packaging_args(
+            "hls",
+            &ts_settings,
+            &packaged(&ts_settings),
+            "/out/preview.m3u8",
+        )
+        .expect("ts args should build");
+        assert!(has_arg_pair(&args, "-hls_segment_type", "mpegts"));
+        assert!(!args.iter().any(|arg| arg == "-hls_fmp4_init_filename"));
+    }
+
+    #[test]
+    fn build_packaging_args_writes_a_dash_manifest_and_rejects_ts() {
+        let settings = ladder();
+        let mut renditions = packaged(&settings);
+        renditions[1].has_audio = false;
+        let args = build_packaging_args("dash", &settings, &renditions, "/out/preview.mpd")
+            .expect("dash args should build");
+
+        assert!(has_arg_pair(&args, "-seg_duration", "4"));
+        assert!(has_arg_pair(
+            &args,
+            "-adaptation_sets",
+            "id=0,streams=v id=1,streams=a"
+        ));
+        assert!(!has_arg_pair(&args, "-map", "1:a:0"));
+        assert_eq!(args.last().map(String::as_str), Some("/out/preview.mpd"));
+
+        let mut ts_settings = ladder();
+        ts_settings.segment_format = Some("ts".to_string());
+        assert!(
+            build_packaging_args("dash", &ts_settings, &renditions, "/out/preview.mpd").is_err()
+        );
+    }
+}
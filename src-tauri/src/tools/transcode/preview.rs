@@ -0,0 +1,333 @@
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::shared::hash::stable_hash64;
+use crate::shared::validation::validate_media_path;
+use crate::tools::ffprobe::{get_media_duration_us_with_ffprobe, probe::probe_file_with_ffprobe};
+
+use super::hdr::probe_hdr_frame_side_data;
+use super::packaging::is_packaging_container;
+use super::target_quality::{build_sample_windows, predict_output_bytes, run_sample_encode};
+use super::transcode::{
+    TranscodeContext, TranscodeRequest, TranscodeTimeRange, build_transcode_run_args,
+    clear_transcode_state, is_transcode_tracked, plan_target_size, prepare_burn_in_fonts,
+    request_with_video_bitrate, resolve_auto_video_modes, resolve_dolby_vision_support,
+    trimmed_duration_us, validate_output_path_matches_container, validate_transcode_ranges,
+};
+
+const DEFAULT_PREVIEW_SAMPLE_COUNT: usize = 3;
+const DEFAULT_PREVIEW_SAMPLE_SECONDS: u32 = 5;
+const MAX_PREVIEW_SAMPLE_COUNT: usize = 10;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TranscodePreviewSample {
+    pub(crate) path: String,
+    pub(crate) start_ms: u64,
+    pub(crate) duration_ms: u64,
+    pub(crate) size_bytes: u64,
+    pub(crate) frames: u64,
+    /// Wall-clock time spent encoding, including every pass.
+    pub(crate) encode_seconds: f64,
+    pub(crate) fps: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TranscodePreviewEstimate {
+    pub(crate) samples: Vec<TranscodePreviewSample>,
+    /// Average encoding speed over all samples.
+    pub(crate) fps: f64,
+    /// Media seconds encoded per wall-clock second.
+    pub(crate) speed: f64,
+    /// Output duration after trimming.
+    pub(crate) duration_ms: u64,
+    pub(crate) estimated_size_bytes: u64,
+    pub(crate) estimated_encode_seconds: f64,
+}
+
+pub(crate) fn preview_sample_count(sample_count: Option<usize>) -> usize {
+    sample_count
+        .unwrap_or(DEFAULT_PREVIEW_SAMPLE_COUNT)
+        .clamp(1, MAX_PREVIEW_SAMPLE_COUNT)
+}
+
+pub(crate) fn preview_sample_ms(sample_seconds: Option<u32>) -> u64 {
+    u64::from(
+        sample_seconds
+            .unwrap_or(DEFAULT_PREVIEW_SAMPLE_SECONDS)
+            .max(1),
+    ) * 1000
+}
+
+/// Samples are kept after the preview so they can be played back.
+pub(crate) fn build_preview_dir(input_path: &str, output_path: &str) -> PathBuf {
+    std::env::temp_dir()
+        .join("mediaflow_transcode_previews")
+        .join(format!(
+            "{:016x}",
+            stable_hash64(&format!("{}\n{}", input_path, output_path))
+        ))
+}
+
+/// Last `frame=` count reported on the `-progress` output.
+pub(crate) fn parse_progress_frames(stdout: &str) -> u64 {
+    stdout
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("frame="))
+        .filter_map(|value| value.trim().parse::<u64>().ok())
+        .next_back()
+        .unwrap_or(0)
+}
+
+/// Extrapolate the sample sizes and timings to the full output duration.
+pub(crate) fn summarize_preview_samples(
+    samples: Vec<TranscodePreviewSample>,
+    duration_us: u64,
+) -> TranscodePreviewEstimate {
+    let sample_ms = samples.iter().map(|sample| sample.duration_ms).sum::<u64>();
+    let sample_bytes = samples.iter().map(|sample| sample.size_bytes).sum::<u64>();
+    let frames = samples.iter().map(|sample| sample.frames).sum::<u64>();
+    let encode_seconds = samples
+        .iter()
+        .map(|sample| sample.encode_seconds)
+        .sum::<f64>();
+
+    let (fps, speed) = if encode_seconds > 0.0 {
+        (
+            frames as f64 / encode_seconds,
+            sample_ms as f64 / 1000.0 / encode_seconds,
+        )
+    } else {
+        (0.0, 0.0)
+    };
+    let duration_seconds = duration_us as f64 / 1_000_000.0;
+
+    TranscodePreviewEstimate {
+        samples,
+        fps,
+        speed,
+        duration_ms: duration_us / 1000,
+        estimated_size_bytes: predict_output_bytes(sample_bytes, sample_ms, duration_us, 0),
+        estimated_encode_seconds: if speed > 0.0 {
+            duration_seconds / speed
+        } else {
+            0.0
+        },
+    }
+}
+
+/// Encode of every output stream over one sample window with the request's settings.
+pub(crate) fn build_preview_sample_request(
+    request: &TranscodeRequest,
+    window: (u64, u64),
+    sample_path: &Path,
+) -> TranscodeRequest {
+    let mut sample = request.clone();
+    sample.output_path = sample_path.to_string_lossy().to_string();
+    sample.ranges = vec![TranscodeTimeRange {
+        start_ms: window.0,
+        end_ms: Some(window.1),
+    }];
+    sample.video.chunked = None;
+    sample.compare_quality = false;
+    sample
+}
+
+/// Encode short samples spread through the input and extrapolate the final size
+/// and encoding time. Loudnorm runs as a single dynamic pass on the samples.
+pub(crate) async fn run_transcode_preview(
+    ffmpeg_path: &str,
+    ffprobe_path: &str,
+    request: &TranscodeRequest,
+    sample_count: Option<usize>,
+    sample_seconds: Option<u32>,
+) -> Result<TranscodePreviewEstimate, String> {
+    validate_media_path(&request.input_path)?;
+    validate_output_path_matches_container(request)?;
+    if request.video.quality_mode.as_deref() == Some("targetQuality") {
+        return Err("Sample previews do not support the target quality mode".to_string());
+    }
+    if request.ranges.len() > 1 {
+        return Err("Sample previews cannot be combined with joining several ranges".to_string());
+    }
+    if is_packaging_container(&request.container_id) {
+        return Err("Sample previews do not support HLS or DASH packaging".to_string());
+    }
+
+    let probe_json = probe_file_with_ffprobe(ffprobe_path, &request.input_path).await?;
+    let probe_value: Value = serde_json::from_str(&probe_json)
+        .map_err(|error| format!("Invalid probe JSON: {}", error))?;
+    let mut streams = probe_value
+        .get("streams")
+        .and_then(|value| value.as_array())
+        .cloned()
+        .unwrap_or_default();
+    probe_hdr_frame_side_data(ffprobe_path, &request.input_path, &mut streams).await;
+    let request = &resolve_auto_video_modes(request, &streams);
+    let duration_us = get_media_duration_us_with_ffprobe(ffprobe_path, &request.input_path)
+        .await
+        .ok();
+    validate_transcode_ranges(&request.ranges, duration_us)?;
+    let Some(duration_us) =
+        trimmed_duration_us(&request.ranges, duration_us).filter(|duration| *duration > 0)
+    else {
+        return Err("Sample previews require a known input duration".to_string());
+    };
+
+    let request = match plan_target_size(request, &streams, Some(duration_us))? {
+        Some(plan) => request_with_video_bitrate(request, plan.video_bitrate_kbps),
+        None => request.clone(),
+    };
+    let (dolby_vision_supported, _) =
+        resolve_dolby_vision_support(ffmpeg_path, &request, &streams).await;
+    let context = TranscodeContext {
+        dolby_vision_supported,
+        ..TranscodeContext::default()
+    };
+    let range_start_ms = request.ranges.first().map_or(0, |range| range.start_ms);
+    let windows = build_sample_windows(
+        duration_us,
+        preview_sample_count(sample_count),
+        preview_sample_ms(sample_seconds),
+    );
+    let extension = Path::new(&request.output_path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or("mkv");
+    let preview_dir = build_preview_dir(&request.input_path, &request.output_path);
+    let _ = std::fs::remove_dir_all(&preview_dir);
+    std::fs::create_dir_all(&preview_dir)
+        .map_err(|error| format!("Failed to create preview directory: {}", error))?;
+    let fonts_dir = prepare_burn_in_fonts(ffmpeg_path, &request, &streams).await?;
+
+    if let Ok(mut guard) = super::state::TRANSCODE_OUTPUT_PATHS.lock() {
+        guard.insert(request.input_path.clone(), request.output_path.clone());
+    }
+    let result = async {
+        let mut samples = Vec::with_capacity(windows.len());
+        for (index, (start_ms, end_ms)) in windows.iter().enumerate() {
+            if !is_transcode_tracked(&request.input_path) {
+                return Err("Transcode cancelled".to_string());
+            }
+
+            let window = (range_start_ms + start_ms, range_start_ms + end_ms);
+            let sample_path = preview_dir.join(format!("sample-{}.{}", index + 1, extension));
+            let sample_request = build_preview_sample_request(&request, window, &sample_path);
+            let passlog_dir = preview_dir.join(format!("passlog-{}", index + 1));
+            let pass_args = build_transcode_run_args(
+                &sample_request,
+                &context,
+                &streams,
+                Some((end_ms - start_ms) * 1000),
+                &passlog_dir,
+            )?;
+
+            let started = Instant::now();
+            let mut frames = 0;
+            for args in &pass_args {
+                let progress = run_sample_encode(ffmpeg_path, &request.input_path, args).await?;
+                frames = parse_progress_frames(&progress);
+            }
+            let encode_seconds = started.elapsed().as_secs_f64();
+            let _ = std::fs::remove_dir_all(&passlog_dir);
+
+            samples.push(TranscodePreviewSample {
+                path: sample_request.output_path,
+                start_ms: window.0,
+                duration_ms: end_ms - start_ms,
+                size_bytes: std::fs::metadata(&sample_path)
+                    .map(|metadata| metadata.len())
+                    .unwrap_or_default(),
+                frames,
+                encode_seconds,
+                fps: if encode_seconds > 0.0 {
+                    frames as f64 / encode_seconds
+                } else {
+                    0.0
+                },
+            });
+        }
+        Ok(samples)
+    }
+    .await;
+    clear_transcode_state(&request.input_path);
+    if let Some(fonts_dir) = fonts_dir {
+        let _ = std::fs::remove_dir_all(fonts_dir);
+    }
+
+    Ok(summarize_preview_samples(result?, duration_us))
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+pub(crate) async fn preview_transcode_with_bins(
+    ffmpeg_path: &str,
+    ffprobe_path: &str,
+    request: &TranscodeRequest,
+    sample_count: Option<usize>,
+    sample_seconds: Option<u32>,
+) -> Result<TranscodePreviewEstimate, String> {
+    run_transcode_preview(
+        ffmpeg_path,
+        ffprobe_path,
+        request,
+        sample_count,
+        sample_seconds,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        TranscodePreviewSample, parse_progress_frames, preview_sample_count,
+        summarize_preview_samples,
+    };
+
+    fn sample(
+        start_ms: u64,
+        size_bytes: u64,
+        frames: u64,
+        encode_seconds: f64,
+    ) -> TranscodePreviewSample {
+        TranscodePreviewSample {
+            path: format!("/tmp/previews/sample-{}.mp4", start_ms),
+            start_ms,
+            duration_ms: 5_000,
+            size_bytes,
+            frames,
+            encode_seconds,
+            fps: frames as f64 / encode_seconds,
+        }
+    }
+
+    #[test]
+    fn parse_progress_frames_reads_the_last_report() {
+        let stdout = "frame=12\nfps=0.0\nprogress=continue\nframe=125\nprogress=end\n";
+        assert_eq!(parse_progress_frames(stdout), 125);
+        assert_eq!(parse_progress_frames("progress=end\n"), 0);
+        assert_eq!(preview_sample_count(Some(50)), 10);
+    }
+
+    #[test]
+    fn preview_samples_extrapolate_to_the_full_duration() {
+        // Two 5 s samples of 1 MB each, 125 frames encoded in 2.5 s per sample.
+        let estimate = summarize_preview_samples(
+            vec![
+                sample(10_000, 1_000_000, 125, 2.5),
+                sample(50_000, 1_000_000, 125, 2.5),
+            ],
+            7_200_000_000,
+        );
+
+        assert_eq!(estimate.samples.len(), 2);
+        assert_eq!(estimate.duration_ms, 7_200_000);
+        assert!((estimate.fps - 50.0).abs() < 1e-9);
+        assert!((estimate.speed - 2.0).abs() < 1e-9);
+        assert_eq!(estimate.estimated_size_bytes, 1_440_000_000);
+        assert!((estimate.estimated_encode_seconds - 3_600.0).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,634 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+use tokio::time::timeout;
+
+use crate::shared::ffmpeg_progress::FfmpegProgressTracker;
+use crate::shared::hash::stable_hash64;
+use crate::shared::process::terminate_process;
+use crate::shared::store::{resolve_ffmpeg_path, resolve_ffprobe_path};
+use crate::shared::validation::validate_media_path;
+use crate::tools::ffprobe::{get_media_duration_us_with_ffprobe, probe::probe_file_with_ffprobe};
+
+use super::burn_in::escape_filter_path;
+use super::capabilities::{FilterMediaType, ffmpeg_filter_output, parse_ffmpeg_filter_names};
+use super::events::TranscodeEvents;
+
+const QUALITY_TIMEOUT: Duration = Duration::from_secs(7200);
+/// Identical frames report an infinite PSNR; capping keeps the scores serializable.
+const MAX_PSNR_DB: f64 = 100.0;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct QualityComparisonRequest {
+    pub(crate) reference_path: String,
+    pub(crate) distorted_path: String,
+    /// Part of the reference the distorted file was cut from.
+    pub(crate) reference_start_ms: Option<u64>,
+    pub(crate) reference_end_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct QualityMetricSummary {
+    pub(crate) mean: f64,
+    pub(crate) min: f64,
+    pub(crate) max: f64,
+    pub(crate) harmonic_mean: f64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct QualityFrameScore {
+    pub(crate) frame: usize,
+    pub(crate) vmaf: Option<f64>,
+    pub(crate) ssim: Option<f64>,
+    pub(crate) psnr: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct QualityComparisonResult {
+    pub(crate) reference_path: String,
+    pub(crate) distorted_path: String,
+    /// Missing when ffmpeg was built without libvmaf.
+    pub(crate) vmaf: Option<QualityMetricSummary>,
+    pub(crate) ssim: Option<QualityMetricSummary>,
+    pub(crate) psnr: Option<QualityMetricSummary>,
+    pub(crate) frames: Vec<QualityFrameScore>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct VideoGeometry {
+    width: u64,
+    height: u64,
+    frame_rate: Option<String>,
+}
+
+struct QualityStatsPaths {
+    ssim: PathBuf,
+    psnr: PathBuf,
+    vmaf: PathBuf,
+}
+
+fn primary_video_geometry(probe_json: &str, path: &str) -> Result<VideoGeometry, String> {
+    let probe_value: Value = serde_json::from_str(probe_json)
+        .map_err(|error| format!("Invalid probe JSON: {}", error))?;
+    let stream = probe_value
+        .get("streams")
+        .and_then(|value| value.as_array())
+        .into_iter()
+        .flatten()
+        .find(|stream| {
+            stream.get("codec_type").and_then(|value| value.as_str()) == Some("video")
+                && stream
+                    .get("disposition")
+                    .and_then(|value| value.get("attached_pic"))
+                    .and_then(|value| value.as_u64())
+                    != Some(1)
+        })
+        .ok_or_else(|| format!("{} has no video stream to compare", path))?;
+
+    let dimension = |key: &str| {
+        stream
+            .get(key)
+            .and_then(|value| value.as_u64())
+            .filter(|value| *value > 0)
+            .ok_or_else(|| format!("{} has no video {}", path, key))
+    };
+
+    Ok(VideoGeometry {
+        width: dimension("width")?,
+        height: dimension("height")?,
+        frame_rate: stream
+            .get("avg_frame_rate")
+            .and_then(|value| value.as_str())
+            .filter(|value| !value.is_empty() && !value.starts_with('0'))
+            .map(|value| value.to_string()),
+    })
+}
+
+fn build_quality_stats_dir(reference_path: &str, distorted_path: &str) -> PathBuf {
+    std::env::temp_dir()
+        .join("mediaflow_transcode_quality")
+        .join(format!(
+            "{:016x}",
+            stable_hash64(&format!("{}\n{}", reference_path, distorted_path))
+        ))
+}
+
+/// Compare graph for inputs `0` (distorted) and `1` (reference).
+///
+/// The distorted frames are retimed and scaled onto the reference grid, then
+/// pass through `ssim` and `psnr` (which forward their main input) and finally
+/// `libvmaf` when it is available.
+fn build_quality_filter_graph(
+    reference: &VideoGeometry,
+    distorted: &VideoGeometry,
+    stats: &QualityStatsPaths,
+    use_vmaf: bool,
+    vmaf_threads: usize,
+) -> String {
+    let mut distorted_chain = vec!["setpts=PTS-STARTPTS".to_string()];
+    if let Some(frame_rate) = reference.frame_rate.as_ref()
+        && distorted.frame_rate.as_ref() != Some(frame_rate)
+    {
+        distorted_chain.push(format!("fps={}", frame_rate));
+    }
+    if (distorted.width, distorted.height) != (reference.width, reference.height) {
+        distorted_chain.push(format!(
+            "scale={}:{}:flags=bicubic",
+            reference.width, reference.height
+        ));
+    }
+    distorted_chain.push("format=yuv420p".to_string());
+
+    let reference_outputs = if use_vmaf { 3 } else { 2 };
+    let mut graph = format!(
+        "[0:v]{}[dist];[1:v]setpts=PTS-STARTPTS,format=yuv420p,split={}{}",
+        distorted_chain.join(","),
+        reference_outputs,
+        (0..reference_outputs)
+            .map(|index| format!("[ref{}]", index))
+            .collect::<String>()
+    );
+    graph.push_str(&format!(
+        ";[dist][ref0]ssim=stats_file={}[ssim];[ssim][ref1]psnr=stats_file={}",
+        escape_filter_path(&stats.ssim.to_string_lossy()),
+        escape_filter_path(&stats.psnr.to_string_lossy())
+    ));
+    if use_vmaf {
+        graph.push_str(&format!(
+            "[psnr];[psnr][ref2]libvmaf=log_fmt=json:log_path={}:n_threads={}",
+            escape_filter_path(&stats.vmaf.to_string_lossy()),
+            vmaf_threads.max(1)
+        ));
+    }
+    graph
+}
+
+fn build_quality_args(request: &QualityComparisonRequest, filter_graph: &str) -> Vec<String> {
+    let mut args = vec![
+        "-hide_banner".to_string(),
+        "-nostats".to_string(),
+        "-i".to_string(),
+        request.distorted_path.clone(),
+    ];
+    if let Some(start_ms) = request.reference_start_ms.filter(|start_ms| *start_ms > 0) {
+        args.push("-ss".to_string());
+        args.push(format!("{:.3}", start_ms as f64 / 1000.0));
+    }
+    if let Some(end_ms) = request.reference_end_ms {
+        args.push("-to".to_string());
+        args.push(format!("{:.3}", end_ms as f64 / 1000.0));
+    }
+    args.extend([
+        "-i".to_string(),
+        request.reference_path.clone(),
+        "-filter_complex".to_string(),
+        filter_graph.to_string(),
+        "-an".to_string(),
+        "-sn".to_string(),
+        "-f".to_string(),
+        "null".to_string(),
+        "-progress".to_string(),
+        "pipe:1".to_string(),
+        "-".to_string(),
+    ]);
+    args
+}
+
+/// Per-frame values of `key` from an `ssim` or `psnr` stats file.
+fn parse_frame_stats(contents: &str, key: &str) -> Vec<f64> {
+    let prefix = format!("{}:", key);
+    contents
+        .lines()
+        .filter_map(|line| {
+            line.split_whitespace()
+                .find_map(|token| token.strip_prefix(prefix.as_str()))
+                .and_then(|value| value.parse::<f64>().ok())
+        })
+        .map(|value| value.min(MAX_PSNR_DB))
+        .collect()
+}
+
+fn parse_vmaf_log(contents: &str) -> Result<Vec<f64>, String> {
+    let log: Value =
+        serde_json::from_str(contents).map_err(|error| format!("Invalid VMAF log: {}", error))?;
+    Ok(log
+        .get("frames")
+        .and_then(|value| value.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|frame| {
+            frame
+                .get("metrics")
+                .and_then(|metrics| metrics.get("vmaf"))
+                .and_then(|value| value.as_f64())
+        })
+        .collect())
+}
+
+/// Pool per-frame scores the way libvmaf does, including its +1 offset on the harmonic mean.
+fn summarize_scores(values: &[f64]) -> Option<QualityMetricSummary> {
+    if values.is_empty() {
+        return None;
+    }
+
+    let count = values.len() as f64;
+    Some(QualityMetricSummary {
+        mean: values.iter().sum::<f64>() / count,
+        min: values.iter().copied().fold(f64::INFINITY, f64::min),
+        max: values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+        harmonic_mean: count / values.iter().map(|value| 1.0 / (value + 1.0)).sum::<f64>() - 1.0,
+    })
+}
+
+fn merge_frame_scores(vmaf: &[f64], ssim: &[f64], psnr: &[f64]) -> Vec<QualityFrameScore> {
+    let frame_count = vmaf.len().max(ssim.len()).max(psnr.len());
+    (0..frame_count)
+        .map(|frame| QualityFrameScore {
+            frame,
+            vmaf: vmaf.get(frame).copied(),
+            ssim: ssim.get(frame).copied(),
+            psnr: psnr.get(frame).copied(),
+        })
+        .collect()
+}
+
+pub(crate) async fn ffmpeg_supports_libvmaf(ffmpeg_path: &str) -> bool {
+    ffmpeg_filter_output(ffmpeg_path)
+        .await
+        .map(|output| {
+            parse_ffmpeg_filter_names(&output, FilterMediaType::Video).contains("libvmaf")
+        })
+        .unwrap_or(false)
+}
+
+fn read_stats_file(path: &Path) -> String {
+    std::fs::read_to_string(path).unwrap_or_default()
+}
+
+/// Which cancel stops a comparison's ffmpeg process.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum QualityProcessOwner<'a> {
+    /// A standalone comparison, cancelled by its distorted path.
+    Comparison,
+    /// A comparison run by a transcode, cancelled with that transcode's input.
+    Transcode(&'a str),
+}
+
+impl QualityProcessOwner<'_> {
+    fn track(&self, request: &QualityComparisonRequest, pid: u32) {
+        let (processes, key) = self.process_slot(request);
+        if let Ok(mut guard) = processes.lock() {
+            guard.insert(key.to_string(), pid);
+        }
+    }
+
+    /// A cancel removes the entry first, so a missing one means the run was cancelled.
+    fn untrack(&self, request: &QualityComparisonRequest) -> bool {
+        let (processes, key) = self.process_slot(request);
+        processes
+            .lock()
+            .map(|mut guard| guard.remove(key).is_some())
+            .unwrap_or(true)
+    }
+
+    fn process_slot<'b>(
+        &'b self,
+        request: &'b QualityComparisonRequest,
+    ) -> (&'static Mutex<HashMap<String, u32>>, &'b str) {
+        match self {
+            Self::Comparison => (&super::state::QUALITY_PROCESS_IDS, &request.distorted_path),
+            Self::Transcode(input_path) => (&super::state::TRANSCODE_PROCESS_IDS, input_path),
+        }
+    }
+}
+
+/// Run a comparison, tracked for cancellation as `owner` says.
+pub(crate) async fn run_quality_comparison<F>(
+    ffmpeg_path: &str,
+    ffprobe_path: &str,
+    request: &QualityComparisonRequest,
+    owner: QualityProcessOwner<'_>,
+    on_progress: F,
+) -> Result<QualityComparisonResult, String>
+where
+    F: Fn(i32) + Send + 'static,
+{
+    validate_media_path(&request.reference_path)?;
+    validate_media_path(&request.distorted_path)?;
+
+    let reference = primary_video_geometry(
+        &probe_file_with_ffprobe(ffprobe_path, &request.reference_path).await?,
+        &request.reference_path,
+    )?;
+    let distorted = primary_video_geometry(
+        &probe_file_with_ffprobe(ffprobe_path, &request.distorted_path).await?,
+        &request.distorted_path,
+    )?;
+    let duration_us = get_media_duration_us_with_ffprobe(ffprobe_path, &request.distorted_path)
+        .await
+        .ok();
+    let use_vmaf = ffmpeg_supports_libvmaf(ffmpeg_path).await;
+
+    let stats_dir = build_quality_stats_dir(&request.reference_path, &request.distorted_path);
+    std::fs::create_dir_all(&stats_dir)
+        .map_err(|error| format!("Failed to create quality stats directory: {}", error))?;
+    let stats = QualityStatsPaths {
+        ssim: stats_dir.join("ssim.log"),
+        psnr: stats_dir.join("psnr.log"),
+        vmaf: stats_dir.join("vmaf.json"),
+    };
+    let vmaf_threads = std::thread::available_parallelism()
+        .map(|threads| threads.get())
+        .unwrap_or(1);
+    let filter_graph =
+        build_quality_filter_graph(&reference, &distorted, &stats, use_vmaf, vmaf_threads);
+    let args = build_quality_args(request, &filter_graph);
+
+    let result = async {
+        let mut child = Command::new(ffmpeg_path)
+            .args(&args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|error| format!("Failed to start ffmpeg: {}", error))?;
+
+        if let Some(pid) = child.id() {
+            owner.track(request, pid);
+        }
+
+        if let Some(stdout) = child.stdout.take() {
+            tokio::spawn(async move {
+                let mut tracker = FfmpegProgressTracker::new(duration_us);
+                let mut lines = BufReader::new(stdout).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    if let Some(progress) = tracker
+                        .handle_line(&line)
+                        .and_then(|update| update.progress)
+                    {
+                        on_progress(progress);
+                    }
+                }
+            });
+        }
+
+        let child_pid = child.id();
+        let output = match timeout(QUALITY_TIMEOUT, child.wait_with_output()).await {
+            Ok(result) => result.map_err(|error| format!("Failed to execute ffmpeg: {}", error))?,
+            Err(_) => {
+                if let Some(pid) = child_pid {
+                    terminate_process(pid);
+                }
+                return Err(format!(
+                    "Quality comparison timeout after {} seconds",
+                    QUALITY_TIMEOUT.as_secs()
+                ));
+            }
+        };
+        if !owner.untrack(request) {
+            return Err("Quality comparison cancelled".to_string());
+        }
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(format!("Quality comparison failed: {}", stderr.trim()));
+        }
+
+        let vmaf = if use_vmaf {
+            parse_vmaf_log(&read_stats_file(&stats.vmaf))?
+        } else {
+            Vec::new()
+        };
+        let ssim = parse_frame_stats(&read_stats_file(&stats.ssim), "All");
+        let psnr = parse_frame_stats(&read_stats_file(&stats.psnr), "psnr_avg");
+        if ssim.is_empty() && psnr.is_empty() && vmaf.is_empty() {
+            return Err("Quality comparison produced no frame scores".to_string());
+        }
+
+        Ok(QualityComparisonResult {
+            reference_path: request.reference_path.clone(),
+            distorted_path: request.distorted_path.clone(),
+            vmaf: summarize_scores(&vmaf),
+            ssim: summarize_scores(&ssim),
+            psnr: summarize_scores(&psnr),
+            frames: merge_frame_scores(&vmaf, &ssim, &psnr),
+        })
+    }
+    .await;
+    let _ = std::fs::remove_dir_all(&stats_dir);
+    result
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+pub(crate) async fn compare_media_quality_with_bins(
+    ffmpeg_path: &str,
+    ffprobe_path: &str,
+    request: &QualityComparisonRequest,
+) -> Result<QualityComparisonResult, String> {
+    run_quality_comparison(
+        ffmpeg_path,
+        ffprobe_path,
+        request,
+        QualityProcessOwner::Comparison,
+        |_| {},
+    )
+    .await
+}
+
+pub(crate) fn emit_quality_progress(
+    events: &impl TranscodeEvents,
+    request: &QualityComparisonRequest,
+    progress: i32,
+) {
+    events.emit_event(
+        "media-quality-progress",
+        serde_json::json!({
+            "referencePath": request.reference_path,
+            "distortedPath": request.distorted_path,
+            "progress": progress
+        }),
+    );
+}
+
+/// Score a distorted file against its reference with VMAF (when available), SSIM and PSNR.
+#[tauri::command]
+pub(crate) async fn compare_media_quality(
+    app: tauri::AppHandle,
+    request: QualityComparisonRequest,
+) -> Result<QualityComparisonResult, String> {
+    let ffmpeg_path = resolve_ffmpeg_path(&app)?;
+    let ffprobe_path = resolve_ffprobe_path(&app)?;
+
+    emit_quality_progress(&app, &request, 0);
+    let app_for_progress = app.clone();
+    let request_for_progress = request.clone();
+    let result = run_quality_comparison(
+        &ffmpeg_path,
+        &ffprobe_path,
+        &request,
+        QualityProcessOwner::Comparison,
+        move |progress| {
+            emit_quality_progress(&app_for_progress, &request_for_progress, progress);
+        },
+    )
+    .await?;
+    emit_quality_progress(&app, &request, 100);
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::test_support::ffmpeg::{ffmpeg_path, ffprobe_path};
+    use crate::test_support::video::generate_test_pattern_video;
+
+    use super::{
+        QualityComparisonRequest, QualityProcessOwner, QualityStatsPaths, VideoGeometry,
+        build_quality_filter_graph, compare_media_quality_with_bins, merge_frame_scores,
+        parse_frame_stats, parse_vmaf_log, summarize_scores,
+    };
+
+    #[test]
+    fn transcode_comparisons_are_tracked_under_the_transcode_input() {
+        let request = QualityComparisonRequest {
+            reference_path: "/tmp/quality-owner-input.mkv".to_string(),
+            distorted_path: "/tmp/quality-owner-sample.mkv".to_string(),
+            reference_start_ms: None,
+            reference_end_ms: None,
+        };
+        let owner = QualityProcessOwner::Transcode("/tmp/quality-owner-input.mkv");
+
+        owner.track(&request, 4242);
+        let tracked = super::super::state::TRANSCODE_PROCESS_IDS
+            .lock()
+            .expect("transcode process lock")
+            .get("/tmp/quality-owner-input.mkv")
+            .copied();
+        assert_eq!(tracked, Some(4242));
+        assert!(
+            !super::super::state::QUALITY_PROCESS_IDS
+                .lock()
+                .expect("quality process lock")
+                .contains_key("/tmp/quality-owner-sample.mkv")
+        );
+
+        assert!(owner.untrack(&request));
+        assert!(
+            !owner.untrack(&request),
+            "a cancelled run is no longer tracked"
+        );
+    }
+
+    fn geometry(width: u64, height: u64, frame_rate: &str) -> VideoGeometry {
+        VideoGeometry {
+            width,
+            height,
+            frame_rate: Some(frame_rate.to_string()),
+        }
+    }
+
+    fn stats() -> QualityStatsPaths {
+        QualityStatsPaths {
+            ssim: PathBuf::from("/tmp/q/ssim.log"),
+            psnr: PathBuf::from("/tmp/q/psnr.log"),
+            vmaf: PathBuf::from("/tmp/q/vmaf.json"),
+        }
+    }
+
+    #[test]
+    fn build_quality_filter_graph_aligns_the_distorted_input() {
+        let graph = build_quality_filter_graph(
+            &geometry(1920, 1080, "24000/1001"),
+            &geometry(1280, 720, "25/1"),
+            &stats(),
+            true,
+            4,
+        );
+        assert_eq!(
+            graph,
+            "[0:v]setpts=PTS-STARTPTS,fps=24000/1001,scale=1920:1080:flags=bicubic,format=yuv420p[dist];\
+             [1:v]setpts=PTS-STARTPTS,format=yuv420p,split=3[ref0][ref1][ref2];\
+             [dist][ref0]ssim=stats_file='/tmp/q/ssim.log'[ssim];\
+             [ssim][ref1]psnr=stats_file='/tmp/q/psnr.log'[psnr];\
+             [psnr][ref2]libvmaf=log_fmt=json:log_path='/tmp/q/vmaf.json':n_threads=4"
+        );
+
+        let fallback = build_quality_filter_graph(
+            &geometry(1920, 1080, "25/1"),
+            &geometry(1920, 1080, "25/1"),
+            &stats(),
+            false,
+            4,
+        );
+        assert!(fallback.starts_with("[0:v]setpts=PTS-STARTPTS,format=yuv420p[dist];"));
+        assert!(fallback.contains("split=2[ref0][ref1]"));
+        assert!(!fallback.contains("libvmaf"));
+    }
+
+    #[test]
+    fn quality_stats_are_parsed_pooled_and_merged() {
+        let ssim = parse_frame_stats(
+            "n:1 Y:0.990000 U:0.995000 V:0.996000 All:0.992000 (20.969100)\n\
+             n:2 Y:0.980000 U:0.985000 V:0.986000 All:0.982000 (17.447275)\n",
+            "All",
+        );
+        assert_eq!(ssim, vec![0.992, 0.982]);
+
+        let psnr = parse_frame_stats(
+            "n:1 mse_avg:0.00 mse_y:0.00 psnr_avg:inf psnr_y:inf\n\
+             n:2 mse_avg:1.20 mse_y:1.30 psnr_avg:47.34 psnr_y:46.99\n",
+            "psnr_avg",
+        );
+        assert_eq!(psnr, vec![100.0, 47.34]);
+
+        let vmaf = parse_vmaf_log(
+            r#"{"frames":[{"frameNum":0,"metrics":{"vmaf":96.5}},{"frameNum":1,"metrics":{"vmaf":93.5}}]}"#,
+        )
+        .expect("vmaf log should parse");
+        let summary = summarize_scores(&vmaf).expect("summary should exist");
+        assert_eq!((summary.mean, summary.min, summary.max), (95.0, 93.5, 96.5));
+        assert!(summary.harmonic_mean < summary.mean);
+        assert!(summarize_scores(&[]).is_none());
+
+        let frames = merge_frame_scores(&vmaf, &ssim, &psnr[..1]);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[1].vmaf, Some(93.5));
+        assert_eq!(frames[1].psnr, None);
+    }
+
+    #[tokio::test]
+    async fn compare_media_quality_scores_identical_files() {
+        let fixture = generate_test_pattern_video(160, 90)
+            .await
+            .expect("failed to generate test video");
+        let path = fixture.path.to_string_lossy().to_string();
+
+        let result = compare_media_quality_with_bins(
+            ffmpeg_path(),
+            ffprobe_path(),
+            &QualityComparisonRequest {
+                reference_path: path.clone(),
+                distorted_path: path,
+                reference_start_ms: None,
+                reference_end_ms: None,
+            },
+        )
+        .await
+        .expect("comparison should succeed");
+
+        let ssim = result.ssim.expect("ssim should be reported");
+        assert!(ssim.min > 0.99);
+        assert!(!result.frames.is_empty());
+    }
+}
@@ -0,0 +1,281 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde_json::Value;
+use tokio::process::Command;
+use tokio::time::timeout;
+
+use super::transcode::TranscodeTimeRange;
+use crate::shared::hash::stable_hash64;
+use crate::tools::concat::concat::{escape_concat_list_path, escape_ffmetadata_value};
+
+const CHAPTER_PROBE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Side inputs that carry subtitles and chapters onto the joined timeline.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RangeJoinInputs {
+    /// Concat demuxer list opening each range; subtitles are mapped from it.
+    pub(crate) subtitle_list_path: Option<String>,
+    /// FFMETADATA with the source chapters clipped and shifted per range.
+    pub(crate) chapters_path: Option<String>,
+}
+
+impl RangeJoinInputs {
+    /// Paths the preparation step writes, before knowing whether the source has chapters.
+    pub(crate) fn planned(input_path: &str, output_path: &str, keep_chapters: bool) -> Self {
+        let work_dir = build_range_join_dir(input_path, output_path);
+        Self {
+            subtitle_list_path: Some(path_string(&work_dir.join("subtitles.txt"))),
+            chapters_path: keep_chapters.then(|| path_string(&work_dir.join("chapters.ffmeta"))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SourceChapter {
+    pub(crate) start_ms: u64,
+    pub(crate) end_ms: u64,
+    pub(crate) title: Option<String>,
+}
+
+fn path_string(path: &Path) -> String {
+    path.to_string_lossy().to_string()
+}
+
+pub(crate) fn build_range_join_dir(input_path: &str, output_path: &str) -> PathBuf {
+    std::env::temp_dir()
+        .join("mediaflow_transcode_ranges")
+        .join(format!(
+            "{:016x}",
+            stable_hash64(&format!("{}\n{}", input_path, output_path))
+        ))
+}
+
+fn format_directive_seconds(ms: u64) -> String {
+    format!("{:.3}", ms as f64 / 1000.0)
+}
+
+/// Concat demuxer list that opens the input once per range, so stream
+/// timestamps restart where the previous range ended.
+pub(crate) fn build_range_subtitle_list(input_path: &str, ranges: &[TranscodeTimeRange]) -> String {
+    let mut list = String::from("ffconcat version 1.0\n");
+    for range in ranges {
+        list.push_str(&format!("file '{}'\n", escape_concat_list_path(input_path)));
+        if range.start_ms > 0 {
+            list.push_str(&format!(
+                "inpoint {}\n",
+                format_directive_seconds(range.start_ms)
+            ));
+        }
+        if let Some(end_ms) = range.end_ms {
+            list.push_str(&format!("outpoint {}\n", format_directive_seconds(end_ms)));
+        }
+    }
+    list
+}
+
+/// Chapters overlapping each range, clipped to it and moved to where the range
+/// starts in the output; `None` when no chapter survives.
+pub(crate) fn build_range_chapter_metadata(
+    ranges: &[TranscodeTimeRange],
+    chapters: &[SourceChapter],
+) -> Option<String> {
+    let mut metadata = String::from(";FFMETADATA1\n");
+    let mut offset_ms = 0u64;
+    let mut chapter_count = 0usize;
+
+    for range in ranges {
+        let range_end_ms = range.end_ms.unwrap_or(u64::MAX);
+        for chapter in chapters {
+            let start_ms = chapter.start_ms.max(range.start_ms);
+            let end_ms = chapter.end_ms.min(range_end_ms);
+            if end_ms <= start_ms {
+                continue;
+            }
+
+            chapter_count += 1;
+            let title = chapter
+                .title
+                .clone()
+                .unwrap_or_else(|| format!("Chapter {}", chapter_count));
+            metadata.push_str("[CHAPTER]\nTIMEBASE=1/1000\n");
+            metadata.push_str(&format!(
+                "START={}\nEND={}\ntitle={}\n",
+                offset_ms + (start_ms - range.start_ms),
+                offset_ms + (end_ms - range.start_ms),
+                escape_ffmetadata_value(&title)
+            ));
+        }
+        if let Some(end_ms) = range.end_ms {
+            offset_ms += end_ms - range.start_ms;
+        }
+    }
+
+    (chapter_count > 0).then_some(metadata)
+}
+
+fn seconds_to_ms(value: &Value) -> Option<u64> {
+    value
+        .as_str()
+        .and_then(|value| value.parse::<f64>().ok())
+        .filter(|value| value.is_finite() && *value >= 0.0)
+        .map(|value| (value * 1000.0).round() as u64)
+}
+
+fn parse_source_chapters(probe: &Value) -> Vec<SourceChapter> {
+    probe
+        .get("chapters")
+        .and_then(|value| value.as_array())
+        .map(|chapters| {
+            chapters
+                .iter()
+                .filter_map(|chapter| {
+                    Some(SourceChapter {
+                        start_ms: chapter.get("start_time").and_then(seconds_to_ms)?,
+                        end_ms: chapter.get("end_time").and_then(seconds_to_ms)?,
+                        title: chapter
+                            .get("tags")
+                            .and_then(|tags| tags.get("title"))
+                            .and_then(|value| value.as_str())
+                            .map(str::trim)
+                            .filter(|title| !title.is_empty())
+                            .map(str::to_string),
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+async fn probe_source_chapters(
+    ffprobe_path: &str,
+    input_path: &str,
+) -> Result<Vec<SourceChapter>, String> {
+    let output = timeout(
+        CHAPTER_PROBE_TIMEOUT,
+        Command::new(ffprobe_path)
+            .args([
+                "-v",
+                "quiet",
+                "-print_format",
+                "json",
+                "-show_chapters",
+                input_path,
+            ])
+            .output(),
+    )
+    .await
+    .map_err(|_| {
+        format!(
+            "FFprobe timeout after {} seconds",
+            CHAPTER_PROBE_TIMEOUT.as_secs()
+        )
+    })?
+    .map_err(|error| format!("Failed to execute ffprobe: {}", error))?;
+
+    if !output.status.success() {
+        return Err("Failed to probe input chapters".to_string());
+    }
+
+    let probe: Value = serde_json::from_slice(&output.stdout)
+        .map_err(|error| format!("Invalid probe JSON: {}", error))?;
+    Ok(parse_source_chapters(&probe))
+}
+
+/// Write the subtitle list and chapter file for joined ranges; chapters are
+/// left out when the source has none inside the ranges.
+pub(crate) async fn prepare_range_join_inputs(
+    ffprobe_path: &str,
+    input_path: &str,
+    output_path: &str,
+    ranges: &[TranscodeTimeRange],
+    keep_chapters: bool,
+) -> Result<RangeJoinInputs, String> {
+    let work_dir = build_range_join_dir(input_path, output_path);
+    std::fs::create_dir_all(&work_dir)
+        .map_err(|error| format!("Failed to create range work directory: {}", error))?;
+
+    let subtitle_list_path = work_dir.join("subtitles.txt");
+    std::fs::write(
+        &subtitle_list_path,
+        build_range_subtitle_list(input_path, ranges),
+    )
+    .map_err(|error| format!("Failed to write range subtitle list: {}", error))?;
+
+    let chapters_path = if keep_chapters {
+        let chapters = probe_source_chapters(ffprobe_path, input_path).await?;
+        match build_range_chapter_metadata(ranges, &chapters) {
+            Some(metadata) => {
+                let path = work_dir.join("chapters.ffmeta");
+                std::fs::write(&path, metadata)
+                    .map_err(|error| format!("Failed to write chapter metadata: {}", error))?;
+                Some(path_string(&path))
+            }
+            None => None,
+        }
+    } else {
+        None
+    };
+
+    Ok(RangeJoinInputs {
+        subtitle_list_path: Some(path_string(&subtitle_list_path)),
+        chapters_path,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{
+        TranscodeTimeRange, build_range_chapter_metadata, build_range_subtitle_list,
+        parse_source_chapters,
+    };
+
+    fn range(start_ms: u64, end_ms: Option<u64>) -> TranscodeTimeRange {
+        TranscodeTimeRange { start_ms, end_ms }
+    }
+
+    #[test]
+    fn build_range_subtitle_list_sets_in_and_out_points_per_range() {
+        let list = build_range_subtitle_list(
+            "/tmp/it's.mkv",
+            &[range(0, Some(10_000)), range(20_500, None)],
+        );
+
+        assert_eq!(
+            list,
+            "ffconcat version 1.0\n\
+             file '/tmp/it'\\''s.mkv'\noutpoint 10.000\n\
+             file '/tmp/it'\\''s.mkv'\ninpoint 20.500\n"
+        );
+    }
+
+    #[test]
+    fn build_range_chapter_metadata_clips_and_shifts_chapters() {
+        let chapters = parse_source_chapters(&json!({
+            "chapters": [
+                { "start_time": "0.000000", "end_time": "15.000000", "tags": { "title": "Intro" } },
+                { "start_time": "15.000000", "end_time": "40.000000", "tags": { "title": "Main" } },
+                { "start_time": "40.000000", "end_time": "60.000000" }
+            ]
+        }));
+        let metadata = build_range_chapter_metadata(
+            &[range(5_000, Some(10_000)), range(30_000, None)],
+            &chapters,
+        )
+        .expect("chapters should survive");
+
+        assert_eq!(
+            metadata,
+            ";FFMETADATA1\n\
+             [CHAPTER]\nTIMEBASE=1/1000\nSTART=0\nEND=5000\ntitle=Intro\n\
+             [CHAPTER]\nTIMEBASE=1/1000\nSTART=5000\nEND=15000\ntitle=Main\n\
+             [CHAPTER]\nTIMEBASE=1/1000\nSTART=15000\nEND=35000\ntitle=Chapter 3\n"
+        );
+        assert_eq!(
+            build_range_chapter_metadata(&[range(0, Some(1_000))], &[]),
+            None
+        );
+    }
+}
@@ -5,6 +5,22 @@ use std::sync::{LazyLock, Mutex};
 pub(super) static TRANSCODE_PROCESS_IDS: LazyLock<Mutex<HashMap<String, u32>>> =
     LazyLock::new(|| Mutex::new(HashMap::new()));
 
+/// Store chunk encode process IDs keyed by input path; chunked encodes run several at once.
+pub(super) static TRANSCODE_CHUNK_PROCESS_IDS: LazyLock<Mutex<HashMap<String, Vec<u32>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
 /// Store output paths so partially-written files can be removed on cancel/error.
 pub(super) static TRANSCODE_OUTPUT_PATHS: LazyLock<Mutex<HashMap<String, String>>> =
     LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Store quality comparison process IDs keyed by distorted path.
+pub(super) static QUALITY_PROCESS_IDS: LazyLock<Mutex<HashMap<String, u32>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Cache `ffmpeg -filters` output keyed by ffmpeg path; a build's filters never change.
+pub(super) static FFMPEG_FILTER_OUTPUTS: LazyLock<Mutex<HashMap<String, String>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Store two-pass log directories so they can be removed on cancel.
+pub(super) static TRANSCODE_PASSLOG_DIRS: LazyLock<Mutex<HashMap<String, String>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
@@ -0,0 +1,535 @@
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::process::Command;
+use tokio::time::timeout;
+
+use crate::shared::hash::stable_hash64;
+use crate::shared::process::terminate_process;
+
+use super::analysis::select_analysis_timestamps;
+use super::events::TranscodeEvents;
+use super::quality::{
+    QualityComparisonRequest, QualityComparisonResult, QualityProcessOwner,
+    ffmpeg_supports_libvmaf, run_quality_comparison,
+};
+use super::transcode::{
+    TRANSCODE_TIMEOUT, TranscodeContext, TranscodeRequest, TranscodeTimeRange,
+    build_transcode_args, clear_transcode_state, estimate_output_audio_bitrate_kbps,
+    is_transcode_tracked, request_with_only_video, request_with_video_crf,
+};
+
+const DEFAULT_SAMPLE_COUNT: usize = 4;
+const DEFAULT_SAMPLE_SECONDS: u32 = 4;
+const DEFAULT_MIN_CRF: u8 = 10;
+const DEFAULT_MAX_CRF: u8 = 40;
+const MAX_CRF: u8 = 63;
+const MAX_SAMPLE_COUNT: usize = 12;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TranscodeTargetQuality {
+    /// `vmaf` (default) or `ssim`
+    pub(crate) metric: Option<String>,
+    /// Mean score the samples must reach, such as 95 for VMAF or 0.98 for SSIM.
+    pub(crate) score: f64,
+    pub(crate) min_crf: Option<u8>,
+    pub(crate) max_crf: Option<u8>,
+    pub(crate) sample_count: Option<usize>,
+    pub(crate) sample_seconds: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TargetQualityMetric {
+    Vmaf,
+    Ssim,
+}
+
+impl TargetQualityMetric {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Self::Vmaf => "vmaf",
+            Self::Ssim => "ssim",
+        }
+    }
+
+    /// Mean score of one sample comparison.
+    pub(crate) fn score(self, result: &QualityComparisonResult) -> Option<f64> {
+        match self {
+            Self::Vmaf => result.vmaf.map(|summary| summary.mean),
+            Self::Ssim => result.ssim.map(|summary| summary.mean),
+        }
+    }
+}
+
+impl TranscodeTargetQuality {
+    pub(crate) fn metric(&self) -> Result<TargetQualityMetric, String> {
+        match self.metric.as_deref().map(str::trim) {
+            None | Some("") | Some("vmaf") => Ok(TargetQualityMetric::Vmaf),
+            Some("ssim") => Ok(TargetQualityMetric::Ssim),
+            Some(other) => Err(format!("Unsupported target quality metric: {}", other)),
+        }
+    }
+
+    pub(crate) fn sample_count(&self) -> usize {
+        self.sample_count
+            .unwrap_or(DEFAULT_SAMPLE_COUNT)
+            .clamp(1, MAX_SAMPLE_COUNT)
+    }
+
+    pub(crate) fn sample_ms(&self) -> u64 {
+        u64::from(self.sample_seconds.unwrap_or(DEFAULT_SAMPLE_SECONDS).max(1)) * 1000
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct CrfSample {
+    pub(crate) crf: u8,
+    pub(crate) score: f64,
+    /// Combined size of the encoded samples.
+    pub(crate) sample_bytes: u64,
+}
+
+/// Binary search for the highest CRF whose samples still reach the target score.
+#[derive(Debug, Clone)]
+pub(crate) struct CrfSearch {
+    target_score: f64,
+    low: i16,
+    high: i16,
+    best: Option<CrfSample>,
+    closest: Option<CrfSample>,
+}
+
+impl CrfSearch {
+    pub(crate) fn new(target: &TranscodeTargetQuality) -> Result<Self, String> {
+        let metric = target.metric()?;
+        let max_score = match metric {
+            TargetQualityMetric::Vmaf => 100.0,
+            TargetQualityMetric::Ssim => 1.0,
+        };
+        if !target.score.is_finite() || target.score <= 0.0 || target.score > max_score {
+            return Err(format!(
+                "Target {} score must be greater than 0 and at most {}",
+                metric.as_str().to_uppercase(),
+                max_score
+            ));
+        }
+
+        let min_crf = target.min_crf.unwrap_or(DEFAULT_MIN_CRF);
+        let max_crf = target.max_crf.unwrap_or(DEFAULT_MAX_CRF);
+        if min_crf > max_crf || max_crf > MAX_CRF {
+            return Err(format!(
+                "Target quality CRF range must be ascending and at most {}",
+                MAX_CRF
+            ));
+        }
+
+        Ok(Self {
+            target_score: target.score,
+            low: i16::from(min_crf),
+            high: i16::from(max_crf),
+            best: None,
+            closest: None,
+        })
+    }
+
+    pub(crate) fn next_crf(&self) -> Option<u8> {
+        (self.low <= self.high).then(|| (self.low + (self.high - self.low) / 2) as u8)
+    }
+
+    pub(crate) fn record(&mut self, sample: CrfSample) {
+        if self
+            .closest
+            .is_none_or(|closest| sample.score > closest.score)
+        {
+            self.closest = Some(sample);
+        }
+
+        if sample.score >= self.target_score {
+            self.best = Some(sample);
+            self.low = i16::from(sample.crf) + 1;
+        } else {
+            self.high = i16::from(sample.crf) - 1;
+        }
+    }
+
+    /// The chosen CRF and whether it actually reached the target; falls back to
+    /// the best-scoring sample when none did.
+    pub(crate) fn finish(self) -> Option<(CrfSample, bool)> {
+        match self.best {
+            Some(best) => Some((best, true)),
+            None => self.closest.map(|closest| (closest, false)),
+        }
+    }
+}
+
+/// Sample windows in milliseconds, centred on the analysis timestamps.
+pub(crate) fn build_sample_windows(
+    duration_us: u64,
+    sample_count: usize,
+    sample_ms: u64,
+) -> Vec<(u64, u64)> {
+    let duration_ms = duration_us / 1000;
+    if duration_ms == 0 {
+        return Vec::new();
+    }
+    if duration_ms <= sample_ms {
+        return vec![(0, duration_ms)];
+    }
+
+    select_analysis_timestamps(duration_us, sample_count)
+        .into_iter()
+        .map(|timestamp| {
+            let center_ms = (timestamp * 1000.0) as u64;
+            let start_ms = center_ms
+                .saturating_sub(sample_ms / 2)
+                .min(duration_ms - sample_ms);
+            (start_ms, start_ms + sample_ms)
+        })
+        .collect()
+}
+
+/// Scale the sample size up to the full duration and add the audio estimate.
+pub(crate) fn predict_output_bytes(
+    sample_bytes: u64,
+    sample_ms: u64,
+    duration_us: u64,
+    audio_bitrate_kbps: u32,
+) -> u64 {
+    if sample_ms == 0 {
+        return 0;
+    }
+
+    let duration_ms = duration_us as f64 / 1000.0;
+    let video_bytes = sample_bytes as f64 * duration_ms / sample_ms as f64;
+    let audio_bytes = f64::from(audio_bitrate_kbps) * 1000.0 / 8.0 * duration_ms / 1000.0;
+    (video_bytes + audio_bytes) as u64
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct TargetQualityPlan {
+    pub(crate) metric: TargetQualityMetric,
+    pub(crate) target_score: f64,
+    pub(crate) sample: CrfSample,
+    /// False when no candidate reached the target and the best-scoring one was used.
+    pub(crate) reached: bool,
+    predicted_bytes: u64,
+}
+
+/// Video-only encode of the main stream over one sample window.
+pub(crate) fn build_sample_request(
+    request: &TranscodeRequest,
+    window: (u64, u64),
+    crf: u8,
+    sample_path: &Path,
+) -> TranscodeRequest {
+    let mut sample = request_with_only_video(&request_with_video_crf(request, crf), sample_path);
+    sample.ranges = vec![TranscodeTimeRange {
+        start_ms: window.0,
+        end_ms: Some(window.1),
+    }];
+    sample.video.track_overrides.clear();
+    sample
+}
+
+fn build_target_quality_samples_dir(input_path: &str, output_path: &str) -> PathBuf {
+    std::env::temp_dir()
+        .join("mediaflow_transcode_samples")
+        .join(format!(
+            "{:016x}",
+            stable_hash64(&format!("{}\n{}", input_path, output_path))
+        ))
+}
+
+/// Run one sample encode; returns its `-progress` output.
+pub(crate) async fn run_sample_encode(
+    ffmpeg_path: &str,
+    input_path: &str,
+    args: &[String],
+) -> Result<String, String> {
+    let child = Command::new(ffmpeg_path)
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|error| format!("Failed to start ffmpeg: {}", error))?;
+
+    if let Some(pid) = child.id()
+        && let Ok(mut guard) = super::state::TRANSCODE_PROCESS_IDS.lock()
+    {
+        guard.insert(input_path.to_string(), pid);
+    }
+
+    let child_pid = child.id();
+    let output = match timeout(TRANSCODE_TIMEOUT, child.wait_with_output()).await {
+        Ok(result) => result.map_err(|error| format!("Failed to execute ffmpeg: {}", error))?,
+        Err(_) => {
+            if let Some(pid) = child_pid {
+                terminate_process(pid);
+            }
+            return Err(format!(
+                "Sample encode timeout after {} seconds",
+                TRANSCODE_TIMEOUT.as_secs()
+            ));
+        }
+    };
+
+    if let Ok(mut guard) = super::state::TRANSCODE_PROCESS_IDS.lock() {
+        guard.remove(input_path);
+    }
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("Sample encode failed: {}", stderr.trim()));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Encode short samples at candidate CRFs for the `targetQuality` mode and pick
+/// the highest CRF whose mean sample score reaches the target.
+pub(crate) async fn plan_target_quality<F>(
+    ffmpeg_path: &str,
+    ffprobe_path: &str,
+    request: &TranscodeRequest,
+    context: &TranscodeContext,
+    streams: &[Value],
+    duration_us: Option<u64>,
+    on_sample: F,
+) -> Result<Option<TargetQualityPlan>, String>
+where
+    F: Fn(TargetQualityMetric, &CrfSample),
+{
+    if request.video.mode != "transcode"
+        || request.video.quality_mode.as_deref() != Some("targetQuality")
+    {
+        return Ok(None);
+    }
+
+    let Some(target) = request.video.target_quality.as_ref() else {
+        return Err("Target quality mode requires a target score".to_string());
+    };
+    let metric = target.metric()?;
+    let mut search = CrfSearch::new(target)?;
+    if request.ranges.len() > 1 {
+        return Err(
+            "Target quality mode cannot be combined with joining several ranges".to_string(),
+        );
+    }
+    let Some(duration_us) = duration_us.filter(|duration| *duration > 0) else {
+        return Err("Target quality mode requires a known input duration".to_string());
+    };
+    if metric == TargetQualityMetric::Vmaf && !ffmpeg_supports_libvmaf(ffmpeg_path).await {
+        return Err(
+            "VMAF is not available in this ffmpeg build. Choose SSIM as the target metric."
+                .to_string(),
+        );
+    }
+
+    let range_start_ms = request.ranges.first().map_or(0, |range| range.start_ms);
+    let windows = build_sample_windows(duration_us, target.sample_count(), target.sample_ms())
+        .into_iter()
+        .map(|(start_ms, end_ms)| (range_start_ms + start_ms, range_start_ms + end_ms))
+        .collect::<Vec<_>>();
+    let sample_ms = windows
+        .iter()
+        .map(|(start_ms, end_ms)| end_ms - start_ms)
+        .sum::<u64>();
+    let extension = Path::new(&request.output_path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or("mkv");
+    let samples_dir = build_target_quality_samples_dir(&request.input_path, &request.output_path);
+    let sample_context = context.for_video_only();
+    std::fs::create_dir_all(&samples_dir)
+        .map_err(|error| format!("Failed to create sample directory: {}", error))?;
+
+    if let Ok(mut guard) = super::state::TRANSCODE_OUTPUT_PATHS.lock() {
+        guard.insert(request.input_path.clone(), request.output_path.clone());
+    }
+    let result = async {
+        while let Some(crf) = search.next_crf() {
+            let mut scores = Vec::with_capacity(windows.len());
+            let mut sample_bytes = 0;
+            for (index, window) in windows.iter().enumerate() {
+                if !is_transcode_tracked(&request.input_path) {
+                    return Err("Transcode cancelled".to_string());
+                }
+
+                let sample_path =
+                    samples_dir.join(format!("sample-{}-crf{}.{}", index + 1, crf, extension));
+                let sample_request = build_sample_request(request, *window, crf, &sample_path);
+                let args = build_transcode_args(
+                    &sample_request,
+                    &sample_context,
+                    streams,
+                    Some((window.1 - window.0) * 1000),
+                )?;
+                run_sample_encode(ffmpeg_path, &request.input_path, &args).await?;
+                sample_bytes += std::fs::metadata(&sample_path)
+                    .map(|metadata| metadata.len())
+                    .unwrap_or_default();
+
+                let comparison = run_quality_comparison(
+                    ffmpeg_path,
+                    ffprobe_path,
+                    &QualityComparisonRequest {
+                        reference_path: request.input_path.clone(),
+                        distorted_path: sample_request.output_path.clone(),
+                        reference_start_ms: Some(window.0),
+                        reference_end_ms: Some(window.1),
+                    },
+                    QualityProcessOwner::Transcode(&request.input_path),
+                    |_| {},
+                )
+                .await?;
+                let _ = std::fs::remove_file(&sample_path);
+                scores.push(metric.score(&comparison).ok_or_else(|| {
+                    format!(
+                        "Sample comparison did not report {}",
+                        metric.as_str().to_uppercase()
+                    )
+                })?);
+            }
+
+            let sample = CrfSample {
+                crf,
+                score: scores.iter().sum::<f64>() / scores.len().max(1) as f64,
+                sample_bytes,
+            };
+            on_sample(metric, &sample);
+            search.record(sample);
+        }
+
+        search
+            .finish()
+            .ok_or_else(|| "Target quality search did not encode any samples".to_string())
+    }
+    .await;
+    clear_transcode_state(&request.input_path);
+    let _ = std::fs::remove_dir_all(&samples_dir);
+    let (sample, reached) = result?;
+
+    Ok(Some(TargetQualityPlan {
+        metric,
+        target_score: target.score,
+        sample,
+        reached,
+        predicted_bytes: predict_output_bytes(
+            sample.sample_bytes,
+            sample_ms,
+            duration_us,
+            estimate_output_audio_bitrate_kbps(request, streams)?,
+        ),
+    }))
+}
+
+pub(crate) fn emit_transcode_target_quality_report(
+    events: &impl TranscodeEvents,
+    request: &TranscodeRequest,
+    plan: &TargetQualityPlan,
+    actual_bytes: u64,
+) {
+    events.emit_event(
+        "media-transcode-target-quality",
+        serde_json::json!({
+            "inputPath": request.input_path,
+            "outputPath": request.output_path,
+            "metric": plan.metric.as_str(),
+            "targetScore": plan.target_score,
+            "crf": plan.sample.crf,
+            "sampleScore": plan.sample.score,
+            "reached": plan.reached,
+            "predictedSizeBytes": plan.predicted_bytes,
+            "actualSizeBytes": actual_bytes
+        }),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        CrfSample, CrfSearch, TranscodeTargetQuality, build_sample_windows, predict_output_bytes,
+    };
+
+    fn target(score: f64) -> TranscodeTargetQuality {
+        TranscodeTargetQuality {
+            score,
+            min_crf: Some(10),
+            max_crf: Some(40),
+            ..TranscodeTargetQuality::default()
+        }
+    }
+
+    /// Score falls by one point per CRF step above 20.
+    fn run_search(search: &mut CrfSearch) -> Vec<u8> {
+        let mut tried = Vec::new();
+        while let Some(crf) = search.next_crf() {
+            tried.push(crf);
+            search.record(CrfSample {
+                crf,
+                score: 100.0 - f64::from(crf.saturating_sub(20)),
+                sample_bytes: u64::from(100 - crf),
+            });
+        }
+        tried
+    }
+
+    #[test]
+    fn crf_search_finds_the_highest_crf_meeting_the_target() {
+        let mut search = CrfSearch::new(&target(95.0)).expect("search should start");
+        let tried = run_search(&mut search);
+
+        assert_eq!(tried.first(), Some(&25));
+        assert!(tried.len() <= 5);
+        let (chosen, reached) = search.finish().expect("a crf should be chosen");
+        assert_eq!(chosen.crf, 25);
+        assert!(reached);
+
+        let mut unreachable = CrfSearch::new(&TranscodeTargetQuality {
+            min_crf: Some(30),
+            ..target(95.0)
+        })
+        .expect("search should start");
+        run_search(&mut unreachable);
+        let (chosen, reached) = unreachable.finish().expect("fallback should be chosen");
+        assert_eq!(chosen.crf, 30);
+        assert!(!reached);
+    }
+
+    #[test]
+    fn crf_search_rejects_invalid_targets() {
+        assert!(CrfSearch::new(&target(101.0)).is_err());
+        assert!(
+            CrfSearch::new(&TranscodeTargetQuality {
+                metric: Some("ssim".to_string()),
+                ..target(95.0)
+            })
+            .is_err()
+        );
+        assert!(
+            CrfSearch::new(&TranscodeTargetQuality {
+                min_crf: Some(41),
+                ..target(95.0)
+            })
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn sample_windows_and_size_prediction() {
+        let windows = build_sample_windows(100_000_000, 4, 4_000);
+        assert_eq!(windows.len(), 4);
+        assert!(windows.iter().all(|(start, end)| end - start == 4_000));
+        assert!(windows.windows(2).all(|pair| pair[1].0 > pair[0].0));
+        assert_eq!(build_sample_windows(3_000_000, 4, 4_000), vec![(0, 3_000)]);
+
+        // 1 MB of video per 10 s of samples over 100 s, plus 128 kb/s of audio.
+        assert_eq!(
+            predict_output_bytes(1_000_000, 10_000, 100_000_000, 128),
+            10_000_000 + 1_600_000
+        );
+    }
+}
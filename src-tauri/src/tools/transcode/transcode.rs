@@ -1,26 +1,70 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
 use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use tauri::Emitter;
 use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::process::Command;
 use tokio::time::timeout;
 
+use crate::shared::dry_run::{FfmpegDryRun, build_dry_run};
 use crate::shared::ffmpeg_progress::FfmpegProgressTracker;
+use crate::shared::hash::stable_hash64;
 use crate::shared::process::terminate_process;
 use crate::shared::sleep_inhibit::SleepInhibitGuard;
 use crate::shared::store::{resolve_ffmpeg_path, resolve_ffprobe_path};
 use crate::shared::validation::{validate_media_path, validate_output_path};
 use crate::tools::ffprobe::{get_media_duration_us_with_ffprobe, probe::probe_file_with_ffprobe};
 use crate::tools::media_metadata::{
-    MediaMetadataRequest, OutputStreamMetadata, apply_metadata_args,
-    output_stream_metadata_from_request,
+    MediaMetadataRequest, OutputStreamMetadata, apply_attachment_args, apply_chapter_args,
+    apply_metadata_args, metadata_schema_for_container, output_stream_metadata_from_request,
 };
 
-const TRANSCODE_TIMEOUT: Duration = Duration::from_secs(7200);
+use super::audio_filters::{
+    AudioFilterSource, LoudnormMeasurement, TranscodeAudioFilters, build_audio_filter_graph,
+    build_loudnorm_measure_graph, parse_loudnorm_measurement,
+};
+use super::burn_in::{
+    SubtitleBurnInPlan, TranscodeSubtitleBurnIn, build_bitmap_burn_in_filter,
+    build_burn_in_fonts_dir, extract_font_attachments, needs_extracted_fonts,
+    plan_subtitle_burn_in,
+};
+use super::capabilities::{
+    codec_for_encoder_id, fallback_audio_bitrate_kbps, fallback_encoders_for_container,
+    validate_audio_filter_support, validate_video_filter_support,
+};
+use super::chunked::{TranscodeChunkedEncoding, build_chunk_dir, encode_chunked_video};
+use super::events::{NoTranscodeEvents, TranscodeEvents};
+use super::filters::{
+    TranscodeVideoFilters, build_video_filter_graph, is_hdr_transfer, with_automatic_tonemap,
+};
+use super::hdr::{
+    encoder_params_flag, ffmpeg_supports_x265_dolby_vision, hdr_encoder_params, hdr_side_data,
+    probe_hdr_frame_side_data,
+};
+use super::packaging::{
+    TranscodePackagingSettings, is_packaging_container, plan_packaged_transcode,
+    run_packaged_transcode, validate_packaging_request,
+};
+use super::preview::{TranscodePreviewEstimate, run_transcode_preview};
+use super::quality::{
+    QualityComparisonRequest, QualityProcessOwner, emit_quality_progress, run_quality_comparison,
+};
+use super::ranges::{RangeJoinInputs, build_range_join_dir, prepare_range_join_inputs};
+use super::target_quality::{
+    TranscodeTargetQuality, emit_transcode_target_quality_report, plan_target_quality,
+};
+use super::verify::{
+    ExpectedOutputStream, TranscodeVerificationReport, TranscodeVerificationSettings,
+    handle_failed_output, verify_output_streams,
+};
+
+pub(crate) const TRANSCODE_TIMEOUT: Duration = Duration::from_secs(7200);
+/// Share of a target size kept free for container headers, indexes and interleaving.
+const TARGET_SIZE_CONTAINER_OVERHEAD: f64 = 0.02;
+const TARGET_SIZE_MIN_VIDEO_BITRATE_KBPS: u32 = 100;
+const TARGET_SIZE_COPY_AUDIO_FALLBACK_KBPS: u32 = 192;
 
 #[cfg_attr(not(test), allow(dead_code))]
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -30,6 +74,8 @@ pub(crate) struct TranscodeProgressEvent {
     pub(crate) output_path: String,
     pub(crate) progress: i32,
     pub(crate) speed_bytes_per_sec: Option<f64>,
+    pub(crate) pass: u8,
+    pub(crate) pass_count: u8,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -45,6 +91,46 @@ pub(crate) struct TranscodeAdditionalArg {
 #[derive(Debug, Clone, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub(crate) struct TranscodeVideoSettings {
+    pub(crate) mode: String,
+    pub(crate) encoder_id: Option<String>,
+    pub(crate) profile: Option<String>,
+    pub(crate) level: Option<String>,
+    pub(crate) pixel_format: Option<String>,
+    pub(crate) quality_mode: Option<String>,
+    pub(crate) crf: Option<f64>,
+    pub(crate) qp: Option<i32>,
+    pub(crate) bitrate_kbps: Option<u32>,
+    pub(crate) preset: Option<String>,
+    /// Run an analysis pass before the real encode (bitrate mode only).
+    #[serde(default)]
+    pub(crate) two_pass: bool,
+    /// Output size in MiB for the `targetSize` quality mode.
+    pub(crate) target_size_mb: Option<f64>,
+    /// Re-encode once at a lower bitrate when the first attempt overshoots the target size.
+    #[serde(default)]
+    pub(crate) retry_target_size: bool,
+    /// Score to reach in the `targetQuality` quality mode.
+    pub(crate) target_quality: Option<TranscodeTargetQuality>,
+    /// Encode the main video in chunks running in parallel.
+    pub(crate) chunked: Option<TranscodeChunkedEncoding>,
+    /// Carry Dolby Vision RPUs into libx265 when the ffmpeg build supports it.
+    #[serde(default)]
+    pub(crate) keep_dolby_vision: bool,
+    #[serde(default)]
+    pub(crate) filters: TranscodeVideoFilters,
+    #[serde(default)]
+    pub(crate) additional_args: Vec<TranscodeAdditionalArg>,
+    #[serde(default)]
+    pub(crate) track_overrides: Vec<TranscodeVideoTrackOverride>,
+    /// Apply the global mode to every video stream instead of only the first one.
+    #[serde(default)]
+    pub(crate) map_all_streams: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TranscodeVideoTrackOverride {
+    pub(crate) track_id: usize,
     pub(crate) mode: String,
     pub(crate) encoder_id: Option<String>,
     pub(crate) profile: Option<String>,
@@ -68,6 +154,26 @@ pub(crate) struct TranscodeAudioTrackOverride {
     pub(crate) bitrate_kbps: Option<u32>,
     pub(crate) channels: Option<u8>,
     pub(crate) sample_rate: Option<u32>,
+    /// Replaces the global audio filters for this track when set.
+    pub(crate) filters: Option<TranscodeAudioFilters>,
+    #[serde(default)]
+    pub(crate) additional_args: Vec<TranscodeAdditionalArg>,
+}
+
+/// Extra output track encoded from a source audio track, placed right after it.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TranscodeDerivedAudioTrack {
+    pub(crate) source_track_id: usize,
+    pub(crate) encoder_id: Option<String>,
+    pub(crate) bitrate_kbps: Option<u32>,
+    pub(crate) channels: Option<u8>,
+    pub(crate) sample_rate: Option<u32>,
+    pub(crate) filters: Option<TranscodeAudioFilters>,
+    pub(crate) title: Option<String>,
+    pub(crate) language: Option<String>,
+    /// Makes this the only default audio track when true.
+    pub(crate) default: Option<bool>,
     #[serde(default)]
     pub(crate) additional_args: Vec<TranscodeAdditionalArg>,
 }
@@ -84,6 +190,20 @@ pub(crate) struct TranscodeAudioSettings {
     pub(crate) additional_args: Vec<TranscodeAdditionalArg>,
     #[serde(default)]
     pub(crate) track_overrides: Vec<TranscodeAudioTrackOverride>,
+    #[serde(default)]
+    pub(crate) filters: TranscodeAudioFilters,
+    #[serde(default)]
+    pub(crate) derived_tracks: Vec<TranscodeDerivedAudioTrack>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TranscodeSubtitleTrackOverride {
+    pub(crate) track_id: usize,
+    pub(crate) mode: String,
+    pub(crate) encoder_id: Option<String>,
+    #[serde(default)]
+    pub(crate) additional_args: Vec<TranscodeAdditionalArg>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -93,6 +213,16 @@ pub(crate) struct TranscodeSubtitleSettings {
     pub(crate) encoder_id: Option<String>,
     #[serde(default)]
     pub(crate) additional_args: Vec<TranscodeAdditionalArg>,
+    #[serde(default)]
+    pub(crate) track_overrides: Vec<TranscodeSubtitleTrackOverride>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TranscodeTimeRange {
+    pub(crate) start_ms: u64,
+    /// Open-ended when missing; only the last range may omit it.
+    pub(crate) end_ms: Option<u64>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -106,18 +236,132 @@ pub(crate) struct TranscodeRequest {
     pub(crate) subtitles: TranscodeSubtitleSettings,
     #[serde(default)]
     pub(crate) metadata: MediaMetadataRequest,
+    /// Parts of the input to keep; several ranges are joined in order.
+    #[serde(default)]
+    pub(crate) ranges: Vec<TranscodeTimeRange>,
+    /// Source stream indices in the desired output order; unlisted streams follow.
+    #[serde(default)]
+    pub(crate) stream_order: Vec<usize>,
+    /// Subtitles drawn onto the main video stream.
+    pub(crate) burn_in: Option<TranscodeSubtitleBurnIn>,
+    /// Score the output against the input once the transcode finishes.
+    #[serde(default)]
+    pub(crate) compare_quality: bool,
+    /// Checks run on the output once the transcode finishes.
+    #[serde(default)]
+    pub(crate) verification: TranscodeVerificationSettings,
+    /// Segmenting and bitrate ladder for the HLS and DASH containers.
+    #[serde(default)]
+    pub(crate) packaging: TranscodePackagingSettings,
+}
+
+/// What the preparation steps learned before encoding; passed to the argument
+/// builders next to the request, which only carries the user's settings.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TranscodeContext {
+    /// Joined chunk encode that the final mux copies the main video from.
+    pub(crate) chunked_video_path: Option<String>,
+    /// Whether libx265 in this ffmpeg build accepts `-dolbyvision`.
+    pub(crate) dolby_vision_supported: bool,
+    /// Loudness measurements keyed by source stream index and derived track index.
+    pub(crate) loudnorm_measurements: Vec<(usize, Option<usize>, LoudnormMeasurement)>,
+    /// Subtitle and chapter inputs for joined ranges.
+    pub(crate) range_join: RangeJoinInputs,
+    /// Packaged renditions force a keyframe at every segment boundary.
+    pub(crate) segment_seconds: Option<u32>,
+}
+
+impl TranscodeContext {
+    /// Context for video-only encodes such as samples and chunks.
+    pub(crate) fn for_video_only(&self) -> Self {
+        Self {
+            dolby_vision_supported: self.dolby_vision_supported,
+            ..Self::default()
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
-struct StreamInfo {
-    stream_index: usize,
-    relative_index: usize,
+pub(crate) struct StreamInfo {
+    pub(crate) stream_index: usize,
+    pub(crate) relative_index: usize,
     codec_name: String,
     channels: u64,
     channel_layout: Option<String>,
     probe_stream: Value,
 }
 
+#[derive(Debug, Clone)]
+struct TranscodePass {
+    number: u8,
+    passlog_prefix: String,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct TranscodePhase {
+    pub(crate) pass: u8,
+    pub(crate) pass_count: u8,
+}
+
+impl TranscodePhase {
+    /// Spread per-pass progress over the whole job so the bar never goes backwards.
+    fn overall_progress(self, pass_progress: i32) -> i32 {
+        let pass_count = i32::from(self.pass_count.max(1));
+        ((i32::from(self.pass) - 1) * 100 + pass_progress.clamp(0, 100)) / pass_count
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct TargetSizePlan {
+    target_bytes: u64,
+    pub(crate) video_bitrate_kbps: u32,
+    audio_bitrate_kbps: u32,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct ResolvedVideoSettings {
+    pub(crate) mode: String,
+    encoder_id: Option<String>,
+    profile: Option<String>,
+    level: Option<String>,
+    pixel_format: Option<String>,
+    quality_mode: Option<String>,
+    crf: Option<f64>,
+    qp: Option<i32>,
+    bitrate_kbps: Option<u32>,
+    preset: Option<String>,
+    additional_args: Vec<TranscodeAdditionalArg>,
+    /// Why `auto` picked the mode; unset for modes chosen in the request.
+    auto_reason: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+enum PlannedStream {
+    Video(StreamInfo, ResolvedVideoSettings),
+    Audio(StreamInfo, ResolvedAudioSettings),
+    Subtitle(StreamInfo, ResolvedSubtitleSettings),
+}
+
+impl PlannedStream {
+    fn stream_index(&self) -> usize {
+        match self {
+            Self::Video(stream, _) | Self::Audio(stream, _) | Self::Subtitle(stream, _) => {
+                stream.stream_index
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ResolvedSubtitleSettings {
+    mode: String,
+    encoder_id: Option<String>,
+    additional_args: Vec<TranscodeAdditionalArg>,
+    /// Set when a track override chose the mode, so incompatible tracks fail instead of being dropped.
+    is_override: bool,
+    auto_reason: Option<String>,
+}
+
 #[derive(Debug, Clone)]
 struct ResolvedAudioSettings {
     mode: String,
@@ -125,23 +369,87 @@ struct ResolvedAudioSettings {
     bitrate_kbps: Option<u32>,
     channels: Option<u8>,
     sample_rate: Option<u32>,
+    filters: TranscodeAudioFilters,
     additional_args: Vec<TranscodeAdditionalArg>,
+    /// Index into `derived_tracks` when this output is a derived copy.
+    derived_index: Option<usize>,
+    auto_reason: Option<String>,
+}
+
+/// What happens to one source stream, reported before the transcode runs.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TranscodeStreamPlan {
+    pub(crate) stream_index: usize,
+    pub(crate) codec_type: String,
+    pub(crate) codec_name: String,
+    /// `copy`, `transcode`, `convert_text`, `disable` or `drop` for tracks the
+    /// container can't hold.
+    pub(crate) action: String,
+    pub(crate) encoder_id: Option<String>,
+    pub(crate) bitrate_kbps: Option<u32>,
+    /// Set for extra outputs encoded from this stream.
+    pub(crate) derived_index: Option<usize>,
+    /// Why `auto` picked the action; unset for modes chosen in the request.
+    pub(crate) reason: Option<String>,
 }
 
-fn emit_transcode_progress(
-    app: &tauri::AppHandle,
+pub(crate) fn emit_transcode_progress(
+    events: &impl TranscodeEvents,
     input_path: &str,
     output_path: &str,
     progress: i32,
     speed_bytes_per_sec: Option<f64>,
+    phase: TranscodePhase,
 ) {
-    let _ = app.emit(
+    events.emit_event(
         "media-transcode-progress",
         serde_json::json!({
             "inputPath": input_path,
             "outputPath": output_path,
             "progress": progress,
-            "speedBytesPerSec": speed_bytes_per_sec
+            "speedBytesPerSec": speed_bytes_per_sec,
+            "pass": phase.pass,
+            "passCount": phase.pass_count
+        }),
+    );
+}
+
+fn emit_transcode_target_size_report(
+    events: &impl TranscodeEvents,
+    request: &TranscodeRequest,
+    plan: &TargetSizePlan,
+    video_bitrate_kbps: u32,
+    actual_bytes: u64,
+    attempts: u8,
+) {
+    let difference_bytes = actual_bytes as i64 - plan.target_bytes as i64;
+    events.emit_event(
+        "media-transcode-target-size",
+        serde_json::json!({
+            "inputPath": request.input_path,
+            "outputPath": request.output_path,
+            "targetSizeBytes": plan.target_bytes,
+            "actualSizeBytes": actual_bytes,
+            "differenceBytes": difference_bytes,
+            "differencePercent": difference_bytes as f64 / plan.target_bytes as f64 * 100.0,
+            "videoBitrateKbps": video_bitrate_kbps,
+            "attempts": attempts
+        }),
+    );
+}
+
+fn emit_transcode_warning(
+    events: &impl TranscodeEvents,
+    request: &TranscodeRequest,
+    message: &str,
+) {
+    events.emit_event(
+        "media-transcode-warning",
+        serde_json::json!({
+            "inputPath": request.input_path,
+            "outputPath": request.output_path,
+            "message": message
         }),
     );
 }
@@ -175,6 +483,13 @@ fn can_copy_subtitle_codec(container_id: &str, codec: &str) -> bool {
     )
 }
 
+fn can_copy_attached_picture(container_id: &str, codec: &str) -> bool {
+    matches!(
+        (container_id, codec),
+        ("mkv" | "mp4" | "mov", "mjpeg" | "png")
+    )
+}
+
 fn can_copy_video_codec(container_id: &str, codec: &str) -> bool {
     matches!(
         (container_id, codec),
@@ -198,6 +513,7 @@ fn can_copy_video_codec(container_id: &str, codec: &str) -> bool {
                 | "vp8"
                 | "vp9"
         ) | ("webm", "av1" | "vp8" | "vp9")
+            | ("hls" | "dash", "h264" | "hevc")
     )
 }
 
@@ -237,6 +553,7 @@ fn can_copy_audio_codec(container_id: &str, codec: &str) -> bool {
                     | "vorbis"
             )
             | ("webm", "opus" | "vorbis")
+            | ("hls" | "dash", "aac" | "ac3" | "eac3")
             | ("aac", "aac")
             | ("mp3", "mp3")
             | ("flac", "flac")
@@ -256,7 +573,9 @@ fn can_copy_audio_codec(container_id: &str, codec: &str) -> bool {
     )
 }
 
-fn validate_output_path_matches_container(request: &TranscodeRequest) -> Result<(), String> {
+pub(crate) fn validate_output_path_matches_container(
+    request: &TranscodeRequest,
+) -> Result<(), String> {
     let Some(expected_extension) =
         super::capabilities::container_extension_for_id(&request.container_id)
     else {
@@ -285,7 +604,7 @@ fn validate_output_path_matches_container(request: &TranscodeRequest) -> Result<
     Ok(())
 }
 
-fn extract_streams_by_type(streams: &[Value], codec_type: &str) -> Vec<StreamInfo> {
+pub(crate) fn extract_streams_by_type(streams: &[Value], codec_type: &str) -> Vec<StreamInfo> {
     streams
         .iter()
         .filter(|stream| {
@@ -342,7 +661,7 @@ fn resolve_audio_settings_for_stream(
         .iter()
         .find(|track_override| track_override.track_id == stream.stream_index);
 
-    ResolvedAudioSettings {
+    let mut settings = ResolvedAudioSettings {
         mode: track_override
             .map(|track_override| track_override.mode.clone())
             .unwrap_or_else(|| request.audio.mode.clone()),
@@ -358,6 +677,9 @@ fn resolve_audio_settings_for_stream(
         sample_rate: track_override
             .and_then(|track_override| track_override.sample_rate)
             .or(request.audio.sample_rate),
+        filters: track_override
+            .and_then(|track_override| track_override.filters.clone())
+            .unwrap_or_else(|| request.audio.filters.clone()),
         additional_args: {
             let mut additional_args = request.audio.additional_args.clone();
             if let Some(track_override) = track_override {
@@ -365,1583 +687,4007 @@ fn resolve_audio_settings_for_stream(
             }
             additional_args
         },
+        derived_index: None,
+        auto_reason: None,
+    };
+    if settings.mode == "auto" {
+        apply_auto_audio_mode(request, stream, &mut settings);
     }
+    settings
 }
 
-fn should_force_libopus_mapping_family_255(
-    settings: &ResolvedAudioSettings,
+/// `auto` copies audio the container can hold when nothing asks for processing,
+/// otherwise it re-encodes with the requested or the container's fallback encoder.
+fn apply_auto_audio_mode(
+    request: &TranscodeRequest,
     stream: &StreamInfo,
-    additional_args: &[TranscodeAdditionalArg],
-) -> bool {
-    if settings.encoder_id.as_deref() != Some("libopus")
-        || settings.channels.is_some()
-        || has_enabled_additional_arg(additional_args, "-mapping_family")
+    settings: &mut ResolvedAudioSettings,
+) {
+    let container = request.container_id.to_uppercase();
+    let transcode_reason = if !can_copy_audio_codec(&request.container_id, &stream.codec_name) {
+        Some(format!(
+            "{} cannot store {} audio",
+            container, stream.codec_name
+        ))
+    } else if !settings.filters.is_empty() {
+        Some("Audio filters need re-encoding".to_string())
+    } else if settings.channels.is_some_and(|channels| channels > 0)
+        || settings
+            .sample_rate
+            .is_some_and(|sample_rate| sample_rate > 0)
     {
-        return false;
-    }
-
-    let channel_layout = stream.channel_layout.as_deref().unwrap_or_default();
+        Some("Changing channels or sample rate needs re-encoding".to_string())
+    } else if request.ranges.len() > 1 {
+        Some("Joining several ranges re-encodes the audio".to_string())
+    } else {
+        None
+    };
+    let Some(reason) = transcode_reason else {
+        settings.mode = "copy".to_string();
+        settings.auto_reason = Some(format!(
+            "{} can store {} audio",
+            container, stream.codec_name
+        ));
+        return;
+    };
 
-    stream.channels > 2
-        && !channel_layout.is_empty()
-        && !libopus_uses_default_mapping_family(channel_layout)
-        && !has_enabled_additional_arg(additional_args, "-mapping_family")
+    settings.mode = "transcode".to_string();
+    if settings.encoder_id.is_none() {
+        settings.encoder_id = fallback_encoders_for_container(&request.container_id)
+            .audio
+            .map(str::to_string);
+    }
+    if settings.bitrate_kbps.is_none()
+        && let Some(encoder_id) = settings.encoder_id.as_deref()
+    {
+        let channels = settings
+            .channels
+            .filter(|channels| *channels > 0)
+            .map_or(stream.channels, u64::from);
+        settings.bitrate_kbps = fallback_audio_bitrate_kbps(encoder_id, channels);
+    }
+    settings.auto_reason = Some(reason);
 }
 
-fn qualify_stream_option(flag: &str, stream_specifier: Option<&str>) -> String {
-    if let Some(stream_specifier) = stream_specifier {
-        if flag.contains(':') {
-            return flag.to_string();
-        }
-
-        return format!("{}:{}", flag, stream_specifier);
+fn resolve_derived_audio_settings(
+    request: &TranscodeRequest,
+    derived_index: usize,
+    derived_track: &TranscodeDerivedAudioTrack,
+) -> ResolvedAudioSettings {
+    ResolvedAudioSettings {
+        mode: "transcode".to_string(),
+        encoder_id: derived_track
+            .encoder_id
+            .clone()
+            .or_else(|| request.audio.encoder_id.clone()),
+        bitrate_kbps: derived_track.bitrate_kbps.or(request.audio.bitrate_kbps),
+        channels: derived_track.channels,
+        sample_rate: derived_track.sample_rate,
+        filters: derived_track.filters.clone().unwrap_or_default(),
+        additional_args: derived_track.additional_args.clone(),
+        derived_index: Some(derived_index),
+        auto_reason: None,
     }
-
-    flag.to_string()
 }
 
-fn apply_safe_additional_args(
-    args: &mut Vec<String>,
-    additional_args: &[TranscodeAdditionalArg],
-    stream_specifier: Option<&str>,
-) -> Result<(), String> {
-    const BLOCKED_FLAGS: &[&str] = &[
-        "-i",
-        "-map",
-        "-progress",
-        "-f",
-        "-y",
-        "-n",
-        "-filter_complex",
-        "-filter_script",
-        "-vf",
-        "-af",
-    ];
-
-    for additional_arg in additional_args {
-        if !additional_arg.enabled {
-            continue;
-        }
+/// Every audio output in default order: each kept source track followed by
+/// the tracks derived from it.
+fn resolve_output_audio_tracks(
+    request: &TranscodeRequest,
+    audio_streams: &[StreamInfo],
+) -> Result<Vec<(StreamInfo, ResolvedAudioSettings)>, String> {
+    if let Some(derived_track) = request.audio.derived_tracks.iter().find(|derived_track| {
+        !audio_streams
+            .iter()
+            .any(|stream| stream.stream_index == derived_track.source_track_id)
+    }) {
+        return Err(format!(
+            "Derived audio track source {} is not an audio track in the input",
+            derived_track.source_track_id
+        ));
+    }
 
-        let flag = additional_arg.flag.trim();
-        if !flag.starts_with('-') {
-            return Err(format!("Invalid additional argument flag: {}", flag));
-        }
-        if BLOCKED_FLAGS.contains(&flag) {
-            return Err(format!("Additional argument is not allowed here: {}", flag));
+    let mut tracks = Vec::new();
+    for audio_stream in audio_streams {
+        let resolved_settings = resolve_audio_settings_for_stream(request, audio_stream);
+        if resolved_settings.mode != "disable" {
+            tracks.push((audio_stream.clone(), resolved_settings));
         }
-
-        args.push(qualify_stream_option(flag, stream_specifier));
-        if let Some(value) = additional_arg.value.as_deref() {
-            if !value.trim().is_empty() {
-                args.push(value.trim().to_string());
+        for (derived_index, derived_track) in request.audio.derived_tracks.iter().enumerate() {
+            if derived_track.source_track_id == audio_stream.stream_index {
+                tracks.push((
+                    audio_stream.clone(),
+                    resolve_derived_audio_settings(request, derived_index, derived_track),
+                ));
             }
         }
     }
 
-    Ok(())
+    Ok(tracks)
 }
 
-fn cpu_used_preset_max(encoder_id: &str) -> Option<u8> {
-    match encoder_id {
-        "libaom-av1" | "libvpx-vp9" => Some(8),
-        "libvpx" => Some(16),
-        _ => None,
+fn apply_derived_audio_metadata(
+    metadata: &mut OutputStreamMetadata,
+    derived_track: &TranscodeDerivedAudioTrack,
+) {
+    metadata.title = derived_track.title.clone();
+    if let Some(language) = derived_track.language.clone() {
+        metadata.language = Some(language);
     }
+    metadata.is_default = derived_track.default.unwrap_or(false);
+    metadata.is_forced = false;
 }
 
-fn validate_cpu_used_preset(encoder_id: &str, preset: &str, max_value: u8) -> Result<(), String> {
-    let error_message = || {
-        format!(
-            "{} preset must be an integer from 0 to {}",
-            encoder_id, max_value
-        )
-    };
-    let parsed = preset.parse::<u8>().map_err(|_| error_message())?;
-
-    if parsed > max_value {
-        return Err(error_message());
-    }
-
-    Ok(())
-}
+fn resolve_subtitle_settings_for_stream(
+    request: &TranscodeRequest,
+    stream: &StreamInfo,
+) -> ResolvedSubtitleSettings {
+    let track_override = request
+        .subtitles
+        .track_overrides
+        .iter()
+        .find(|track_override| track_override.track_id == stream.stream_index);
 
-fn apply_video_preset_arg(
-    args: &mut Vec<String>,
-    encoder_id: &str,
-    preset: &str,
-) -> Result<(), String> {
-    if let Some(max_value) = cpu_used_preset_max(encoder_id) {
-        validate_cpu_used_preset(encoder_id, preset, max_value)?;
-        args.push("-cpu-used".to_string());
-        args.push(preset.to_string());
-        return Ok(());
+    let mut settings = ResolvedSubtitleSettings {
+        mode: track_override
+            .map(|track_override| track_override.mode.clone())
+            .unwrap_or_else(|| request.subtitles.mode.clone()),
+        encoder_id: track_override
+            .and_then(|track_override| track_override.encoder_id.clone())
+            .or_else(|| request.subtitles.encoder_id.clone()),
+        additional_args: track_override
+            .map(|track_override| track_override.additional_args.clone())
+            .unwrap_or_default(),
+        is_override: track_override.is_some(),
+        auto_reason: None,
+    };
+    if settings.mode == "auto" {
+        apply_auto_subtitle_mode(request, stream, &mut settings);
     }
-
-    args.push("-preset".to_string());
-    args.push(preset.to_string());
-    Ok(())
+    settings
 }
 
-fn build_transcode_args(
+/// `auto` copies subtitles the container can hold and converts other text
+/// subtitles to the container's text format.
+fn apply_auto_subtitle_mode(
     request: &TranscodeRequest,
-    streams: &[Value],
-    duration_us: Option<u64>,
-) -> Result<Vec<String>, String> {
-    let video_streams = extract_streams_by_type(streams, "video");
-    let audio_streams = extract_streams_by_type(streams, "audio");
-    let subtitle_streams = extract_streams_by_type(streams, "subtitle");
+    stream: &StreamInfo,
+    settings: &mut ResolvedSubtitleSettings,
+) {
+    let container = request.container_id.to_uppercase();
+    let fallback_encoder = fallback_encoders_for_container(&request.container_id).subtitle;
+    settings.is_override = false;
+    let (mode, reason) = if fallback_encoder.is_none() {
+        ("disable", format!("{} cannot store subtitles", container))
+    } else if can_copy_subtitle_codec(&request.container_id, &stream.codec_name) {
+        (
+            "copy",
+            format!("{} can store {} subtitles", container, stream.codec_name),
+        )
+    } else if is_text_subtitle_codec(&stream.codec_name) {
+        let encoder_id = settings
+            .encoder_id
+            .clone()
+            .or(fallback_encoder.map(str::to_string));
+        let reason = format!(
+            "{} cannot store {} subtitles; converted to {}",
+            container,
+            stream.codec_name,
+            encoder_id.as_deref().unwrap_or_default()
+        );
+        settings.encoder_id = encoder_id;
+        ("convert_text", reason)
+    } else {
+        // Left on copy so the track is dropped like any other unsupported bitmap track.
+        (
+            "copy",
+            format!(
+                "{} cannot store {} bitmap subtitles",
+                container, stream.codec_name
+            ),
+        )
+    };
+    settings.mode = mode.to_string();
+    settings.auto_reason = Some(reason);
+}
 
-    let mut args = vec![
-        "-hide_banner".to_string(),
-        "-y".to_string(),
-        "-i".to_string(),
-        request.input_path.clone(),
-    ];
+/// Bitmap subtitles can't be converted to text, so without an explicit
+/// override they are left out when the container can't hold them.
+fn is_subtitle_auto_dropped(
+    request: &TranscodeRequest,
+    stream: &StreamInfo,
+    settings: &ResolvedSubtitleSettings,
+) -> bool {
+    !settings.is_override
+        && settings.mode != "disable"
+        && !is_text_subtitle_codec(&stream.codec_name)
+        && !can_copy_subtitle_codec(&request.container_id, &stream.codec_name)
+}
 
-    let mut mapped_any_stream = false;
-    let mut output_metadata = Vec::<OutputStreamMetadata>::new();
+fn build_subtitle_drop_warnings(request: &TranscodeRequest, streams: &[Value]) -> Vec<String> {
+    extract_streams_by_type(streams, "subtitle")
+        .iter()
+        .filter(|stream| {
+            let settings = resolve_subtitle_settings_for_stream(request, stream);
+            is_subtitle_auto_dropped(request, stream, &settings)
+        })
+        .map(|stream| {
+            format!(
+                "Subtitle track {} ({}) was dropped because {} cannot store bitmap subtitles",
+                stream.stream_index,
+                stream.codec_name,
+                request.container_id.to_uppercase()
+            )
+        })
+        .collect()
+}
 
-    if !video_streams.is_empty() && request.video.mode != "disable" {
-        args.push("-map".to_string());
-        args.push("0:v:0".to_string());
-        output_metadata.push(output_stream_metadata_from_request(
-            output_metadata.len(),
-            &video_streams[0].probe_stream,
-            &request.metadata,
-        ));
-        mapped_any_stream = true;
-    }
+pub(crate) fn is_attached_picture(stream: &StreamInfo) -> bool {
+    stream
+        .probe_stream
+        .get("disposition")
+        .and_then(|disposition| disposition.get("attached_pic"))
+        .and_then(|value| value.as_u64())
+        .is_some_and(|value| value == 1)
+}
 
-    let mut mapped_audio_streams = Vec::new();
-    if !audio_streams.is_empty() {
-        for audio_stream in &audio_streams {
-            let resolved_settings = resolve_audio_settings_for_stream(request, audio_stream);
-            if resolved_settings.mode == "disable" {
-                continue;
-            }
+/// Cover art is copied when the container can hold it; extra angles stay off
+/// unless `map_all_streams` or a track override asks for them.
+pub(crate) fn resolve_video_settings_for_stream(
+    request: &TranscodeRequest,
+    stream: &StreamInfo,
+    is_primary: bool,
+) -> ResolvedVideoSettings {
+    let track_override = request
+        .video
+        .track_overrides
+        .iter()
+        .find(|track_override| track_override.track_id == stream.stream_index);
 
-            args.push("-map".to_string());
-            args.push(format!("0:a:{}", audio_stream.relative_index));
-            output_metadata.push(output_stream_metadata_from_request(
-                output_metadata.len(),
-                &audio_stream.probe_stream,
-                &request.metadata,
-            ));
-            mapped_any_stream = true;
-            mapped_audio_streams.push((
-                mapped_audio_streams.len(),
-                audio_stream.clone(),
-                resolved_settings,
-            ));
+    let default_mode = if request.video.mode == "disable" {
+        "disable"
+    } else if is_attached_picture(stream) {
+        if can_copy_attached_picture(&request.container_id, &stream.codec_name) {
+            "copy"
+        } else {
+            "disable"
         }
-    }
+    } else if is_primary || request.video.map_all_streams {
+        request.video.mode.as_str()
+    } else {
+        "disable"
+    };
 
-    let mut mapped_subtitle_output_indices = Vec::new();
-    if !subtitle_streams.is_empty() && request.subtitles.mode != "disable" {
-        for subtitle_stream in &subtitle_streams {
-            args.push("-map".to_string());
-            args.push(format!("0:s:{}", subtitle_stream.relative_index));
-            output_metadata.push(output_stream_metadata_from_request(
-                output_metadata.len(),
-                &subtitle_stream.probe_stream,
-                &request.metadata,
-            ));
-            mapped_subtitle_output_indices.push(subtitle_stream.codec_name.clone());
-            mapped_any_stream = true;
-        }
+    let mut settings = ResolvedVideoSettings {
+        mode: track_override
+            .map(|track_override| track_override.mode.clone())
+            .unwrap_or_else(|| default_mode.to_string()),
+        encoder_id: track_override
+            .and_then(|track_override| track_override.encoder_id.clone())
+            .or_else(|| request.video.encoder_id.clone()),
+        profile: track_override
+            .and_then(|track_override| track_override.profile.clone())
+            .or_else(|| request.video.profile.clone()),
+        level: track_override
+            .and_then(|track_override| track_override.level.clone())
+            .or_else(|| request.video.level.clone()),
+        pixel_format: track_override
+            .and_then(|track_override| track_override.pixel_format.clone())
+            .or_else(|| request.video.pixel_format.clone()),
+        quality_mode: track_override
+            .and_then(|track_override| track_override.quality_mode.clone())
+            .or_else(|| request.video.quality_mode.clone()),
+        crf: track_override
+            .and_then(|track_override| track_override.crf)
+            .or(request.video.crf),
+        qp: track_override
+            .and_then(|track_override| track_override.qp)
+            .or(request.video.qp),
+        bitrate_kbps: track_override
+            .and_then(|track_override| track_override.bitrate_kbps)
+            .or(request.video.bitrate_kbps),
+        preset: track_override
+            .and_then(|track_override| track_override.preset.clone())
+            .or_else(|| request.video.preset.clone()),
+        additional_args: {
+            let mut additional_args = request.video.additional_args.clone();
+            if let Some(track_override) = track_override {
+                additional_args.extend(track_override.additional_args.clone());
+            }
+            additional_args
+        },
+        auto_reason: None,
+    };
+    if settings.mode == "auto" {
+        apply_auto_video_mode(request, stream, is_primary, &mut settings);
     }
+    settings
+}
 
-    if !mapped_any_stream {
-        return Err("No streams selected for output".to_string());
+/// `auto` copies video the container can hold when nothing needs a re-encode,
+/// otherwise it encodes with the requested or the container's fallback encoder.
+fn apply_auto_video_mode(
+    request: &TranscodeRequest,
+    stream: &StreamInfo,
+    is_primary: bool,
+    settings: &mut ResolvedVideoSettings,
+) {
+    let container = request.container_id.to_uppercase();
+    let fallback_encoder = fallback_encoders_for_container(&request.container_id).video;
+    if is_attached_picture(stream) {
+        let can_copy = can_copy_attached_picture(&request.container_id, &stream.codec_name);
+        settings.mode = if can_copy { "copy" } else { "disable" }.to_string();
+        settings.auto_reason = Some(format!(
+            "{} {} {} cover art",
+            container,
+            if can_copy {
+                "can store"
+            } else {
+                "cannot store"
+            },
+            stream.codec_name
+        ));
+        return;
+    }
+    if fallback_encoder.is_none() {
+        settings.mode = "disable".to_string();
+        settings.auto_reason = Some(format!("{} cannot store video", container));
+        return;
     }
 
-    if !video_streams.is_empty() {
-        match request.video.mode.as_str() {
-            "copy" => {
-                if let Some(unsupported_codec) = video_streams
-                    .first()
-                    .map(|stream| stream.codec_name.as_str())
-                    .filter(|codec| !can_copy_video_codec(&request.container_id, codec))
-                {
-                    return Err(format!(
-                        "Container {} cannot copy video codec {}. Choose video transcoding or a compatible container.",
-                        request.container_id.to_uppercase(),
-                        unsupported_codec
-                    ));
-                }
-
-                args.push("-c:v".to_string());
-                args.push("copy".to_string());
-            }
-            "transcode" => {
-                let Some(encoder_id) = request.video.encoder_id.as_deref() else {
-                    return Err(
-                        "A video encoder is required when video transcoding is enabled".to_string(),
-                    );
-                };
-
-                args.push("-c:v".to_string());
-                args.push(encoder_id.to_string());
-
-                if let Some(profile) = request.video.profile.as_deref() {
-                    if !profile.trim().is_empty() {
-                        args.push("-profile:v".to_string());
-                        args.push(profile.trim().to_string());
-                    }
-                }
-
-                if let Some(level) = request.video.level.as_deref() {
-                    if !level.trim().is_empty() {
-                        args.push("-level:v".to_string());
-                        args.push(level.trim().to_string());
-                    }
-                }
-
-                if let Some(pixel_format) = request.video.pixel_format.as_deref() {
-                    if !pixel_format.trim().is_empty() {
-                        args.push("-pix_fmt".to_string());
-                        args.push(pixel_format.trim().to_string());
-                    }
-                }
-
-                match request.video.quality_mode.as_deref().unwrap_or("crf") {
-                    "crf" => {
-                        if let Some(crf) = request.video.crf {
-                            args.push("-crf".to_string());
-                            args.push(crf.to_string());
-                        }
-                    }
-                    "qp" => {
-                        if let Some(qp) = request.video.qp {
-                            args.push("-qp".to_string());
-                            args.push(qp.to_string());
-                        }
-                    }
-                    "bitrate" => {
-                        if let Some(bitrate_kbps) = request.video.bitrate_kbps {
-                            args.push("-b:v".to_string());
-                            args.push(format!("{}k", bitrate_kbps));
-                        }
-                    }
-                    _ => {}
-                }
-
-                if let Some(preset) = request.video.preset.as_deref() {
-                    if !preset.trim().is_empty() {
-                        apply_video_preset_arg(&mut args, encoder_id, preset.trim())?;
-                    }
-                }
-
-                if matches!(request.container_id.as_str(), "mp4" | "mov")
-                    && encoder_id.starts_with("hevc")
-                {
-                    args.push("-tag:v".to_string());
-                    args.push("hvc1".to_string());
-                }
+    let transcode_reason = if !can_copy_video_codec(&request.container_id, &stream.codec_name) {
+        Some(format!(
+            "{} cannot store {} video",
+            container, stream.codec_name
+        ))
+    } else if !matches!(
+        video_filter_graph_for_stream(request, stream, settings),
+        Ok(None)
+    ) {
+        Some("Video filters need re-encoding".to_string())
+    } else if request.ranges.len() > 1 {
+        Some("Joining several ranges re-encodes the video".to_string())
+    } else if is_primary && request.burn_in.is_some() {
+        Some("Subtitle burn-in needs re-encoding".to_string())
+    } else if is_primary
+        && (request.video.chunked.is_some()
+            || matches!(
+                settings.quality_mode.as_deref(),
+                Some("targetSize" | "targetQuality")
+            ))
+    {
+        Some("The quality mode needs re-encoding".to_string())
+    } else {
+        None
+    };
+    let Some(reason) = transcode_reason else {
+        settings.mode = "copy".to_string();
+        settings.auto_reason = Some(format!(
+            "{} can store {} video",
+            container, stream.codec_name
+        ));
+        return;
+    };
 
-                if request.container_id == "mp4" {
-                    args.push("-movflags".to_string());
-                    args.push("+faststart".to_string());
-                }
+    settings.mode = "transcode".to_string();
+    if settings.encoder_id.is_none() {
+        // Encoder specific options were not picked for the fallback encoder.
+        settings.encoder_id = fallback_encoder.map(str::to_string);
+        settings.profile = None;
+        settings.level = None;
+        settings.preset = None;
+    }
+    settings.auto_reason = Some(reason);
+}
 
-                apply_safe_additional_args(&mut args, &request.video.additional_args, None)?;
+/// Settle `auto` video modes up front, since target size, target quality,
+/// two-pass and chunked encodes are decided from the request's video mode.
+pub(crate) fn resolve_auto_video_modes(
+    request: &TranscodeRequest,
+    streams: &[Value],
+) -> TranscodeRequest {
+    let mut resolved = request.clone();
+    let video_streams = extract_streams_by_type(streams, "video");
+    let primary_video_stream_index = video_streams
+        .iter()
+        .find(|stream| !is_attached_picture(stream))
+        .map(|stream| stream.stream_index);
+    for stream in &video_streams {
+        let is_primary = primary_video_stream_index == Some(stream.stream_index);
+        let settings = resolve_video_settings_for_stream(request, stream, is_primary);
+        if let Some(track_override) = resolved
+            .video
+            .track_overrides
+            .iter_mut()
+            .find(|track_override| track_override.track_id == stream.stream_index)
+        {
+            if track_override.mode == "auto" {
+                track_override.mode = settings.mode;
+                track_override.encoder_id = settings.encoder_id;
             }
-            "disable" => {}
-            other => return Err(format!("Unsupported video mode: {}", other)),
+        } else if request.video.mode != "auto" || is_attached_picture(stream) {
+            continue;
+        } else if is_primary {
+            resolved.video.mode = settings.mode;
+            resolved.video.encoder_id = settings.encoder_id;
+            resolved.video.profile = settings.profile;
+            resolved.video.level = settings.level;
+            resolved.video.preset = settings.preset;
+        } else if request.video.map_all_streams {
+            resolved
+                .video
+                .track_overrides
+                .push(TranscodeVideoTrackOverride {
+                    track_id: stream.stream_index,
+                    mode: settings.mode,
+                    encoder_id: settings.encoder_id,
+                    ..TranscodeVideoTrackOverride::default()
+                });
         }
     }
+    resolved
+}
 
-    if !mapped_audio_streams.is_empty() {
-        for (output_index, source_stream, resolved_settings) in &mapped_audio_streams {
-            match resolved_settings.mode.as_str() {
-                "copy" => {
-                    if !can_copy_audio_codec(&request.container_id, &source_stream.codec_name) {
-                        return Err(format!(
-                            "Container {} cannot copy audio codec {}. Choose audio transcoding or a compatible container.",
-                            request.container_id.to_uppercase(),
-                            source_stream.codec_name
-                        ));
-                    }
+/// Typed video filters for one stream, with tonemapping added for HDR sources
+/// encoded to an 8-bit pixel format.
+fn video_filter_graph_for_stream(
+    request: &TranscodeRequest,
+    stream: &StreamInfo,
+    settings: &ResolvedVideoSettings,
+) -> Result<Option<String>, String> {
+    let color_transfer = stream
+        .probe_stream
+        .get("color_transfer")
+        .and_then(|value| value.as_str());
+    let pixel_format = settings.pixel_format.as_deref();
+    let filters = with_automatic_tonemap(&request.video.filters, color_transfer, pixel_format);
+    build_video_filter_graph(&filters, pixel_format)
+}
 
-                    args.push(format!("-c:a:{}", output_index));
-                    args.push("copy".to_string());
-                }
-                "transcode" => {
-                    let Some(encoder_id) = resolved_settings.encoder_id.as_deref() else {
-                        return Err(
-                            "An audio encoder is required when audio transcoding is enabled"
-                                .to_string(),
-                        );
-                    };
-
-                    args.push(format!("-c:a:{}", output_index));
-                    args.push(encoder_id.to_string());
+/// HDR metadata is only signalled when the output keeps the HDR transfer.
+fn keeps_hdr_output(
+    request: &TranscodeRequest,
+    stream: &StreamInfo,
+    settings: &ResolvedVideoSettings,
+) -> bool {
+    let color_transfer = stream
+        .probe_stream
+        .get("color_transfer")
+        .and_then(|value| value.as_str());
+    if !color_transfer.is_some_and(is_hdr_transfer) {
+        return false;
+    }
 
-                    if should_force_libopus_mapping_family_255(
-                        resolved_settings,
-                        source_stream,
-                        &resolved_settings.additional_args,
-                    ) {
-                        args.push(format!("-mapping_family:a:{}", output_index));
-                        args.push("255".to_string());
-                    }
+    let filters = with_automatic_tonemap(
+        &request.video.filters,
+        color_transfer,
+        settings.pixel_format.as_deref(),
+    );
+    filters
+        .tonemap
+        .as_ref()
+        .is_none_or(|tonemap| tonemap.is_off())
+}
 
-                    if let Some(bitrate_kbps) = resolved_settings.bitrate_kbps {
-                        args.push(format!("-b:a:{}", output_index));
-                        args.push(format!("{}k", bitrate_kbps));
-                    }
+/// Fold encoder parameters from the additional args into `encoder_params`, so a
+/// single `-x265-params`/`-svtav1-params` carries both; returns the other args.
+fn merge_encoder_params_args(
+    encoder_id: &str,
+    additional_args: &[TranscodeAdditionalArg],
+    encoder_params: &mut Vec<String>,
+) -> Vec<TranscodeAdditionalArg> {
+    let Some(params_flag) = encoder_params_flag(encoder_id) else {
+        return additional_args.to_vec();
+    };
 
-                    if let Some(channels) =
-                        resolved_settings.channels.filter(|channels| *channels > 0)
-                    {
-                        args.push(format!("-ac:a:{}", output_index));
-                        args.push(channels.to_string());
-                    }
+    additional_args
+        .iter()
+        .filter(|additional_arg| {
+            if !additional_arg.enabled || additional_arg.flag.trim() != params_flag {
+                return true;
+            }
+            if let Some(value) = additional_arg.value.as_deref()
+                && !value.trim().is_empty()
+            {
+                encoder_params.push(value.trim().to_string());
+            }
+            false
+        })
+        .cloned()
+        .collect()
+}
 
-                    if let Some(sample_rate) = resolved_settings
-                        .sample_rate
-                        .filter(|sample_rate| *sample_rate > 0)
-                    {
-                        args.push(format!("-ar:a:{}", output_index));
-                        args.push(sample_rate.to_string());
-                    }
+/// Probe `-dolbyvision` support only for libx265 encodes of Dolby Vision sources;
+/// returns a warning when the RPUs were asked for but cannot be kept.
+pub(crate) async fn resolve_dolby_vision_support(
+    ffmpeg_path: &str,
+    request: &TranscodeRequest,
+    streams: &[Value],
+) -> (bool, Option<String>) {
+    let video_streams = extract_streams_by_type(streams, "video");
+    let encodes_dolby_vision_with_x265 = video_streams.iter().any(|stream| {
+        let is_primary = video_streams
+            .iter()
+            .find(|candidate| !is_attached_picture(candidate))
+            .is_some_and(|primary| primary.stream_index == stream.stream_index);
+        let settings = resolve_video_settings_for_stream(request, stream, is_primary);
+        settings.mode == "transcode"
+            && settings.encoder_id.as_deref() == Some("libx265")
+            && hdr_side_data(&stream.probe_stream)
+                .dolby_vision
+                .is_some_and(|dolby_vision| dolby_vision.rpu_present)
+    });
+    if !encodes_dolby_vision_with_x265 {
+        return (false, None);
+    }
 
-                    apply_safe_additional_args(
-                        &mut args,
-                        &resolved_settings.additional_args,
-                        Some(&format!("a:{}", output_index)),
-                    )?;
-                }
-                "disable" => {}
-                other => return Err(format!("Unsupported audio mode: {}", other)),
-            }
-        }
+    let supported = ffmpeg_supports_x265_dolby_vision(ffmpeg_path).await;
+    let warning = (request.video.keep_dolby_vision && !supported).then(|| {
+        "This ffmpeg build cannot pass Dolby Vision to libx265; only the HDR10 base layer is kept"
+            .to_string()
+    });
+    (supported, warning)
+}
+
+/// Stable sort so listed streams come first in the requested order and the
+/// rest keep the default video, audio, subtitle order.
+fn order_planned_streams(planned_streams: &mut [PlannedStream], stream_order: &[usize]) {
+    if stream_order.is_empty() {
+        return;
     }
 
-    if !mapped_subtitle_output_indices.is_empty() {
-        match request.subtitles.mode.as_str() {
-            "copy" => {
-                if let Some(unsupported_codec) = mapped_subtitle_output_indices
-                    .iter()
-                    .find(|codec| !can_copy_subtitle_codec(&request.container_id, codec))
-                {
-                    return Err(format!(
-                        "Container {} cannot copy subtitle codec {}. Choose subtitle conversion or disable subtitles for this output format.",
-                        request.container_id.to_uppercase(),
-                        unsupported_codec
-                    ));
-                }
+    planned_streams.sort_by_key(|planned| {
+        stream_order
+            .iter()
+            .position(|stream_index| *stream_index == planned.stream_index())
+            .unwrap_or(stream_order.len())
+    });
+}
 
-                for output_index in 0..mapped_subtitle_output_indices.len() {
-                    args.push(format!("-c:s:{}", output_index));
-                    args.push("copy".to_string());
-                }
-            }
-            "convert_text" => {
-                let Some(encoder_id) = request.subtitles.encoder_id.as_deref() else {
-                    return Err(
-                        "A subtitle encoder is required when subtitle conversion is enabled"
-                            .to_string(),
-                    );
-                };
+/// Qualify a video flag for one output stream when several video streams are mapped.
+fn video_stream_flag(flag: &str, output_index: Option<usize>) -> String {
+    let Some(output_index) = output_index else {
+        return flag.to_string();
+    };
 
-                for (output_index, codec_name) in mapped_subtitle_output_indices.iter().enumerate()
-                {
-                    if !is_text_subtitle_codec(codec_name)
-                        && !can_copy_subtitle_codec(&request.container_id, codec_name)
-                    {
-                        return Err(format!(
-                            "Container {} cannot keep subtitle codec {} while converting text subtitles. Disable subtitles or choose a compatible container.",
-                            request.container_id.to_uppercase(),
-                            codec_name
-                        ));
-                    }
+    let flag = match flag {
+        "-vf" => "-filter",
+        other => other.strip_suffix(":v").unwrap_or(other),
+    };
+    format!("{}:v:{}", flag, output_index)
+}
 
-                    args.push(format!("-c:s:{}", output_index));
-                    if is_text_subtitle_codec(codec_name) {
-                        args.push(encoder_id.to_string());
-                    } else {
-                        args.push("copy".to_string());
-                    }
-                }
+fn audio_filter_source(stream: &StreamInfo) -> AudioFilterSource {
+    AudioFilterSource {
+        channels: stream.channels,
+        sample_rate: stream
+            .probe_stream
+            .get("sample_rate")
+            .and_then(|value| value.as_str())
+            .and_then(|value| value.parse::<u32>().ok()),
+    }
+}
 
-                apply_safe_additional_args(&mut args, &request.subtitles.additional_args, None)?;
-            }
-            "disable" => {}
-            other => return Err(format!("Unsupported subtitle mode: {}", other)),
-        }
+fn should_force_libopus_mapping_family_255(
+    settings: &ResolvedAudioSettings,
+    stream: &StreamInfo,
+    additional_args: &[TranscodeAdditionalArg],
+) -> bool {
+    if settings.encoder_id.as_deref() != Some("libopus")
+        || settings.channels.is_some()
+        || has_enabled_additional_arg(additional_args, "-mapping_family")
+    {
+        return false;
     }
 
-    apply_metadata_args(
-        &mut args,
-        &request.container_id,
-        Some(&request.metadata),
-        &output_metadata,
-    );
+    let channel_layout = stream.channel_layout.as_deref().unwrap_or_default();
 
-    if let Some(duration_us) = duration_us {
-        if duration_us > 0 {
-            let duration_seconds = duration_us as f64 / 1_000_000.0;
-            args.push("-metadata".to_string());
-            args.push(format!(
-                "mediaflow.duration_seconds={:.3}",
-                duration_seconds
-            ));
+    stream.channels > 2
+        && !channel_layout.is_empty()
+        && !libopus_uses_default_mapping_family(channel_layout)
+        && !has_enabled_additional_arg(additional_args, "-mapping_family")
+}
+
+fn qualify_stream_option(flag: &str, stream_specifier: Option<&str>) -> String {
+    if let Some(stream_specifier) = stream_specifier {
+        if flag.contains(':') {
+            return flag.to_string();
         }
-    }
 
-    args.push("-progress".to_string());
-    args.push("pipe:1".to_string());
-    args.push(request.output_path.clone());
+        return format!("{}:{}", flag, stream_specifier);
+    }
 
-    Ok(args)
+    flag.to_string()
 }
 
-#[cfg_attr(not(test), allow(dead_code))]
-pub(crate) async fn transcode_media_with_bins(
-    ffmpeg_path: &str,
-    ffprobe_path: &str,
-    request: &TranscodeRequest,
-) -> Result<String, String> {
-    validate_media_path(&request.input_path)?;
-    validate_output_path(&request.output_path)?;
-    validate_output_path_matches_container(request)?;
-
-    let probe_json = probe_file_with_ffprobe(ffprobe_path, &request.input_path).await?;
-    let probe_value: Value = serde_json::from_str(&probe_json)
-        .map_err(|error| format!("Invalid probe JSON: {}", error))?;
-    let streams = probe_value
-        .get("streams")
-        .and_then(|value| value.as_array())
-        .cloned()
-        .unwrap_or_default();
-    let duration_us = get_media_duration_us_with_ffprobe(ffprobe_path, &request.input_path)
-        .await
-        .ok();
+fn apply_safe_additional_args(
+    args: &mut Vec<String>,
+    additional_args: &[TranscodeAdditionalArg],
+    stream_specifier: Option<&str>,
+) -> Result<(), String> {
+    const BLOCKED_FLAGS: &[&str] = &[
+        "-i",
+        "-map",
+        "-progress",
+        "-f",
+        "-y",
+        "-n",
+        "-filter_complex",
+        "-filter_script",
+        "-vf",
+        "-af",
+    ];
 
-    let args = build_transcode_args(request, &streams, duration_us)?;
+    for additional_arg in additional_args {
+        if !additional_arg.enabled {
+            continue;
+        }
 
-    let output = timeout(
-        TRANSCODE_TIMEOUT,
-        Command::new(ffmpeg_path).args(&args).output(),
-    )
-    .await
-    .map_err(|_| {
-        format!(
-            "Transcode timeout after {} seconds",
-            TRANSCODE_TIMEOUT.as_secs()
-        )
-    })?
-    .map_err(|error| format!("Failed to execute ffmpeg: {}", error))?;
+        let flag = additional_arg.flag.trim();
+        if !flag.starts_with('-') {
+            return Err(format!("Invalid additional argument flag: {}", flag));
+        }
+        if BLOCKED_FLAGS.contains(&flag) {
+            return Err(format!("Additional argument is not allowed here: {}", flag));
+        }
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(format!("Transcode failed: {}", stderr.trim()));
+        args.push(qualify_stream_option(flag, stream_specifier));
+        if let Some(value) = additional_arg.value.as_deref() {
+            if !value.trim().is_empty() {
+                args.push(value.trim().to_string());
+            }
+        }
     }
 
-    if !Path::new(&request.output_path).exists() {
-        return Err("Transcode failed: output file not created".to_string());
-    }
+    Ok(())
+}
 
-    Ok(request.output_path.clone())
+fn cpu_used_preset_max(encoder_id: &str) -> Option<u8> {
+    match encoder_id {
+        "libaom-av1" | "libvpx-vp9" => Some(8),
+        "libvpx" => Some(16),
+        _ => None,
+    }
 }
 
-#[tauri::command]
-pub(crate) async fn transcode_media(
-    app: tauri::AppHandle,
-    request: TranscodeRequest,
-) -> Result<String, String> {
-    validate_media_path(&request.input_path)?;
-    validate_output_path(&request.output_path)?;
-    validate_output_path_matches_container(&request)?;
+fn validate_cpu_used_preset(encoder_id: &str, preset: &str, max_value: u8) -> Result<(), String> {
+    let error_message = || {
+        format!(
+            "{} preset must be an integer from 0 to {}",
+            encoder_id, max_value
+        )
+    };
+    let parsed = preset.parse::<u8>().map_err(|_| error_message())?;
 
-    let _sleep_guard = SleepInhibitGuard::try_acquire("Media transcoding").ok();
-    let ffmpeg_path = resolve_ffmpeg_path(&app)?;
-    let ffprobe_path = resolve_ffprobe_path(&app)?;
+    if parsed > max_value {
+        return Err(error_message());
+    }
 
-    let probe_json = probe_file_with_ffprobe(&ffprobe_path, &request.input_path).await?;
-    let probe_value: Value = serde_json::from_str(&probe_json)
-        .map_err(|error| format!("Invalid probe JSON: {}", error))?;
-    let streams = probe_value
-        .get("streams")
-        .and_then(|value| value.as_array())
-        .cloned()
-        .unwrap_or_default();
-    let duration_us = get_media_duration_us_with_ffprobe(&ffprobe_path, &request.input_path)
-        .await
-        .ok();
-    let args = build_transcode_args(&request, &streams, duration_us)?;
+    Ok(())
+}
 
-    let mut child = Command::new(&ffmpeg_path)
-        .args(&args)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .map_err(|error| format!("Failed to start ffmpeg: {}", error))?;
+fn apply_video_preset_arg(
+    args: &mut Vec<String>,
+    encoder_id: &str,
+    preset: &str,
+    output_index: Option<usize>,
+) -> Result<(), String> {
+    if let Some(max_value) = cpu_used_preset_max(encoder_id) {
+        validate_cpu_used_preset(encoder_id, preset, max_value)?;
+        args.push(video_stream_flag("-cpu-used", output_index));
+        args.push(preset.to_string());
+        return Ok(());
+    }
 
-    emit_transcode_progress(&app, &request.input_path, &request.output_path, 0, None);
+    args.push(video_stream_flag("-preset", output_index));
+    args.push(preset.to_string());
+    Ok(())
+}
 
-    if let Some(pid) = child.id() {
-        if let Ok(mut guard) = super::state::TRANSCODE_PROCESS_IDS.lock() {
-            guard.insert(request.input_path.clone(), pid);
+pub(crate) fn validate_transcode_ranges(
+    ranges: &[TranscodeTimeRange],
+    source_duration_us: Option<u64>,
+) -> Result<(), String> {
+    for (index, range) in ranges.iter().enumerate() {
+        if range.end_ms.is_some_and(|end_ms| end_ms <= range.start_ms) {
+            return Err(format!("Range {} must end after it starts", index + 1));
+        }
+        if range.end_ms.is_none() && index + 1 < ranges.len() {
+            return Err("Every range except the last needs an end point".to_string());
+        }
+        if let Some(previous_end_ms) = index
+            .checked_sub(1)
+            .and_then(|previous| ranges[previous].end_ms)
+            && range.start_ms < previous_end_ms
+        {
+            return Err(format!(
+                "Range {} must start after range {} ends",
+                index + 1,
+                index
+            ));
+        }
+        if let Some(duration_us) = source_duration_us
+            && range.start_ms.saturating_mul(1000) >= duration_us
+        {
+            return Err(format!(
+                "Range {} starts after the end of the input",
+                index + 1
+            ));
         }
     }
 
-    if let Ok(mut guard) = super::state::TRANSCODE_OUTPUT_PATHS.lock() {
-        guard.insert(request.input_path.clone(), request.output_path.clone());
-    }
+    Ok(())
+}
 
-    if let Some(stdout) = child.stdout.take() {
-        let app_for_progress = app.clone();
-        let input_path_for_progress = request.input_path.clone();
-        let output_path_for_progress = request.output_path.clone();
+/// Duration of the output once ranges are applied, used for progress and size planning.
+pub(crate) fn trimmed_duration_us(
+    ranges: &[TranscodeTimeRange],
+    source_duration_us: Option<u64>,
+) -> Option<u64> {
+    if ranges.is_empty() {
+        return source_duration_us;
+    }
 
-        tokio::spawn(async move {
-            let mut tracker = FfmpegProgressTracker::new(duration_us);
-            let mut last_progress = 0;
-            let reader = BufReader::new(stdout);
-            let mut lines = reader.lines();
+    ranges.iter().try_fold(0u64, |total, range| {
+        let end_us = match (range.end_ms, source_duration_us) {
+            (Some(end_ms), Some(duration_us)) => (end_ms * 1000).min(duration_us),
+            (Some(end_ms), None) => end_ms * 1000,
+            (None, duration_us) => duration_us?,
+        };
+        Some(total + end_us.saturating_sub(range.start_ms * 1000))
+    })
+}
 
-            while let Ok(Some(line)) = lines.next_line().await {
-                if let Some(update) = tracker.handle_line(&line) {
-                    if let Some(progress) = update.progress {
-                        last_progress = progress;
-                    }
+fn format_range_seconds(ms: u64) -> String {
+    format!("{:.3}", ms as f64 / 1000.0)
+}
 
-                    emit_transcode_progress(
-                        &app_for_progress,
-                        &input_path_for_progress,
-                        &output_path_for_progress,
-                        last_progress,
-                        update.speed_bytes_per_sec,
-                    );
+/// Input options for the requested ranges; joined ranges open the input once per range.
+fn build_range_input_args(request: &TranscodeRequest) -> Vec<String> {
+    let mut args = Vec::new();
+
+    match request.ranges.as_slice() {
+        [] => {}
+        // The end of a single range is applied as an output `-t` so chapters are clipped too.
+        [range] => {
+            if range.start_ms > 0 {
+                args.push("-ss".to_string());
+                args.push(format_range_seconds(range.start_ms));
+            }
+        }
+        ranges => {
+            for range in ranges {
+                if range.start_ms > 0 {
+                    args.push("-ss".to_string());
+                    args.push(format_range_seconds(range.start_ms));
                 }
+                if let Some(end_ms) = range.end_ms {
+                    args.push("-t".to_string());
+                    args.push(format_range_seconds(end_ms - range.start_ms));
+                }
+                args.push("-i".to_string());
+                args.push(request.input_path.clone());
             }
-        });
+            return args;
+        }
     }
 
-    let input_path_for_cleanup = request.input_path.clone();
-    let output_path_for_cleanup = request.output_path.clone();
-    let child_pid = child.id();
-    let output = match timeout(TRANSCODE_TIMEOUT, child.wait_with_output()).await {
-        Ok(result) => result.map_err(|error| {
-            if let Ok(mut guard) = super::state::TRANSCODE_PROCESS_IDS.lock() {
-                guard.remove(&input_path_for_cleanup);
-            }
-            if let Ok(mut guard) = super::state::TRANSCODE_OUTPUT_PATHS.lock() {
-                guard.remove(&input_path_for_cleanup);
-            }
-            let _ = std::fs::remove_file(&output_path_for_cleanup);
-            format!("Failed to execute ffmpeg: {}", error)
-        })?,
-        Err(_) => {
-            if let Some(pid) = child_pid {
-                terminate_process(pid);
-                tokio::time::sleep(Duration::from_millis(200)).await;
-            }
+    args.push("-i".to_string());
+    args.push(request.input_path.clone());
+    args
+}
 
-            if let Ok(mut guard) = super::state::TRANSCODE_PROCESS_IDS.lock() {
-                guard.remove(&input_path_for_cleanup);
-            }
-            if let Ok(mut guard) = super::state::TRANSCODE_OUTPUT_PATHS.lock() {
-                guard.remove(&input_path_for_cleanup);
-            }
-            let _ = std::fs::remove_file(&output_path_for_cleanup);
-            return Err(format!(
-                "Transcode timeout after {} seconds",
-                TRANSCODE_TIMEOUT.as_secs()
-            ));
+/// `concat` graph joining every range; outputs `[v]` and `[a0]`, `[a1]`, ...
+fn build_range_concat_filter(
+    range_count: usize,
+    include_video: bool,
+    audio_relative_indices: &[usize],
+    video_filter_graph: Option<&str>,
+) -> String {
+    let mut graph = String::new();
+    for input_index in 0..range_count {
+        if include_video {
+            graph.push_str(&format!("[{}:v:0]", input_index));
         }
-    };
+        for relative_index in audio_relative_indices {
+            graph.push_str(&format!("[{}:a:{}]", input_index, relative_index));
+        }
+    }
 
-    if let Ok(mut guard) = super::state::TRANSCODE_PROCESS_IDS.lock() {
-        guard.remove(&request.input_path);
+    graph.push_str(&format!(
+        "concat=n={}:v={}:a={}",
+        range_count,
+        u8::from(include_video),
+        audio_relative_indices.len()
+    ));
+    if include_video {
+        graph.push_str(if video_filter_graph.is_some() {
+            "[vcat]"
+        } else {
+            "[v]"
+        });
     }
-    if let Ok(mut guard) = super::state::TRANSCODE_OUTPUT_PATHS.lock() {
-        guard.remove(&request.input_path);
+    for output_index in 0..audio_relative_indices.len() {
+        graph.push_str(&format!("[a{}]", output_index));
+    }
+    if include_video && let Some(video_filter_graph) = video_filter_graph {
+        graph.push_str(&format!(";[vcat]{}[v]", video_filter_graph));
     }
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        let _ = std::fs::remove_file(&request.output_path);
-        return Err(format!("Transcode failed: {}", stderr.trim()));
+    graph
+}
+
+fn supports_two_pass_encoding(encoder_id: &str) -> bool {
+    matches!(
+        encoder_id,
+        "libx264" | "libx265" | "libvpx" | "libvpx-vp9" | "libaom-av1"
+    )
+}
+
+/// Whether the request asks for a two-pass encode that applies to these streams.
+fn uses_two_pass_encoding(request: &TranscodeRequest, streams: &[Value]) -> Result<bool, String> {
+    if !request.video.two_pass
+        || request.video.mode != "transcode"
+        || request.video.quality_mode.as_deref() != Some("bitrate")
+        || extract_streams_by_type(streams, "video").is_empty()
+    {
+        return Ok(false);
     }
 
-    if !Path::new(&request.output_path).exists() {
-        return Err("Transcode failed: output file not created".to_string());
+    let encoder_id = request.video.encoder_id.as_deref().unwrap_or_default();
+    if !supports_two_pass_encoding(encoder_id) {
+        return Err(format!(
+            "Two-pass encoding is not supported by {}",
+            if encoder_id.is_empty() {
+                "this encoder"
+            } else {
+                encoder_id
+            }
+        ));
+    }
+    if request.video.bitrate_kbps.is_none() {
+        return Err("Two-pass encoding requires a target video bitrate".to_string());
     }
 
-    emit_transcode_progress(&app, &request.input_path, &request.output_path, 100, None);
+    Ok(true)
+}
 
-    Ok(request.output_path)
+fn estimate_audio_bitrate_kbps(settings: &ResolvedAudioSettings, stream: &StreamInfo) -> u32 {
+    let probed_kbps = stream
+        .probe_stream
+        .get("bit_rate")
+        .and_then(|value| value.as_str())
+        .and_then(|value| value.parse::<u64>().ok())
+        .map(|bits_per_sec| (bits_per_sec / 1000) as u32);
+    let channels = settings
+        .channels
+        .map(u64::from)
+        .filter(|channels| *channels > 0)
+        .unwrap_or(stream.channels.max(2));
+
+    match settings.mode.as_str() {
+        "copy" => probed_kbps.unwrap_or(TARGET_SIZE_COPY_AUDIO_FALLBACK_KBPS),
+        _ => match settings.encoder_id.as_deref().unwrap_or_default() {
+            "flac" | "alac" => probed_kbps.unwrap_or((channels * 48 * 16 * 6 / 10) as u32),
+            encoder if encoder.starts_with("pcm_") => {
+                let sample_rate = u64::from(settings.sample_rate.unwrap_or(48_000));
+                (channels * sample_rate * 24 / 1000) as u32
+            }
+            _ => settings
+                .bitrate_kbps
+                .unwrap_or((channels * 64).clamp(96, 640) as u32),
+        },
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use std::path::Path;
+pub(crate) fn estimate_output_audio_bitrate_kbps(
+    request: &TranscodeRequest,
+    streams: &[Value],
+) -> Result<u32, String> {
+    Ok(
+        resolve_output_audio_tracks(request, &extract_streams_by_type(streams, "audio"))?
+            .iter()
+            .map(|(stream, settings)| estimate_audio_bitrate_kbps(settings, stream))
+            .sum::<u32>(),
+    )
+}
 
-    use serde_json::{Value, json};
+/// Work out the video bitrate for the `targetSize` quality mode from the probed
+/// duration, the audio tracks that will be written and a fixed container overhead.
+pub(crate) fn plan_target_size(
+    request: &TranscodeRequest,
+    streams: &[Value],
+    duration_us: Option<u64>,
+) -> Result<Option<TargetSizePlan>, String> {
+    if request.video.mode != "transcode"
+        || request.video.quality_mode.as_deref() != Some("targetSize")
+    {
+        return Ok(None);
+    }
 
-    use crate::test_support::audio::{
-        ProbedAudioStream, generate_silence_wav, probe_audio_encoder_runtime_info,
-        probe_primary_audio_stream,
-    };
-    use crate::test_support::ffmpeg::{ffmpeg_path, ffprobe_path};
-    use crate::test_support::paths::new_temp_dir;
-    use crate::test_support::video::{
-        MediaStreamCounts, ProbedVideoStream, generate_test_pattern_av_mp4,
-        generate_test_pattern_video, probe_media_stream_counts, probe_primary_video_stream,
+    let Some(target_size_mb) = request
+        .video
+        .target_size_mb
+        .filter(|size| size.is_finite() && *size > 0.0)
+    else {
+        return Err("Target size mode requires a target size in MB".to_string());
     };
-    use crate::tools::ffprobe::probe::probe_file_with_ffprobe;
-    use crate::tools::transcode::capabilities::{
-        TranscodeAudioEncoderCapability, TranscodeCapabilities, TranscodeContainerCapability,
-        TranscodeVideoEncoderCapability, get_transcode_capabilities_with_ffmpeg_path,
+    let Some(duration_us) = duration_us.filter(|duration| *duration > 0) else {
+        return Err("Target size mode requires a known input duration".to_string());
     };
 
-    use crate::tools::media_metadata::{
-        MediaMetadataRequest, TrackMetadataEdit, metadata_schema_for_container,
-    };
+    let audio_bitrate_kbps = estimate_output_audio_bitrate_kbps(request, streams)?;
 
-    use super::{
-        TranscodeAdditionalArg, TranscodeAudioSettings, TranscodeAudioTrackOverride,
-        TranscodeRequest, TranscodeSubtitleSettings, TranscodeVideoSettings, build_transcode_args,
-        cpu_used_preset_max, transcode_media_with_bins,
-    };
+    let target_bytes = (target_size_mb * 1024.0 * 1024.0) as u64;
+    let duration_seconds = duration_us as f64 / 1_000_000.0;
+    let total_kbps = target_bytes as f64 * 8.0 * (1.0 - TARGET_SIZE_CONTAINER_OVERHEAD)
+        / duration_seconds
+        / 1000.0;
+    let video_bitrate_kbps = (total_kbps - f64::from(audio_bitrate_kbps)).floor();
 
-    const AUDIO_LAYOUT_CASES: &[(&str, u64)] = &[
-        ("mono", 1),
-        ("stereo", 2),
-        ("4.0", 4),
-        ("5.1", 6),
-        ("5.1(side)", 6),
-        ("7.1", 8),
-        ("7.1(wide-side)", 8),
-    ];
-    const VIDEO_TEST_WIDTH: u64 = 160;
-    const VIDEO_TEST_HEIGHT: u64 = 90;
-
-    fn build_request(output_path: &str) -> TranscodeRequest {
-        TranscodeRequest {
-            input_path: "/tmp/input.mp4".to_string(),
-            output_path: output_path.to_string(),
-            container_id: "mp4".to_string(),
-            video: TranscodeVideoSettings {
-                mode: "transcode".to_string(),
-                encoder_id: Some("libx264".to_string()),
-                profile: Some("high".to_string()),
-                level: Some("4.1".to_string()),
-                pixel_format: Some("yuv420p".to_string()),
-                quality_mode: Some("crf".to_string()),
-                crf: Some(20.0),
-                qp: None,
-                bitrate_kbps: None,
-                preset: Some("medium".to_string()),
-                additional_args: Vec::new(),
-            },
-            audio: TranscodeAudioSettings {
-                mode: "transcode".to_string(),
-                encoder_id: Some("aac".to_string()),
-                bitrate_kbps: Some(160),
-                channels: Some(2),
-                sample_rate: Some(48000),
-                additional_args: Vec::new(),
-                track_overrides: Vec::new(),
-            },
-            subtitles: TranscodeSubtitleSettings {
-                mode: "convert_text".to_string(),
-                encoder_id: Some("mov_text".to_string()),
-                additional_args: Vec::new(),
-            },
-            metadata: MediaMetadataRequest::default(),
-        }
+    if video_bitrate_kbps < f64::from(TARGET_SIZE_MIN_VIDEO_BITRATE_KBPS) {
+        return Err(format!(
+            "Target size of {} MB is too small for {:.0} seconds of media with {} kb/s of audio",
+            target_size_mb, duration_seconds, audio_bitrate_kbps
+        ));
     }
 
-    fn build_audio_only_request(
-        input_path: &Path,
-        output_path: &Path,
-        container_id: &str,
-        encoder: &TranscodeAudioEncoderCapability,
-    ) -> TranscodeRequest {
-        let mut request = build_request(output_path.to_string_lossy().as_ref());
-        request.input_path = input_path.to_string_lossy().to_string();
-        request.output_path = output_path.to_string_lossy().to_string();
-        request.container_id = container_id.to_string();
+    Ok(Some(TargetSizePlan {
+        target_bytes,
+        video_bitrate_kbps: video_bitrate_kbps as u32,
+        audio_bitrate_kbps,
+    }))
+}
 
-        request.video.mode = "disable".to_string();
-        request.video.encoder_id = None;
-        request.video.profile = None;
-        request.video.level = None;
-        request.video.pixel_format = None;
-        request.video.quality_mode = None;
-        request.video.crf = None;
-        request.video.qp = None;
-        request.video.bitrate_kbps = None;
-        request.video.preset = None;
-        request.video.additional_args = Vec::new();
+/// Lower the video bitrate in proportion to the overshoot, or `None` when the output fits.
+fn retry_video_bitrate_kbps(
+    plan: &TargetSizePlan,
+    video_bitrate_kbps: u32,
+    actual_bytes: u64,
+) -> Option<u32> {
+    if actual_bytes <= plan.target_bytes || actual_bytes == 0 {
+        return None;
+    }
 
-        apply_audio_transcode_settings(&mut request, encoder);
+    let total_kbps = f64::from(video_bitrate_kbps + plan.audio_bitrate_kbps);
+    let fitted_total_kbps = total_kbps * plan.target_bytes as f64 / actual_bytes as f64 * 0.98;
+    let retry_kbps = (fitted_total_kbps - f64::from(plan.audio_bitrate_kbps)).floor();
 
-        request.subtitles.mode = "disable".to_string();
-        request.subtitles.encoder_id = None;
-        request.subtitles.additional_args = Vec::new();
+    (retry_kbps >= f64::from(TARGET_SIZE_MIN_VIDEO_BITRATE_KBPS)
+        && retry_kbps < f64::from(video_bitrate_kbps))
+    .then_some(retry_kbps as u32)
+}
 
-        request
-    }
+/// Turn a `targetSize` request into a bitrate request, two-pass when the encoder allows it.
+pub(crate) fn request_with_video_bitrate(
+    request: &TranscodeRequest,
+    bitrate_kbps: u32,
+) -> TranscodeRequest {
+    let mut request = request.clone();
+    request.video.quality_mode = Some("bitrate".to_string());
+    request.video.bitrate_kbps = Some(bitrate_kbps);
+    request.video.two_pass = request
+        .video
+        .encoder_id
+        .as_deref()
+        .is_some_and(supports_two_pass_encoding);
+    request
+}
 
-    fn build_video_only_request(
-        input_path: &Path,
-        output_path: &Path,
-        container_id: &str,
-        encoder: &TranscodeVideoEncoderCapability,
-        scenario: &VideoRuntimeScenario,
-    ) -> TranscodeRequest {
-        let mut request = build_request(output_path.to_string_lossy().as_ref());
-        request.input_path = input_path.to_string_lossy().to_string();
-        request.output_path = output_path.to_string_lossy().to_string();
-        request.container_id = container_id.to_string();
+pub(crate) fn request_with_video_crf(request: &TranscodeRequest, crf: u8) -> TranscodeRequest {
+    let mut request = request.clone();
+    request.video.quality_mode = Some("crf".to_string());
+    request.video.crf = Some(f64::from(crf));
+    request.video.two_pass = false;
+    request
+}
 
-        apply_video_transcode_settings(&mut request, encoder, scenario);
+/// Copy of the request that writes only video to `output_path`.
+pub(crate) fn request_with_only_video(
+    request: &TranscodeRequest,
+    output_path: &Path,
+) -> TranscodeRequest {
+    let mut video_only = request.clone();
+    video_only.output_path = output_path.to_string_lossy().to_string();
+    video_only.video.map_all_streams = false;
+    video_only.audio.mode = "disable".to_string();
+    video_only.audio.track_overrides.clear();
+    video_only.audio.derived_tracks.clear();
+    video_only.subtitles.mode = "disable".to_string();
+    video_only.subtitles.track_overrides.clear();
+    video_only.metadata = MediaMetadataRequest::default();
+    video_only.stream_order.clear();
+    video_only.burn_in = None;
+    video_only.compare_quality = false;
+    video_only
+}
 
-        request.audio.mode = "disable".to_string();
-        request.audio.encoder_id = None;
-        request.audio.bitrate_kbps = None;
-        request.audio.channels = None;
-        request.audio.sample_rate = None;
-        request.audio.additional_args = Vec::new();
-        request.audio.track_overrides = Vec::new();
+fn build_transcode_passlog_dir(input_path: &str, output_path: &str) -> PathBuf {
+    std::env::temp_dir()
+        .join("mediaflow_transcode_passlog")
+        .join(format!(
+            "{:016x}",
+            stable_hash64(&format!("{}\n{}", input_path, output_path))
+        ))
+}
 
-        request.subtitles.mode = "disable".to_string();
-        request.subtitles.encoder_id = None;
-        request.subtitles.additional_args = Vec::new();
+fn build_transcode_passes(passlog_dir: &Path) -> Vec<TranscodePass> {
+    let passlog_prefix = passlog_dir
+        .join("ffmpeg2pass")
+        .to_string_lossy()
+        .to_string();
+    (1..=2)
+        .map(|number| TranscodePass {
+            number,
+            passlog_prefix: passlog_prefix.clone(),
+        })
+        .collect()
+}
 
-        request
-    }
+/// `-x265-params` splits on `:`, so Windows drive letters must be escaped.
+fn escape_x265_param_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace(':', "\\:")
+}
 
-    fn build_av_request(
-        input_path: &Path,
-        output_path: &Path,
-        container_id: &str,
-    ) -> TranscodeRequest {
-        let mut request = build_request(output_path.to_string_lossy().as_ref());
-        request.input_path = input_path.to_string_lossy().to_string();
-        request.output_path = output_path.to_string_lossy().to_string();
-        request.container_id = container_id.to_string();
-        request.subtitles.mode = "disable".to_string();
-        request.subtitles.encoder_id = None;
-        request.subtitles.additional_args = Vec::new();
-        request
+/// libx265 takes its pass settings through `encoder_params`; the caller emits them.
+fn apply_two_pass_args(
+    args: &mut Vec<String>,
+    encoder_params: &mut Vec<String>,
+    encoder_id: &str,
+    video_pass: &TranscodePass,
+    output_index: Option<usize>,
+) {
+    if encoder_id == "libx265" {
+        let stats_path = match output_index {
+            Some(output_index) => {
+                format!("{}-{}.x265.log", video_pass.passlog_prefix, output_index)
+            }
+            None => format!("{}.x265.log", video_pass.passlog_prefix),
+        };
+        encoder_params.push(format!("pass={}", video_pass.number));
+        encoder_params.push(format!("stats={}", escape_x265_param_value(&stats_path)));
+        return;
     }
 
-    fn apply_video_transcode_settings(
-        request: &mut TranscodeRequest,
-        encoder: &TranscodeVideoEncoderCapability,
-        scenario: &VideoRuntimeScenario,
-    ) {
-        request.video.mode = "transcode".to_string();
-        request.video.encoder_id = Some(encoder.id.clone());
-        request.video.profile = scenario.profile.clone();
-        request.video.level = scenario.level.clone();
-        request.video.pixel_format = scenario
-            .pixel_format
-            .clone()
-            .or_else(|| preferred_video_pixel_format(encoder));
-        request.video.quality_mode = scenario.quality_mode.map(|value| value.to_string());
-        request.video.crf = scenario.crf;
-        request.video.qp = scenario.qp;
-        request.video.bitrate_kbps = scenario.bitrate_kbps;
-        request.video.preset = scenario.preset.as_ref().map(|value| value.to_string());
-        request.video.additional_args = scenario.additional_args.clone();
-    }
+    args.push(video_stream_flag("-pass", output_index));
+    args.push(video_pass.number.to_string());
+    args.push(video_stream_flag("-passlogfile", output_index));
+    args.push(video_pass.passlog_prefix.clone());
+}
 
-    fn apply_audio_transcode_settings(
-        request: &mut TranscodeRequest,
-        encoder: &TranscodeAudioEncoderCapability,
-    ) {
-        request.audio.mode = "transcode".to_string();
-        request.audio.encoder_id = Some(encoder.id.clone());
-        request.audio.bitrate_kbps = audio_bitrate_for_encoder(&encoder.id);
-        request.audio.channels = None;
-        request.audio.sample_rate = None;
-        request.audio.additional_args = Vec::new();
-        request.audio.track_overrides = Vec::new();
+pub(crate) fn build_transcode_args(
+    request: &TranscodeRequest,
+    context: &TranscodeContext,
+    streams: &[Value],
+    duration_us: Option<u64>,
+) -> Result<Vec<String>, String> {
+    build_transcode_pass_args(request, context, streams, duration_us, None)
+}
+
+/// Streams mapped to the output, grouped by type with their per-type output index.
+#[derive(Default)]
+struct MappedStreams {
+    video: Vec<(usize, StreamInfo, ResolvedVideoSettings)>,
+    audio: Vec<(usize, StreamInfo, ResolvedAudioSettings)>,
+    subtitle: Vec<(usize, StreamInfo, ResolvedSubtitleSettings)>,
+}
+
+/// Build ffmpeg args for one pass; the first pass of a two-pass encode only
+/// analyses the video stream and writes to a null output.
+fn build_transcode_pass_args(
+    request: &TranscodeRequest,
+    context: &TranscodeContext,
+    streams: &[Value],
+    duration_us: Option<u64>,
+    video_pass: Option<&TranscodePass>,
+) -> Result<Vec<String>, String> {
+    let is_analysis_pass = video_pass.is_some_and(|video_pass| video_pass.number == 1);
+    let joins_ranges = request.ranges.len() > 1;
+    let video_streams = extract_streams_by_type(streams, "video");
+    let audio_streams = extract_streams_by_type(streams, "audio");
+
+    validate_transcode_ranges(&request.ranges, None)?;
+
+    if !video_streams.is_empty()
+        && request.video.mode == "copy"
+        && !request.video.filters.is_empty()
+    {
+        return Err(
+            "Video filters require video transcoding. Switch video to transcode or clear the filters."
+                .to_string(),
+        );
     }
 
-    fn audio_bitrate_for_encoder(encoder_id: &str) -> Option<u32> {
-        match encoder_id {
-            "flac" | "pcm_s16le" => None,
-            "libmp3lame" => Some(192),
-            _ => Some(160),
+    let burn_in_plan = match request.burn_in.as_ref() {
+        Some(_) if joins_ranges => {
+            return Err(
+                "Subtitle burn-in cannot be combined with joining several ranges".to_string(),
+            );
         }
+        Some(burn_in) => {
+            let fonts_dir = needs_extracted_fonts(burn_in, streams)
+                .then(|| build_burn_in_fonts_dir(&request.input_path, &request.output_path));
+            let start_offset_ms = request.ranges.first().map_or(0, |range| range.start_ms);
+            Some(plan_subtitle_burn_in(
+                burn_in,
+                &request.input_path,
+                streams,
+                start_offset_ms,
+                fonts_dir.as_deref(),
+            )?)
+        }
+        None => None,
+    };
+
+    let mut args = vec!["-hide_banner".to_string(), "-y".to_string()];
+    if let Some(SubtitleBurnInPlan::Bitmap {
+        subtitle_relative_index,
+        forced_only: true,
+    }) = burn_in_plan.as_ref()
+    {
+        args.push(format!("-forced_subs_only:s:{}", subtitle_relative_index));
+        args.push("1".to_string());
+    }
+    args.extend(build_range_input_args(request));
+    if let Some(chunked_video_path) = context.chunked_video_path.as_ref() {
+        args.push("-i".to_string());
+        args.push(chunked_video_path.clone());
     }
 
-    fn pick_audio_test_container_id(
-        capabilities: &TranscodeCapabilities,
-        encoder_id: &str,
-    ) -> Option<String> {
-        let preferred_container_id = match encoder_id {
-            "aac" | "aac_at" => Some("aac"),
-            "libopus" => Some("opus"),
-            "libvorbis" => Some("ogg"),
-            "libmp3lame" => Some("mp3"),
-            "flac" => Some("flac"),
-            "pcm_s16le" => Some("wav"),
-            _ => None,
+    let primary_video_stream_index = video_streams
+        .iter()
+        .find(|stream| !is_attached_picture(stream))
+        .map(|stream| stream.stream_index);
+    let planned_streams = plan_pass_streams(
+        request,
+        streams,
+        &video_streams,
+        &audio_streams,
+        primary_video_stream_index,
+        video_pass,
+    )?;
+    let (range_subtitle_input, range_chapters_input) = if joins_ranges {
+        push_range_join_inputs(&mut args, request, context, &planned_streams)?
+    } else {
+        (None, None)
+    };
+
+    let mut output_metadata = Vec::<OutputStreamMetadata>::new();
+    let mut mapped = MappedStreams::default();
+    for planned in planned_streams {
+        let (map_target, source_stream) = match &planned {
+            PlannedStream::Video(stream, _) => (
+                if joins_ranges {
+                    "[v]".to_string()
+                } else if context.chunked_video_path.is_some()
+                    && primary_video_stream_index == Some(stream.stream_index)
+                {
+                    "1:v:0".to_string()
+                } else if matches!(burn_in_plan, Some(SubtitleBurnInPlan::Bitmap { .. }))
+                    && primary_video_stream_index == Some(stream.stream_index)
+                {
+                    "[vburn]".to_string()
+                } else {
+                    format!("0:v:{}", stream.relative_index)
+                },
+                stream,
+            ),
+            PlannedStream::Audio(stream, _) => (
+                if joins_ranges {
+                    format!("[a{}]", mapped.audio.len())
+                } else {
+                    format!("0:a:{}", stream.relative_index)
+                },
+                stream,
+            ),
+            PlannedStream::Subtitle(stream, _) => (
+                format!(
+                    "{}:s:{}",
+                    range_subtitle_input.unwrap_or_default(),
+                    stream.relative_index
+                ),
+                stream,
+            ),
         };
+        args.push("-map".to_string());
+        args.push(map_target);
+        let mut stream_metadata = output_stream_metadata_from_request(
+            output_metadata.len(),
+            &source_stream.probe_stream,
+            &request.metadata,
+        );
+        if let PlannedStream::Audio(_, settings) = &planned
+            && let Some(derived_track) = settings
+                .derived_index
+                .and_then(|derived_index| request.audio.derived_tracks.get(derived_index))
+        {
+            apply_derived_audio_metadata(&mut stream_metadata, derived_track);
+        }
+        output_metadata.push(stream_metadata);
 
-        if let Some(container_id) = preferred_container_id.filter(|container_id| {
-            capabilities.containers.iter().any(|container| {
-                container.kind == "audio"
-                    && container.id == *container_id
-                    && container
-                        .supported_audio_encoder_ids
-                        .iter()
-                        .any(|supported_id| supported_id == encoder_id)
-            })
-        }) {
-            return Some(container_id.to_string());
+        match planned {
+            PlannedStream::Video(stream, settings) => {
+                mapped.video.push((mapped.video.len(), stream, settings));
+            }
+            PlannedStream::Audio(stream, settings) => {
+                mapped.audio.push((mapped.audio.len(), stream, settings));
+            }
+            PlannedStream::Subtitle(stream, settings) => {
+                mapped
+                    .subtitle
+                    .push((mapped.subtitle.len(), stream, settings));
+            }
         }
+    }
 
-        capabilities
-            .containers
-            .iter()
-            .find(|container| {
-                container.kind == "audio"
-                    && container
-                        .supported_audio_encoder_ids
-                        .iter()
-                        .any(|supported_id| supported_id == encoder_id)
-            })
-            .map(|container| container.id.clone())
+    if output_metadata.is_empty() {
+        return Err("No streams selected for output".to_string());
     }
+    apply_default_derived_audio(&mut output_metadata, request, &audio_streams, &mapped.audio);
 
-    fn pick_video_test_container_id(
-        capabilities: &TranscodeCapabilities,
-        encoder_id: &str,
-    ) -> Option<String> {
-        let preferred_container_id = match encoder_id {
-            "libx264" | "h264_videotoolbox" => Some("mp4"),
-            "libx265" | "hevc_videotoolbox" => Some("mp4"),
-            "libsvtav1" | "libaom-av1" | "libvpx" | "libvpx-vp9" => Some("webm"),
-            "prores_ks" | "prores_videotoolbox" => Some("mov"),
-            _ => None,
-        };
+    if joins_ranges {
+        apply_range_join_filter(&mut args, request, &mapped, range_chapters_input)?;
+    }
 
-        if let Some(container_id) = preferred_container_id.filter(|container_id| {
-            capabilities.containers.iter().any(|container| {
-                container.kind == "video"
-                    && container.id == *container_id
-                    && container
-                        .supported_video_encoder_ids
-                        .iter()
-                        .any(|supported_id| supported_id == encoder_id)
-            })
-        }) {
-            return Some(container_id.to_string());
-        }
+    if burn_in_plan.is_some()
+        && !mapped.video.iter().any(|(_, stream, settings)| {
+            primary_video_stream_index == Some(stream.stream_index) && settings.mode == "transcode"
+        })
+    {
+        return Err("Subtitle burn-in requires the main video stream to be transcoded".to_string());
+    }
 
-        capabilities
-            .containers
-            .iter()
-            .find(|container| {
-                container.kind == "video"
-                    && container
-                        .supported_video_encoder_ids
-                        .iter()
-                        .any(|supported_id| supported_id == encoder_id)
-            })
-            .map(|container| container.id.clone())
+    let transcodes_video = apply_video_codec_args(
+        &mut args,
+        request,
+        context,
+        &mapped.video,
+        primary_video_stream_index,
+        burn_in_plan.as_ref(),
+        video_pass,
+    )?;
+    if transcodes_video && request.container_id == "mp4" && !is_analysis_pass {
+        args.push("-movflags".to_string());
+        args.push("+faststart".to_string());
     }
+    apply_audio_codec_args(&mut args, request, context, &mapped.audio)?;
+    apply_subtitle_codec_args(&mut args, request, &mapped.subtitle)?;
 
-    fn sanitize_case_id(value: &str) -> String {
-        value
-            .chars()
-            .map(|character| {
-                if character.is_ascii_alphanumeric() {
-                    character.to_ascii_lowercase()
-                } else {
-                    '_'
-                }
-            })
-            .collect()
+    if let [range] = request.ranges.as_slice()
+        && let Some(end_ms) = range.end_ms
+    {
+        args.push("-t".to_string());
+        args.push(format_range_seconds(end_ms - range.start_ms));
     }
 
-    fn expected_output_channels(encoder_id: &str, input_channels: u64) -> u64 {
-        if encoder_id == "libmp3lame" && input_channels > 2 {
-            2
-        } else {
-            input_channels
+    if is_analysis_pass {
+        for flag in [
+            "-an",
+            "-sn",
+            "-dn",
+            "-progress",
+            "pipe:1",
+            "-f",
+            "null",
+            "-",
+        ] {
+            args.push(flag.to_string());
         }
+        return Ok(args);
     }
 
-    fn expected_audio_codec_name(encoder_id: &str) -> &'static str {
-        match encoder_id {
-            "aac" | "aac_at" => "aac",
-            "libopus" => "opus",
-            "libvorbis" => "vorbis",
-            "libmp3lame" => "mp3",
-            "flac" => "flac",
-            "pcm_s16le" => "pcm_s16le",
-            _ => "",
-        }
+    if !joins_ranges {
+        apply_chapter_args(
+            &mut args,
+            &request.container_id,
+            request.metadata.keep_chapters,
+        );
     }
+    apply_attachment_args(
+        &mut args,
+        &request.container_id,
+        request.metadata.keep_attachments,
+    );
+    apply_metadata_args(
+        &mut args,
+        &request.container_id,
+        Some(&request.metadata),
+        &output_metadata,
+    );
 
-    fn validate_transcoded_audio_stream(
-        encoder_id: &str,
-        input_channels: u64,
-        probed_stream: &ProbedAudioStream,
-    ) -> Option<String> {
-        let expected_codec_name = expected_audio_codec_name(encoder_id);
-        if !expected_codec_name.is_empty() && probed_stream.codec_name != expected_codec_name {
-            return Some(format!(
-                "expected codec {}, got {}",
-                expected_codec_name, probed_stream.codec_name
+    if let Some(duration_us) = duration_us {
+        if duration_us > 0 {
+            let duration_seconds = duration_us as f64 / 1_000_000.0;
+            args.push("-metadata".to_string());
+            args.push(format!(
+                "mediaflow.duration_seconds={:.3}",
+                duration_seconds
             ));
         }
+    }
 
-        let expected_channels = expected_output_channels(encoder_id, input_channels);
-        if probed_stream.channels != expected_channels {
-            return Some(format!(
-                "expected {} channels, got {}",
-                expected_channels, probed_stream.channels
+    args.push("-progress".to_string());
+    args.push("pipe:1".to_string());
+    args.push(request.output_path.clone());
+
+    Ok(args)
+}
+
+/// Output streams of one pass in their final order; the analysis pass keeps only video.
+fn plan_pass_streams(
+    request: &TranscodeRequest,
+    streams: &[Value],
+    video_streams: &[StreamInfo],
+    audio_streams: &[StreamInfo],
+    primary_video_stream_index: Option<usize>,
+    video_pass: Option<&TranscodePass>,
+) -> Result<Vec<PlannedStream>, String> {
+    let joins_ranges = request.ranges.len() > 1;
+    let is_analysis_pass = video_pass.is_some_and(|video_pass| video_pass.number == 1);
+    let mut planned_streams = Vec::new();
+    for video_stream in video_streams {
+        if joins_ranges && is_attached_picture(video_stream) {
+            continue;
+        }
+        let resolved_settings = resolve_video_settings_for_stream(
+            request,
+            video_stream,
+            primary_video_stream_index == Some(video_stream.stream_index),
+        );
+        if resolved_settings.mode != "disable" {
+            planned_streams.push(PlannedStream::Video(
+                video_stream.clone(),
+                resolved_settings,
             ));
         }
-
-        None
     }
-
-    fn pick_audio_override_sample_rate(
-        encoder_id: &str,
-        supported_sample_rates: &[u32],
-    ) -> Option<u32> {
-        let preferred = if encoder_id == "libopus" {
-            &[24_000, 16_000, 12_000, 8_000][..]
-        } else {
-            &[44_100, 32_000, 24_000, 22_050, 16_000][..]
-        };
-
-        preferred.iter().copied().find(|rate| {
-            *rate != 48_000
-                && (supported_sample_rates.is_empty() || supported_sample_rates.contains(rate))
-        })
+    if !is_analysis_pass {
+        for (audio_stream, resolved_settings) in
+            resolve_output_audio_tracks(request, audio_streams)?
+        {
+            planned_streams.push(PlannedStream::Audio(audio_stream, resolved_settings));
+        }
+        for subtitle_stream in &extract_streams_by_type(streams, "subtitle") {
+            let resolved_settings = resolve_subtitle_settings_for_stream(request, subtitle_stream);
+            if resolved_settings.mode != "disable"
+                && !is_subtitle_auto_dropped(request, subtitle_stream, &resolved_settings)
+            {
+                planned_streams.push(PlannedStream::Subtitle(
+                    subtitle_stream.clone(),
+                    resolved_settings,
+                ));
+            }
+        }
+    }
+    order_planned_streams(&mut planned_streams, &request.stream_order);
+    let is_video = |planned: &PlannedStream| matches!(planned, PlannedStream::Video(..));
+    // Pass logs are named after output indices, so video must lead in both passes.
+    if video_pass.is_some()
+        && planned_streams
+            .iter()
+            .skip_while(|planned| is_video(planned))
+            .any(is_video)
+    {
+        return Err(
+            "Two-pass encoding needs the video streams first. Move them to the top of the stream order or turn off two-pass."
+                .to_string(),
+        );
     }
+    Ok(planned_streams)
+}
 
-    fn expected_output_sample_rate(encoder_id: &str, requested_sample_rate: u32) -> u32 {
-        if encoder_id == "libopus" {
-            48_000
-        } else {
-            requested_sample_rate
+/// Joined ranges take subtitles from a concat list of the same ranges and
+/// chapters from a metadata file, both already shifted to the output timeline.
+/// Returns the input indices of the subtitle list and the chapter file.
+fn push_range_join_inputs(
+    args: &mut Vec<String>,
+    request: &TranscodeRequest,
+    context: &TranscodeContext,
+    planned_streams: &[PlannedStream],
+) -> Result<(Option<usize>, Option<usize>), String> {
+    let mut input_index = request.ranges.len();
+    let mut range_subtitle_input = None;
+    let mut range_chapters_input = None;
+    if planned_streams
+        .iter()
+        .any(|planned| matches!(planned, PlannedStream::Subtitle(..)))
+    {
+        let list_path = context
+            .range_join
+            .subtitle_list_path
+            .as_ref()
+            .ok_or_else(|| "Subtitles for the joined ranges were not prepared".to_string())?;
+        for flag in ["-f", "concat", "-safe", "0", "-i", list_path] {
+            args.push(flag.to_string());
         }
+        range_subtitle_input = Some(input_index);
+        input_index += 1;
     }
+    if let Some(chapters_path) = context.range_join.chapters_path.as_ref() {
+        args.push("-i".to_string());
+        args.push(chapters_path.clone());
+        range_chapters_input = Some(input_index);
+    }
+    Ok((range_subtitle_input, range_chapters_input))
+}
 
-    fn pick_preferred_audio_encoders<'a>(
-        capabilities: &'a TranscodeCapabilities,
-    ) -> Vec<&'a TranscodeAudioEncoderCapability> {
-        let preferred_ids = [
-            "aac",
-            "aac_at",
-            "libopus",
-            "libvorbis",
-            "flac",
-            "pcm_s16le",
-            "libmp3lame",
-        ];
-
-        preferred_ids
-            .iter()
-            .filter_map(|encoder_id| {
-                capabilities
-                    .audio_encoders
+/// A derived track marked default takes the default flag from the other audio tracks.
+fn apply_default_derived_audio(
+    output_metadata: &mut [OutputStreamMetadata],
+    request: &TranscodeRequest,
+    audio_streams: &[StreamInfo],
+    mapped_audio_streams: &[(usize, StreamInfo, ResolvedAudioSettings)],
+) {
+    let Some(default_output_index) = mapped_audio_streams
+        .iter()
+        .find(|(_, _, settings)| {
+            settings
+                .derived_index
+                .and_then(|derived_index| request.audio.derived_tracks.get(derived_index))
+                .is_some_and(|derived_track| derived_track.default == Some(true))
+        })
+        .map(|(output_index, _, _)| *output_index)
+    else {
+        return;
+    };
+    let audio_metadata_indices = output_metadata
+        .iter()
+        .enumerate()
+        .filter(|(_, metadata)| {
+            metadata.source_track_id.is_some_and(|track_id| {
+                audio_streams
                     .iter()
-                    .find(|encoder| encoder.id == *encoder_id)
+                    .any(|stream| stream.stream_index == track_id)
             })
-            .collect()
+        })
+        .map(|(index, _)| index)
+        .collect::<Vec<_>>();
+    for (audio_output_index, metadata_index) in audio_metadata_indices.into_iter().enumerate() {
+        output_metadata[metadata_index].is_default = audio_output_index == default_output_index;
     }
+}
 
-    async fn collect_probe_streams(path: &Path) -> Result<Vec<Value>, String> {
-        let probe_json =
-            probe_file_with_ffprobe(ffprobe_path(), path.to_string_lossy().as_ref()).await?;
-        let probe_value: Value = serde_json::from_str(&probe_json)
-            .map_err(|error| format!("Invalid probe JSON: {}", error))?;
-
-        Ok(probe_value
-            .get("streams")
-            .and_then(|value| value.as_array())
-            .cloned()
-            .unwrap_or_default())
+/// Concat filter joining the ranges, plus the chapter mapping for the joined timeline.
+fn apply_range_join_filter(
+    args: &mut Vec<String>,
+    request: &TranscodeRequest,
+    mapped: &MappedStreams,
+    range_chapters_input: Option<usize>,
+) -> Result<(), String> {
+    if mapped.video.len() > 1 {
+        return Err(
+            "Joining several ranges supports a single video stream. Disable the other video streams."
+                .to_string(),
+        );
     }
-
-    fn args_contain_pair(args: &[String], flag: &str, value: &str) -> bool {
-        args.windows(2)
-            .any(|window| window[0] == flag && window[1] == value)
+    let include_video = !mapped.video.is_empty();
+    if mapped
+        .video
+        .iter()
+        .any(|(_, _, settings)| settings.mode == "copy")
+    {
+        return Err(
+            "Joining several ranges re-encodes the video. Switch video to transcode.".to_string(),
+        );
     }
-
-    fn expect_arg_pair(args: &[String], flag: &str, value: &str) -> Option<String> {
-        if args_contain_pair(args, flag, value) {
-            None
-        } else {
-            Some(format!(
-                "expected ffmpeg args to contain `{}` `{}`",
-                flag, value
-            ))
-        }
+    if mapped
+        .audio
+        .iter()
+        .any(|(_, _, settings)| settings.derived_index.is_some())
+    {
+        return Err(
+            "Derived audio tracks cannot be combined with joining several ranges".to_string(),
+        );
     }
-
-    fn expect_flag(args: &[String], flag: &str) -> Option<String> {
-        if args.iter().any(|arg| arg == flag) {
-            None
-        } else {
-            Some(format!("expected ffmpeg args to contain `{}`", flag))
-        }
+    if mapped
+        .audio
+        .iter()
+        .any(|(_, _, settings)| !settings.filters.is_empty())
+    {
+        return Err("Audio filters cannot be combined with joining several ranges".to_string());
+    }
+    if mapped
+        .audio
+        .iter()
+        .any(|(_, _, settings)| settings.mode == "copy")
+    {
+        return Err(
+            "Joining several ranges re-encodes the audio. Switch audio tracks to transcode."
+                .to_string(),
+        );
     }
+    let video_filter_graph = match mapped.video.first() {
+        Some((_, stream, settings)) => video_filter_graph_for_stream(request, stream, settings)?,
+        None => None,
+    };
+    let audio_relative_indices = mapped
+        .audio
+        .iter()
+        .map(|(_, stream, _)| stream.relative_index)
+        .collect::<Vec<_>>();
+    args.push("-filter_complex".to_string());
+    args.push(build_range_concat_filter(
+        request.ranges.len(),
+        include_video,
+        &audio_relative_indices,
+        video_filter_graph.as_deref(),
+    ));
+    args.push("-map_chapters".to_string());
+    args.push(match range_chapters_input {
+        Some(input_index) if keeps_chapters(request) => input_index.to_string(),
+        _ => "-1".to_string(),
+    });
+    Ok(())
+}
 
-    fn collect_audio_request_arg_failures(
-        args: &[String],
-        request: &TranscodeRequest,
-    ) -> Vec<String> {
-        let mut failures = Vec::new();
+/// Codec, filter and burn-in args for every mapped video stream; returns
+/// whether any of them is transcoded.
+fn apply_video_codec_args(
+    args: &mut Vec<String>,
+    request: &TranscodeRequest,
+    context: &TranscodeContext,
+    mapped_video_streams: &[(usize, StreamInfo, ResolvedVideoSettings)],
+    primary_video_stream_index: Option<usize>,
+    burn_in_plan: Option<&SubtitleBurnInPlan>,
+    video_pass: Option<&TranscodePass>,
+) -> Result<bool, String> {
+    let joins_ranges = request.ranges.len() > 1;
+    let qualify_video_flags = mapped_video_streams.len() > 1;
+    let mut transcodes_video = false;
+    for (output_index, source_stream, resolved_settings) in mapped_video_streams {
+        let stream_index = qualify_video_flags.then_some(*output_index);
+        match resolved_settings.mode.as_str() {
+            "copy" => {
+                let from_chunks = context.chunked_video_path.is_some()
+                    && primary_video_stream_index == Some(source_stream.stream_index);
+                let can_copy = if from_chunks {
+                    true
+                } else if is_attached_picture(source_stream) {
+                    can_copy_attached_picture(&request.container_id, &source_stream.codec_name)
+                } else {
+                    can_copy_video_codec(&request.container_id, &source_stream.codec_name)
+                };
+                if !can_copy {
+                    return Err(format!(
+                        "Container {} cannot copy video codec {}. Choose video transcoding or a compatible container.",
+                        request.container_id.to_uppercase(),
+                        source_stream.codec_name
+                    ));
+                }
 
-        if let Some(bitrate_kbps) = request.audio.bitrate_kbps {
-            if let Some(error) = expect_arg_pair(args, "-b:a:0", &format!("{}k", bitrate_kbps)) {
-                failures.push(error);
+                args.push(video_stream_flag("-c:v", stream_index));
+                args.push("copy".to_string());
             }
-        }
+            "transcode" => {
+                let Some(encoder_id) = resolved_settings.encoder_id.as_deref() else {
+                    return Err(
+                        "A video encoder is required when video transcoding is enabled".to_string(),
+                    );
+                };
+                transcodes_video = true;
 
-        if let Some(channels) = request.audio.channels.filter(|channels| *channels > 0) {
-            if let Some(error) = expect_arg_pair(args, "-ac:a:0", &channels.to_string()) {
-                failures.push(error);
-            }
-        }
+                args.push(video_stream_flag("-c:v", stream_index));
+                args.push(encoder_id.to_string());
 
-        if let Some(sample_rate) = request
-            .audio
-            .sample_rate
-            .filter(|sample_rate| *sample_rate > 0)
-        {
-            if let Some(error) = expect_arg_pair(args, "-ar:a:0", &sample_rate.to_string()) {
-                failures.push(error);
+                let video_filter_graph = if joins_ranges {
+                    None
+                } else {
+                    video_filter_graph_for_stream(request, source_stream, resolved_settings)?
+                };
+                let burns_subtitles =
+                    primary_video_stream_index == Some(source_stream.stream_index);
+                match burn_in_plan.filter(|_| burns_subtitles) {
+                    Some(SubtitleBurnInPlan::Text(burn_in_filter)) => {
+                        args.push(video_stream_flag("-vf", stream_index));
+                        args.push(match video_filter_graph.as_ref() {
+                            Some(filter_graph) => format!("{},{}", burn_in_filter, filter_graph),
+                            None => burn_in_filter.clone(),
+                        });
+                    }
+                    Some(SubtitleBurnInPlan::Bitmap {
+                        subtitle_relative_index,
+                        ..
+                    }) => {
+                        args.push("-filter_complex".to_string());
+                        args.push(build_bitmap_burn_in_filter(
+                            source_stream.relative_index,
+                            *subtitle_relative_index,
+                            video_filter_graph.as_deref(),
+                        ));
+                    }
+                    None => {
+                        if let Some(filter_graph) = video_filter_graph.as_ref()
+                            && !is_attached_picture(source_stream)
+                        {
+                            args.push(video_stream_flag("-vf", stream_index));
+                            args.push(filter_graph.clone());
+                        }
+                    }
+                }
+
+                apply_video_encoder_args(
+                    args,
+                    request,
+                    context,
+                    source_stream,
+                    resolved_settings,
+                    encoder_id,
+                    stream_index,
+                    video_pass,
+                )?;
             }
+            other => return Err(format!("Unsupported video mode: {}", other)),
         }
+    }
+    Ok(transcodes_video)
+}
 
-        failures
+/// Encoder settings of one transcoded video stream: profile, rate control,
+/// preset, keyframes, two-pass, HDR metadata and encoder params.
+fn apply_video_encoder_args(
+    args: &mut Vec<String>,
+    request: &TranscodeRequest,
+    context: &TranscodeContext,
+    source_stream: &StreamInfo,
+    resolved_settings: &ResolvedVideoSettings,
+    encoder_id: &str,
+    stream_index: Option<usize>,
+    video_pass: Option<&TranscodePass>,
+) -> Result<(), String> {
+    if let Some(profile) = resolved_settings.profile.as_deref() {
+        if !profile.trim().is_empty() {
+            args.push(video_stream_flag("-profile:v", stream_index));
+            args.push(profile.trim().to_string());
+        }
     }
 
-    fn collect_video_request_arg_failures(
-        args: &[String],
-        request: &TranscodeRequest,
-        encoder: &TranscodeVideoEncoderCapability,
-    ) -> Vec<String> {
-        let mut failures = Vec::new();
+    if let Some(level) = resolved_settings.level.as_deref() {
+        if !level.trim().is_empty() {
+            args.push(video_stream_flag("-level:v", stream_index));
+            args.push(level.trim().to_string());
+        }
+    }
 
-        if let Some(profile) = request
-            .video
-            .profile
-            .as_deref()
-            .filter(|value| !value.trim().is_empty())
-        {
-            if let Some(error) = expect_arg_pair(args, "-profile:v", profile.trim()) {
-                failures.push(error);
-            }
+    if let Some(pixel_format) = resolved_settings.pixel_format.as_deref() {
+        if !pixel_format.trim().is_empty() {
+            args.push(video_stream_flag("-pix_fmt", stream_index));
+            args.push(pixel_format.trim().to_string());
         }
+    }
 
-        if let Some(level) = request
-            .video
-            .level
-            .as_deref()
-            .filter(|value| !value.trim().is_empty())
-        {
-            if let Some(error) = expect_arg_pair(args, "-level:v", level.trim()) {
-                failures.push(error);
+    let quality_mode = resolved_settings.quality_mode.as_deref().unwrap_or("crf");
+    match quality_mode {
+        "crf" => {
+            if let Some(crf) = resolved_settings.crf {
+                args.push(video_stream_flag("-crf", stream_index));
+                args.push(crf.to_string());
+            }
+        }
+        "qp" => {
+            if let Some(qp) = resolved_settings.qp {
+                args.push(video_stream_flag("-qp", stream_index));
+                args.push(qp.to_string());
+            }
+        }
+        "bitrate" => {
+            if let Some(bitrate_kbps) = resolved_settings.bitrate_kbps {
+                args.push(video_stream_flag("-b:v", stream_index));
+                args.push(format!("{}k", bitrate_kbps));
             }
         }
+        _ => {}
+    }
 
-        if let Some(pixel_format) = request
-            .video
-            .pixel_format
-            .as_deref()
-            .filter(|value| !value.trim().is_empty())
+    if let Some(preset) = resolved_settings.preset.as_deref() {
+        if !preset.trim().is_empty() {
+            apply_video_preset_arg(args, encoder_id, preset.trim(), stream_index)?;
+        }
+    }
+
+    if let Some(segment_seconds) = context.segment_seconds {
+        args.push(video_stream_flag("-force_key_frames:v", stream_index));
+        args.push(format!("expr:gte(t,n_forced*{})", segment_seconds));
+    }
+
+    let mut encoder_params = Vec::new();
+    if let Some(video_pass) = video_pass
+        && quality_mode == "bitrate"
+        && supports_two_pass_encoding(encoder_id)
+    {
+        apply_two_pass_args(
+            args,
+            &mut encoder_params,
+            encoder_id,
+            video_pass,
+            stream_index,
+        );
+    }
+
+    if keeps_hdr_output(request, source_stream, resolved_settings) {
+        let side_data = hdr_side_data(&source_stream.probe_stream);
+        encoder_params.extend(hdr_encoder_params(encoder_id, &side_data));
+        if encoder_id == "libx265"
+            && context.dolby_vision_supported
+            && side_data
+                .dolby_vision
+                .is_some_and(|dolby_vision| dolby_vision.rpu_present)
         {
-            if let Some(error) = expect_arg_pair(args, "-pix_fmt", pixel_format.trim()) {
-                failures.push(error);
-            }
+            args.push(video_stream_flag("-dolbyvision", stream_index));
+            args.push(
+                if request.video.keep_dolby_vision {
+                    "1"
+                } else {
+                    "0"
+                }
+                .to_string(),
+            );
         }
+    }
 
-        match request.video.quality_mode.as_deref() {
-            Some("crf") => {
-                if let Some(crf) = request.video.crf {
-                    if let Some(error) = expect_arg_pair(args, "-crf", &crf.to_string()) {
-                        failures.push(error);
-                    }
+    if matches!(request.container_id.as_str(), "mp4" | "mov") && encoder_id.starts_with("hevc") {
+        args.push(video_stream_flag("-tag:v", stream_index));
+        args.push("hvc1".to_string());
+    }
+
+    let additional_args = merge_encoder_params_args(
+        encoder_id,
+        &resolved_settings.additional_args,
+        &mut encoder_params,
+    );
+    if let Some(params_flag) = encoder_params_flag(encoder_id)
+        && !encoder_params.is_empty()
+    {
+        args.push(video_stream_flag(params_flag, stream_index));
+        args.push(encoder_params.join(":"));
+    }
+
+    apply_safe_additional_args(
+        args,
+        &additional_args,
+        stream_index
+            .map(|stream_index| format!("v:{}", stream_index))
+            .as_deref(),
+    )
+}
+
+fn apply_audio_codec_args(
+    args: &mut Vec<String>,
+    request: &TranscodeRequest,
+    context: &TranscodeContext,
+    mapped_audio_streams: &[(usize, StreamInfo, ResolvedAudioSettings)],
+) -> Result<(), String> {
+    for (output_index, source_stream, resolved_settings) in mapped_audio_streams {
+        match resolved_settings.mode.as_str() {
+            "copy" => {
+                if !resolved_settings.filters.is_empty() {
+                    return Err(
+                        "Audio filters require audio transcoding. Switch the track to transcode or clear its filters."
+                            .to_string(),
+                    );
                 }
-            }
-            Some("qp") => {
-                if let Some(qp) = request.video.qp {
-                    if let Some(error) = expect_arg_pair(args, "-qp", &qp.to_string()) {
-                        failures.push(error);
-                    }
+                if !can_copy_audio_codec(&request.container_id, &source_stream.codec_name) {
+                    return Err(format!(
+                        "Container {} cannot copy audio codec {}. Choose audio transcoding or a compatible container.",
+                        request.container_id.to_uppercase(),
+                        source_stream.codec_name
+                    ));
                 }
+
+                args.push(format!("-c:a:{}", output_index));
+                args.push("copy".to_string());
             }
-            Some("bitrate") => {
-                if let Some(bitrate_kbps) = request.video.bitrate_kbps {
-                    if let Some(error) =
-                        expect_arg_pair(args, "-b:v", &format!("{}k", bitrate_kbps))
-                    {
-                        failures.push(error);
-                    }
+            "transcode" => {
+                let Some(encoder_id) = resolved_settings.encoder_id.as_deref() else {
+                    return Err(
+                        "An audio encoder is required when audio transcoding is enabled"
+                            .to_string(),
+                    );
+                };
+
+                args.push(format!("-c:a:{}", output_index));
+                args.push(encoder_id.to_string());
+
+                if should_force_libopus_mapping_family_255(
+                    resolved_settings,
+                    source_stream,
+                    &resolved_settings.additional_args,
+                ) {
+                    args.push(format!("-mapping_family:a:{}", output_index));
+                    args.push("255".to_string());
                 }
-            }
-            _ => {}
-        }
 
-        if let Some(preset) = request
-            .video
-            .preset
-            .as_deref()
-            .filter(|value| !value.trim().is_empty())
-        {
-            let preset_flag = if cpu_used_preset_max(&encoder.id).is_some() {
-                "-cpu-used"
-            } else {
-                "-preset"
-            };
-            if let Some(error) = expect_arg_pair(args, preset_flag, preset.trim()) {
-                failures.push(error);
-            }
-        }
+                if let Some(bitrate_kbps) = resolved_settings.bitrate_kbps {
+                    args.push(format!("-b:a:{}", output_index));
+                    args.push(format!("{}k", bitrate_kbps));
+                }
 
-        for additional_arg in request
-            .video
-            .additional_args
-            .iter()
-            .filter(|arg| arg.enabled)
-        {
-            match additional_arg.value.as_deref() {
-                Some(value) => {
-                    if let Some(error) = expect_arg_pair(args, &additional_arg.flag, value) {
-                        failures.push(error);
-                    }
+                if let Some(channels) = resolved_settings.channels.filter(|channels| *channels > 0)
+                {
+                    args.push(format!("-ac:a:{}", output_index));
+                    args.push(channels.to_string());
                 }
-                None => {
-                    if let Some(error) = expect_flag(args, &additional_arg.flag) {
-                        failures.push(error);
-                    }
+
+                if let Some(sample_rate) = resolved_settings
+                    .sample_rate
+                    .filter(|sample_rate| *sample_rate > 0)
+                {
+                    args.push(format!("-ar:a:{}", output_index));
+                    args.push(sample_rate.to_string());
+                }
+
+                let measurement = context
+                    .loudnorm_measurements
+                    .iter()
+                    .find(|(stream_index, derived_index, _)| {
+                        *stream_index == source_stream.stream_index
+                            && *derived_index == resolved_settings.derived_index
+                    })
+                    .map(|(_, _, measurement)| measurement);
+                if let Some(filter_graph) = build_audio_filter_graph(
+                    &resolved_settings.filters,
+                    audio_filter_source(source_stream),
+                    measurement,
+                )? {
+                    args.push(format!("-filter:a:{}", output_index));
+                    args.push(filter_graph);
                 }
+
+                apply_safe_additional_args(
+                    args,
+                    &resolved_settings.additional_args,
+                    Some(&format!("a:{}", output_index)),
+                )?;
             }
+            "disable" => {}
+            other => return Err(format!("Unsupported audio mode: {}", other)),
         }
+    }
+    Ok(())
+}
 
-        if matches!(request.container_id.as_str(), "mp4" | "mov") && encoder.id.starts_with("hevc")
-        {
-            if let Some(error) = expect_arg_pair(args, "-tag:v", "hvc1") {
-                failures.push(error);
+fn apply_subtitle_codec_args(
+    args: &mut Vec<String>,
+    request: &TranscodeRequest,
+    mapped_subtitle_streams: &[(usize, StreamInfo, ResolvedSubtitleSettings)],
+) -> Result<(), String> {
+    let mut converts_text_subtitles = false;
+    for (output_index, source_stream, resolved_settings) in mapped_subtitle_streams {
+        match resolved_settings.mode.as_str() {
+            "copy" => {
+                if !can_copy_subtitle_codec(&request.container_id, &source_stream.codec_name) {
+                    return Err(format!(
+                        "Container {} cannot copy subtitle codec {}. Choose subtitle conversion or disable subtitles for this output format.",
+                        request.container_id.to_uppercase(),
+                        source_stream.codec_name
+                    ));
+                }
+
+                args.push(format!("-c:s:{}", output_index));
+                args.push("copy".to_string());
             }
-        }
+            "convert_text" => {
+                let Some(encoder_id) = resolved_settings.encoder_id.as_deref() else {
+                    return Err(
+                        "A subtitle encoder is required when subtitle conversion is enabled"
+                            .to_string(),
+                    );
+                };
 
-        if request.container_id == "mp4" {
-            if let Some(error) = expect_arg_pair(args, "-movflags", "+faststart") {
-                failures.push(error);
+                if !is_text_subtitle_codec(&source_stream.codec_name)
+                    && !can_copy_subtitle_codec(&request.container_id, &source_stream.codec_name)
+                {
+                    return Err(format!(
+                        "Container {} cannot keep subtitle codec {} while converting text subtitles. Disable subtitles or choose a compatible container.",
+                        request.container_id.to_uppercase(),
+                        source_stream.codec_name
+                    ));
+                }
+
+                converts_text_subtitles = true;
+                args.push(format!("-c:s:{}", output_index));
+                if is_text_subtitle_codec(&source_stream.codec_name) {
+                    args.push(encoder_id.to_string());
+                } else {
+                    args.push("copy".to_string());
+                }
             }
+            other => return Err(format!("Unsupported subtitle mode: {}", other)),
         }
 
-        failures
+        apply_safe_additional_args(
+            args,
+            &resolved_settings.additional_args,
+            Some(&format!("s:{}", output_index)),
+        )?;
     }
 
-    fn normalize_comparable_value(value: &str) -> String {
-        value
-            .chars()
-            .filter(|character| character.is_ascii_alphanumeric())
-            .map(|character| character.to_ascii_lowercase())
-            .collect()
+    if converts_text_subtitles {
+        apply_safe_additional_args(args, &request.subtitles.additional_args, None)?;
     }
+    Ok(())
+}
 
-    #[derive(Debug, Clone)]
-    struct VideoRuntimeScenario {
-        name: &'static str,
-        quality_mode: Option<&'static str>,
-        crf: Option<f64>,
-        qp: Option<i32>,
-        bitrate_kbps: Option<u32>,
-        preset: Option<&'static str>,
-        profile: Option<String>,
-        level: Option<String>,
-        pixel_format: Option<String>,
-        additional_args: Vec<TranscodeAdditionalArg>,
+/// Per-stream plan with `auto` modes settled; fails with the same errors the
+/// transcode itself would.
+fn build_transcode_stream_plan(
+    request: &TranscodeRequest,
+    streams: &[Value],
+) -> Result<Vec<TranscodeStreamPlan>, String> {
+    let request = resolve_auto_video_modes(request, streams);
+    let context = TranscodeContext {
+        range_join: planned_range_join(&request),
+        ..TranscodeContext::default()
+    };
+    build_transcode_args(&request, &context, streams, None)?;
+
+    let entry = |stream: &StreamInfo, codec_type: &str, action: &str| TranscodeStreamPlan {
+        stream_index: stream.stream_index,
+        codec_type: codec_type.to_string(),
+        codec_name: stream.codec_name.clone(),
+        action: action.to_string(),
+        encoder_id: None,
+        bitrate_kbps: None,
+        derived_index: None,
+        reason: None,
+    };
+    let joins_ranges = request.ranges.len() > 1;
+    let mut plan = Vec::new();
+
+    let video_streams = extract_streams_by_type(streams, "video");
+    let primary_video_stream_index = video_streams
+        .iter()
+        .find(|stream| !is_attached_picture(stream))
+        .map(|stream| stream.stream_index);
+    for stream in &video_streams {
+        let settings = resolve_video_settings_for_stream(
+            &request,
+            stream,
+            primary_video_stream_index == Some(stream.stream_index),
+        );
+        let action = if joins_ranges && is_attached_picture(stream) {
+            "disable"
+        } else {
+            settings.mode.as_str()
+        };
+        plan.push(TranscodeStreamPlan {
+            encoder_id: settings.encoder_id.filter(|_| action == "transcode"),
+            reason: settings.auto_reason,
+            ..entry(stream, "video", action)
+        });
     }
 
-    fn preferred_video_pixel_format(encoder: &TranscodeVideoEncoderCapability) -> Option<String> {
-        encoder
-            .supported_pixel_formats
-            .iter()
-            .find(|pixel_format| {
-                matches!(
-                    pixel_format.as_str(),
-                    "yuv420p" | "nv12" | "yuv420p10le" | "p010le"
-                )
-            })
-            .cloned()
-            .or_else(|| encoder.supported_pixel_formats.first().cloned())
+    for stream in &extract_streams_by_type(streams, "audio") {
+        let settings = resolve_audio_settings_for_stream(&request, stream);
+        let transcodes = settings.mode == "transcode";
+        plan.push(TranscodeStreamPlan {
+            encoder_id: settings.encoder_id.filter(|_| transcodes),
+            bitrate_kbps: settings.bitrate_kbps.filter(|_| transcodes),
+            reason: settings.auto_reason,
+            ..entry(stream, "audio", &settings.mode)
+        });
+        for (derived_index, derived_track) in request.audio.derived_tracks.iter().enumerate() {
+            if derived_track.source_track_id == stream.stream_index {
+                let settings =
+                    resolve_derived_audio_settings(&request, derived_index, derived_track);
+                plan.push(TranscodeStreamPlan {
+                    encoder_id: settings.encoder_id,
+                    bitrate_kbps: settings.bitrate_kbps,
+                    derived_index: Some(derived_index),
+                    ..entry(stream, "audio", &settings.mode)
+                });
+            }
+        }
     }
 
-    fn preferred_video_preset(encoder_id: &str) -> Option<&'static str> {
-        match encoder_id {
-            "libx264" | "libx265" => Some("fast"),
-            "libsvtav1" => Some("10"),
-            "libaom-av1" => Some("8"),
-            _ => None,
-        }
+    for stream in &extract_streams_by_type(streams, "subtitle") {
+        let settings = resolve_subtitle_settings_for_stream(&request, stream);
+        let action = if is_subtitle_auto_dropped(&request, stream, &settings) {
+            "drop"
+        } else {
+            settings.mode.as_str()
+        };
+        plan.push(TranscodeStreamPlan {
+            encoder_id: settings
+                .encoder_id
+                .clone()
+                .filter(|_| action == "convert_text" && is_text_subtitle_codec(&stream.codec_name)),
+            reason: settings.auto_reason.clone(),
+            ..entry(stream, "subtitle", action)
+        });
     }
 
-    fn preferred_video_crf(codec: &str) -> f64 {
-        match codec {
-            "av1" => 35.0,
-            "hevc" => 30.0,
-            "vp9" => 32.0,
-            _ => 28.0,
-        }
-    }
+    Ok(plan)
+}
 
-    fn preferred_video_qp(codec: &str) -> i32 {
-        match codec {
-            "av1" => 36,
-            "hevc" => 30,
-            "vp9" => 32,
-            _ => 28,
-        }
-    }
+/// Streams the output should hold, taken from the stream plan.
+pub(crate) fn expected_output_streams(
+    request: &TranscodeRequest,
+    streams: &[Value],
+) -> Result<Vec<ExpectedOutputStream>, String> {
+    Ok(build_transcode_stream_plan(request, streams)?
+        .into_iter()
+        .filter(|entry| !matches!(entry.action.as_str(), "disable" | "drop"))
+        .map(|entry| ExpectedOutputStream {
+            codec_name: match entry.encoder_id.as_deref() {
+                Some(encoder_id) => codec_for_encoder_id(encoder_id).map(str::to_string),
+                None => Some(entry.codec_name),
+            },
+            codec_type: entry.codec_type,
+        })
+        .collect())
+}
 
-    fn preferred_video_bitrate_kbps(codec: &str) -> u32 {
-        match codec {
-            "prores" => 1500,
-            "av1" => 500,
-            "vp9" => 500,
-            _ => 700,
-        }
+/// Probe the finished output and check it against the request; failed outputs
+/// are deleted or quarantined as the verification settings ask.
+pub(crate) async fn verify_transcode_output(
+    ffprobe_path: &str,
+    request: &TranscodeRequest,
+    expected_streams: &[ExpectedOutputStream],
+    expected_duration_us: Option<u64>,
+) -> Result<TranscodeVerificationReport, String> {
+    let mut report = check_output_streams(
+        ffprobe_path,
+        &request.output_path,
+        &request.verification,
+        expected_streams,
+        expected_duration_us,
+    )
+    .await;
+    if !report.passed {
+        handle_failed_output(&request.output_path, &request.verification, &mut report)?;
     }
+    Ok(report)
+}
 
-    fn build_primary_video_scenario(
-        encoder: &TranscodeVideoEncoderCapability,
-    ) -> VideoRuntimeScenario {
-        let quality_mode = if encoder.supports_crf {
-            Some("crf")
-        } else if encoder.supports_qp {
-            Some("qp")
-        } else if encoder.supports_bitrate {
-            Some("bitrate")
-        } else {
-            None
-        };
+pub(crate) async fn check_output_streams(
+    ffprobe_path: &str,
+    output_path: &str,
+    settings: &TranscodeVerificationSettings,
+    expected_streams: &[ExpectedOutputStream],
+    expected_duration_us: Option<u64>,
+) -> TranscodeVerificationReport {
+    let output_streams = probe_file_with_ffprobe(ffprobe_path, output_path)
+        .await
+        .ok()
+        .and_then(|probe_json| serde_json::from_str::<Value>(&probe_json).ok())
+        .and_then(|probe_value| probe_value.get("streams")?.as_array().cloned())
+        .unwrap_or_default();
+    let output_duration_us = get_media_duration_us_with_ffprobe(ffprobe_path, output_path)
+        .await
+        .ok();
 
-        VideoRuntimeScenario {
-            name: "primary",
-            quality_mode,
-            crf: quality_mode
-                .filter(|mode| *mode == "crf")
-                .map(|_| preferred_video_crf(&encoder.codec)),
-            qp: quality_mode
-                .filter(|mode| *mode == "qp")
-                .map(|_| preferred_video_qp(&encoder.codec)),
-            bitrate_kbps: quality_mode
-                .filter(|mode| *mode == "bitrate")
-                .map(|_| preferred_video_bitrate_kbps(&encoder.codec)),
-            preset: encoder
-                .supports_preset
-                .then(|| preferred_video_preset(&encoder.id))
-                .flatten(),
-            profile: None,
-            level: None,
-            pixel_format: None,
-            additional_args: Vec::new(),
-        }
+    verify_output_streams(
+        expected_streams,
+        &output_streams,
+        expected_duration_us,
+        output_duration_us,
+        settings,
+    )
+}
+
+/// Outputs kept despite failing verification are still returned.
+pub(crate) fn verification_failure(report: &TranscodeVerificationReport) -> Option<String> {
+    (!report.passed && report.action != "kept").then(|| {
+        format!(
+            "Output verification failed and the output was {}: {}",
+            report.action,
+            report.issues.join("; ")
+        )
+    })
+}
+
+fn transcoded_audio_tracks(
+    request: &TranscodeRequest,
+    streams: &[Value],
+) -> Result<Vec<(StreamInfo, ResolvedAudioSettings)>, String> {
+    Ok(
+        resolve_output_audio_tracks(request, &extract_streams_by_type(streams, "audio"))?
+            .into_iter()
+            .filter(|(_, settings)| settings.mode == "transcode")
+            .collect(),
+    )
+}
+
+/// Check that ffmpeg has every filter the video chains need, including the
+/// tonemapping added automatically for HDR sources.
+async fn validate_video_filters(
+    ffmpeg_path: &str,
+    request: &TranscodeRequest,
+    streams: &[Value],
+) -> Result<(), String> {
+    let video_streams = extract_streams_by_type(streams, "video");
+    let primary_video_stream_index = video_streams
+        .iter()
+        .find(|stream| !is_attached_picture(stream))
+        .map(|stream| stream.stream_index);
+    let filters = video_streams
+        .iter()
+        .filter_map(|stream| {
+            let settings = resolve_video_settings_for_stream(
+                request,
+                stream,
+                primary_video_stream_index == Some(stream.stream_index),
+            );
+            (settings.mode == "transcode").then(|| {
+                let color_transfer = stream
+                    .probe_stream
+                    .get("color_transfer")
+                    .and_then(|value| value.as_str());
+                with_automatic_tonemap(
+                    &request.video.filters,
+                    color_transfer,
+                    settings.pixel_format.as_deref(),
+                )
+                .into_owned()
+            })
+        })
+        .collect::<Vec<_>>();
+    validate_video_filter_support(ffmpeg_path, &filters.iter().collect::<Vec<_>>()).await
+}
+
+/// Measure the track as the encode will see it: joined ranges go through the
+/// same audio-only `concat` graph before the measuring chain.
+fn build_loudnorm_measure_args(
+    request: &TranscodeRequest,
+    stream: &StreamInfo,
+    filter_graph: &str,
+) -> Vec<String> {
+    let mut args = vec!["-hide_banner".to_string(), "-nostats".to_string()];
+    args.extend(build_range_input_args(request));
+    if request.ranges.len() > 1 {
+        let concat_graph =
+            build_range_concat_filter(request.ranges.len(), false, &[stream.relative_index], None);
+        args.push("-filter_complex".to_string());
+        args.push(format!("{};[a0]{}[measured]", concat_graph, filter_graph));
+        args.push("-map".to_string());
+        args.push("[measured]".to_string());
+    } else {
+        args.push("-map".to_string());
+        args.push(format!("0:a:{}", stream.relative_index));
+        args.push("-af".to_string());
+        args.push(filter_graph.to_string());
     }
+    if let [range] = request.ranges.as_slice()
+        && let Some(end_ms) = range.end_ms
+    {
+        args.push("-t".to_string());
+        args.push(format_range_seconds(end_ms - range.start_ms));
+    }
+    for flag in ["-f", "null", "-"] {
+        args.push(flag.to_string());
+    }
+    args
+}
 
-    fn build_secondary_video_scenario(
-        encoder: &TranscodeVideoEncoderCapability,
-    ) -> Option<VideoRuntimeScenario> {
-        if encoder.id == "libaom-av1" {
-            return None;
-        }
+/// Check filter support and run the loudness measurement for two-pass
+/// `loudnorm` tracks; returns the measurements for the transcode context.
+async fn prepare_audio_filters(
+    ffmpeg_path: &str,
+    request: &TranscodeRequest,
+    streams: &[Value],
+) -> Result<Vec<(usize, Option<usize>, LoudnormMeasurement)>, String> {
+    let tracks = transcoded_audio_tracks(request, streams)?;
+    let filters = tracks
+        .iter()
+        .map(|(_, settings)| &settings.filters)
+        .collect::<Vec<_>>();
+    validate_audio_filter_support(ffmpeg_path, &filters).await?;
+
+    let mut measurements = Vec::new();
+    for (stream, settings) in &tracks {
+        let Some(filter_graph) =
+            build_loudnorm_measure_graph(&settings.filters, audio_filter_source(stream))?
+        else {
+            continue;
+        };
 
-        if encoder.supports_bitrate {
-            return Some(VideoRuntimeScenario {
-                name: "bitrate_gop",
-                quality_mode: Some("bitrate"),
-                crf: None,
-                qp: None,
-                bitrate_kbps: Some(preferred_video_bitrate_kbps(&encoder.codec)),
-                preset: encoder
-                    .supports_preset
-                    .then(|| preferred_video_preset(&encoder.id))
-                    .flatten(),
-                profile: None,
-                level: None,
-                pixel_format: None,
-                additional_args: vec![TranscodeAdditionalArg {
-                    _id: Some("gop".to_string()),
-                    flag: "-g".to_string(),
-                    value: Some("12".to_string()),
-                    enabled: true,
-                }],
-            });
+        let child = Command::new(ffmpeg_path)
+            .args(build_loudnorm_measure_args(request, stream, &filter_graph))
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|error| format!("Failed to start ffmpeg: {}", error))?;
+        if let Some(pid) = child.id()
+            && let Ok(mut guard) = super::state::TRANSCODE_PROCESS_IDS.lock()
+        {
+            guard.insert(request.input_path.clone(), pid);
         }
 
-        if encoder.supports_qp && !encoder.supports_crf {
-            return Some(VideoRuntimeScenario {
-                name: "qp_gop",
-                quality_mode: Some("qp"),
-                crf: None,
-                qp: Some(preferred_video_qp(&encoder.codec)),
-                bitrate_kbps: None,
-                preset: encoder
-                    .supports_preset
-                    .then(|| preferred_video_preset(&encoder.id))
-                    .flatten(),
-                profile: None,
-                level: None,
-                pixel_format: None,
-                additional_args: vec![TranscodeAdditionalArg {
-                    _id: Some("gop".to_string()),
-                    flag: "-g".to_string(),
-                    value: Some("12".to_string()),
-                    enabled: true,
-                }],
-            });
+        let output = child.wait_with_output().await;
+        if let Ok(mut guard) = super::state::TRANSCODE_PROCESS_IDS.lock() {
+            guard.remove(&request.input_path);
+        }
+        let output = output.map_err(|error| format!("Failed to run ffmpeg: {}", error))?;
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if !output.status.success() {
+            return Err(format!("Loudness measurement failed: {}", stderr.trim()));
         }
 
-        None
+        measurements.push((
+            stream.stream_index,
+            settings.derived_index,
+            parse_loudnorm_measurement(&stderr)?,
+        ));
     }
 
-    fn build_representative_video_scenarios(
-        encoder: &TranscodeVideoEncoderCapability,
-    ) -> Vec<VideoRuntimeScenario> {
-        let mut scenarios = vec![build_primary_video_scenario(encoder)];
-        if let Some(secondary) = build_secondary_video_scenario(encoder) {
-            scenarios.push(secondary);
-        }
-        scenarios
+    Ok(measurements)
+}
+
+/// Validate an external burn-in file and dump the source's fonts for it when needed.
+pub(crate) async fn prepare_burn_in_fonts(
+    ffmpeg_path: &str,
+    request: &TranscodeRequest,
+    streams: &[Value],
+) -> Result<Option<PathBuf>, String> {
+    let Some(burn_in) = request.burn_in.as_ref() else {
+        return Ok(None);
+    };
+    if let Some(external_path) = burn_in.external_path.as_deref() {
+        validate_media_path(external_path)?;
+    }
+    if !needs_extracted_fonts(burn_in, streams) {
+        return Ok(None);
     }
 
-    fn preferred_profile_value(encoder: &TranscodeVideoEncoderCapability) -> Option<String> {
-        let preferred = match encoder.codec.as_str() {
-            "h264" => &["high", "main", "baseline"][..],
-            "hevc" => &["main", "main10"][..],
-            "av1" => &["main"][..],
-            "prores" => &["standard", "hq", "proxy"][..],
-            _ => &[][..],
-        };
+    let fonts_dir = build_burn_in_fonts_dir(&request.input_path, &request.output_path);
+    extract_font_attachments(ffmpeg_path, &request.input_path, &fonts_dir).await?;
+    Ok(Some(fonts_dir))
+}
 
-        preferred
-            .iter()
-            .find_map(|candidate| {
-                encoder
-                    .supported_profiles
-                    .iter()
-                    .find(|value| value.eq_ignore_ascii_case(candidate))
-                    .cloned()
-            })
-            .or_else(|| encoder.supported_profiles.first().cloned())
+fn keeps_chapters(request: &TranscodeRequest) -> bool {
+    request.metadata.keep_chapters
+        && metadata_schema_for_container(&request.container_id).supports_chapters
+}
+
+/// Range join inputs as the dry run and stream plan see them, before anything is written.
+fn planned_range_join(request: &TranscodeRequest) -> RangeJoinInputs {
+    if request.ranges.len() < 2 {
+        return RangeJoinInputs::default();
     }
+    RangeJoinInputs::planned(
+        &request.input_path,
+        &request.output_path,
+        keeps_chapters(request),
+    )
+}
 
-    fn preferred_level_value(encoder: &TranscodeVideoEncoderCapability) -> Option<String> {
-        let preferred = ["4.1", "4.0", "5.0", "3.1", "3.0"];
+async fn prepare_range_join(
+    ffprobe_path: &str,
+    request: &TranscodeRequest,
+) -> Result<RangeJoinInputs, String> {
+    if request.ranges.len() < 2 {
+        return Ok(RangeJoinInputs::default());
+    }
+    prepare_range_join_inputs(
+        ffprobe_path,
+        &request.input_path,
+        &request.output_path,
+        &request.ranges,
+        keeps_chapters(request),
+    )
+    .await
+}
 
-        preferred
-            .iter()
-            .find_map(|candidate| {
-                encoder
-                    .supported_levels
-                    .iter()
-                    .find(|value| value == candidate)
-                    .cloned()
-            })
-            .or_else(|| encoder.supported_levels.first().cloned())
+/// Build the ffmpeg invocations for a request: one for a normal encode, two for two-pass.
+pub(crate) fn build_transcode_run_args(
+    request: &TranscodeRequest,
+    context: &TranscodeContext,
+    streams: &[Value],
+    duration_us: Option<u64>,
+    passlog_dir: &Path,
+) -> Result<Vec<Vec<String>>, String> {
+    let pass_args =
+        build_transcode_command_args(request, context, streams, duration_us, passlog_dir)?;
+    if pass_args.len() > 1 {
+        std::fs::create_dir_all(passlog_dir)
+            .map_err(|error| format!("Failed to create pass log directory: {}", error))?;
     }
+    Ok(pass_args)
+}
 
-    fn preferred_10bit_pixel_format(encoder: &TranscodeVideoEncoderCapability) -> Option<String> {
-        ["yuv420p10le", "p010le", "x2rgb10le", "ayuv64le"]
-            .iter()
-            .find_map(|candidate| {
-                encoder
-                    .supported_pixel_formats
-                    .iter()
-                    .find(|value| value.as_str() == *candidate)
-                    .cloned()
-            })
+/// Same as `build_transcode_run_args` without creating the pass log directory.
+pub(crate) fn build_transcode_command_args(
+    request: &TranscodeRequest,
+    context: &TranscodeContext,
+    streams: &[Value],
+    duration_us: Option<u64>,
+    passlog_dir: &Path,
+) -> Result<Vec<Vec<String>>, String> {
+    if !uses_two_pass_encoding(request, streams)? {
+        return Ok(vec![build_transcode_args(
+            request,
+            context,
+            streams,
+            duration_us,
+        )?]);
     }
 
-    fn build_advanced_video_capability_scenarios(
-        encoder: &TranscodeVideoEncoderCapability,
-    ) -> Vec<VideoRuntimeScenario> {
-        let mut scenarios = Vec::new();
+    build_transcode_passes(passlog_dir)
+        .iter()
+        .map(|video_pass| {
+            build_transcode_pass_args(request, context, streams, duration_us, Some(video_pass))
+        })
+        .collect()
+}
 
-        if !encoder.is_hardware
-            && (!encoder.supported_profiles.is_empty() || !encoder.supported_levels.is_empty())
-        {
-            let mut scenario = build_primary_video_scenario(encoder);
-            scenario.name = "profile_level";
-            scenario.profile = preferred_profile_value(encoder);
-            scenario.level = preferred_level_value(encoder);
-            if scenario.profile.is_some() || scenario.level.is_some() {
-                scenarios.push(scenario);
-            }
+/// Warnings shown next to a dry run: streams the encode drops or forces, and
+/// the steps that only happen when the job really runs.
+fn build_transcode_dry_run_warnings(
+    request: &TranscodeRequest,
+    streams: &[Value],
+) -> Result<Vec<String>, String> {
+    let mut warnings = build_subtitle_drop_warnings(request, streams);
+
+    for (stream, settings) in transcoded_audio_tracks(request, streams)? {
+        if should_force_libopus_mapping_family_255(&settings, &stream, &settings.additional_args) {
+            warnings.push(format!(
+                "Audio track {} ({}) is encoded with libopus mapping family 255 because its channel layout has no default mapping",
+                stream.stream_index,
+                stream.channel_layout.as_deref().unwrap_or_default()
+            ));
         }
-
-        if !encoder.is_hardware {
-            if let Some(pixel_format) = preferred_10bit_pixel_format(encoder) {
-                let mut scenario = build_primary_video_scenario(encoder);
-                scenario.name = "ten_bit";
-                scenario.pixel_format = Some(pixel_format);
-                scenario.profile = preferred_profile_value(encoder);
-                scenarios.push(scenario);
-            }
+        if build_loudnorm_measure_graph(&settings.filters, audio_filter_source(&stream))?.is_some()
+        {
+            warnings.push(format!(
+                "Audio track {} runs a loudness measurement first; without it the command falls back to single-pass loudnorm",
+                stream.stream_index
+            ));
         }
+    }
 
-        scenarios
+    if request.video.target_quality.is_some() {
+        warnings.push(
+            "The target quality CRF search is not run; the command uses the configured CRF"
+                .to_string(),
+        );
+    }
+    if request.video.chunked.is_some() {
+        warnings.push(
+            "Chunked encoding is shown as a single encode; the real job encodes the video in parallel chunks and muxes them"
+                .to_string(),
+        );
+    }
+    if request
+        .burn_in
+        .as_ref()
+        .is_some_and(|burn_in| needs_extracted_fonts(burn_in, streams))
+    {
+        warnings.push(
+            "Subtitle burn-in fonts are extracted from the input before encoding".to_string(),
+        );
     }
 
-    fn expected_video_codec_name(encoder_id: &str) -> &'static str {
-        match encoder_id {
-            "libx264" | "h264_videotoolbox" => "h264",
-            "libx265" | "hevc_videotoolbox" => "hevc",
-            "libsvtav1" | "libaom-av1" => "av1",
-            "libvpx-vp9" => "vp9",
-            "prores_ks" | "prores_videotoolbox" => "prores",
-            _ => "",
-        }
+    Ok(warnings)
+}
+
+async fn run_transcode_dry_run(
+    ffmpeg_path: &str,
+    ffprobe_path: &str,
+    request: &TranscodeRequest,
+) -> Result<FfmpegDryRun, String> {
+    validate_media_path(&request.input_path)?;
+    validate_output_path(&request.output_path)?;
+    validate_output_path_matches_container(request)?;
+
+    let probe_json = probe_file_with_ffprobe(ffprobe_path, &request.input_path).await?;
+    let probe_value: Value = serde_json::from_str(&probe_json)
+        .map_err(|error| format!("Invalid probe JSON: {}", error))?;
+    let mut streams = probe_value
+        .get("streams")
+        .and_then(|value| value.as_array())
+        .cloned()
+        .unwrap_or_default();
+    probe_hdr_frame_side_data(ffprobe_path, &request.input_path, &mut streams).await;
+    let request = &resolve_auto_video_modes(request, &streams);
+    let duration_us = get_media_duration_us_with_ffprobe(ffprobe_path, &request.input_path)
+        .await
+        .ok();
+    validate_transcode_ranges(&request.ranges, duration_us)?;
+    let duration_us = trimmed_duration_us(&request.ranges, duration_us);
+
+    let request = match plan_target_size(request, &streams, duration_us)? {
+        Some(plan) => request_with_video_bitrate(request, plan.video_bitrate_kbps),
+        None => request.clone(),
+    };
+    let (dolby_vision_supported, dolby_vision_warning) =
+        resolve_dolby_vision_support(ffmpeg_path, &request, &streams).await;
+    let context = TranscodeContext {
+        dolby_vision_supported,
+        range_join: planned_range_join(&request),
+        ..TranscodeContext::default()
+    };
+    let mut warnings = dolby_vision_warning.into_iter().collect::<Vec<_>>();
+    warnings.extend(build_transcode_dry_run_warnings(&request, &streams)?);
+
+    let command_args = if is_packaging_container(&request.container_id) {
+        let plan = plan_packaged_transcode(&request, &context, &streams, duration_us)?;
+        plan.rendition_args
+            .into_iter()
+            .flatten()
+            .chain(std::iter::once(plan.packaging_args))
+            .collect()
+    } else {
+        let passlog_dir = build_transcode_passlog_dir(&request.input_path, &request.output_path);
+        build_transcode_command_args(&request, &context, &streams, duration_us, &passlog_dir)?
+    };
+
+    Ok(build_dry_run(ffmpeg_path, command_args, warnings))
+}
+
+pub(crate) fn clear_transcode_state(input_path: &str) {
+    if let Ok(mut guard) = super::state::TRANSCODE_PROCESS_IDS.lock() {
+        guard.remove(input_path);
+    }
+    if let Ok(mut guard) = super::state::TRANSCODE_OUTPUT_PATHS.lock() {
+        guard.remove(input_path);
+    }
+    if let Ok(mut guard) = super::state::TRANSCODE_PASSLOG_DIRS.lock() {
+        guard.remove(input_path);
     }
+}
 
-    fn validate_transcoded_video_stream(
-        encoder_id: &str,
-        probed_stream: &ProbedVideoStream,
-    ) -> Option<String> {
-        let expected_codec_name = expected_video_codec_name(encoder_id);
-        if !expected_codec_name.is_empty() && probed_stream.codec_name != expected_codec_name {
-            return Some(format!(
-                "expected codec {}, got {}",
-                expected_codec_name, probed_stream.codec_name
-            ));
-        }
+/// A cancel removes the output path entry, which stops any remaining passes.
+pub(crate) fn is_transcode_tracked(input_path: &str) -> bool {
+    super::state::TRANSCODE_OUTPUT_PATHS
+        .lock()
+        .map(|guard| guard.contains_key(input_path))
+        .unwrap_or(false)
+}
 
-        if probed_stream.width != VIDEO_TEST_WIDTH || probed_stream.height != VIDEO_TEST_HEIGHT {
-            return Some(format!(
-                "expected resolution {}x{}, got {}x{}",
-                VIDEO_TEST_WIDTH, VIDEO_TEST_HEIGHT, probed_stream.width, probed_stream.height
-            ));
-        }
+pub(crate) fn ensure_transcode_tracked(input_path: &str) -> Result<(), String> {
+    if is_transcode_tracked(input_path) {
+        Ok(())
+    } else {
+        Err("Transcode cancelled".to_string())
+    }
+}
 
-        None
+pub(crate) async fn run_transcode_pass(
+    events: &impl TranscodeEvents,
+    ffmpeg_path: &str,
+    request: &TranscodeRequest,
+    args: &[String],
+    duration_us: Option<u64>,
+    phase: TranscodePhase,
+) -> Result<(), String> {
+    let mut child = Command::new(ffmpeg_path)
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|error| format!("Failed to start ffmpeg: {}", error))?;
+
+    if let Some(pid) = child.id()
+        && let Ok(mut guard) = super::state::TRANSCODE_PROCESS_IDS.lock()
+    {
+        guard.insert(request.input_path.clone(), pid);
     }
 
-    fn validate_transcoded_video_output(
-        encoder: &TranscodeVideoEncoderCapability,
-        container_id: &str,
-        scenario: &VideoRuntimeScenario,
-        probed_stream: &ProbedVideoStream,
-    ) -> Option<String> {
-        if let Some(error) = validate_transcoded_video_stream(&encoder.id, probed_stream) {
-            return Some(error);
-        }
+    if let Some(stdout) = child.stdout.take() {
+        let events_for_progress = events.clone();
+        let input_path_for_progress = request.input_path.clone();
+        let output_path_for_progress = request.output_path.clone();
 
-        if let Some(expected_profile) = scenario.profile.as_deref() {
-            let Some(actual_profile) = probed_stream.profile.as_deref() else {
-                return Some(format!(
-                    "expected profile {}, but ffprobe did not report one",
-                    expected_profile
-                ));
-            };
+        tokio::spawn(async move {
+            let mut tracker = FfmpegProgressTracker::new(duration_us);
+            let mut last_progress = 0;
+            let reader = BufReader::new(stdout);
+            let mut lines = reader.lines();
 
-            if normalize_comparable_value(actual_profile)
-                != normalize_comparable_value(expected_profile)
-            {
-                return Some(format!(
-                    "expected profile {}, got {}",
-                    expected_profile, actual_profile
-                ));
-            }
-        }
+            while let Ok(Some(line)) = lines.next_line().await {
+                if let Some(update) = tracker.handle_line(&line) {
+                    if let Some(progress) = update.progress {
+                        last_progress = progress;
+                    }
 
-        if let Some(expected_pixel_format) = scenario.pixel_format.as_deref() {
-            if probed_stream.pixel_format.as_deref() != Some(expected_pixel_format) {
-                return Some(format!(
-                    "expected pixel format {}, got {:?}",
-                    expected_pixel_format, probed_stream.pixel_format
-                ));
+                    emit_transcode_progress(
+                        &events_for_progress,
+                        &input_path_for_progress,
+                        &output_path_for_progress,
+                        phase.overall_progress(last_progress),
+                        update.speed_bytes_per_sec,
+                        phase,
+                    );
+                }
             }
-        }
+        });
+    }
 
-        if matches!(container_id, "mp4" | "mov") && encoder.id.starts_with("hevc") {
-            if probed_stream.codec_tag_string.as_deref() != Some("hvc1") {
-                return Some(format!(
-                    "expected codec tag hvc1, got {:?}",
-                    probed_stream.codec_tag_string
-                ));
+    let child_pid = child.id();
+    let output = match timeout(TRANSCODE_TIMEOUT, child.wait_with_output()).await {
+        Ok(result) => result.map_err(|error| format!("Failed to execute ffmpeg: {}", error))?,
+        Err(_) => {
+            if let Some(pid) = child_pid {
+                terminate_process(pid);
+                tokio::time::sleep(Duration::from_millis(200)).await;
             }
+            return Err(format!(
+                "Transcode timeout after {} seconds",
+                TRANSCODE_TIMEOUT.as_secs()
+            ));
         }
+    };
 
-        None
+    if let Ok(mut guard) = super::state::TRANSCODE_PROCESS_IDS.lock() {
+        guard.remove(&request.input_path);
     }
 
-    fn select_representative_video_encoders<'a>(
-        capabilities: &'a TranscodeCapabilities,
-    ) -> Vec<&'a TranscodeVideoEncoderCapability> {
-        let preferred_ids = [
-            "libx264",
-            "h264_videotoolbox",
-            "libx265",
-            "hevc_videotoolbox",
-            "libsvtav1",
-            "libaom-av1",
-            "libvpx",
-            "libvpx-vp9",
-            "prores_ks",
-            "prores_videotoolbox",
-        ];
-
-        preferred_ids
-            .iter()
-            .filter_map(|encoder_id| {
-                capabilities
-                    .video_encoders
-                    .iter()
-                    .find(|encoder| encoder.id == *encoder_id)
-            })
-            .filter(|encoder| !should_skip_runtime_video_encoder(&encoder.id))
-            .collect()
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("Transcode failed: {}", stderr.trim()));
     }
 
-    fn should_skip_runtime_video_encoder(encoder_id: &str) -> bool {
-        should_skip_videotoolbox_encoder_in_ci(encoder_id, ci_env_enabled())
+    Ok(())
+}
+
+/// Run every pass of one encode, tracking it for cancellation; returns the pass
+/// count including `completed_passes` already reported for this job.
+async fn run_transcode_passes(
+    events: &impl TranscodeEvents,
+    ffmpeg_path: &str,
+    request: &TranscodeRequest,
+    context: &TranscodeContext,
+    streams: &[Value],
+    duration_us: Option<u64>,
+    completed_passes: u8,
+) -> Result<u8, String> {
+    let passlog_dir = build_transcode_passlog_dir(&request.input_path, &request.output_path);
+    let pass_args = build_transcode_run_args(request, context, streams, duration_us, &passlog_dir)?;
+    let encode_passes = pass_args.len() as u8;
+    let pass_count = completed_passes + encode_passes;
+
+    if let Ok(mut guard) = super::state::TRANSCODE_OUTPUT_PATHS.lock() {
+        guard.insert(request.input_path.clone(), request.output_path.clone());
+    }
+    if encode_passes > 1
+        && let Ok(mut guard) = super::state::TRANSCODE_PASSLOG_DIRS.lock()
+    {
+        guard.insert(
+            request.input_path.clone(),
+            passlog_dir.to_string_lossy().to_string(),
+        );
     }
 
-    fn should_skip_videotoolbox_encoder_in_ci(encoder_id: &str, ci_enabled: bool) -> bool {
-        ci_enabled && is_videotoolbox_encoder(encoder_id)
+    let first_phase = TranscodePhase {
+        pass: completed_passes + 1,
+        pass_count,
+    };
+    emit_transcode_progress(
+        events,
+        &request.input_path,
+        &request.output_path,
+        first_phase.overall_progress(0),
+        None,
+        first_phase,
+    );
+
+    let mut result = Ok(());
+    for (index, args) in pass_args.iter().enumerate() {
+        if !is_transcode_tracked(&request.input_path) {
+            result = Err("Transcode cancelled".to_string());
+            break;
+        }
+
+        let phase = TranscodePhase {
+            pass: completed_passes + index as u8 + 1,
+            pass_count,
+        };
+        result = run_transcode_pass(events, ffmpeg_path, request, args, duration_us, phase).await;
+        if result.is_err() {
+            break;
+        }
     }
 
-    fn is_videotoolbox_encoder(encoder_id: &str) -> bool {
-        matches!(
-            encoder_id,
-            "h264_videotoolbox" | "hevc_videotoolbox" | "prores_videotoolbox"
-        )
+    clear_transcode_state(&request.input_path);
+    if encode_passes > 1 {
+        let _ = std::fs::remove_dir_all(&passlog_dir);
     }
 
-    fn ci_env_enabled() -> bool {
-        std::env::var("CI").is_ok_and(|value| value.eq_ignore_ascii_case("true"))
+    if let Err(error) = result {
+        let _ = std::fs::remove_file(&request.output_path);
+        return Err(error);
     }
 
-    fn select_runtime_video_encoder_for_container<'a>(
-        capabilities: &'a TranscodeCapabilities,
-        container: &TranscodeContainerCapability,
-    ) -> Option<&'a TranscodeVideoEncoderCapability> {
-        select_runtime_video_encoder_for_container_with_ci(
-            capabilities,
-            container,
-            ci_env_enabled(),
-        )
+    if !Path::new(&request.output_path).exists() {
+        return Err("Transcode failed: output file not created".to_string());
     }
 
-    fn select_runtime_video_encoder_for_container_with_ci<'a>(
-        capabilities: &'a TranscodeCapabilities,
-        container: &TranscodeContainerCapability,
-        ci_enabled: bool,
-    ) -> Option<&'a TranscodeVideoEncoderCapability> {
+    Ok(pass_count)
+}
+
+/// Score a finished transcode against its input; failures are reported as warnings.
+async fn compare_transcode_quality(
+    events: &impl TranscodeEvents,
+    ffmpeg_path: &str,
+    ffprobe_path: &str,
+    request: &TranscodeRequest,
+) {
+    let (reference_start_ms, reference_end_ms) = match request.ranges.as_slice() {
+        [] => (None, None),
+        [range] => (Some(range.start_ms), range.end_ms),
+        _ => {
+            emit_transcode_warning(
+                events,
+                request,
+                "Quality comparison is not available when several ranges are joined",
+            );
+            return;
+        }
+    };
+    let quality_request = QualityComparisonRequest {
+        reference_path: request.input_path.clone(),
+        distorted_path: request.output_path.clone(),
+        reference_start_ms,
+        reference_end_ms,
+    };
+
+    let events_for_progress = events.clone();
+    let request_for_progress = quality_request.clone();
+    match run_quality_comparison(
+        ffmpeg_path,
+        ffprobe_path,
+        &quality_request,
+        QualityProcessOwner::Transcode(&request.input_path),
+        move |progress| {
+            emit_quality_progress(&events_for_progress, &request_for_progress, progress);
+        },
+    )
+    .await
+    {
+        Ok(result) => {
+            events.emit_event(
+                "media-transcode-quality",
+                serde_json::json!({
+                    "inputPath": request.input_path,
+                    "outputPath": request.output_path,
+                    "result": result
+                }),
+            );
+        }
+        Err(error) => emit_transcode_warning(
+            events,
+            request,
+            &format!("Could not compare quality: {}", error),
+        ),
+    }
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+pub(crate) async fn transcode_media_with_bins(
+    ffmpeg_path: &str,
+    ffprobe_path: &str,
+    request: &TranscodeRequest,
+) -> Result<String, String> {
+    run_transcode(&NoTranscodeEvents, ffmpeg_path, ffprobe_path, request).await
+}
+
+/// Probe, plan and encode one request, reporting through `events`; shared by
+/// the app command and the `_with_bins` entry point.
+async fn run_transcode(
+    events: &impl TranscodeEvents,
+    ffmpeg_path: &str,
+    ffprobe_path: &str,
+    request: &TranscodeRequest,
+) -> Result<String, String> {
+    validate_media_path(&request.input_path)?;
+    validate_output_path(&request.output_path)?;
+    validate_output_path_matches_container(request)?;
+    request.verification.failure_action()?;
+    validate_packaging_request(request)?;
+
+    let probe_json = probe_file_with_ffprobe(ffprobe_path, &request.input_path).await?;
+    let probe_value: Value = serde_json::from_str(&probe_json)
+        .map_err(|error| format!("Invalid probe JSON: {}", error))?;
+    let mut streams = probe_value
+        .get("streams")
+        .and_then(|value| value.as_array())
+        .cloned()
+        .unwrap_or_default();
+    probe_hdr_frame_side_data(ffprobe_path, &request.input_path, &mut streams).await;
+    let request = resolve_auto_video_modes(request, &streams);
+    let duration_us = get_media_duration_us_with_ffprobe(ffprobe_path, &request.input_path)
+        .await
+        .ok();
+    validate_transcode_ranges(&request.ranges, duration_us)?;
+    let duration_us = trimmed_duration_us(&request.ranges, duration_us);
+
+    let target_size_plan = plan_target_size(&request, &streams, duration_us)?;
+    validate_video_filters(ffmpeg_path, &request, &streams).await?;
+    let loudnorm_measurements = prepare_audio_filters(ffmpeg_path, &request, &streams).await?;
+    let (dolby_vision_supported, dolby_vision_warning) =
+        resolve_dolby_vision_support(ffmpeg_path, &request, &streams).await;
+    let context = TranscodeContext {
+        dolby_vision_supported,
+        loudnorm_measurements,
+        ..TranscodeContext::default()
+    };
+    if let Some(warning) = dolby_vision_warning {
+        emit_transcode_warning(events, &request, &warning);
+    }
+    for warning in build_subtitle_drop_warnings(&request, &streams) {
+        emit_transcode_warning(events, &request, &warning);
+    }
+    if is_packaging_container(&request.container_id) {
+        let context = TranscodeContext {
+            range_join: prepare_range_join(ffprobe_path, &request).await?,
+            ..context
+        };
+        let result = run_packaged_transcode(
+            events,
+            ffmpeg_path,
+            ffprobe_path,
+            &request,
+            &context,
+            &streams,
+            duration_us,
+        )
+        .await;
+        let _ = std::fs::remove_dir_all(build_range_join_dir(
+            &request.input_path,
+            &request.output_path,
+        ));
+        result?;
+        return Ok(request.output_path);
+    }
+    let target_quality_plan = plan_target_quality(
+        ffmpeg_path,
+        ffprobe_path,
+        &request,
+        &context,
+        &streams,
+        duration_us,
+        |metric, sample| {
+            events.emit_event(
+                "media-transcode-quality-search",
+                serde_json::json!({
+                    "inputPath": request.input_path,
+                    "outputPath": request.output_path,
+                    "metric": metric.as_str(),
+                    "crf": sample.crf,
+                    "score": sample.score
+                }),
+            );
+        },
+    )
+    .await?;
+    let request = match target_quality_plan.as_ref() {
+        Some(plan) => {
+            if !plan.reached {
+                emit_transcode_warning(
+                    events,
+                    &request,
+                    &format!(
+                        "No CRF reached the target {} of {}; using CRF {} (score {:.2})",
+                        plan.metric.as_str().to_uppercase(),
+                        plan.target_score,
+                        plan.sample.crf,
+                        plan.sample.score
+                    ),
+                );
+            }
+            request_with_video_crf(&request, plan.sample.crf)
+        }
+        None => request,
+    };
+    let expected_streams = expected_output_streams(&request, &streams)?;
+    let chunk_dir = build_chunk_dir(&request.input_path, &request.output_path);
+    let chunk_progress_events = events.clone();
+    let chunk_input_path = request.input_path.clone();
+    let chunk_output_path = request.output_path.clone();
+    let chunked_mux = encode_chunked_video(
+        ffmpeg_path,
+        ffprobe_path,
+        &request,
+        &context,
+        &streams,
+        &chunk_dir,
+        move |progress| {
+            let phase = TranscodePhase {
+                pass: 1,
+                pass_count: 2,
+            };
+            emit_transcode_progress(
+                &chunk_progress_events,
+                &chunk_input_path,
+                &chunk_output_path,
+                phase.overall_progress(progress),
+                None,
+                phase,
+            );
+        },
+    )
+    .await
+    .inspect_err(|_| {
+        let _ = std::fs::remove_dir_all(&chunk_dir);
+    })?;
+    let completed_passes = u8::from(chunked_mux.is_some());
+    let (request, context) = chunked_mux.unwrap_or((request, context));
+    let mut video_bitrate_kbps = target_size_plan.map(|plan| plan.video_bitrate_kbps);
+    let mut attempts = 1u8;
+
+    // Chunked encoding rejects ranges, so there is no chunk folder to clean up here.
+    let context = TranscodeContext {
+        range_join: prepare_range_join(ffprobe_path, &request).await?,
+        ..context
+    };
+    let range_join_dir = build_range_join_dir(&request.input_path, &request.output_path);
+    let fonts_dir = prepare_burn_in_fonts(ffmpeg_path, &request, &streams).await?;
+    let result = async {
+        loop {
+            let attempt_request = match video_bitrate_kbps {
+                Some(bitrate_kbps) => request_with_video_bitrate(&request, bitrate_kbps),
+                None => request.clone(),
+            };
+            let pass_count = run_transcode_passes(
+                events,
+                ffmpeg_path,
+                &attempt_request,
+                &context,
+                &streams,
+                duration_us,
+                completed_passes,
+            )
+            .await?;
+
+            let (Some(plan), Some(bitrate_kbps)) = (target_size_plan.as_ref(), video_bitrate_kbps)
+            else {
+                break Ok::<u8, String>(pass_count);
+            };
+            let actual_bytes = std::fs::metadata(&request.output_path)
+                .map(|metadata| metadata.len())
+                .unwrap_or_default();
+
+            if attempts == 1
+                && request.video.retry_target_size
+                && let Some(retry_kbps) = retry_video_bitrate_kbps(plan, bitrate_kbps, actual_bytes)
+            {
+                attempts += 1;
+                video_bitrate_kbps = Some(retry_kbps);
+                continue;
+            }
+
+            emit_transcode_target_size_report(
+                events,
+                &request,
+                plan,
+                bitrate_kbps,
+                actual_bytes,
+                attempts,
+            );
+            break Ok(pass_count);
+        }
+    }
+    .await;
+    let _ = std::fs::remove_dir_all(&chunk_dir);
+    let _ = std::fs::remove_dir_all(&range_join_dir);
+    if let Some(fonts_dir) = fonts_dir {
+        let _ = std::fs::remove_dir_all(fonts_dir);
+    }
+    let pass_count = result?;
+
+    emit_transcode_progress(
+        events,
+        &request.input_path,
+        &request.output_path,
+        100,
+        None,
+        TranscodePhase {
+            pass: pass_count,
+            pass_count,
+        },
+    );
+
+    if let Some(plan) = target_quality_plan.as_ref() {
+        let actual_bytes = std::fs::metadata(&request.output_path)
+            .map(|metadata| metadata.len())
+            .unwrap_or_default();
+        emit_transcode_target_quality_report(events, &request, plan, actual_bytes);
+    }
+
+    let verification =
+        verify_transcode_output(ffprobe_path, &request, &expected_streams, duration_us).await?;
+    events.emit_event(
+        "media-transcode-verification",
+        serde_json::json!({
+            "inputPath": request.input_path,
+            "outputPath": request.output_path,
+            "report": verification
+        }),
+    );
+    if let Some(error) = verification_failure(&verification) {
+        return Err(error);
+    }
+
+    if request.compare_quality {
+        compare_transcode_quality(events, ffmpeg_path, ffprobe_path, &request).await;
+    }
+
+    Ok(request.output_path)
+}
+
+#[tauri::command]
+pub(crate) async fn transcode_media(
+    app: tauri::AppHandle,
+    request: TranscodeRequest,
+) -> Result<String, String> {
+    let _sleep_guard = SleepInhibitGuard::try_acquire("Media transcoding").ok();
+    let ffmpeg_path = resolve_ffmpeg_path(&app)?;
+    let ffprobe_path = resolve_ffprobe_path(&app)?;
+
+    run_transcode(&app, &ffmpeg_path, &ffprobe_path, &request).await
+}
+
+async fn run_transcode_stream_plan(
+    ffprobe_path: &str,
+    request: &TranscodeRequest,
+) -> Result<Vec<TranscodeStreamPlan>, String> {
+    validate_media_path(&request.input_path)?;
+    validate_output_path_matches_container(request)?;
+
+    let probe_json = probe_file_with_ffprobe(ffprobe_path, &request.input_path).await?;
+    let probe_value: Value = serde_json::from_str(&probe_json)
+        .map_err(|error| format!("Invalid probe JSON: {}", error))?;
+    let streams = probe_value
+        .get("streams")
+        .and_then(|value| value.as_array())
+        .cloned()
+        .unwrap_or_default();
+
+    build_transcode_stream_plan(request, &streams)
+}
+
+/// Return the ffmpeg commands a transcode would run, without running them.
+#[tauri::command]
+pub(crate) async fn dry_run_transcode(
+    app: tauri::AppHandle,
+    request: TranscodeRequest,
+) -> Result<FfmpegDryRun, String> {
+    let ffmpeg_path = resolve_ffmpeg_path(&app)?;
+    let ffprobe_path = resolve_ffprobe_path(&app)?;
+
+    run_transcode_dry_run(&ffmpeg_path, &ffprobe_path, &request).await
+}
+
+/// What happens to each stream, with `auto` modes settled, before running.
+#[tauri::command]
+pub(crate) async fn plan_transcode_streams(
+    app: tauri::AppHandle,
+    request: TranscodeRequest,
+) -> Result<Vec<TranscodeStreamPlan>, String> {
+    let ffprobe_path = resolve_ffprobe_path(&app)?;
+
+    run_transcode_stream_plan(&ffprobe_path, &request).await
+}
+
+/// Encode a few short samples with the request's settings and estimate the
+/// final size and encoding time.
+#[tauri::command]
+pub(crate) async fn preview_transcode(
+    app: tauri::AppHandle,
+    request: TranscodeRequest,
+    sample_count: Option<usize>,
+    sample_seconds: Option<u32>,
+) -> Result<TranscodePreviewEstimate, String> {
+    let ffmpeg_path = resolve_ffmpeg_path(&app)?;
+    let ffprobe_path = resolve_ffprobe_path(&app)?;
+
+    run_transcode_preview(
+        &ffmpeg_path,
+        &ffprobe_path,
+        &request,
+        sample_count,
+        sample_seconds,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use serde_json::{Value, json};
+
+    use crate::test_support::audio::{
+        ProbedAudioStream, generate_silence_wav, probe_audio_encoder_runtime_info,
+        probe_primary_audio_stream,
+    };
+    use crate::test_support::ffmpeg::{ffmpeg_path, ffprobe_path};
+    use crate::test_support::paths::new_temp_dir;
+    use crate::test_support::video::{
+        MediaStreamCounts, ProbedVideoStream, generate_test_pattern_av_mp4,
+        generate_test_pattern_video, probe_media_stream_counts, probe_primary_video_stream,
+    };
+    use crate::tools::ffprobe::probe::probe_file_with_ffprobe;
+    use crate::tools::transcode::audio_filters::{
+        TranscodeAudioFilters, TranscodeDownmixFilter, TranscodeGainFilter,
+    };
+    use crate::tools::transcode::burn_in::TranscodeSubtitleBurnIn;
+    use crate::tools::transcode::capabilities::{
+        TranscodeAudioEncoderCapability, TranscodeCapabilities, TranscodeContainerCapability,
+        TranscodeVideoEncoderCapability, get_transcode_capabilities_with_ffmpeg_path,
+    };
+    use crate::tools::transcode::chunked::{
+        TranscodeChunkedEncoding, build_chunk_request, request_for_chunked_mux,
+        validate_chunked_request,
+    };
+    use crate::tools::transcode::filters::{TranscodeScaleFilter, TranscodeVideoFilters};
+    use crate::tools::transcode::packaging::{
+        TranscodePackagingSettings, TranscodeRendition, plan_packaged_transcode,
+    };
+    use crate::tools::transcode::preview::{
+        build_preview_sample_request, preview_transcode_with_bins,
+    };
+    use crate::tools::transcode::target_quality::build_sample_request;
+    use crate::tools::transcode::verify::{ExpectedOutputStream, TranscodeVerificationSettings};
+
+    use crate::tools::media_metadata::{
+        MediaMetadataRequest, TrackMetadataEdit, metadata_schema_for_container,
+    };
+
+    use super::{
+        RangeJoinInputs, TargetSizePlan, TranscodeAdditionalArg, TranscodeAudioSettings,
+        TranscodeAudioTrackOverride, TranscodeContext, TranscodeDerivedAudioTrack, TranscodePhase,
+        TranscodeRequest, TranscodeSubtitleSettings, TranscodeSubtitleTrackOverride,
+        TranscodeTimeRange, TranscodeVideoSettings, TranscodeVideoTrackOverride,
+        build_loudnorm_measure_args, build_subtitle_drop_warnings, build_transcode_args,
+        build_transcode_command_args, build_transcode_dry_run_warnings, build_transcode_run_args,
+        build_transcode_stream_plan, cpu_used_preset_max, escape_x265_param_value,
+        expected_output_streams, extract_streams_by_type, plan_target_size,
+        request_with_video_bitrate, resolve_auto_video_modes, retry_video_bitrate_kbps,
+        transcode_media_with_bins, trimmed_duration_us, validate_transcode_ranges,
+    };
+
+    const AUDIO_LAYOUT_CASES: &[(&str, u64)] = &[
+        ("mono", 1),
+        ("stereo", 2),
+        ("4.0", 4),
+        ("5.1", 6),
+        ("5.1(side)", 6),
+        ("7.1", 8),
+        ("7.1(wide-side)", 8),
+    ];
+    const VIDEO_TEST_WIDTH: u64 = 160;
+    const VIDEO_TEST_HEIGHT: u64 = 90;
+
+    fn build_request(output_path: &str) -> TranscodeRequest {
+        TranscodeRequest {
+            input_path: "/tmp/input.mp4".to_string(),
+            output_path: output_path.to_string(),
+            container_id: "mp4".to_string(),
+            video: TranscodeVideoSettings {
+                mode: "transcode".to_string(),
+                encoder_id: Some("libx264".to_string()),
+                profile: Some("high".to_string()),
+                level: Some("4.1".to_string()),
+                pixel_format: Some("yuv420p".to_string()),
+                quality_mode: Some("crf".to_string()),
+                crf: Some(20.0),
+                qp: None,
+                bitrate_kbps: None,
+                preset: Some("medium".to_string()),
+                two_pass: false,
+                target_size_mb: None,
+                retry_target_size: false,
+                target_quality: None,
+                chunked: None,
+                keep_dolby_vision: false,
+                filters: TranscodeVideoFilters::default(),
+                additional_args: Vec::new(),
+                track_overrides: Vec::new(),
+                map_all_streams: false,
+            },
+            audio: TranscodeAudioSettings {
+                mode: "transcode".to_string(),
+                encoder_id: Some("aac".to_string()),
+                bitrate_kbps: Some(160),
+                channels: Some(2),
+                sample_rate: Some(48000),
+                additional_args: Vec::new(),
+                track_overrides: Vec::new(),
+                filters: TranscodeAudioFilters::default(),
+                derived_tracks: Vec::new(),
+            },
+            subtitles: TranscodeSubtitleSettings {
+                mode: "convert_text".to_string(),
+                encoder_id: Some("mov_text".to_string()),
+                additional_args: Vec::new(),
+                track_overrides: Vec::new(),
+            },
+            metadata: MediaMetadataRequest::default(),
+            ranges: Vec::new(),
+            stream_order: Vec::new(),
+            burn_in: None,
+            compare_quality: false,
+            verification: TranscodeVerificationSettings::default(),
+            packaging: TranscodePackagingSettings::default(),
+        }
+    }
+
+    fn build_audio_only_request(
+        input_path: &Path,
+        output_path: &Path,
+        container_id: &str,
+        encoder: &TranscodeAudioEncoderCapability,
+    ) -> TranscodeRequest {
+        let mut request = build_request(output_path.to_string_lossy().as_ref());
+        request.input_path = input_path.to_string_lossy().to_string();
+        request.output_path = output_path.to_string_lossy().to_string();
+        request.container_id = container_id.to_string();
+
+        request.video.mode = "disable".to_string();
+        request.video.encoder_id = None;
+        request.video.profile = None;
+        request.video.level = None;
+        request.video.pixel_format = None;
+        request.video.quality_mode = None;
+        request.video.crf = None;
+        request.video.qp = None;
+        request.video.bitrate_kbps = None;
+        request.video.preset = None;
+        request.video.additional_args = Vec::new();
+
+        apply_audio_transcode_settings(&mut request, encoder);
+
+        request.subtitles.mode = "disable".to_string();
+        request.subtitles.encoder_id = None;
+        request.subtitles.additional_args = Vec::new();
+
+        request
+    }
+
+    fn build_video_only_request(
+        input_path: &Path,
+        output_path: &Path,
+        container_id: &str,
+        encoder: &TranscodeVideoEncoderCapability,
+        scenario: &VideoRuntimeScenario,
+    ) -> TranscodeRequest {
+        let mut request = build_request(output_path.to_string_lossy().as_ref());
+        request.input_path = input_path.to_string_lossy().to_string();
+        request.output_path = output_path.to_string_lossy().to_string();
+        request.container_id = container_id.to_string();
+
+        apply_video_transcode_settings(&mut request, encoder, scenario);
+
+        request.audio.mode = "disable".to_string();
+        request.audio.encoder_id = None;
+        request.audio.bitrate_kbps = None;
+        request.audio.channels = None;
+        request.audio.sample_rate = None;
+        request.audio.additional_args = Vec::new();
+        request.audio.track_overrides = Vec::new();
+
+        request.subtitles.mode = "disable".to_string();
+        request.subtitles.encoder_id = None;
+        request.subtitles.additional_args = Vec::new();
+
+        request
+    }
+
+    fn build_av_request(
+        input_path: &Path,
+        output_path: &Path,
+        container_id: &str,
+    ) -> TranscodeRequest {
+        let mut request = build_request(output_path.to_string_lossy().as_ref());
+        request.input_path = input_path.to_string_lossy().to_string();
+        request.output_path = output_path.to_string_lossy().to_string();
+        request.container_id = container_id.to_string();
+        request.subtitles.mode = "disable".to_string();
+        request.subtitles.encoder_id = None;
+        request.subtitles.additional_args = Vec::new();
+        request
+    }
+
+    fn apply_video_transcode_settings(
+        request: &mut TranscodeRequest,
+        encoder: &TranscodeVideoEncoderCapability,
+        scenario: &VideoRuntimeScenario,
+    ) {
+        request.video.mode = "transcode".to_string();
+        request.video.encoder_id = Some(encoder.id.clone());
+        request.video.profile = scenario.profile.clone();
+        request.video.level = scenario.level.clone();
+        request.video.pixel_format = scenario
+            .pixel_format
+            .clone()
+            .or_else(|| preferred_video_pixel_format(encoder));
+        request.video.quality_mode = scenario.quality_mode.map(|value| value.to_string());
+        request.video.crf = scenario.crf;
+        request.video.qp = scenario.qp;
+        request.video.bitrate_kbps = scenario.bitrate_kbps;
+        request.video.preset = scenario.preset.as_ref().map(|value| value.to_string());
+        request.video.additional_args = scenario.additional_args.clone();
+    }
+
+    fn apply_audio_transcode_settings(
+        request: &mut TranscodeRequest,
+        encoder: &TranscodeAudioEncoderCapability,
+    ) {
+        request.audio.mode = "transcode".to_string();
+        request.audio.encoder_id = Some(encoder.id.clone());
+        request.audio.bitrate_kbps = audio_bitrate_for_encoder(&encoder.id);
+        request.audio.channels = None;
+        request.audio.sample_rate = None;
+        request.audio.additional_args = Vec::new();
+        request.audio.track_overrides = Vec::new();
+    }
+
+    fn audio_bitrate_for_encoder(encoder_id: &str) -> Option<u32> {
+        match encoder_id {
+            "flac" | "pcm_s16le" => None,
+            "libmp3lame" => Some(192),
+            _ => Some(160),
+        }
+    }
+
+    fn pick_audio_test_container_id(
+        capabilities: &TranscodeCapabilities,
+        encoder_id: &str,
+    ) -> Option<String> {
+        let preferred_container_id = match encoder_id {
+            "aac" | "aac_at" => Some("aac"),
+            "libopus" => Some("opus"),
+            "libvorbis" => Some("ogg"),
+            "libmp3lame" => Some("mp3"),
+            "flac" => Some("flac"),
+            "pcm_s16le" => Some("wav"),
+            _ => None,
+        };
+
+        if let Some(container_id) = preferred_container_id.filter(|container_id| {
+            capabilities.containers.iter().any(|container| {
+                container.kind == "audio"
+                    && container.id == *container_id
+                    && container
+                        .supported_audio_encoder_ids
+                        .iter()
+                        .any(|supported_id| supported_id == encoder_id)
+            })
+        }) {
+            return Some(container_id.to_string());
+        }
+
+        capabilities
+            .containers
+            .iter()
+            .find(|container| {
+                container.kind == "audio"
+                    && container
+                        .supported_audio_encoder_ids
+                        .iter()
+                        .any(|supported_id| supported_id == encoder_id)
+            })
+            .map(|container| container.id.clone())
+    }
+
+    fn pick_video_test_container_id(
+        capabilities: &TranscodeCapabilities,
+        encoder_id: &str,
+    ) -> Option<String> {
+        let preferred_container_id = match encoder_id {
+            "libx264" | "h264_videotoolbox" => Some("mp4"),
+            "libx265" | "hevc_videotoolbox" => Some("mp4"),
+            "libsvtav1" | "libaom-av1" | "libvpx" | "libvpx-vp9" => Some("webm"),
+            "prores_ks" | "prores_videotoolbox" => Some("mov"),
+            _ => None,
+        };
+
+        if let Some(container_id) = preferred_container_id.filter(|container_id| {
+            capabilities.containers.iter().any(|container| {
+                container.kind == "video"
+                    && container.id == *container_id
+                    && container
+                        .supported_video_encoder_ids
+                        .iter()
+                        .any(|supported_id| supported_id == encoder_id)
+            })
+        }) {
+            return Some(container_id.to_string());
+        }
+
+        capabilities
+            .containers
+            .iter()
+            .find(|container| {
+                container.kind == "video"
+                    && container
+                        .supported_video_encoder_ids
+                        .iter()
+                        .any(|supported_id| supported_id == encoder_id)
+            })
+            .map(|container| container.id.clone())
+    }
+
+    fn sanitize_case_id(value: &str) -> String {
+        value
+            .chars()
+            .map(|character| {
+                if character.is_ascii_alphanumeric() {
+                    character.to_ascii_lowercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect()
+    }
+
+    fn expected_output_channels(encoder_id: &str, input_channels: u64) -> u64 {
+        if encoder_id == "libmp3lame" && input_channels > 2 {
+            2
+        } else {
+            input_channels
+        }
+    }
+
+    fn expected_audio_codec_name(encoder_id: &str) -> &'static str {
+        match encoder_id {
+            "aac" | "aac_at" => "aac",
+            "libopus" => "opus",
+            "libvorbis" => "vorbis",
+            "libmp3lame" => "mp3",
+            "flac" => "flac",
+            "pcm_s16le" => "pcm_s16le",
+            _ => "",
+        }
+    }
+
+    fn validate_transcoded_audio_stream(
+        encoder_id: &str,
+        input_channels: u64,
+        probed_stream: &ProbedAudioStream,
+    ) -> Option<String> {
+        let expected_codec_name = expected_audio_codec_name(encoder_id);
+        if !expected_codec_name.is_empty() && probed_stream.codec_name != expected_codec_name {
+            return Some(format!(
+                "expected codec {}, got {}",
+                expected_codec_name, probed_stream.codec_name
+            ));
+        }
+
+        let expected_channels = expected_output_channels(encoder_id, input_channels);
+        if probed_stream.channels != expected_channels {
+            return Some(format!(
+                "expected {} channels, got {}",
+                expected_channels, probed_stream.channels
+            ));
+        }
+
+        None
+    }
+
+    fn pick_audio_override_sample_rate(
+        encoder_id: &str,
+        supported_sample_rates: &[u32],
+    ) -> Option<u32> {
+        let preferred = if encoder_id == "libopus" {
+            &[24_000, 16_000, 12_000, 8_000][..]
+        } else {
+            &[44_100, 32_000, 24_000, 22_050, 16_000][..]
+        };
+
+        preferred.iter().copied().find(|rate| {
+            *rate != 48_000
+                && (supported_sample_rates.is_empty() || supported_sample_rates.contains(rate))
+        })
+    }
+
+    fn expected_output_sample_rate(encoder_id: &str, requested_sample_rate: u32) -> u32 {
+        if encoder_id == "libopus" {
+            48_000
+        } else {
+            requested_sample_rate
+        }
+    }
+
+    fn pick_preferred_audio_encoders<'a>(
+        capabilities: &'a TranscodeCapabilities,
+    ) -> Vec<&'a TranscodeAudioEncoderCapability> {
+        let preferred_ids = [
+            "aac",
+            "aac_at",
+            "libopus",
+            "libvorbis",
+            "flac",
+            "pcm_s16le",
+            "libmp3lame",
+        ];
+
+        preferred_ids
+            .iter()
+            .filter_map(|encoder_id| {
+                capabilities
+                    .audio_encoders
+                    .iter()
+                    .find(|encoder| encoder.id == *encoder_id)
+            })
+            .collect()
+    }
+
+    async fn collect_probe_streams(path: &Path) -> Result<Vec<Value>, String> {
+        let probe_json =
+            probe_file_with_ffprobe(ffprobe_path(), path.to_string_lossy().as_ref()).await?;
+        let probe_value: Value = serde_json::from_str(&probe_json)
+            .map_err(|error| format!("Invalid probe JSON: {}", error))?;
+
+        Ok(probe_value
+            .get("streams")
+            .and_then(|value| value.as_array())
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    fn args_contain_pair(args: &[String], flag: &str, value: &str) -> bool {
+        args.windows(2)
+            .any(|window| window[0] == flag && window[1] == value)
+    }
+
+    fn expect_arg_pair(args: &[String], flag: &str, value: &str) -> Option<String> {
+        if args_contain_pair(args, flag, value) {
+            None
+        } else {
+            Some(format!(
+                "expected ffmpeg args to contain `{}` `{}`",
+                flag, value
+            ))
+        }
+    }
+
+    fn expect_flag(args: &[String], flag: &str) -> Option<String> {
+        if args.iter().any(|arg| arg == flag) {
+            None
+        } else {
+            Some(format!("expected ffmpeg args to contain `{}`", flag))
+        }
+    }
+
+    fn collect_audio_request_arg_failures(
+        args: &[String],
+        request: &TranscodeRequest,
+    ) -> Vec<String> {
+        let mut failures = Vec::new();
+
+        if let Some(bitrate_kbps) = request.audio.bitrate_kbps {
+            if let Some(error) = expect_arg_pair(args, "-b:a:0", &format!("{}k", bitrate_kbps)) {
+                failures.push(error);
+            }
+        }
+
+        if let Some(channels) = request.audio.channels.filter(|channels| *channels > 0) {
+            if let Some(error) = expect_arg_pair(args, "-ac:a:0", &channels.to_string()) {
+                failures.push(error);
+            }
+        }
+
+        if let Some(sample_rate) = request
+            .audio
+            .sample_rate
+            .filter(|sample_rate| *sample_rate > 0)
+        {
+            if let Some(error) = expect_arg_pair(args, "-ar:a:0", &sample_rate.to_string()) {
+                failures.push(error);
+            }
+        }
+
+        failures
+    }
+
+    fn collect_video_request_arg_failures(
+        args: &[String],
+        request: &TranscodeRequest,
+        encoder: &TranscodeVideoEncoderCapability,
+    ) -> Vec<String> {
+        let mut failures = Vec::new();
+
+        if let Some(profile) = request
+            .video
+            .profile
+            .as_deref()
+            .filter(|value| !value.trim().is_empty())
+        {
+            if let Some(error) = expect_arg_pair(args, "-profile:v", profile.trim()) {
+                failures.push(error);
+            }
+        }
+
+        if let Some(level) = request
+            .video
+            .level
+            .as_deref()
+            .filter(|value| !value.trim().is_empty())
+        {
+            if let Some(error) = expect_arg_pair(args, "-level:v", level.trim()) {
+                failures.push(error);
+            }
+        }
+
+        if let Some(pixel_format) = request
+            .video
+            .pixel_format
+            .as_deref()
+            .filter(|value| !value.trim().is_empty())
+        {
+            if let Some(error) = expect_arg_pair(args, "-pix_fmt", pixel_format.trim()) {
+                failures.push(error);
+            }
+        }
+
+        match request.video.quality_mode.as_deref() {
+            Some("crf") => {
+                if let Some(crf) = request.video.crf {
+                    if let Some(error) = expect_arg_pair(args, "-crf", &crf.to_string()) {
+                        failures.push(error);
+                    }
+                }
+            }
+            Some("qp") => {
+                if let Some(qp) = request.video.qp {
+                    if let Some(error) = expect_arg_pair(args, "-qp", &qp.to_string()) {
+                        failures.push(error);
+                    }
+                }
+            }
+            Some("bitrate") => {
+                if let Some(bitrate_kbps) = request.video.bitrate_kbps {
+                    if let Some(error) =
+                        expect_arg_pair(args, "-b:v", &format!("{}k", bitrate_kbps))
+                    {
+                        failures.push(error);
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        if let Some(preset) = request
+            .video
+            .preset
+            .as_deref()
+            .filter(|value| !value.trim().is_empty())
+        {
+            let preset_flag = if cpu_used_preset_max(&encoder.id).is_some() {
+                "-cpu-used"
+            } else {
+                "-preset"
+            };
+            if let Some(error) = expect_arg_pair(args, preset_flag, preset.trim()) {
+                failures.push(error);
+            }
+        }
+
+        for additional_arg in request
+            .video
+            .additional_args
+            .iter()
+            .filter(|arg| arg.enabled)
+        {
+            match additional_arg.value.as_deref() {
+                Some(value) => {
+                    if let Some(error) = expect_arg_pair(args, &additional_arg.flag, value) {
+                        failures.push(error);
+                    }
+                }
+                None => {
+                    if let Some(error) = expect_flag(args, &additional_arg.flag) {
+                        failures.push(error);
+                    }
+                }
+            }
+        }
+
+        if matches!(request.container_id.as_str(), "mp4" | "mov") && encoder.id.starts_with("hevc")
+        {
+            if let Some(error) = expect_arg_pair(args, "-tag:v", "hvc1") {
+                failures.push(error);
+            }
+        }
+
+        if request.container_id == "mp4" {
+            if let Some(error) = expect_arg_pair(args, "-movflags", "+faststart") {
+                failures.push(error);
+            }
+        }
+
+        failures
+    }
+
+    fn normalize_comparable_value(value: &str) -> String {
+        value
+            .chars()
+            .filter(|character| character.is_ascii_alphanumeric())
+            .map(|character| character.to_ascii_lowercase())
+            .collect()
+    }
+
+    #[derive(Debug, Clone)]
+    struct VideoRuntimeScenario {
+        name: &'static str,
+        quality_mode: Option<&'static str>,
+        crf: Option<f64>,
+        qp: Option<i32>,
+        bitrate_kbps: Option<u32>,
+        preset: Option<&'static str>,
+        profile: Option<String>,
+        level: Option<String>,
+        pixel_format: Option<String>,
+        additional_args: Vec<TranscodeAdditionalArg>,
+    }
+
+    fn preferred_video_pixel_format(encoder: &TranscodeVideoEncoderCapability) -> Option<String> {
+        encoder
+            .supported_pixel_formats
+            .iter()
+            .find(|pixel_format| {
+                matches!(
+                    pixel_format.as_str(),
+                    "yuv420p" | "nv12" | "yuv420p10le" | "p010le"
+                )
+            })
+            .cloned()
+            .or_else(|| encoder.supported_pixel_formats.first().cloned())
+    }
+
+    fn preferred_video_preset(encoder_id: &str) -> Option<&'static str> {
+        match encoder_id {
+            "libx264" | "libx265" => Some("fast"),
+            "libsvtav1" => Some("10"),
+            "libaom-av1" => Some("8"),
+            _ => None,
+        }
+    }
+
+    fn preferred_video_crf(codec: &str) -> f64 {
+        match codec {
+            "av1" => 35.0,
+            "hevc" => 30.0,
+            "vp9" => 32.0,
+            _ => 28.0,
+        }
+    }
+
+    fn preferred_video_qp(codec: &str) -> i32 {
+        match codec {
+            "av1" => 36,
+            "hevc" => 30,
+            "vp9" => 32,
+            _ => 28,
+        }
+    }
+
+    fn preferred_video_bitrate_kbps(codec: &str) -> u32 {
+        match codec {
+            "prores" => 1500,
+            "av1" => 500,
+            "vp9" => 500,
+            _ => 700,
+        }
+    }
+
+    fn build_primary_video_scenario(
+        encoder: &TranscodeVideoEncoderCapability,
+    ) -> VideoRuntimeScenario {
+        let quality_mode = if encoder.supports_crf {
+            Some("crf")
+        } else if encoder.supports_qp {
+            Some("qp")
+        } else if encoder.supports_bitrate {
+            Some("bitrate")
+        } else {
+            None
+        };
+
+        VideoRuntimeScenario {
+            name: "primary",
+            quality_mode,
+            crf: quality_mode
+                .filter(|mode| *mode == "crf")
+                .map(|_| preferred_video_crf(&encoder.codec)),
+            qp: quality_mode
+                .filter(|mode| *mode == "qp")
+                .map(|_| preferred_video_qp(&encoder.codec)),
+            bitrate_kbps: quality_mode
+                .filter(|mode| *mode == "bitrate")
+                .map(|_| preferred_video_bitrate_kbps(&encoder.codec)),
+            preset: encoder
+                .supports_preset
+                .then(|| preferred_video_preset(&encoder.id))
+                .flatten(),
+            profile: None,
+            level: None,
+            pixel_format: None,
+            additional_args: Vec::new(),
+        }
+    }
+
+    fn build_secondary_video_scenario(
+        encoder: &TranscodeVideoEncoderCapability,
+    ) -> Option<VideoRuntimeScenario> {
+        if encoder.id == "libaom-av1" {
+            return None;
+        }
+
+        if encoder.supports_bitrate {
+            return Some(VideoRuntimeScenario {
+                name: "bitrate_gop",
+                quality_mode: Some("bitrate"),
+                crf: None,
+                qp: None,
+                bitrate_kbps: Some(preferred_video_bitrate_kbps(&encoder.codec)),
+                preset: encoder
+                    .supports_preset
+                    .then(|| preferred_video_preset(&encoder.id))
+                    .flatten(),
+                profile: None,
+                level: None,
+                pixel_format: None,
+                additional_args: vec![TranscodeAdditionalArg {
+                    _id: Some("gop".to_string()),
+                    flag: "-g".to_string(),
+                    value: Some("12".to_string()),
+                    enabled: true,
+                }],
+            });
+        }
+
+        if encoder.supports_qp && !encoder.supports_crf {
+            return Some(VideoRuntimeScenario {
+                name: "qp_gop",
+                quality_mode: Some("qp"),
+                crf: None,
+                qp: Some(preferred_video_qp(&encoder.codec)),
+                bitrate_kbps: None,
+                preset: encoder
+                    .supports_preset
+                    .then(|| preferred_video_preset(&encoder.id))
+                    .flatten(),
+                profile: None,
+                level: None,
+                pixel_format: None,
+                additional_args: vec![TranscodeAdditionalArg {
+                    _id: Some("gop".to_string()),
+                    flag: "-g".to_string(),
+                    value: Some("12".to_string()),
+                    enabled: true,
+                }],
+            });
+        }
+
+        None
+    }
+
+    fn build_representative_video_scenarios(
+        encoder: &TranscodeVideoEncoderCapability,
+    ) -> Vec<VideoRuntimeScenario> {
+        let mut scenarios = vec![build_primary_video_scenario(encoder)];
+        if let Some(secondary) = build_secondary_video_scenario(encoder) {
+            scenarios.push(secondary);
+        }
+        scenarios
+    }
+
+    fn preferred_profile_value(encoder: &TranscodeVideoEncoderCapability) -> Option<String> {
+        let preferred = match encoder.codec.as_str() {
+            "h264" => &["high", "main", "baseline"][..],
+            "hevc" => &["main", "main10"][..],
+            "av1" => &["main"][..],
+            "prores" => &["standard", "hq", "proxy"][..],
+            _ => &[][..],
+        };
+
+        preferred
+            .iter()
+            .find_map(|candidate| {
+                encoder
+                    .supported_profiles
+                    .iter()
+                    .find(|value| value.eq_ignore_ascii_case(candidate))
+                    .cloned()
+            })
+            .or_else(|| encoder.supported_profiles.first().cloned())
+    }
+
+    fn preferred_level_value(encoder: &TranscodeVideoEncoderCapability) -> Option<String> {
+        let preferred = ["4.1", "4.0", "5.0", "3.1", "3.0"];
+
+        preferred
+            .iter()
+            .find_map(|candidate| {
+                encoder
+                    .supported_levels
+                    .iter()
+                    .find(|value| value == candidate)
+                    .cloned()
+            })
+            .or_else(|| encoder.supported_levels.first().cloned())
+    }
+
+    fn preferred_10bit_pixel_format(encoder: &TranscodeVideoEncoderCapability) -> Option<String> {
+        ["yuv420p10le", "p010le", "x2rgb10le", "ayuv64le"]
+            .iter()
+            .find_map(|candidate| {
+                encoder
+                    .supported_pixel_formats
+                    .iter()
+                    .find(|value| value.as_str() == *candidate)
+                    .cloned()
+            })
+    }
+
+    fn build_advanced_video_capability_scenarios(
+        encoder: &TranscodeVideoEncoderCapability,
+    ) -> Vec<VideoRuntimeScenario> {
+        let mut scenarios = Vec::new();
+
+        if !encoder.is_hardware
+            && (!encoder.supported_profiles.is_empty() || !encoder.supported_levels.is_empty())
+        {
+            let mut scenario = build_primary_video_scenario(encoder);
+            scenario.name = "profile_level";
+            scenario.profile = preferred_profile_value(encoder);
+            scenario.level = preferred_level_value(encoder);
+            if scenario.profile.is_some() || scenario.level.is_some() {
+                scenarios.push(scenario);
+            }
+        }
+
+        if !encoder.is_hardware {
+            if let Some(pixel_format) = preferred_10bit_pixel_format(encoder) {
+                let mut scenario = build_primary_video_scenario(encoder);
+                scenario.name = "ten_bit";
+                scenario.pixel_format = Some(pixel_format);
+                scenario.profile = preferred_profile_value(encoder);
+                scenarios.push(scenario);
+            }
+        }
+
+        scenarios
+    }
+
+    fn expected_video_codec_name(encoder_id: &str) -> &'static str {
+        match encoder_id {
+            "libx264" | "h264_videotoolbox" => "h264",
+            "libx265" | "hevc_videotoolbox" => "hevc",
+            "libsvtav1" | "libaom-av1" => "av1",
+            "libvpx-vp9" => "vp9",
+            "prores_ks" | "prores_videotoolbox" => "prores",
+            _ => "",
+        }
+    }
+
+    fn validate_transcoded_video_stream(
+        encoder_id: &str,
+        probed_stream: &ProbedVideoStream,
+    ) -> Option<String> {
+        let expected_codec_name = expected_video_codec_name(encoder_id);
+        if !expected_codec_name.is_empty() && probed_stream.codec_name != expected_codec_name {
+            return Some(format!(
+                "expected codec {}, got {}",
+                expected_codec_name, probed_stream.codec_name
+            ));
+        }
+
+        if probed_stream.width != VIDEO_TEST_WIDTH || probed_stream.height != VIDEO_TEST_HEIGHT {
+            return Some(format!(
+                "expected resolution {}x{}, got {}x{}",
+                VIDEO_TEST_WIDTH, VIDEO_TEST_HEIGHT, probed_stream.width, probed_stream.height
+            ));
+        }
+
+        None
+    }
+
+    fn validate_transcoded_video_output(
+        encoder: &TranscodeVideoEncoderCapability,
+        container_id: &str,
+        scenario: &VideoRuntimeScenario,
+        probed_stream: &ProbedVideoStream,
+    ) -> Option<String> {
+        if let Some(error) = validate_transcoded_video_stream(&encoder.id, probed_stream) {
+            return Some(error);
+        }
+
+        if let Some(expected_profile) = scenario.profile.as_deref() {
+            let Some(actual_profile) = probed_stream.profile.as_deref() else {
+                return Some(format!(
+                    "expected profile {}, but ffprobe did not report one",
+                    expected_profile
+                ));
+            };
+
+            if normalize_comparable_value(actual_profile)
+                != normalize_comparable_value(expected_profile)
+            {
+                return Some(format!(
+                    "expected profile {}, got {}",
+                    expected_profile, actual_profile
+                ));
+            }
+        }
+
+        if let Some(expected_pixel_format) = scenario.pixel_format.as_deref() {
+            if probed_stream.pixel_format.as_deref() != Some(expected_pixel_format) {
+                return Some(format!(
+                    "expected pixel format {}, got {:?}",
+                    expected_pixel_format, probed_stream.pixel_format
+                ));
+            }
+        }
+
+        if matches!(container_id, "mp4" | "mov") && encoder.id.starts_with("hevc") {
+            if probed_stream.codec_tag_string.as_deref() != Some("hvc1") {
+                return Some(format!(
+                    "expected codec tag hvc1, got {:?}",
+                    probed_stream.codec_tag_string
+                ));
+            }
+        }
+
+        None
+    }
+
+    fn select_representative_video_encoders<'a>(
+        capabilities: &'a TranscodeCapabilities,
+    ) -> Vec<&'a TranscodeVideoEncoderCapability> {
+        let preferred_ids = [
+            "libx264",
+            "h264_videotoolbox",
+            "libx265",
+            "hevc_videotoolbox",
+            "libsvtav1",
+            "libaom-av1",
+            "libvpx",
+            "libvpx-vp9",
+            "prores_ks",
+            "prores_videotoolbox",
+        ];
+
+        preferred_ids
+            .iter()
+            .filter_map(|encoder_id| {
+                capabilities
+                    .video_encoders
+                    .iter()
+                    .find(|encoder| encoder.id == *encoder_id)
+            })
+            .filter(|encoder| !should_skip_runtime_video_encoder(&encoder.id))
+            .collect()
+    }
+
+    fn should_skip_runtime_video_encoder(encoder_id: &str) -> bool {
+        should_skip_videotoolbox_encoder_in_ci(encoder_id, ci_env_enabled())
+    }
+
+    fn should_skip_videotoolbox_encoder_in_ci(encoder_id: &str, ci_enabled: bool) -> bool {
+        ci_enabled && is_videotoolbox_encoder(encoder_id)
+    }
+
+    fn is_videotoolbox_encoder(encoder_id: &str) -> bool {
+        matches!(
+            encoder_id,
+            "h264_videotoolbox" | "hevc_videotoolbox" | "prores_videotoolbox"
+        )
+    }
+
+    fn ci_env_enabled() -> bool {
+        std::env::var("CI").is_ok_and(|value| value.eq_ignore_ascii_case("true"))
+    }
+
+    fn select_runtime_video_encoder_for_container<'a>(
+        capabilities: &'a TranscodeCapabilities,
+        container: &TranscodeContainerCapability,
+    ) -> Option<&'a TranscodeVideoEncoderCapability> {
+        select_runtime_video_encoder_for_container_with_ci(
+            capabilities,
+            container,
+            ci_env_enabled(),
+        )
+    }
+
+    fn select_runtime_video_encoder_for_container_with_ci<'a>(
+        capabilities: &'a TranscodeCapabilities,
+        container: &TranscodeContainerCapability,
+        ci_enabled: bool,
+    ) -> Option<&'a TranscodeVideoEncoderCapability> {
         let is_supported = |encoder: &&TranscodeVideoEncoderCapability| {
             container
                 .supported_video_encoder_ids
@@ -1949,195 +4695,1392 @@ mod tests {
                 .any(|supported_id| supported_id == &encoder.id)
         };
 
-        capabilities
-            .video_encoders
+        capabilities
+            .video_encoders
+            .iter()
+            .find(|encoder| {
+                Some(&encoder.id) == container.default_video_encoder_id.as_ref()
+                    && !should_skip_videotoolbox_encoder_in_ci(&encoder.id, ci_enabled)
+            })
+            .or_else(|| {
+                capabilities.video_encoders.iter().find(|encoder| {
+                    is_supported(encoder)
+                        && !should_skip_videotoolbox_encoder_in_ci(&encoder.id, ci_enabled)
+                })
+            })
+    }
+
+    fn test_video_encoder(id: &str, is_hardware: bool) -> TranscodeVideoEncoderCapability {
+        TranscodeVideoEncoderCapability {
+            id: id.to_string(),
+            codec: "h264".to_string(),
+            label: id.to_string(),
+            is_hardware,
+            supported_pixel_formats: Vec::new(),
+            supported_profiles: Vec::new(),
+            supported_levels: Vec::new(),
+            supported_bit_depths: Vec::new(),
+            supports_preset: false,
+            supports_crf: false,
+            supports_qp: false,
+            supports_bitrate: false,
+            options: Vec::new(),
+        }
+    }
+
+    fn test_video_container(
+        default_video_encoder_id: Option<&str>,
+        supported_video_encoder_ids: &[&str],
+    ) -> TranscodeContainerCapability {
+        TranscodeContainerCapability {
+            id: "mkv".to_string(),
+            label: "MKV".to_string(),
+            extension: ".mkv".to_string(),
+            kind: "video".to_string(),
+            muxer_name: "matroska".to_string(),
+            supported_video_encoder_ids: supported_video_encoder_ids
+                .iter()
+                .map(|id| id.to_string())
+                .collect(),
+            supported_audio_encoder_ids: Vec::new(),
+            supported_subtitle_encoder_ids: Vec::new(),
+            supported_subtitle_modes: Vec::new(),
+            default_video_encoder_id: default_video_encoder_id.map(str::to_string),
+            default_audio_encoder_id: None,
+            default_subtitle_encoder_id: None,
+            metadata_schema: metadata_schema_for_container("mkv"),
+        }
+    }
+
+    #[test]
+    fn videotoolbox_skip_predicate_only_applies_in_ci() {
+        assert!(should_skip_videotoolbox_encoder_in_ci(
+            "h264_videotoolbox",
+            true
+        ));
+        assert!(!should_skip_videotoolbox_encoder_in_ci(
+            "h264_videotoolbox",
+            false
+        ));
+        assert!(!should_skip_videotoolbox_encoder_in_ci("libx264", true));
+    }
+
+    #[test]
+    fn runtime_video_encoder_selection_prefers_software_when_default_is_skipped() {
+        let capabilities = TranscodeCapabilities {
+            ffmpeg_version: "8.1".to_string(),
+            hwaccels: Vec::new(),
+            containers: Vec::new(),
+            video_encoders: vec![
+                test_video_encoder("hevc_videotoolbox", true),
+                test_video_encoder("libx264", false),
+            ],
+            audio_encoders: Vec::new(),
+            subtitle_encoders: Vec::new(),
+            supported_video_filters: Vec::new(),
+            supported_audio_filters: Vec::new(),
+            supports_soxr: false,
+            default_analysis_frame_count: 6,
+        };
+        let container =
+            test_video_container(Some("hevc_videotoolbox"), &["hevc_videotoolbox", "libx264"]);
+
+        assert!(should_skip_videotoolbox_encoder_in_ci(
+            "hevc_videotoolbox",
+            true
+        ));
+        let selected =
+            select_runtime_video_encoder_for_container_with_ci(&capabilities, &container, true)
+                .expect("software fallback should be selected");
+
+        assert_eq!(selected.id, "libx264");
+    }
+
+    fn assert_media_counts(
+        counts: &MediaStreamCounts,
+        expected_video_streams: usize,
+        expected_audio_streams: usize,
+    ) -> Option<String> {
+        if counts.video_streams != expected_video_streams
+            || counts.audio_streams != expected_audio_streams
+        {
+            return Some(format!(
+                "expected {} video and {} audio streams, got {} video and {} audio streams",
+                expected_video_streams,
+                expected_audio_streams,
+                counts.video_streams,
+                counts.audio_streams
+            ));
+        }
+
+        None
+    }
+
+    fn should_skip_audio_matrix_case(
+        encoder_id: &str,
+        layout: &str,
+        input_channels: u64,
+    ) -> Option<&'static str> {
+        if encoder_id == "libvorbis" && input_channels > 6 {
+            return Some("libvorbis does not support more than 6 channels in this FFmpeg build");
+        }
+
+        if encoder_id == "aac_at" && layout == "7.1" {
+            return Some(
+                "AudioToolbox AAC exposes 7.1(wide) instead of standard 7.1 on this platform",
+            );
+        }
+
+        None
+    }
+
+    #[test]
+    fn build_transcode_args_includes_main_video_audio_and_safe_subtitle_handling() {
+        let mut request = build_request("/tmp/output.mkv");
+        request.container_id = "mkv".to_string();
+        request.subtitles.encoder_id = Some("srt".to_string());
+        let streams = vec![
+            json!({ "codec_type": "video", "codec_name": "h264" }),
+            json!({ "codec_type": "audio", "codec_name": "aac" }),
+            json!({ "codec_type": "subtitle", "codec_name": "subrip" }),
+            json!({ "codec_type": "subtitle", "codec_name": "hdmv_pgs_subtitle" }),
+        ];
+
+        let args = build_transcode_args(
+            &request,
+            &TranscodeContext::default(),
+            &streams,
+            Some(10_000_000),
+        )
+        .expect("args should build");
+
+        assert!(args.windows(2).any(|window| window == ["-c:v", "libx264"]));
+        assert!(args.windows(2).any(|window| window == ["-c:a:0", "aac"]));
+        assert!(args.windows(2).any(|window| window == ["-c:s:0", "srt"]));
+        assert!(args.windows(2).any(|window| window == ["-c:s:1", "copy"]));
+        assert!(
+            args.windows(2)
+                .any(|window| window == ["-progress", "pipe:1"])
+        );
+    }
+
+    #[test]
+    fn build_transcode_args_rejects_blocked_additional_args() {
+        let mut request = build_request("/tmp/output.mp4");
+        request.video.additional_args = vec![TranscodeAdditionalArg {
+            _id: None,
+            flag: "-map".to_string(),
+            value: Some("0".to_string()),
+            enabled: true,
+        }];
+        let streams = vec![json!({ "codec_type": "video", "codec_name": "h264" })];
+
+        let error = build_transcode_args(&request, &TranscodeContext::default(), &streams, None)
+            .expect_err("blocked arg should fail");
+        assert!(error.contains("not allowed"));
+    }
+
+    #[test]
+    fn build_transcode_args_applies_video_filter_graph() {
+        let mut request = build_request("/tmp/output.mp4");
+        request.video.filters.scale = Some(TranscodeScaleFilter {
+            width: Some(1920),
+            height: Some(1080),
+            aspect_mode: Some("keep".to_string()),
+            algorithm: None,
+        });
+        let streams = vec![json!({ "codec_type": "video", "codec_name": "hevc" })];
+
+        let args = build_transcode_args(&request, &TranscodeContext::default(), &streams, None)
+            .expect("args should build");
+
+        assert!(args.windows(2).any(|window| {
+            window
+                == [
+                    "-vf",
+                    "scale=1920:1080:force_original_aspect_ratio=decrease:force_divisible_by=2",
+                ]
+        }));
+    }
+
+    #[test]
+    fn build_transcode_args_tonemaps_hdr_sources_encoded_to_8bit() {
+        let request = build_request("/tmp/output.mp4");
+        let hdr_streams = vec![json!({
+            "codec_type": "video",
+            "codec_name": "hevc",
+            "color_transfer": "smpte2084",
+            "color_primaries": "bt2020"
+        })];
+
+        let args = build_transcode_args(&request, &TranscodeContext::default(), &hdr_streams, None)
+            .expect("args should build");
+        let filter_graph = args
+            .windows(2)
+            .find(|window| window[0] == "-vf")
+            .map(|window| window[1].clone())
+            .expect("tonemap filter should be applied");
+        assert!(filter_graph.starts_with("zscale=t=linear:npl=100,"));
+        assert!(filter_graph.contains("tonemap=tonemap=hable"));
+        assert!(filter_graph.ends_with("format=yuv420p"));
+
+        let sdr_streams = vec![json!({ "codec_type": "video", "codec_name": "hevc" })];
+        let args = build_transcode_args(&request, &TranscodeContext::default(), &sdr_streams, None)
+            .expect("args should build");
+        assert!(!args.iter().any(|arg| arg == "-vf"));
+    }
+
+    #[test]
+    fn target_quality_samples_encode_only_the_main_video_at_the_candidate_crf() {
+        let mut request = build_request("/tmp/output.mp4");
+        request.video.quality_mode = Some("targetQuality".to_string());
+        request.compare_quality = true;
+        let streams = vec![
+            json!({ "index": 0, "codec_type": "video", "codec_name": "h264" }),
+            json!({ "index": 1, "codec_type": "audio", "codec_name": "aac", "channels": 2 }),
+            json!({ "index": 2, "codec_type": "subtitle", "codec_name": "mov_text" }),
+        ];
+
+        let sample = build_sample_request(
+            &request,
+            (30_000, 34_000),
+            27,
+            Path::new("/tmp/samples/sample-1-crf27.mp4"),
+        );
+        assert!(!sample.compare_quality);
+        let args = build_transcode_args(
+            &sample,
+            &TranscodeContext::default(),
+            &streams,
+            Some(4_000_000),
+        )
+        .expect("sample args should build");
+
+        assert!(args.windows(2).any(|window| window == ["-ss", "30.000"]));
+        assert!(args.windows(2).any(|window| window == ["-t", "4.000"]));
+        assert!(args.windows(2).any(|window| window == ["-crf", "27"]));
+        assert!(args.windows(2).any(|window| window == ["-map", "0:v:0"]));
+        assert!(!args.iter().any(|arg| arg == "0:a:0" || arg == "0:s:0"));
+        assert_eq!(
+            args.last().map(String::as_str),
+            Some("/tmp/samples/sample-1-crf27.mp4")
+        );
+    }
+
+    #[test]
+    fn chunked_encodes_split_the_video_and_mux_the_joined_chunks() {
+        let mut request = build_request("/tmp/output.mp4");
+        request.video.chunked = Some(TranscodeChunkedEncoding::default());
+        let streams = vec![
+            json!({ "index": 0, "codec_type": "video", "codec_name": "h264" }),
+            json!({ "index": 1, "codec_type": "audio", "codec_name": "aac", "channels": 2 }),
+        ];
+        let video_streams = extract_streams_by_type(&streams, "video");
+        let primary = validate_chunked_request(&request, &video_streams)
+            .expect("single video stream should be chunkable");
+
+        let chunk = build_chunk_request(
+            &request,
+            &video_streams,
+            &primary,
+            Path::new("/tmp/chunks/chunk-0001.mkv"),
+        );
+        let args = build_transcode_args(
+            &chunk,
+            &TranscodeContext::default(),
+            &streams,
+            Some(30_000_000),
+        )
+        .expect("chunk args should build");
+        assert!(args.windows(2).any(|window| window == ["-c:v", "libx264"]));
+        assert!(!args.iter().any(|arg| arg == "0:a:0"));
+        assert_eq!(
+            args.last().map(String::as_str),
+            Some("/tmp/chunks/chunk-0001.mkv")
+        );
+
+        let mux = request_for_chunked_mux(&request);
+        let mux_context = TranscodeContext {
+            chunked_video_path: Some("/tmp/chunks/video.mkv".to_string()),
+            ..TranscodeContext::default()
+        };
+        let args = build_transcode_args(&mux, &mux_context, &streams, None)
+            .expect("mux args should build");
+        assert!(
+            args.windows(2)
+                .any(|window| window == ["-i", "/tmp/chunks/video.mkv"])
+        );
+        assert!(args.windows(2).any(|window| window == ["-map", "1:v:0"]));
+        assert!(args.windows(2).any(|window| window == ["-map", "0:a:0"]));
+        assert!(args.windows(2).any(|window| window == ["-c:v", "copy"]));
+
+        request.ranges = vec![TranscodeTimeRange {
+            start_ms: 0,
+            end_ms: Some(1_000),
+        }];
+        assert!(validate_chunked_request(&request, &video_streams).is_err());
+    }
+
+    #[test]
+    fn preview_samples_keep_every_stream_and_the_request_settings() {
+        let mut request = build_request("/tmp/output.mp4");
+        request.compare_quality = true;
+        let streams = vec![
+            json!({ "index": 0, "codec_type": "video", "codec_name": "h264" }),
+            json!({ "index": 1, "codec_type": "audio", "codec_name": "aac", "channels": 2 }),
+        ];
+
+        let sample = build_preview_sample_request(
+            &request,
+            (60_000, 65_000),
+            Path::new("/tmp/previews/sample-1.mp4"),
+        );
+        assert!(!sample.compare_quality);
+        let args = build_transcode_args(
+            &sample,
+            &TranscodeContext::default(),
+            &streams,
+            Some(5_000_000),
+        )
+        .expect("preview args should build");
+
+        assert!(args.windows(2).any(|window| window == ["-ss", "60.000"]));
+        assert!(args.windows(2).any(|window| window == ["-t", "5.000"]));
+        assert!(args.windows(2).any(|window| window == ["-crf", "20"]));
+        assert!(args.windows(2).any(|window| window == ["-map", "0:a:0"]));
+        assert!(args.windows(2).any(|window| window == ["-b:a:0", "160k"]));
+        assert_eq!(
+            args.last().map(String::as_str),
+            Some("/tmp/previews/sample-1.mp4")
+        );
+    }
+
+    #[test]
+    fn build_transcode_args_keeps_chapters_and_attachments_when_requested() {
+        let mut request = build_request("/tmp/output.mkv");
+        request.container_id = "mkv".to_string();
+        request.subtitles.mode = "copy".to_string();
+        request.metadata.keep_chapters = true;
+        request.metadata.keep_attachments = true;
+        let streams = vec![
+            json!({ "index": 0, "codec_type": "video", "codec_name": "h264" }),
+            json!({ "index": 1, "codec_type": "audio", "codec_name": "aac", "channels": 2 }),
+            json!({ "index": 2, "codec_type": "subtitle", "codec_name": "ass" }),
+            json!({ "index": 3, "codec_type": "attachment", "codec_name": "ttf" }),
+        ];
+
+        let args = build_transcode_args(&request, &TranscodeContext::default(), &streams, None)
+            .expect("args should build");
+        let last_stream_map = args
+            .windows(2)
+            .rposition(|window| window == ["-map", "0:s:0"])
+            .expect("subtitle should be mapped");
+        let attachment_map = args
+            .windows(2)
+            .position(|window| window == ["-map", "0:t?"])
+            .expect("attachments should be mapped");
+        assert!(attachment_map > last_stream_map);
+        assert!(args.windows(2).any(|window| window == ["-c:t", "copy"]));
+        assert!(
+            args.windows(2)
+                .any(|window| window == ["-map_chapters", "0"])
+        );
+
+        request.metadata.keep_chapters = false;
+        request.metadata.keep_attachments = false;
+        let args = build_transcode_args(&request, &TranscodeContext::default(), &streams, None)
+            .expect("args should build");
+        assert!(
+            args.windows(2)
+                .any(|window| window == ["-map_chapters", "-1"])
+        );
+        assert!(!args.iter().any(|arg| arg == "0:t?"));
+    }
+
+    #[test]
+    fn build_transcode_args_passes_hdr_metadata_into_encoder_params() {
+        let mut request = build_request("/tmp/output.mkv");
+        request.container_id = "mkv".to_string();
+        request.video.encoder_id = Some("libx265".to_string());
+        request.video.profile = None;
+        request.video.level = None;
+        request.video.pixel_format = Some("yuv420p10le".to_string());
+        request.video.keep_dolby_vision = true;
+        request.video.additional_args = vec![TranscodeAdditionalArg {
+            _id: None,
+            flag: "-x265-params".to_string(),
+            value: Some("aq-mode=3".to_string()),
+            enabled: true,
+        }];
+        request.subtitles.mode = "disable".to_string();
+        let streams = vec![json!({
+            "index": 0,
+            "codec_type": "video",
+            "codec_name": "hevc",
+            "color_transfer": "smpte2084",
+            "side_data_list": [
+                {
+                    "side_data_type": "DOVI configuration record",
+                    "dv_profile": 8,
+                    "rpu_present_flag": 1
+                },
+                {
+                    "side_data_type": "Content light level metadata",
+                    "max_content": 1000,
+                    "max_average": 400
+                }
+            ]
+        })];
+
+        let context = TranscodeContext {
+            dolby_vision_supported: true,
+            ..TranscodeContext::default()
+        };
+        let args =
+            build_transcode_args(&request, &context, &streams, None).expect("args should build");
+        let params = args
+            .iter()
+            .enumerate()
+            .filter(|(_, arg)| *arg == "-x265-params")
+            .map(|(index, _)| args[index + 1].as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(params, vec!["max-cll=1000,400:aq-mode=3"]);
+        assert!(
+            args.windows(2)
+                .any(|window| window == ["-dolbyvision", "1"])
+        );
+
+        request.video.pixel_format = Some("yuv420p".to_string());
+        let args =
+            build_transcode_args(&request, &context, &streams, None).expect("args should build");
+        assert!(
+            args.windows(2)
+                .any(|window| window == ["-x265-params", "aq-mode=3"])
+        );
+        assert!(!args.iter().any(|arg| arg == "-dolbyvision"));
+    }
+
+    #[test]
+    fn build_transcode_args_rejects_video_filters_with_copy() {
+        let mut request = build_request("/tmp/output.mp4");
+        request.video.mode = "copy".to_string();
+        request.video.filters.scale = Some(TranscodeScaleFilter {
+            width: Some(1280),
+            ..TranscodeScaleFilter::default()
+        });
+        let streams = vec![json!({ "codec_type": "video", "codec_name": "h264" })];
+
+        let error = build_transcode_args(&request, &TranscodeContext::default(), &streams, None)
+            .expect_err("filters with copy should fail");
+        assert!(error.contains("Video filters require video transcoding"));
+    }
+
+    fn build_two_pass_request(encoder_id: &str) -> TranscodeRequest {
+        let mut request = build_request("/tmp/output.mkv");
+        request.container_id = "mkv".to_string();
+        request.video.encoder_id = Some(encoder_id.to_string());
+        request.video.quality_mode = Some("bitrate".to_string());
+        request.video.bitrate_kbps = Some(2500);
+        request.video.two_pass = true;
+        request.subtitles.encoder_id = Some("srt".to_string());
+        request
+    }
+
+    #[test]
+    fn build_transcode_run_args_splits_two_pass_bitrate_encodes() {
+        let request = build_two_pass_request("libx264");
+        let streams = vec![
+            json!({ "codec_type": "video", "codec_name": "h264" }),
+            json!({ "codec_type": "audio", "codec_name": "aac" }),
+            json!({ "codec_type": "subtitle", "codec_name": "subrip" }),
+        ];
+        let passlog_dir = tempfile::tempdir().expect("failed to create tempdir");
+        let prefix = passlog_dir
+            .path()
+            .join("ffmpeg2pass")
+            .to_string_lossy()
+            .to_string();
+
+        let passes = build_transcode_run_args(
+            &request,
+            &TranscodeContext::default(),
+            &streams,
+            None,
+            passlog_dir.path(),
+        )
+        .expect("two-pass args should build");
+
+        assert_eq!(passes.len(), 2);
+        let first = &passes[0];
+        assert!(first.windows(2).any(|window| window == ["-pass", "1"]));
+        assert!(
+            first
+                .windows(2)
+                .any(|window| window[0] == "-passlogfile" && window[1] == prefix)
+        );
+        assert!(first.windows(2).any(|window| window == ["-b:v", "2500k"]));
+        assert!(first.iter().any(|arg| arg == "-an"));
+        assert!(!first.iter().any(|arg| arg == "0:a:0" || arg == "0:s:0"));
+        assert!(first.ends_with(&["-f".to_string(), "null".to_string(), "-".to_string()]));
+
+        let second = &passes[1];
+        assert!(second.windows(2).any(|window| window == ["-pass", "2"]));
+        assert!(second.windows(2).any(|window| window == ["-c:a:0", "aac"]));
+        assert_eq!(
+            second.last().map(String::as_str),
+            Some(request.output_path.as_str())
+        );
+    }
+
+    #[test]
+    fn build_transcode_run_args_rejects_stream_order_moving_video_in_two_pass() {
+        let mut request = build_two_pass_request("libx264");
+        let streams = vec![
+            json!({ "index": 0, "codec_type": "video", "codec_name": "h264" }),
+            json!({ "index": 1, "codec_type": "audio", "codec_name": "aac" }),
+        ];
+        let passlog_dir = tempfile::tempdir().expect("failed to create tempdir");
+
+        request.stream_order = vec![0, 1];
+        assert!(
+            build_transcode_run_args(
+                &request,
+                &TranscodeContext::default(),
+                &streams,
+                None,
+                passlog_dir.path(),
+            )
+            .is_ok()
+        );
+
+        request.stream_order = vec![1, 0];
+        let error = build_transcode_run_args(
+            &request,
+            &TranscodeContext::default(),
+            &streams,
+            None,
+            passlog_dir.path(),
+        )
+        .expect_err("audio before video should be rejected in two-pass mode");
+        assert!(error.contains("Two-pass encoding needs the video streams first"));
+
+        request.video.two_pass = false;
+        let args = build_transcode_args(&request, &TranscodeContext::default(), &streams, None)
+            .expect("single-pass args should keep the requested order");
+        let maps = args
+            .windows(2)
+            .filter(|window| window[0] == "-map")
+            .map(|window| window[1].as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(maps, ["0:a:0", "0:v:0"]);
+    }
+
+    #[test]
+    fn build_transcode_run_args_uses_x265_params_for_two_pass() {
+        let request = build_two_pass_request("libx265");
+        let streams = vec![json!({ "codec_type": "video", "codec_name": "h264" })];
+        let passlog_dir = tempfile::tempdir().expect("failed to create tempdir");
+
+        let passes = build_transcode_run_args(
+            &request,
+            &TranscodeContext::default(),
+            &streams,
+            None,
+            passlog_dir.path(),
+        )
+        .expect("two-pass args should build");
+
+        assert!(
+            passes[0]
+                .windows(2)
+                .any(|window| window[0] == "-x265-params" && window[1].starts_with("pass=1:stats="))
+        );
+        assert!(!passes[0].iter().any(|arg| arg == "-pass"));
+        assert_eq!(
+            escape_x265_param_value(r"C:\passlog\ffmpeg2pass.x265.log"),
+            r"C\:\\passlog\\ffmpeg2pass.x265.log"
+        );
+    }
+
+    #[test]
+    fn build_transcode_run_args_rejects_two_pass_for_unsupported_encoders() {
+        let request = build_two_pass_request("h264_videotoolbox");
+        let streams = vec![json!({ "codec_type": "video", "codec_name": "h264" })];
+        let passlog_dir = tempfile::tempdir().expect("failed to create tempdir");
+
+        let error = build_transcode_run_args(
+            &request,
+            &TranscodeContext::default(),
+            &streams,
+            None,
+            passlog_dir.path(),
+        )
+        .expect_err("hardware two-pass should fail");
+        assert!(error.contains("Two-pass encoding is not supported by h264_videotoolbox"));
+    }
+
+    #[test]
+    fn build_transcode_run_args_ignores_two_pass_outside_bitrate_mode() {
+        let mut request = build_two_pass_request("libx264");
+        request.video.quality_mode = Some("crf".to_string());
+        let streams = vec![json!({ "codec_type": "video", "codec_name": "h264" })];
+        let passlog_dir = tempfile::tempdir().expect("failed to create tempdir");
+
+        let passes = build_transcode_run_args(
+            &request,
+            &TranscodeContext::default(),
+            &streams,
+            None,
+            passlog_dir.path(),
+        )
+        .expect("single-pass args should build");
+
+        assert_eq!(passes.len(), 1);
+        assert!(!passes[0].iter().any(|arg| arg == "-pass"));
+    }
+
+    #[test]
+    fn plan_target_size_subtracts_audio_and_container_overhead() {
+        let mut request = build_request("/tmp/output.mkv");
+        request.container_id = "mkv".to_string();
+        request.video.quality_mode = Some("targetSize".to_string());
+        request.video.target_size_mb = Some(700.0);
+        request.audio.bitrate_kbps = Some(128);
+        request.audio.track_overrides = vec![TranscodeAudioTrackOverride {
+            track_id: 2,
+            mode: "copy".to_string(),
+            encoder_id: None,
+            bitrate_kbps: None,
+            channels: None,
+            sample_rate: None,
+            filters: None,
+            additional_args: Vec::new(),
+        }];
+        let streams = vec![
+            json!({ "index": 0, "codec_type": "video", "codec_name": "h264" }),
+            json!({ "index": 1, "codec_type": "audio", "codec_name": "aac", "channels": 2 }),
+            json!({ "index": 2, "codec_type": "audio", "codec_name": "ac3", "channels": 6, "bit_rate": "448000" }),
+        ];
+
+        let plan = plan_target_size(&request, &streams, Some(2_640_000_000))
+            .expect("plan should build")
+            .expect("target size plan expected");
+
+        assert_eq!(plan.target_bytes, 734_003_200);
+        assert_eq!(plan.audio_bitrate_kbps, 128 + 448);
+        // 734003200 B * 8 * 0.98 / 2640 s = 2179.8 kb/s in total
+        assert_eq!(plan.video_bitrate_kbps, 2179 - 576);
+
+        let bitrate_request = request_with_video_bitrate(&request, plan.video_bitrate_kbps);
+        assert_eq!(
+            bitrate_request.video.quality_mode.as_deref(),
+            Some("bitrate")
+        );
+        assert_eq!(bitrate_request.video.bitrate_kbps, Some(1603));
+        assert!(bitrate_request.video.two_pass);
+    }
+
+    #[test]
+    fn plan_target_size_rejects_targets_below_the_audio_budget() {
+        let mut request = build_request("/tmp/output.mp4");
+        request.video.quality_mode = Some("targetSize".to_string());
+        request.video.target_size_mb = Some(5.0);
+        let streams = vec![
+            json!({ "codec_type": "video", "codec_name": "h264" }),
+            json!({ "codec_type": "audio", "codec_name": "aac", "channels": 2 }),
+        ];
+
+        let error = plan_target_size(&request, &streams, Some(3_600_000_000))
+            .expect_err("tiny target should fail");
+        assert!(error.contains("too small"));
+
+        let error =
+            plan_target_size(&request, &streams, None).expect_err("unknown duration should fail");
+        assert!(error.contains("known input duration"));
+    }
+
+    #[test]
+    fn retry_video_bitrate_scales_down_only_after_overshoot() {
+        let plan = TargetSizePlan {
+            target_bytes: 100_000_000,
+            video_bitrate_kbps: 2000,
+            audio_bitrate_kbps: 200,
+        };
+
+        assert_eq!(retry_video_bitrate_kbps(&plan, 2000, 99_000_000), None);
+        let retry =
+            retry_video_bitrate_kbps(&plan, 2000, 110_000_000).expect("overshoot should retry");
+        // (2200 * 100 / 110 * 0.98) - 200 = 1760
+        assert_eq!(retry, 1760);
+    }
+
+    fn range(start_ms: u64, end_ms: Option<u64>) -> TranscodeTimeRange {
+        TranscodeTimeRange { start_ms, end_ms }
+    }
+
+    #[test]
+    fn build_transcode_args_seeks_and_limits_a_single_range() {
+        let mut request = build_request("/tmp/output.mkv");
+        request.container_id = "mkv".to_string();
+        request.subtitles.encoder_id = Some("srt".to_string());
+        request.ranges = vec![range(90_500, Some(150_000))];
+        let streams = vec![
+            json!({ "codec_type": "video", "codec_name": "h264" }),
+            json!({ "codec_type": "audio", "codec_name": "aac" }),
+            json!({ "codec_type": "subtitle", "codec_name": "subrip" }),
+        ];
+
+        let args = build_transcode_args(&request, &TranscodeContext::default(), &streams, None)
+            .expect("args should build");
+
+        assert_eq!(&args[2..6], ["-ss", "90.500", "-i", "/tmp/input.mp4"]);
+        assert!(args.windows(2).any(|window| window == ["-t", "59.500"]));
+        assert!(args.windows(2).any(|window| window == ["-map", "0:s:0"]));
+        assert!(!args.iter().any(|arg| arg == "-filter_complex"));
+    }
+
+    #[test]
+    fn build_transcode_args_joins_several_ranges_with_concat() {
+        let mut request = build_request("/tmp/output.mp4");
+        request.subtitles.mode = "disable".to_string();
+        request.ranges = vec![range(0, Some(10_000)), range(20_000, None)];
+        request.video.filters.scale = Some(TranscodeScaleFilter {
+            height: Some(720),
+            ..TranscodeScaleFilter::default()
+        });
+        let streams = vec![
+            json!({ "codec_type": "video", "codec_name": "h264" }),
+            json!({ "codec_type": "audio", "codec_name": "aac" }),
+            json!({ "codec_type": "audio", "codec_name": "ac3" }),
+        ];
+
+        let args = build_transcode_args(&request, &TranscodeContext::default(), &streams, None)
+            .expect("args should build");
+
+        assert_eq!(
+            &args[2..11],
+            [
+                "-t",
+                "10.000",
+                "-i",
+                "/tmp/input.mp4",
+                "-ss",
+                "20.000",
+                "-i",
+                "/tmp/input.mp4",
+                "-map"
+            ]
+        );
+        assert!(args.windows(2).any(|window| {
+            window
+                == [
+                    "-filter_complex",
+                    "[0:v:0][0:a:0][0:a:1][1:v:0][1:a:0][1:a:1]concat=n=2:v=1:a=2[vcat][a0][a1];[vcat]scale=-2:720[v]",
+                ]
+        }));
+        assert!(args.windows(2).any(|window| window == ["-map", "[a1]"]));
+        assert!(
+            args.windows(2)
+                .any(|window| window == ["-map_chapters", "-1"])
+        );
+        assert!(!args.iter().any(|arg| arg == "-vf"));
+    }
+
+    #[test]
+    fn build_transcode_args_shifts_subtitles_and_chapters_when_joining_ranges() {
+        let mut request = build_request("/tmp/output.mkv");
+        request.container_id = "mkv".to_string();
+        request.metadata.keep_chapters = true;
+        request.ranges = vec![range(0, Some(10_000)), range(20_000, Some(30_000))];
+        let streams = vec![
+            json!({ "codec_type": "video", "codec_name": "h264" }),
+            json!({ "codec_type": "subtitle", "codec_name": "subrip" }),
+        ];
+        let context = TranscodeContext {
+            range_join: RangeJoinInputs {
+                subtitle_list_path: Some("/tmp/ranges/subtitles.txt".to_string()),
+                chapters_path: Some("/tmp/ranges/chapters.ffmeta".to_string()),
+            },
+            ..TranscodeContext::default()
+        };
+
+        let args =
+            build_transcode_args(&request, &context, &streams, None).expect("args should build");
+
+        let list_position = args
             .iter()
-            .find(|encoder| {
-                Some(&encoder.id) == container.default_video_encoder_id.as_ref()
-                    && !should_skip_videotoolbox_encoder_in_ci(&encoder.id, ci_enabled)
-            })
-            .or_else(|| {
-                capabilities.video_encoders.iter().find(|encoder| {
-                    is_supported(encoder)
-                        && !should_skip_videotoolbox_encoder_in_ci(&encoder.id, ci_enabled)
-                })
-            })
+            .position(|arg| arg == "/tmp/ranges/subtitles.txt")
+            .expect("subtitle list should be an input");
+        assert_eq!(
+            &args[list_position - 5..list_position],
+            ["-f", "concat", "-safe", "0", "-i"]
+        );
+        assert!(
+            args.windows(2)
+                .any(|window| window == ["-i", "/tmp/ranges/chapters.ffmeta"])
+        );
+        assert!(args.windows(2).any(|window| window == ["-map", "2:s:0"]));
+        assert!(
+            args.windows(2)
+                .any(|window| window == ["-map_chapters", "3"])
+        );
+
+        let error = build_transcode_args(&request, &TranscodeContext::default(), &streams, None)
+            .expect_err("subtitles need the prepared list");
+        assert!(error.contains("were not prepared"));
     }
 
-    fn test_video_encoder(id: &str, is_hardware: bool) -> TranscodeVideoEncoderCapability {
-        TranscodeVideoEncoderCapability {
-            id: id.to_string(),
-            codec: "h264".to_string(),
-            label: id.to_string(),
-            is_hardware,
-            supported_pixel_formats: Vec::new(),
-            supported_profiles: Vec::new(),
-            supported_levels: Vec::new(),
-            supported_bit_depths: Vec::new(),
-            supports_preset: false,
-            supports_crf: false,
-            supports_qp: false,
-            supports_bitrate: false,
-            options: Vec::new(),
-        }
+    #[test]
+    fn build_transcode_args_rejects_copy_when_joining_ranges() {
+        let mut request = build_request("/tmp/output.mp4");
+        request.ranges = vec![range(0, Some(10_000)), range(20_000, Some(30_000))];
+        let streams = vec![
+            json!({ "codec_type": "video", "codec_name": "h264" }),
+            json!({ "codec_type": "subtitle", "codec_name": "mov_text" }),
+        ];
+
+        request.subtitles.mode = "disable".to_string();
+        request.video.mode = "copy".to_string();
+        let error = build_transcode_args(&request, &TranscodeContext::default(), &streams, None)
+            .expect_err("video copy should be rejected");
+        assert!(error.contains("re-encodes the video"));
     }
 
-    fn test_video_container(
-        default_video_encoder_id: Option<&str>,
-        supported_video_encoder_ids: &[&str],
-    ) -> TranscodeContainerCapability {
-        TranscodeContainerCapability {
-            id: "mkv".to_string(),
-            label: "MKV".to_string(),
-            extension: ".mkv".to_string(),
-            kind: "video".to_string(),
-            muxer_name: "matroska".to_string(),
-            supported_video_encoder_ids: supported_video_encoder_ids
-                .iter()
-                .map(|id| id.to_string())
-                .collect(),
-            supported_audio_encoder_ids: Vec::new(),
-            supported_subtitle_encoder_ids: Vec::new(),
-            supported_subtitle_modes: Vec::new(),
-            default_video_encoder_id: default_video_encoder_id.map(str::to_string),
-            default_audio_encoder_id: None,
-            default_subtitle_encoder_id: None,
-            metadata_schema: metadata_schema_for_container("mkv"),
-        }
+    #[test]
+    fn build_transcode_args_copies_attached_pictures_and_skips_extra_angles() {
+        let mut request = build_request("/tmp/output.mkv");
+        request.container_id = "mkv".to_string();
+        request.subtitles.mode = "disable".to_string();
+        let streams = vec![
+            json!({ "index": 0, "codec_type": "video", "codec_name": "h264" }),
+            json!({ "index": 1, "codec_type": "video", "codec_name": "h264" }),
+            json!({ "index": 2, "codec_type": "audio", "codec_name": "aac" }),
+            json!({
+                "index": 3,
+                "codec_type": "video",
+                "codec_name": "mjpeg",
+                "disposition": { "attached_pic": 1 }
+            }),
+        ];
+
+        let args = build_transcode_args(&request, &TranscodeContext::default(), &streams, None)
+            .expect("args should build");
+
+        assert!(args.windows(2).any(|window| window == ["-map", "0:v:0"]));
+        assert!(!args.windows(2).any(|window| window == ["-map", "0:v:1"]));
+        assert!(args.windows(2).any(|window| window == ["-map", "0:v:2"]));
+        assert!(
+            args.windows(2)
+                .any(|window| window == ["-c:v:0", "libx264"])
+        );
+        assert!(args.windows(2).any(|window| window == ["-c:v:1", "copy"]));
+        assert!(
+            args.windows(2)
+                .any(|window| window == ["-disposition:1", "attached_pic"])
+        );
     }
 
     #[test]
-    fn videotoolbox_skip_predicate_only_applies_in_ci() {
-        assert!(should_skip_videotoolbox_encoder_in_ci(
-            "h264_videotoolbox",
-            true
-        ));
-        assert!(!should_skip_videotoolbox_encoder_in_ci(
-            "h264_videotoolbox",
-            false
-        ));
-        assert!(!should_skip_videotoolbox_encoder_in_ci("libx264", true));
+    fn build_transcode_args_applies_video_track_overrides_with_qualified_flags() {
+        let mut request = build_request("/tmp/output.mkv");
+        request.container_id = "mkv".to_string();
+        request.subtitles.mode = "disable".to_string();
+        request.video.track_overrides = vec![TranscodeVideoTrackOverride {
+            track_id: 1,
+            mode: "transcode".to_string(),
+            encoder_id: Some("libx265".to_string()),
+            profile: Some("main".to_string()),
+            level: None,
+            pixel_format: None,
+            quality_mode: None,
+            crf: Some(26.0),
+            qp: None,
+            bitrate_kbps: None,
+            preset: Some("slow".to_string()),
+            additional_args: Vec::new(),
+        }];
+        let streams = vec![
+            json!({ "index": 0, "codec_type": "video", "codec_name": "h264" }),
+            json!({ "index": 1, "codec_type": "video", "codec_name": "h264" }),
+        ];
+
+        let args = build_transcode_args(&request, &TranscodeContext::default(), &streams, None)
+            .expect("args should build");
+
+        assert!(args.windows(2).any(|window| window == ["-map", "0:v:1"]));
+        assert!(
+            args.windows(2)
+                .any(|window| window == ["-c:v:0", "libx264"])
+        );
+        assert!(
+            args.windows(2)
+                .any(|window| window == ["-c:v:1", "libx265"])
+        );
+        assert!(
+            args.windows(2)
+                .any(|window| window == ["-profile:v:1", "main"])
+        );
+        assert!(args.windows(2).any(|window| window == ["-crf:v:1", "26"]));
+        assert!(
+            args.windows(2)
+                .any(|window| window == ["-preset:v:1", "slow"])
+        );
+        assert!(!args.iter().any(|arg| arg == "-c:v"));
+    }
+
+    #[test]
+    fn build_transcode_args_follows_requested_stream_order() {
+        let mut request = build_request("/tmp/output.mkv");
+        request.container_id = "mkv".to_string();
+        request.subtitles.mode = "disable".to_string();
+        request.stream_order = vec![2, 0];
+        request.metadata.track_edits = vec![TrackMetadataEdit {
+            source_track_id: 2,
+            title: None,
+            language: None,
+            default: Some(true),
+            forced: None,
+        }];
+        let streams = vec![
+            json!({ "index": 0, "codec_type": "video", "codec_name": "h264" }),
+            json!({ "index": 1, "codec_type": "audio", "codec_name": "ac3" }),
+            json!({ "index": 2, "codec_type": "audio", "codec_name": "aac", "tags": { "language": "eng" } }),
+        ];
+
+        let args = build_transcode_args(&request, &TranscodeContext::default(), &streams, None)
+            .expect("args should build");
+        let maps = args
+            .windows(2)
+            .filter(|window| window[0] == "-map")
+            .map(|window| window[1].as_str())
+            .collect::<Vec<_>>();
+
+        assert_eq!(maps, ["0:a:1", "0:v:0", "0:a:0"]);
+        assert!(args.windows(2).any(|window| window == ["-c:a:0", "aac"]));
+        assert!(
+            args.windows(2)
+                .any(|window| window == ["-disposition:0", "default"])
+        );
+        assert!(
+            args.windows(2)
+                .any(|window| window == ["-disposition:2", "0"])
+        );
+    }
+
+    #[test]
+    fn build_transcode_args_burns_in_text_and_bitmap_subtitles() {
+        let mut request = build_request("/tmp/output.mp4");
+        request.input_path = "/tmp/input.mkv".to_string();
+        request.subtitles.mode = "disable".to_string();
+        request.video.filters.scale = Some(TranscodeScaleFilter {
+            height: Some(720),
+            ..TranscodeScaleFilter::default()
+        });
+        request.burn_in = Some(TranscodeSubtitleBurnIn {
+            track_id: Some(1),
+            ..TranscodeSubtitleBurnIn::default()
+        });
+        let streams = vec![
+            json!({ "index": 0, "codec_type": "video", "codec_name": "h264" }),
+            json!({ "index": 1, "codec_type": "subtitle", "codec_name": "ass" }),
+            json!({ "index": 2, "codec_type": "subtitle", "codec_name": "hdmv_pgs_subtitle" }),
+        ];
+
+        let args = build_transcode_args(&request, &TranscodeContext::default(), &streams, None)
+            .expect("args should build");
+        assert!(args.windows(2).any(|window| {
+            window
+                == [
+                    "-vf",
+                    "subtitles=filename='/tmp/input.mkv':si=0,scale=-2:720",
+                ]
+        }));
+
+        request.burn_in = Some(TranscodeSubtitleBurnIn {
+            track_id: Some(2),
+            forced_only: true,
+            ..TranscodeSubtitleBurnIn::default()
+        });
+        let args = build_transcode_args(&request, &TranscodeContext::default(), &streams, None)
+            .expect("args should build");
+        assert_eq!(
+            &args[2..6],
+            ["-forced_subs_only:s:1", "1", "-i", "/tmp/input.mkv"]
+        );
+        assert!(args.windows(2).any(|window| {
+            window
+                == [
+                    "-filter_complex",
+                    "[0:v:0][0:s:1]overlay,scale=-2:720[vburn]",
+                ]
+        }));
+        assert!(args.windows(2).any(|window| window == ["-map", "[vburn]"]));
+        assert!(!args.iter().any(|arg| arg == "-vf"));
+
+        request.video.mode = "copy".to_string();
+        request.video.filters = TranscodeVideoFilters::default();
+        let error = build_transcode_args(&request, &TranscodeContext::default(), &streams, None)
+            .expect_err("burn-in needs video transcoding");
+        assert!(error.contains("burn-in requires"));
+    }
+
+    #[test]
+    fn build_transcode_args_applies_per_track_audio_filters() {
+        let mut request = build_request("/tmp/output.mkv");
+        request.container_id = "mkv".to_string();
+        request.subtitles.mode = "disable".to_string();
+        request.audio.channels = None;
+        request.audio.filters.gain = Some(TranscodeGainFilter { db: 2.5 });
+        request.audio.track_overrides = vec![TranscodeAudioTrackOverride {
+            track_id: 2,
+            mode: "transcode".to_string(),
+            encoder_id: None,
+            bitrate_kbps: None,
+            channels: None,
+            sample_rate: None,
+            filters: Some(TranscodeAudioFilters {
+                downmix: Some(TranscodeDownmixFilter::default()),
+                ..TranscodeAudioFilters::default()
+            }),
+            additional_args: Vec::new(),
+        }];
+        let streams = vec![
+            json!({ "index": 0, "codec_type": "video", "codec_name": "h264" }),
+            json!({ "index": 1, "codec_type": "audio", "codec_name": "aac", "channels": 2 }),
+            json!({ "index": 2, "codec_type": "audio", "codec_name": "ac3", "channels": 6 }),
+        ];
+
+        let args = build_transcode_args(&request, &TranscodeContext::default(), &streams, None)
+            .expect("args should build");
+        assert!(
+            args.windows(2)
+                .any(|window| window == ["-filter:a:0", "volume=2.5dB"])
+        );
+        assert!(args.windows(2).any(|window| {
+            window
+                == [
+                    "-filter:a:1",
+                    "pan=stereo|FL<c0+0.707*c2+0.707*c4|FR<c1+0.707*c2+0.707*c5",
+                ]
+        }));
+
+        request.audio.mode = "copy".to_string();
+        request.audio.track_overrides.clear();
+        let error = build_transcode_args(&request, &TranscodeContext::default(), &streams, None)
+            .expect_err("filters need audio transcoding");
+        assert!(error.contains("Audio filters require audio transcoding"));
+    }
+
+    #[test]
+    fn build_transcode_args_adds_derived_audio_track_next_to_original() {
+        let mut request = build_request("/tmp/output.mkv");
+        request.container_id = "mkv".to_string();
+        request.subtitles.mode = "disable".to_string();
+        request.audio.mode = "copy".to_string();
+        request.audio.derived_tracks = vec![TranscodeDerivedAudioTrack {
+            source_track_id: 1,
+            encoder_id: Some("aac".to_string()),
+            bitrate_kbps: Some(192),
+            channels: Some(2),
+            sample_rate: None,
+            filters: None,
+            title: Some("Stereo".to_string()),
+            language: None,
+            default: Some(true),
+            additional_args: Vec::new(),
+        }];
+        let streams = vec![
+            json!({ "index": 0, "codec_type": "video", "codec_name": "h264" }),
+            json!({
+                "index": 1,
+                "codec_type": "audio",
+                "codec_name": "truehd",
+                "channels": 8,
+                "tags": { "language": "eng" },
+                "disposition": { "default": 1 }
+            }),
+            json!({ "index": 2, "codec_type": "audio", "codec_name": "ac3", "channels": 6 }),
+        ];
+
+        let args = build_transcode_args(&request, &TranscodeContext::default(), &streams, None)
+            .expect("args should build");
+        let maps = args
+            .windows(2)
+            .filter(|window| window[0] == "-map")
+            .map(|window| window[1].as_str())
+            .collect::<Vec<_>>();
+
+        assert_eq!(maps, ["0:v:0", "0:a:0", "0:a:0", "0:a:1"]);
+        assert!(args.windows(2).any(|window| window == ["-c:a:0", "copy"]));
+        assert!(args.windows(2).any(|window| window == ["-c:a:1", "aac"]));
+        assert!(args.windows(2).any(|window| window == ["-ac:a:1", "2"]));
+        assert!(args.windows(2).any(|window| window == ["-c:a:2", "copy"]));
+        assert!(
+            args.windows(2)
+                .any(|window| window == ["-metadata:s:2", "title=Stereo"])
+        );
+        assert!(
+            args.windows(2)
+                .any(|window| window == ["-metadata:s:2", "language=eng"])
+        );
+        assert!(
+            args.windows(2)
+                .any(|window| window == ["-disposition:1", "0"])
+        );
+        assert!(
+            args.windows(2)
+                .any(|window| window == ["-disposition:2", "default"])
+        );
+
+        request.audio.derived_tracks[0].source_track_id = 0;
+        let error = build_transcode_args(&request, &TranscodeContext::default(), &streams, None)
+            .expect_err("derived source must be audio");
+        assert!(error.contains("is not an audio track"));
+    }
+
+    #[test]
+    fn transcode_ranges_validate_and_shorten_the_duration() {
+        let ranges = vec![range(0, Some(10_000)), range(50_000, None)];
+        assert_eq!(
+            trimmed_duration_us(&ranges, Some(60_000_000)),
+            Some(20_000_000)
+        );
+        assert_eq!(trimmed_duration_us(&ranges, None), None);
+        assert_eq!(trimmed_duration_us(&[], Some(5)), Some(5));
+
+        assert!(validate_transcode_ranges(&ranges, Some(60_000_000)).is_ok());
+        assert!(validate_transcode_ranges(&ranges, Some(40_000_000)).is_err());
+        assert!(validate_transcode_ranges(&[range(5_000, Some(5_000))], None).is_err());
+        assert!(
+            validate_transcode_ranges(&[range(0, None), range(10_000, Some(20_000))], None)
+                .is_err()
+        );
     }
 
     #[test]
-    fn runtime_video_encoder_selection_prefers_software_when_default_is_skipped() {
-        let capabilities = TranscodeCapabilities {
-            ffmpeg_version: "8.1".to_string(),
-            hwaccels: Vec::new(),
-            containers: Vec::new(),
-            video_encoders: vec![
-                test_video_encoder("hevc_videotoolbox", true),
-                test_video_encoder("libx264", false),
-            ],
-            audio_encoders: Vec::new(),
-            subtitle_encoders: Vec::new(),
-            default_analysis_frame_count: 6,
-        };
-        let container =
-            test_video_container(Some("hevc_videotoolbox"), &["hevc_videotoolbox", "libx264"]);
+    fn validate_transcode_ranges_rejects_unordered_and_overlapping_ranges() {
+        let overlapping = vec![range(0, Some(10_000)), range(5_000, Some(20_000))];
+        let error = validate_transcode_ranges(&overlapping, None)
+            .expect_err("overlapping ranges should fail");
+        assert_eq!(error, "Range 2 must start after range 1 ends");
 
-        assert!(should_skip_videotoolbox_encoder_in_ci(
-            "hevc_videotoolbox",
-            true
-        ));
-        let selected =
-            select_runtime_video_encoder_for_container_with_ci(&capabilities, &container, true)
-                .expect("software fallback should be selected");
+        let unordered = vec![range(20_000, Some(30_000)), range(0, Some(10_000))];
+        assert!(validate_transcode_ranges(&unordered, None).is_err());
 
-        assert_eq!(selected.id, "libx264");
+        let touching = vec![range(0, Some(10_000)), range(10_000, None)];
+        assert!(validate_transcode_ranges(&touching, None).is_ok());
     }
 
-    fn assert_media_counts(
-        counts: &MediaStreamCounts,
-        expected_video_streams: usize,
-        expected_audio_streams: usize,
-    ) -> Option<String> {
-        if counts.video_streams != expected_video_streams
-            || counts.audio_streams != expected_audio_streams
-        {
-            return Some(format!(
-                "expected {} video and {} audio streams, got {} video and {} audio streams",
-                expected_video_streams,
-                expected_audio_streams,
-                counts.video_streams,
-                counts.audio_streams
-            ));
-        }
+    #[test]
+    fn transcode_phase_spreads_progress_across_passes() {
+        let first = TranscodePhase {
+            pass: 1,
+            pass_count: 2,
+        };
+        let second = TranscodePhase {
+            pass: 2,
+            pass_count: 2,
+        };
 
-        None
+        assert_eq!(first.overall_progress(50), 25);
+        assert_eq!(second.overall_progress(0), 50);
+        assert_eq!(second.overall_progress(100), 100);
     }
 
-    fn should_skip_audio_matrix_case(
-        encoder_id: &str,
-        layout: &str,
-        input_channels: u64,
-    ) -> Option<&'static str> {
-        if encoder_id == "libvorbis" && input_channels > 6 {
-            return Some("libvorbis does not support more than 6 channels in this FFmpeg build");
-        }
-
-        if encoder_id == "aac_at" && layout == "7.1" {
-            return Some(
-                "AudioToolbox AAC exposes 7.1(wide) instead of standard 7.1 on this platform",
-            );
-        }
+    #[test]
+    fn build_transcode_args_rejects_copying_subrip_subtitles_into_mp4() {
+        let mut request = build_request("/tmp/output.mp4");
+        request.subtitles.mode = "copy".to_string();
+        request.subtitles.encoder_id = None;
+        let streams = vec![
+            json!({ "codec_type": "video", "codec_name": "h264" }),
+            json!({ "codec_type": "subtitle", "codec_name": "subrip" }),
+        ];
 
-        None
+        let error = build_transcode_args(&request, &TranscodeContext::default(), &streams, None)
+            .expect_err("subrip copy to mp4 should fail");
+        assert!(error.contains("cannot copy subtitle codec"));
     }
 
     #[test]
-    fn build_transcode_args_includes_main_video_audio_and_safe_subtitle_handling() {
-        let mut request = build_request("/tmp/output.mkv");
-        request.container_id = "mkv".to_string();
-        request.subtitles.encoder_id = Some("srt".to_string());
+    fn build_transcode_args_drops_bitmap_subtitles_that_mp4_cannot_store() {
+        let request = build_request("/tmp/output.mp4");
         let streams = vec![
-            json!({ "codec_type": "video", "codec_name": "h264" }),
-            json!({ "codec_type": "audio", "codec_name": "aac" }),
-            json!({ "codec_type": "subtitle", "codec_name": "subrip" }),
-            json!({ "codec_type": "subtitle", "codec_name": "hdmv_pgs_subtitle" }),
+            json!({ "index": 0, "codec_type": "video", "codec_name": "h264" }),
+            json!({ "index": 1, "codec_type": "subtitle", "codec_name": "hdmv_pgs_subtitle" }),
+            json!({ "index": 2, "codec_type": "subtitle", "codec_name": "subrip" }),
         ];
 
-        let args =
-            build_transcode_args(&request, &streams, Some(10_000_000)).expect("args should build");
+        let args = build_transcode_args(&request, &TranscodeContext::default(), &streams, None)
+            .expect("args should build");
+        let warnings = build_subtitle_drop_warnings(&request, &streams);
 
-        assert!(args.windows(2).any(|window| window == ["-c:v", "libx264"]));
-        assert!(args.windows(2).any(|window| window == ["-c:a:0", "aac"]));
-        assert!(args.windows(2).any(|window| window == ["-c:s:0", "srt"]));
-        assert!(args.windows(2).any(|window| window == ["-c:s:1", "copy"]));
+        assert!(!args.windows(2).any(|window| window == ["-map", "0:s:0"]));
+        assert!(args.windows(2).any(|window| window == ["-map", "0:s:1"]));
         assert!(
             args.windows(2)
-                .any(|window| window == ["-progress", "pipe:1"])
+                .any(|window| window == ["-c:s:0", "mov_text"])
         );
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("hdmv_pgs_subtitle"));
+    }
+
+    fn build_auto_request(container_id: &str) -> TranscodeRequest {
+        let mut request = build_request(&format!("/tmp/output.{}", container_id));
+        request.container_id = container_id.to_string();
+        request.video.mode = "auto".to_string();
+        request.video.encoder_id = None;
+        request.audio.mode = "auto".to_string();
+        request.audio.encoder_id = None;
+        request.audio.bitrate_kbps = None;
+        request.audio.channels = None;
+        request.audio.sample_rate = None;
+        request.subtitles.mode = "auto".to_string();
+        request.subtitles.encoder_id = None;
+        request
     }
 
     #[test]
-    fn build_transcode_args_rejects_blocked_additional_args() {
-        let mut request = build_request("/tmp/output.mp4");
-        request.video.additional_args = vec![TranscodeAdditionalArg {
-            _id: None,
-            flag: "-map".to_string(),
-            value: Some("0".to_string()),
-            enabled: true,
-        }];
-        let streams = vec![json!({ "codec_type": "video", "codec_name": "h264" })];
+    fn auto_mode_copies_what_fits_and_falls_back_per_container() {
+        let streams = vec![
+            json!({ "index": 0, "codec_type": "video", "codec_name": "h264" }),
+            json!({ "index": 1, "codec_type": "audio", "codec_name": "dts", "channels": 6 }),
+            json!({ "index": 2, "codec_type": "audio", "codec_name": "aac", "channels": 2 }),
+            json!({ "index": 3, "codec_type": "subtitle", "codec_name": "subrip" }),
+            json!({ "index": 4, "codec_type": "subtitle", "codec_name": "hdmv_pgs_subtitle" }),
+        ];
 
-        let error =
-            build_transcode_args(&request, &streams, None).expect_err("blocked arg should fail");
-        assert!(error.contains("not allowed"));
+        let request = build_auto_request("mp4");
+        let plan = build_transcode_stream_plan(&request, &streams).expect("plan should build");
+        let actions = plan
+            .iter()
+            .map(|entry| {
+                (
+                    entry.action.as_str(),
+                    entry.encoder_id.as_deref(),
+                    entry.bitrate_kbps,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            actions,
+            vec![
+                ("copy", None, None),
+                ("transcode", Some("aac"), Some(640)),
+                ("copy", None, None),
+                ("convert_text", Some("mov_text"), None),
+                ("drop", None, None),
+            ]
+        );
+        assert_eq!(
+            plan[1].reason.as_deref(),
+            Some("MP4 cannot store dts audio")
+        );
+
+        let args = build_transcode_args(&request, &TranscodeContext::default(), &streams, None)
+            .expect("args should build");
+        assert!(args_contain_pair(&args, "-c:v", "copy"));
+        assert!(args_contain_pair(&args, "-c:a:0", "aac"));
+        assert!(args_contain_pair(&args, "-b:a:0", "640k"));
+        assert!(args_contain_pair(&args, "-c:a:1", "copy"));
+        assert!(args_contain_pair(&args, "-c:s:0", "mov_text"));
+        assert!(!args.windows(2).any(|window| window == ["-map", "0:s:1"]));
+
+        let plan = build_transcode_stream_plan(&build_auto_request("webm"), &streams)
+            .expect("plan should build");
+        assert_eq!(plan[0].encoder_id.as_deref(), Some("libvpx-vp9"));
+        assert_eq!(
+            (plan[2].encoder_id.as_deref(), plan[2].bitrate_kbps),
+            (Some("libopus"), Some(160))
+        );
+        assert_eq!(plan[3].encoder_id.as_deref(), Some("webvtt"));
+
+        let mut filtered = build_auto_request("mp4");
+        filtered.video.filters.scale = Some(TranscodeScaleFilter {
+            width: Some(1280),
+            height: None,
+            aspect_mode: None,
+            algorithm: None,
+        });
+        let resolved = resolve_auto_video_modes(&filtered, &streams);
+        assert_eq!(resolved.video.mode, "transcode");
+        assert_eq!(resolved.video.encoder_id.as_deref(), Some("libx264"));
     }
 
     #[test]
-    fn build_transcode_args_rejects_copying_subrip_subtitles_into_mp4() {
-        let mut request = build_request("/tmp/output.mp4");
+    fn expected_output_streams_use_the_encoder_codec_or_the_copied_codec() {
+        let mut request = build_auto_request("mkv");
+        request.video.mode = "transcode".to_string();
+        request.video.encoder_id = Some("libx265".to_string());
+        request.subtitles.mode = "convert_text".to_string();
+        request.subtitles.encoder_id = Some("srt".to_string());
+        let streams = vec![
+            json!({ "index": 0, "codec_type": "video", "codec_name": "h264" }),
+            json!({ "index": 1, "codec_type": "audio", "codec_name": "truehd", "channels": 8 }),
+            json!({ "index": 2, "codec_type": "subtitle", "codec_name": "ass" }),
+            json!({ "index": 3, "codec_type": "subtitle", "codec_name": "hdmv_pgs_subtitle" }),
+        ];
+
+        let expected = |codec_type: &str, codec_name: &str| ExpectedOutputStream {
+            codec_type: codec_type.to_string(),
+            codec_name: Some(codec_name.to_string()),
+        };
+        assert_eq!(
+            expected_output_streams(&request, &streams).expect("expected streams"),
+            vec![
+                expected("video", "hevc"),
+                expected("audio", "truehd"),
+                expected("subtitle", "srt"),
+                expected("subtitle", "hdmv_pgs_subtitle"),
+            ]
+        );
+    }
+
+    #[test]
+    fn build_transcode_args_applies_subtitle_track_overrides() {
+        let mut request = build_request("/tmp/output.mkv");
+        request.container_id = "mkv".to_string();
         request.subtitles.mode = "copy".to_string();
         request.subtitles.encoder_id = None;
+        request.subtitles.track_overrides = vec![
+            TranscodeSubtitleTrackOverride {
+                track_id: 1,
+                mode: "disable".to_string(),
+                encoder_id: None,
+                additional_args: Vec::new(),
+            },
+            TranscodeSubtitleTrackOverride {
+                track_id: 3,
+                mode: "convert_text".to_string(),
+                encoder_id: Some("ass".to_string()),
+                additional_args: Vec::new(),
+            },
+        ];
         let streams = vec![
-            json!({ "codec_type": "video", "codec_name": "h264" }),
-            json!({ "codec_type": "subtitle", "codec_name": "subrip" }),
+            json!({ "index": 0, "codec_type": "video", "codec_name": "h264" }),
+            json!({ "index": 1, "codec_type": "subtitle", "codec_name": "subrip" }),
+            json!({ "index": 2, "codec_type": "subtitle", "codec_name": "hdmv_pgs_subtitle" }),
+            json!({ "index": 3, "codec_type": "subtitle", "codec_name": "subrip" }),
         ];
 
-        let error = build_transcode_args(&request, &streams, None)
-            .expect_err("subrip copy to mp4 should fail");
+        let args = build_transcode_args(&request, &TranscodeContext::default(), &streams, None)
+            .expect("args should build");
+
+        assert!(!args.windows(2).any(|window| window == ["-map", "0:s:0"]));
+        assert!(args.windows(2).any(|window| window == ["-c:s:0", "copy"]));
+        assert!(args.windows(2).any(|window| window == ["-c:s:1", "ass"]));
+
+        request.container_id = "mp4".to_string();
+        request.output_path = "/tmp/output.mp4".to_string();
+        request.subtitles.mode = "disable".to_string();
+        request.subtitles.track_overrides = vec![TranscodeSubtitleTrackOverride {
+            track_id: 2,
+            mode: "copy".to_string(),
+            encoder_id: None,
+            additional_args: Vec::new(),
+        }];
+        let error = build_transcode_args(&request, &TranscodeContext::default(), &streams, None)
+            .expect_err("explicit bitmap copy to mp4 should fail");
         assert!(error.contains("cannot copy subtitle codec"));
     }
 
@@ -2150,7 +6093,7 @@ mod tests {
         request.subtitles.mode = "disable".to_string();
         let streams = vec![json!({ "codec_type": "video", "codec_name": "h264" })];
 
-        let error = build_transcode_args(&request, &streams, None)
+        let error = build_transcode_args(&request, &TranscodeContext::default(), &streams, None)
             .expect_err("h264 copy to webm should fail");
         assert!(error.contains("cannot copy video codec"));
     }
@@ -2164,7 +6107,7 @@ mod tests {
         request.subtitles.mode = "disable".to_string();
         let streams = vec![json!({ "codec_type": "audio", "codec_name": "aac" })];
 
-        let error = build_transcode_args(&request, &streams, None)
+        let error = build_transcode_args(&request, &TranscodeContext::default(), &streams, None)
             .expect_err("aac copy to webm should fail");
         assert!(error.contains("cannot copy audio codec"));
     }
@@ -2177,8 +6120,8 @@ mod tests {
         request.video.preset = Some("4".to_string());
         let streams = vec![json!({ "codec_type": "video", "codec_name": "h264" })];
 
-        let args =
-            build_transcode_args(&request, &streams, None).expect("libaom args should build");
+        let args = build_transcode_args(&request, &TranscodeContext::default(), &streams, None)
+            .expect("libaom args should build");
 
         assert!(args.windows(2).any(|window| window == ["-cpu-used", "4"]));
         assert!(!args.iter().any(|arg| arg == "-preset"));
@@ -2192,7 +6135,7 @@ mod tests {
         request.video.preset = Some("9".to_string());
         let streams = vec![json!({ "codec_type": "video", "codec_name": "h264" })];
 
-        let error = build_transcode_args(&request, &streams, None)
+        let error = build_transcode_args(&request, &TranscodeContext::default(), &streams, None)
             .expect_err("invalid libaom preset should fail");
 
         assert!(error.contains("libaom-av1 preset must be an integer from 0 to 8"));
@@ -2206,8 +6149,8 @@ mod tests {
         request.video.preset = Some("16".to_string());
         let streams = vec![json!({ "codec_type": "video", "codec_name": "h264" })];
 
-        let args =
-            build_transcode_args(&request, &streams, None).expect("libvpx args should build");
+        let args = build_transcode_args(&request, &TranscodeContext::default(), &streams, None)
+            .expect("libvpx args should build");
 
         assert!(args.windows(2).any(|window| window == ["-cpu-used", "16"]));
         assert!(!args.iter().any(|arg| arg == "-preset"));
@@ -2221,8 +6164,8 @@ mod tests {
         request.video.preset = Some("8".to_string());
         let streams = vec![json!({ "codec_type": "video", "codec_name": "h264" })];
 
-        let args =
-            build_transcode_args(&request, &streams, None).expect("libvpx-vp9 args should build");
+        let args = build_transcode_args(&request, &TranscodeContext::default(), &streams, None)
+            .expect("libvpx-vp9 args should build");
 
         assert!(args.windows(2).any(|window| window == ["-cpu-used", "8"]));
         assert!(!args.iter().any(|arg| arg == "-preset"));
@@ -2236,7 +6179,7 @@ mod tests {
         request.video.preset = Some("17".to_string());
         let streams = vec![json!({ "codec_type": "video", "codec_name": "h264" })];
 
-        let error = build_transcode_args(&request, &streams, None)
+        let error = build_transcode_args(&request, &TranscodeContext::default(), &streams, None)
             .expect_err("invalid libvpx preset should fail");
 
         assert!(error.contains("libvpx preset must be an integer from 0 to 16"));
@@ -2250,7 +6193,7 @@ mod tests {
         request.video.preset = Some("9".to_string());
         let streams = vec![json!({ "codec_type": "video", "codec_name": "h264" })];
 
-        let error = build_transcode_args(&request, &streams, None)
+        let error = build_transcode_args(&request, &TranscodeContext::default(), &streams, None)
             .expect_err("invalid libvpx-vp9 preset should fail");
 
         assert!(error.contains("libvpx-vp9 preset must be an integer from 0 to 8"));
@@ -2272,8 +6215,8 @@ mod tests {
             "channel_layout": "5.1(side)"
         })];
 
-        let args =
-            build_transcode_args(&request, &streams, None).expect("libopus args should build");
+        let args = build_transcode_args(&request, &TranscodeContext::default(), &streams, None)
+            .expect("libopus args should build");
 
         assert!(
             args.windows(2)
@@ -2281,6 +6224,209 @@ mod tests {
         );
     }
 
+    #[test]
+    fn build_transcode_args_forces_keyframes_at_segment_boundaries() {
+        let mut request = build_request("/tmp/output.mp4");
+        request.subtitles.mode = "disable".to_string();
+        let streams = vec![json!({ "codec_type": "video", "codec_name": "h264" })];
+
+        let args = build_transcode_args(&request, &TranscodeContext::default(), &streams, None)
+            .expect("args should build");
+        assert!(!args.iter().any(|arg| arg.starts_with("-force_key_frames")));
+
+        let context = TranscodeContext {
+            segment_seconds: Some(6),
+            ..TranscodeContext::default()
+        };
+        let args =
+            build_transcode_args(&request, &context, &streams, None).expect("args should build");
+        assert!(
+            args.windows(2)
+                .any(|window| { window == ["-force_key_frames:v", "expr:gte(t,n_forced*6)"] })
+        );
+    }
+
+    #[test]
+    fn plan_packaged_transcode_encodes_each_rendition_then_packages_them() {
+        let mut request = build_request("/tmp/out/preview.m3u8");
+        request.container_id = "hls".to_string();
+        request.audio.encoder_id = Some("aac".to_string());
+        request.subtitles.mode = "disable".to_string();
+        request.packaging = TranscodePackagingSettings {
+            segment_format: None,
+            segment_seconds: Some(4),
+            renditions: vec![
+                TranscodeRendition {
+                    height: Some(720),
+                    video_bitrate_kbps: Some(3000),
+                    ..Default::default()
+                },
+                TranscodeRendition {
+                    height: Some(360),
+                    video_bitrate_kbps: Some(800),
+                    audio_bitrate_kbps: Some(96),
+                    ..Default::default()
+                },
+            ],
+        };
+        let streams = vec![
+            json!({ "index": 0, "codec_type": "video", "codec_name": "h264" }),
+            json!({ "index": 1, "codec_type": "audio", "codec_name": "aac", "channels": 2 }),
+            json!({ "index": 2, "codec_type": "subtitle", "codec_name": "subrip" }),
+        ];
+
+        let plan = plan_packaged_transcode(
+            &request,
+            &TranscodeContext::default(),
+            &streams,
+            Some(10_000_000),
+        )
+        .expect("packaging plan should build");
+        assert_eq!(plan.pass_count(), 3);
+        assert_eq!(plan.rendition_args.len(), 2);
+
+        let low = &plan.rendition_args[1][0];
+        assert!(low.windows(2).any(|window| window == ["-b:v", "800k"]));
+        assert!(
+            low.windows(2)
+                .any(|window| { window == ["-force_key_frames:v", "expr:gte(t,n_forced*4)"] })
+        );
+        assert!(low.iter().any(|arg| arg.contains("scale=-2:360")));
+        assert!(!low.iter().any(|arg| arg == "0:2"));
+        assert_eq!(
+            low.last().map(String::as_str),
+            plan.renditions[1].path.to_str()
+        );
+        assert!(plan.renditions.iter().all(|rendition| rendition.has_audio));
+        assert_eq!(plan.expected_streams.len(), 4);
+        assert_eq!(
+            plan.staged_output_path,
+            "/tmp/out/.preview.m3u8.partial/preview.m3u8"
+        );
+        assert_eq!(
+            plan.packaging_args.last().map(String::as_str),
+            Some("/tmp/out/.preview.m3u8.partial/preview_%v.m3u8")
+        );
+        assert!(
+            plan.rendition_requests
+                .iter()
+                .all(|rendition| rendition.verification.on_failure.is_none())
+        );
+        assert!(
+            plan.packaging_args
+                .windows(2)
+                .any(|window| window == ["-var_stream_map", "v:0,a:0,name:720p v:1,a:1,name:360p"])
+        );
+
+        request.video.quality_mode = Some("targetSize".to_string());
+        assert!(
+            plan_packaged_transcode(
+                &request,
+                &TranscodeContext::default(),
+                &streams,
+                Some(10_000_000)
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn build_loudnorm_measure_args_measure_every_joined_range() {
+        let mut request = build_request("/tmp/output.mp4");
+        request.ranges = vec![
+            TranscodeTimeRange {
+                start_ms: 0,
+                end_ms: Some(10_000),
+            },
+            TranscodeTimeRange {
+                start_ms: 20_000,
+                end_ms: Some(30_000),
+            },
+        ];
+        let streams = vec![
+            json!({ "index": 0, "codec_type": "audio", "codec_name": "aac", "channels": 2 }),
+            json!({ "index": 1, "codec_type": "audio", "codec_name": "aac", "channels": 2 }),
+        ];
+        let audio_streams = extract_streams_by_type(&streams, "audio");
+
+        let args = build_loudnorm_measure_args(
+            &request,
+            &audio_streams[1],
+            "loudnorm=I=-16:print_format=json",
+        );
+
+        assert_eq!(args.iter().filter(|arg| *arg == "-i").count(), 2);
+        assert!(args.windows(2).any(|window| {
+            window
+                == [
+                    "-filter_complex",
+                    "[0:a:1][1:a:1]concat=n=2:v=0:a=1[a0];[a0]loudnorm=I=-16:print_format=json[measured]",
+                ]
+        }));
+        assert!(
+            args.windows(2)
+                .any(|window| window == ["-map", "[measured]"])
+        );
+        assert!(!args.iter().any(|arg| arg == "-af" || arg == "0:a:1"));
+    }
+
+    #[test]
+    fn build_transcode_dry_run_warnings_flag_forced_mapping_family_and_skipped_steps() {
+        let mut request = build_request("/tmp/output.mkv");
+        request.container_id = "mkv".to_string();
+        request.video.mode = "disable".to_string();
+        request.audio.encoder_id = Some("libopus".to_string());
+        request.audio.channels = None;
+        request.subtitles.mode = "disable".to_string();
+        let streams = vec![
+            json!({
+                "index": 0,
+                "codec_type": "audio",
+                "codec_name": "flac",
+                "channels": 6,
+                "channel_layout": "5.1(side)"
+            }),
+            json!({
+                "index": 1,
+                "codec_type": "audio",
+                "codec_name": "aac",
+                "channels": 2,
+                "channel_layout": "stereo"
+            }),
+        ];
+
+        let warnings =
+            build_transcode_dry_run_warnings(&request, &streams).expect("warnings should build");
+        assert_eq!(
+            warnings,
+            vec![
+                "Audio track 0 (5.1(side)) is encoded with libopus mapping family 255 because its channel layout has no default mapping"
+            ]
+        );
+
+        request.video.two_pass = true;
+        request.video.mode = "transcode".to_string();
+        request.video.quality_mode = Some("bitrate".to_string());
+        request.video.bitrate_kbps = Some(2000);
+        request.video.crf = None;
+        let mut streams = streams;
+        streams.insert(
+            0,
+            json!({ "index": 2, "codec_type": "video", "codec_name": "h264" }),
+        );
+        let passlog_dir = std::env::temp_dir().join("mediaflow-dry-run-passlog-test");
+        let command_args = build_transcode_command_args(
+            &request,
+            &TranscodeContext::default(),
+            &streams,
+            None,
+            &passlog_dir,
+        )
+        .expect("two-pass args should build");
+        assert_eq!(command_args.len(), 2);
+        assert!(!passlog_dir.exists());
+    }
+
     #[test]
     fn build_transcode_args_keeps_default_mapping_family_for_supported_libopus_layouts() {
         let mut request = build_request("/tmp/output.opus");
@@ -2297,8 +6443,8 @@ mod tests {
             "channel_layout": "5.1"
         })];
 
-        let args =
-            build_transcode_args(&request, &streams, None).expect("libopus args should build");
+        let args = build_transcode_args(&request, &TranscodeContext::default(), &streams, None)
+            .expect("libopus args should build");
 
         assert!(!args.iter().any(|arg| arg.starts_with("-mapping_family")));
     }
@@ -2319,8 +6465,8 @@ mod tests {
             "channel_layout": "5.1(side)"
         })];
 
-        let args =
-            build_transcode_args(&request, &streams, None).expect("libopus args should build");
+        let args = build_transcode_args(&request, &TranscodeContext::default(), &streams, None)
+            .expect("libopus args should build");
 
         assert!(!args.iter().any(|arg| arg.starts_with("-mapping_family")));
         assert!(args.windows(2).any(|window| window == ["-ac:a:0", "6"]));
@@ -2351,6 +6497,7 @@ mod tests {
                 bitrate_kbps: Some(96),
                 channels: None,
                 sample_rate: Some(48_000),
+                filters: None,
                 additional_args: vec![TranscodeAdditionalArg {
                     _id: Some("cutoff".to_string()),
                     flag: "-cutoff".to_string(),
@@ -2365,6 +6512,7 @@ mod tests {
                 bitrate_kbps: None,
                 channels: None,
                 sample_rate: None,
+                filters: None,
                 additional_args: Vec::new(),
             },
         ];
@@ -2374,7 +6522,7 @@ mod tests {
             json!({ "index": 6, "codec_type": "audio", "codec_name": "aac" }),
         ];
 
-        let args = build_transcode_args(&request, &streams, None)
+        let args = build_transcode_args(&request, &TranscodeContext::default(), &streams, None)
             .expect("mixed audio override args should build");
 
         assert!(args.windows(2).any(|window| window == ["-map", "0:a:0"]));
@@ -2417,7 +6565,7 @@ mod tests {
             json!({ "index": 2, "codec_type": "audio", "codec_name": "opus" }),
         ];
 
-        let error = build_transcode_args(&request, &streams, None)
+        let error = build_transcode_args(&request, &TranscodeContext::default(), &streams, None)
             .expect_err("aac copy to webm should fail when one track is incompatible");
 
         assert!(error.contains("cannot copy audio codec aac"));
@@ -2441,6 +6589,7 @@ mod tests {
             bitrate_kbps: None,
             channels: None,
             sample_rate: None,
+            filters: None,
             additional_args: Vec::new(),
         }];
         let streams = vec![
@@ -2449,7 +6598,7 @@ mod tests {
             json!({ "index": 7, "codec_type": "audio", "codec_name": "mp3" }),
         ];
 
-        let args = build_transcode_args(&request, &streams, None)
+        let args = build_transcode_args(&request, &TranscodeContext::default(), &streams, None)
             .expect("copy args should build when first track is disabled");
 
         let mapped_streams = args
@@ -2483,6 +6632,7 @@ mod tests {
             bitrate_kbps: None,
             channels: None,
             sample_rate: None,
+            filters: None,
             additional_args: Vec::new(),
         }];
         request.metadata = MediaMetadataRequest {
@@ -2503,6 +6653,7 @@ mod tests {
                     forced: Some(true),
                 },
             ],
+            ..MediaMetadataRequest::default()
         };
         let streams = vec![
             json!({ "index": 3, "codec_type": "audio", "codec_name": "flac" }),
@@ -2510,7 +6661,7 @@ mod tests {
             json!({ "index": 7, "codec_type": "audio", "codec_name": "mp3" }),
         ];
 
-        let args = build_transcode_args(&request, &streams, None)
+        let args = build_transcode_args(&request, &TranscodeContext::default(), &streams, None)
             .expect("metadata args should build after disabling the first track");
 
         assert!(args.windows(2).any(|window| window == ["-map", "0:a:1"]));
@@ -2557,7 +6708,7 @@ mod tests {
             json!({ "index": 2, "codec_type": "audio", "codec_name": "aac" }),
         ];
 
-        let error = build_transcode_args(&request, &streams, None)
+        let error = build_transcode_args(&request, &TranscodeContext::default(), &streams, None)
             .expect_err("all disabled audio-only output should fail");
 
         assert!(error.contains("No streams selected for output"));
@@ -2571,8 +6722,8 @@ mod tests {
         request.video.preset = Some("6".to_string());
         let streams = vec![json!({ "codec_type": "video", "codec_name": "h264" })];
 
-        let args =
-            build_transcode_args(&request, &streams, None).expect("svtav1 args should build");
+        let args = build_transcode_args(&request, &TranscodeContext::default(), &streams, None)
+            .expect("svtav1 args should build");
 
         assert!(args.windows(2).any(|window| window == ["-preset", "6"]));
         assert!(!args.iter().any(|arg| arg == "-cpu-used"));
@@ -2606,6 +6757,30 @@ mod tests {
         assert!(output.exists());
     }
 
+    #[tokio::test]
+    async fn preview_transcode_with_bins_estimates_from_encoded_samples() {
+        let input = crate::test_support::assets::ensure_sample_video()
+            .await
+            .expect("failed to load local sample video");
+        let mut request = build_request("/tmp/preview-output.mp4");
+        request.input_path = input.to_string_lossy().to_string();
+
+        let estimate =
+            preview_transcode_with_bins(ffmpeg_path(), ffprobe_path(), &request, Some(2), Some(1))
+                .await
+                .expect("preview should succeed");
+
+        assert_eq!(estimate.samples.len(), 2);
+        assert!(
+            estimate
+                .samples
+                .iter()
+                .all(|sample| Path::new(&sample.path).exists() && sample.frames > 0)
+        );
+        assert!(estimate.fps > 0.0);
+        assert!(estimate.estimated_size_bytes > 0);
+    }
+
     #[tokio::test]
     async fn transcode_audio_matrix_succeeds_for_available_encoders_and_layouts() {
         let capabilities = get_transcode_capabilities_with_ffmpeg_path(ffmpeg_path())
@@ -2668,8 +6843,13 @@ mod tests {
                 let request =
                     build_audio_only_request(&fixture.path, &output_path, &container_id, encoder);
                 for error in collect_audio_request_arg_failures(
-                    &build_transcode_args(&request, &fixture_streams, None)
-                        .expect("audio request args should build"),
+                    &build_transcode_args(
+                        &request,
+                        &TranscodeContext::default(),
+                        &fixture_streams,
+                        None,
+                    )
+                    .expect("audio request args should build"),
                     &request,
                 ) {
                     failures.push(format!(
@@ -2842,8 +7022,13 @@ mod tests {
                     "libmp3lame" => 128,
                     _ => 112,
                 });
-                let args = build_transcode_args(&bitrate_request, &stereo_streams, None)
-                    .expect("bitrate request args should build");
+                let args = build_transcode_args(
+                    &bitrate_request,
+                    &TranscodeContext::default(),
+                    &stereo_streams,
+                    None,
+                )
+                .expect("bitrate request args should build");
                 for error in collect_audio_request_arg_failures(&args, &bitrate_request) {
                     failures.push(format!(
                         "encoder={} scenario=bitrate: {}",
@@ -2901,8 +7086,13 @@ mod tests {
                     encoder,
                 );
                 sample_rate_request.audio.sample_rate = Some(sample_rate);
-                let args = build_transcode_args(&sample_rate_request, &stereo_streams, None)
-                    .expect("sample rate request args should build");
+                let args = build_transcode_args(
+                    &sample_rate_request,
+                    &TranscodeContext::default(),
+                    &stereo_streams,
+                    None,
+                )
+                .expect("sample rate request args should build");
                 for error in collect_audio_request_arg_failures(&args, &sample_rate_request) {
                     failures.push(format!(
                         "encoder={} scenario=sample_rate: {}",
@@ -2961,8 +7151,13 @@ mod tests {
                 encoder,
             );
             channels_request.audio.channels = Some(2);
-            let args = build_transcode_args(&channels_request, &surround_streams, None)
-                .expect("channel override request args should build");
+            let args = build_transcode_args(
+                &channels_request,
+                &TranscodeContext::default(),
+                &surround_streams,
+                None,
+            )
+            .expect("channel override request args should build");
             for error in collect_audio_request_arg_failures(&args, &channels_request) {
                 failures.push(format!(
                     "encoder={} scenario=downmix: {}",
@@ -3056,8 +7251,13 @@ mod tests {
                 &scenario,
             );
             for error in collect_video_request_arg_failures(
-                &build_transcode_args(&request, &fixture_streams, None)
-                    .expect("video request args should build"),
+                &build_transcode_args(
+                    &request,
+                    &TranscodeContext::default(),
+                    &fixture_streams,
+                    None,
+                )
+                .expect("video request args should build"),
                 &request,
                 encoder,
             ) {
@@ -3147,8 +7347,13 @@ mod tests {
                     &scenario,
                 );
                 for error in collect_video_request_arg_failures(
-                    &build_transcode_args(&request, &fixture_streams, None)
-                        .expect("representative video request args should build"),
+                    &build_transcode_args(
+                        &request,
+                        &TranscodeContext::default(),
+                        &fixture_streams,
+                        None,
+                    )
+                    .expect("representative video request args should build"),
                     &request,
                     encoder,
                 ) {
@@ -3242,8 +7447,13 @@ mod tests {
                     &scenario,
                 );
                 for error in collect_video_request_arg_failures(
-                    &build_transcode_args(&request, &fixture_streams, None)
-                        .expect("advanced video request args should build"),
+                    &build_transcode_args(
+                        &request,
+                        &TranscodeContext::default(),
+                        &fixture_streams,
+                        None,
+                    )
+                    .expect("advanced video request args should build"),
                     &request,
                     encoder,
                 ) {
@@ -0,0 +1,431 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::shared::hash::stable_hash64;
+
+const DEFAULT_DURATION_TOLERANCE_MS: u64 = 1000;
+const VERIFIED_CODEC_TYPES: &[&str] = &["video", "audio", "subtitle"];
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TranscodeVerificationSettings {
+    /// `keep`, `delete` or `quarantine`; failed outputs are quarantined when unset.
+    pub(crate) on_failure: Option<String>,
+    /// How much shorter than the input the output may be; one second when unset.
+    pub(crate) duration_tolerance_ms: Option<u64>,
+}
+
+impl TranscodeVerificationSettings {
+    pub(crate) fn failure_action(&self) -> Result<&str, String> {
+        match self.on_failure.as_deref().unwrap_or("quarantine") {
+            action @ ("keep" | "delete" | "quarantine") => Ok(action),
+            other => Err(format!(
+                "Unsupported verification failure action: {}",
+                other
+            )),
+        }
+    }
+}
+
+/// One stream the request should produce; the codec is unknown for encoders
+/// missing from the capability tables.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ExpectedOutputStream {
+    pub(crate) codec_type: String,
+    pub(crate) codec_name: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TranscodeVerifiedStreams {
+    pub(crate) codec_type: String,
+    pub(crate) expected_codecs: Vec<String>,
+    pub(crate) output_codecs: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TranscodeVerificationReport {
+    pub(crate) passed: bool,
+    pub(crate) expected_duration_ms: Option<u64>,
+    pub(crate) output_duration_ms: Option<u64>,
+    pub(crate) streams: Vec<TranscodeVerifiedStreams>,
+    pub(crate) issues: Vec<String>,
+    /// `kept`, `deleted` or `quarantined`.
+    pub(crate) action: String,
+    pub(crate) quarantine_path: Option<String>,
+}
+
+/// ffprobe reports the `srt` encoder's output as `subrip`.
+fn normalize_codec_name(codec_name: &str) -> &str {
+    match codec_name {
+        "srt" => "subrip",
+        other => other,
+    }
+}
+
+fn output_codecs(output_streams: &[Value], codec_type: &str) -> Vec<String> {
+    output_streams
+        .iter()
+        .filter(|stream| {
+            stream.get("codec_type").and_then(|value| value.as_str()) == Some(codec_type)
+        })
+        .map(|stream| {
+            normalize_codec_name(
+                stream
+                    .get("codec_name")
+                    .and_then(|value| value.as_str())
+                    .unwrap_or_default(),
+            )
+            .to_string()
+        })
+        .collect()
+}
+
+/// Compare the probed output with what the request should have produced.
+pub(crate) fn verify_output_streams(
+    expected_streams: &[ExpectedOutputStream],
+    output_streams: &[Value],
+    expected_duration_us: Option<u64>,
+    output_duration_us: Option<u64>,
+    settings: &TranscodeVerificationSettings,
+) -> TranscodeVerificationReport {
+    let mut issues = Vec::new();
+
+    if let Some(expected_duration_us) = expected_duration_us.filter(|duration| *duration > 0) {
+        match output_duration_us {
+            Some(output_duration_us) => {
+                let tolerance_us = settings
+                    .duration_tolerance_ms
+                    .unwrap_or(DEFAULT_DURATION_TOLERANCE_MS)
+                    * 1000;
+                if output_duration_us + tolerance_us < expected_duration_us {
+                    issues.push(format!(
+                        "Output is {:.1} s shorter than expected ({:.1} s of {:.1} s)",
+                        (expected_duration_us - output_duration_us) as f64 / 1_000_000.0,
+                        output_duration_us as f64 / 1_000_000.0,
+                        expected_duration_us as f64 / 1_000_000.0
+                    ));
+                }
+            }
+            None => issues.push("Could not read the output duration".to_string()),
+        }
+    }
+
+    let mut streams = Vec::new();
+    for codec_type in VERIFIED_CODEC_TYPES {
+        let expected = expected_streams
+            .iter()
+            .filter(|stream| stream.codec_type == *codec_type)
+            .collect::<Vec<_>>();
+        let output = output_codecs(output_streams, codec_type);
+
+        if expected.len() != output.len() {
+            issues.push(format!(
+                "Expected {} {} stream(s), found {}",
+                expected.len(),
+                codec_type,
+                output.len()
+            ));
+        } else {
+            let mut unmatched = output.clone();
+            for codec_name in expected
+                .iter()
+                .filter_map(|stream| stream.codec_name.as_deref())
+                .map(normalize_codec_name)
+            {
+                match unmatched
+                    .iter()
+                    .position(|output_codec| output_codec == codec_name)
+                {
+                    Some(position) => {
+                        unmatched.remove(position);
+                    }
+                    None => issues.push(format!(
+                        "Expected {} codec {}, found {}",
+                        codec_type,
+                        codec_name,
+                        output.join(", ")
+                    )),
+                }
+            }
+        }
+
+        if !expected.is_empty() || !output.is_empty() {
+            streams.push(TranscodeVerifiedStreams {
+                codec_type: codec_type.to_string(),
+                expected_codecs: expected
+                    .iter()
+                    .map(|stream| {
+                        stream
+                            .codec_name
+                            .as_deref()
+                            .map(normalize_codec_name)
+                            .unwrap_or("unknown")
+                            .to_string()
+                    })
+                    .collect(),
+                output_codecs: output,
+            });
+        }
+    }
+
+    TranscodeVerificationReport {
+        passed: issues.is_empty(),
+        expected_duration_ms: expected_duration_us.map(|duration| duration / 1000),
+        output_duration_ms: output_duration_us.map(|duration| duration / 1000),
+        streams,
+        issues,
+        action: "kept".to_string(),
+        quarantine_path: None,
+    }
+}
+
+fn build_quarantine_dir(output_path: &str) -> PathBuf {
+    std::env::temp_dir()
+        .join("mediaflow_transcode_quarantine")
+        .join(format!("{:016x}", stable_hash64(output_path)))
+}
+
+/// Delete or quarantine an output that failed verification, as the settings ask.
+pub(crate) fn handle_failed_output(
+    output_path: &str,
+    settings: &TranscodeVerificationSettings,
+    report: &mut TranscodeVerificationReport,
+) -> Result<(), String> {
+    match settings.failure_action()? {
+        "delete" => {
+            std::fs::remove_file(output_path)
+                .map_err(|error| format!("Failed to delete unverified output: {}", error))?;
+            report.action = "deleted".to_string();
+        }
+        "quarantine" => {
+            let quarantine_dir = build_quarantine_dir(output_path);
+            std::fs::create_dir_all(&quarantine_dir)
+                .map_err(|error| format!("Failed to create quarantine folder: {}", error))?;
+            let file_name = Path::new(output_path)
+                .file_name()
+                .ok_or_else(|| "Output path has no file name".to_string())?;
+            let quarantine_path = quarantine_dir.join(file_name);
+            // Renaming fails across volumes, so fall back to copy and delete.
+            if std::fs::rename(output_path, &quarantine_path).is_err() {
+                std::fs::copy(output_path, &quarantine_path)
+                    .and_then(|_| std::fs::remove_file(output_path))
+                    .map_err(|error| {
+                        format!("Failed to quarantine unverified output: {}", error)
+                    })?;
+            }
+            report.action = "quarantined".to_string();
+            report.quarantine_path = Some(quarantine_path.to_string_lossy().to_string());
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Move every file of a flat folder into `target_dir`, replacing files already there.
+pub(crate) fn move_folder_files(source_dir: &Path, target_dir: &Path) -> Result<(), String> {
+    std::fs::create_dir_all(target_dir)
+        .map_err(|error| format!("Failed to create {}: {}", target_dir.display(), error))?;
+    let entries = std::fs::read_dir(source_dir)
+        .map_err(|error| format!("Failed to read {}: {}", source_dir.display(), error))?;
+    for entry in entries {
+        let source_path = entry
+            .map_err(|error| format!("Failed to read {}: {}", source_dir.display(), error))?
+            .path();
+        let Some(file_name) = source_path.file_name() else {
+            continue;
+        };
+        let target_path = target_dir.join(file_name);
+        // Renaming fails across volumes, so fall back to copy and delete.
+        if std::fs::rename(&source_path, &target_path).is_err() {
+            std::fs::copy(&source_path, &target_path)
+                .and_then(|_| std::fs::remove_file(&source_path))
+                .map_err(|error| format!("Failed to move {}: {}", source_path.display(), error))?;
+        }
+    }
+    let _ = std::fs::remove_dir_all(source_dir);
+    Ok(())
+}
+
+/// Delete or quarantine a staged HLS or DASH package that failed verification;
+/// `output_path` is the playlist or manifest the package was meant for.
+pub(crate) fn handle_failed_package(
+    staging_dir: &Path,
+    output_path: &str,
+    settings: &TranscodeVerificationSettings,
+    report: &mut TranscodeVerificationReport,
+) -> Result<(), String> {
+    match settings.failure_action()? {
+        "delete" => {
+            std::fs::remove_dir_all(staging_dir)
+                .map_err(|error| format!("Failed to delete unverified output: {}", error))?;
+            report.action = "deleted".to_string();
+        }
+        "quarantine" => {
+            let quarantine_dir = build_quarantine_dir(output_path);
+            move_folder_files(staging_dir, &quarantine_dir)?;
+            let file_name = Path::new(output_path)
+                .file_name()
+                .ok_or_else(|| "Output path has no file name".to_string())?;
+            report.action = "quarantined".to_string();
+            report.quarantine_path =
+                Some(quarantine_dir.join(file_name).to_string_lossy().to_string());
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::test_support::paths::new_temp_dir;
+
+    use super::{
+        ExpectedOutputStream, TranscodeVerificationSettings, handle_failed_output,
+        handle_failed_package, verify_output_streams,
+    };
+
+    fn expected(codec_type: &str, codec_name: &str) -> ExpectedOutputStream {
+        ExpectedOutputStream {
+            codec_type: codec_type.to_string(),
+            codec_name: Some(codec_name.to_string()),
+        }
+    }
+
+    #[test]
+    fn verify_output_streams_flags_truncation_and_missing_streams() {
+        let expected_streams = vec![
+            expected("video", "hevc"),
+            expected("audio", "aac"),
+            expected("subtitle", "srt"),
+        ];
+        let output_streams = vec![
+            json!({ "codec_type": "video", "codec_name": "hevc" }),
+            json!({ "codec_type": "audio", "codec_name": "aac" }),
+            json!({ "codec_type": "subtitle", "codec_name": "subrip" }),
+            json!({ "codec_type": "attachment", "codec_name": "ttf" }),
+        ];
+        let settings = TranscodeVerificationSettings::default();
+
+        let report = verify_output_streams(
+            &expected_streams,
+            &output_streams,
+            Some(60_000_000),
+            Some(59_500_000),
+            &settings,
+        );
+        assert!(report.passed, "{:?}", report.issues);
+        assert_eq!(report.streams.len(), 3);
+
+        let report = verify_output_streams(
+            &expected_streams,
+            &output_streams[..2],
+            Some(60_000_000),
+            Some(42_000_000),
+            &settings,
+        );
+        assert!(!report.passed);
+        assert_eq!(
+            report.issues,
+            vec![
+                "Output is 18.0 s shorter than expected (42.0 s of 60.0 s)",
+                "Expected 1 subtitle stream(s), found 0",
+            ]
+        );
+
+        let report = verify_output_streams(
+            &[expected("audio", "opus")],
+            &output_streams[1..2],
+            None,
+            None,
+            &settings,
+        );
+        assert_eq!(report.issues, vec!["Expected audio codec opus, found aac"]);
+    }
+
+    #[test]
+    fn handle_failed_output_deletes_or_quarantines_per_setting() {
+        let temp_dir = new_temp_dir("mediaflow-verify-");
+        let output_path = temp_dir.path().join("broken.mkv");
+        let output_path_str = output_path.to_string_lossy().to_string();
+        let mut report = verify_output_streams(&[], &[], None, None, &Default::default());
+
+        std::fs::write(&output_path, b"partial").expect("write output");
+        let settings = TranscodeVerificationSettings {
+            on_failure: Some("quarantine".to_string()),
+            duration_tolerance_ms: None,
+        };
+        handle_failed_output(&output_path_str, &settings, &mut report).expect("quarantine");
+        let quarantine_path = report.quarantine_path.clone().expect("quarantine path");
+        assert_eq!(report.action, "quarantined");
+        assert!(!output_path.exists());
+        assert_eq!(
+            std::fs::read(&quarantine_path).expect("read quarantined output"),
+            b"partial"
+        );
+        let _ = std::fs::remove_file(quarantine_path);
+
+        std::fs::write(&output_path, b"partial").expect("write output");
+        let settings = TranscodeVerificationSettings {
+            on_failure: Some("delete".to_string()),
+            duration_tolerance_ms: None,
+        };
+        handle_failed_output(&output_path_str, &settings, &mut report).expect("delete");
+        assert_eq!(report.action, "deleted");
+        assert!(!output_path.exists());
+
+        let settings = TranscodeVerificationSettings {
+            on_failure: Some("rename".to_string()),
+            duration_tolerance_ms: None,
+        };
+        assert!(settings.failure_action().is_err());
+    }
+
+    #[test]
+    fn handle_failed_package_moves_or_deletes_the_whole_package() {
+        let temp_dir = new_temp_dir("mediaflow-verify-package-");
+        let staging_dir = temp_dir.path().join(".master.m3u8.partial");
+        let output_path = temp_dir.path().join("master.m3u8");
+        let output_path_str = output_path.to_string_lossy().to_string();
+        let write_package = || {
+            std::fs::create_dir_all(&staging_dir).expect("create staging dir");
+            for file_name in ["master.m3u8", "master_0.m3u8", "master_0_00000.m4s"] {
+                std::fs::write(staging_dir.join(file_name), b"partial").expect("write file");
+            }
+        };
+        let mut report = verify_output_streams(&[], &[], None, None, &Default::default());
+
+        write_package();
+        let settings = TranscodeVerificationSettings {
+            on_failure: Some("quarantine".to_string()),
+            duration_tolerance_ms: None,
+        };
+        handle_failed_package(&staging_dir, &output_path_str, &settings, &mut report)
+            .expect("quarantine");
+        let quarantine_path = report.quarantine_path.clone().expect("quarantine path");
+        let quarantine_dir = std::path::Path::new(&quarantine_path)
+            .parent()
+            .expect("quarantine dir")
+            .to_path_buf();
+        assert_eq!(report.action, "quarantined");
+        assert!(!staging_dir.exists());
+        assert!(quarantine_dir.join("master_0_00000.m4s").exists());
+        let _ = std::fs::remove_dir_all(quarantine_dir);
+
+        write_package();
+        let settings = TranscodeVerificationSettings {
+            on_failure: Some("delete".to_string()),
+            duration_tolerance_ms: None,
+        };
+        handle_failed_package(&staging_dir, &output_path_str, &settings, &mut report)
+            .expect("delete");
+        assert_eq!(report.action, "deleted");
+        assert!(!staging_dir.exists());
+        assert!(!output_path.exists());
+    }
+}